@@ -0,0 +1,92 @@
+#ifndef MARKOV_CHAINS_H
+#define MARKOV_CHAINS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Language model loaded by the host application
+ */
+typedef struct McModel McModel;
+
+/**
+ * Get message of the last error happened in the calling thread
+ *
+ * Returns NULL if there were no errors. The string is owned by
+ * the library and valid until the next error in the thread.
+ */
+const char *mc_last_error(void);
+
+/**
+ * Load model from the file
+ *
+ * Returns NULL on failure, see `mc_last_error`.
+ * The model must be freed by `mc_model_free`.
+ *
+ * # Safety
+ *
+ * `path` must be a valid nul-terminated string.
+ */
+struct McModel *mc_model_load(const char *path);
+
+/**
+ * Load model from the bytes of its bundle
+ *
+ * Returns NULL on failure, see `mc_last_error`. The bytes are
+ * not used after the call. The model must be freed by `mc_model_free`.
+ *
+ * # Safety
+ *
+ * `bytes` must point to `len` readable bytes.
+ */
+struct McModel *mc_model_from_bytes(const uint8_t *bytes, size_t len);
+
+/**
+ * Replace default generation params of the model by the JSON object
+ *
+ * Missing params take their default values. Returns false
+ * on failure, see `mc_last_error`.
+ *
+ * # Safety
+ *
+ * `model` must be returned by the library and not freed,
+ * `params` must be a valid nul-terminated string.
+ */
+bool mc_model_set_params(struct McModel *model, const char *params);
+
+/**
+ * Generate text continuing the prompt into the buffer
+ *
+ * Empty prompt generates a new message. `overrides` is NULL or
+ * a JSON object with `temperature`, `max_len`, `seed` and `top_k`
+ * fields changing the params of this call.
+ *
+ * The text is written nul-terminated and cut at a character boundary
+ * to fit the buffer. Returns the length of the whole text in bytes
+ * without the terminator, so the text was cut if it's not less than
+ * `buffer_len`, or -1 on failure, see `mc_last_error`. Set `seed`
+ * to get the same text when retrying with a larger buffer.
+ *
+ * # Safety
+ *
+ * `model` must be returned by the library and not freed, `prompt`
+ * and non-NULL `overrides` must be valid nul-terminated strings,
+ * `buffer` must point to `buffer_len` writable bytes.
+ */
+ptrdiff_t mc_model_generate(const struct McModel *model,
+                            const char *prompt,
+                            const char *overrides,
+                            char *buffer,
+                            size_t buffer_len);
+
+/**
+ * Free the model
+ *
+ * # Safety
+ *
+ * `model` must be NULL or returned by the library and not freed.
+ */
+void mc_model_free(struct McModel *model);
+
+#endif  /* MARKOV_CHAINS_H */
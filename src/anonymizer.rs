@@ -0,0 +1,214 @@
+use regex::Regex;
+
+use crate::prelude::Messages;
+use crate::Error;
+
+pub const EMAIL_PLACEHOLDER: &str = "<EMAIL>";
+pub const PHONE_PLACEHOLDER: &str = "<PHONE>";
+pub const HANDLE_PLACEHOLDER: &str = "<HANDLE>";
+pub const NAME_PLACEHOLDER: &str = "<NAME>";
+pub const URL_PLACEHOLDER: &str = "<URL>";
+pub const USER_PLACEHOLDER: &str = "<USER>";
+pub const CARD_PLACEHOLDER: &str = "<CARD>";
+
+#[derive(Debug, Clone)]
+/// Replaces personal data in the messages by placeholders
+///
+/// Patterns are applied to the whole message text in
+/// the order they were added.
+pub struct Anonymizer {
+    patterns: Vec<(Regex, String)>
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        let patterns = [
+            (r"[\w.+-]+@[\w-]+(\.[\w-]+)+", EMAIL_PLACEHOLDER),
+            (r"@[\w.]*\w", HANDLE_PLACEHOLDER),
+            (r"\+?\d[\d\s().-]{5,}\d", PHONE_PLACEHOLDER)
+        ];
+
+        Self {
+            patterns: patterns.into_iter()
+                .map(|(pattern, placeholder)| (Regex::new(pattern).unwrap(), placeholder.to_string()))
+                .collect()
+        }
+    }
+}
+
+impl Anonymizer {
+    /// Anonymizer collapsing URLs, emails and user mentions
+    /// into the `<URL>`, `<EMAIL>` and `<USER>` placeholders
+    pub fn special_tokens() -> Self {
+        let patterns = [
+            (r#"(https?://|www\.)[^\s()\[\]<>"]*[^\s()\[\]<>".,!?;:']"#, URL_PLACEHOLDER),
+            (r"[\w.+-]+@[\w-]+(\.[\w-]+)+", EMAIL_PLACEHOLDER),
+            (r"<@[!&]?\d+>|@[\w.]*\w", USER_PLACEHOLDER)
+        ];
+
+        Self {
+            patterns: patterns.into_iter()
+                .map(|(pattern, placeholder)| (Regex::new(pattern).unwrap(), placeholder.to_string()))
+                .collect()
+        }
+    }
+
+    /// Anonymizer scrubbing emails, credit card like numbers,
+    /// phone numbers and user mentions from the chat exports
+    ///
+    /// Cards are matched before phones since both are digit runs.
+    pub fn scrubber() -> Self {
+        let patterns = [
+            (r"[\w.+-]+@[\w-]+(\.[\w-]+)+", EMAIL_PLACEHOLDER),
+            (r"\b\d(?:[ -]?\d){12,18}\b", CARD_PLACEHOLDER),
+            (r"\+?\d[\d\s().-]{5,}\d", PHONE_PLACEHOLDER),
+            (r"<@[!&]?\d+>|@[\w.]*\w", USER_PLACEHOLDER)
+        ];
+
+        Self {
+            patterns: patterns.into_iter()
+                .map(|(pattern, placeholder)| (Regex::new(pattern).unwrap(), placeholder.to_string()))
+                .collect()
+        }
+    }
+
+    #[inline]
+    /// Anonymizer without any patterns
+    pub fn empty() -> Self {
+        Self {
+            patterns: Vec::new()
+        }
+    }
+
+    #[inline]
+    /// Placeholders of the patterns in their order
+    pub fn placeholders(&self) -> impl Iterator<Item = &'_ str> {
+        self.patterns.iter()
+            .map(|(_, placeholder)| placeholder.as_str())
+    }
+
+    /// Add regex pattern replaced by the placeholder
+    pub fn with_pattern(mut self, pattern: impl AsRef<str>, placeholder: impl ToString) -> Result<Self, Error> {
+        self.patterns.push((Regex::new(pattern.as_ref())?, placeholder.to_string()));
+
+        Ok(self)
+    }
+
+    /// Add names replaced by the `<NAME>` placeholder
+    ///
+    /// Names are matched case-insensitively as whole words.
+    pub fn with_names<T: AsRef<str>>(self, names: impl IntoIterator<Item = T>) -> Result<Self, Error> {
+        let names = names.into_iter()
+            .map(|name| regex::escape(name.as_ref().trim()))
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            return Ok(self);
+        }
+
+        self.with_pattern(format!(r"(?i)\b({})\b", names.join("|")), NAME_PLACEHOLDER)
+    }
+
+    #[inline]
+    /// Check if the text has matches of any pattern
+    pub fn is_match(&self, text: &str) -> bool {
+        self.patterns.iter().any(|(pattern, _)| pattern.is_match(text))
+    }
+
+    /// Replace all the patterns in the text
+    pub fn anonymize(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for (pattern, placeholder) in &self.patterns {
+            text = pattern.replace_all(&text, regex::NoExpand(placeholder)).into_owned();
+        }
+
+        text
+    }
+}
+
+impl Messages {
+    /// Replace personal data in all the messages
+    ///
+    /// Words of each message are joined by spaces before
+    /// matching so patterns can span multiple words.
+    pub fn anonymize(self, anonymizer: &Anonymizer) -> Self {
+        let messages = self.messages.into_iter()
+            .map(|message| {
+                anonymizer.anonymize(&message.join(" "))
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|message| !message.is_empty())
+            .collect();
+
+        Self {
+            messages
+        }
+    }
+
+    /// Remove messages with personal data instead of replacing it
+    pub fn drop_anonymized(mut self, anonymizer: &Anonymizer) -> Self {
+        self.messages.retain(|message| !anonymizer.is_match(&message.join(" ")));
+
+        self
+    }
+}
+
+mod tests {
+    #[test]
+    fn anonymize() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let anonymizer = Anonymizer::default()
+            .with_names(["Alice", "Bob"])?;
+
+        assert_eq!(
+            anonymizer.anonymize("alice, write to bob.smith@mail.com or @bob_1"),
+            "<NAME>, write to <EMAIL> or <HANDLE>"
+        );
+
+        assert_eq!(
+            anonymizer.anonymize("call +1 (555) 123-45-67 tomorrow"),
+            "call <PHONE> tomorrow"
+        );
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("my number is 555 123 4567")
+        ]);
+
+        let messages = messages.anonymize(&anonymizer);
+
+        assert!(messages.messages().contains(&vec![
+            String::from("my"),
+            String::from("number"),
+            String::from("is"),
+            String::from("<PHONE>")
+        ]));
+
+        let special = Anonymizer::special_tokens();
+
+        assert_eq!(
+            special.anonymize("see (https://example.com/a?b=c), mail me@mail.com or <@!123> @bob"),
+            "see (<URL>), mail <EMAIL> or <USER> <USER>"
+        );
+
+        let scrubber = Anonymizer::scrubber();
+
+        assert_eq!(
+            scrubber.anonymize("pay with 4111 1111 1111 1111 or call 555-123-4567, @bob <@!42>"),
+            "pay with <CARD> or call <PHONE>, <USER> <USER>"
+        );
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("write me at me@mail.com"),
+            String::from("nothing personal")
+        ]);
+
+        assert_eq!(messages.drop_anonymized(&scrubber).messages(), [["nothing", "personal"]]);
+
+        Ok(())
+    }
+}
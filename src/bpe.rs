@@ -0,0 +1,376 @@
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::cmp::Reverse;
+
+use crate::prelude::{
+    Messages,
+    Tokens,
+    Model,
+    TokenizerKind,
+    TOKENIZER_HEADER
+};
+
+/// Suffix of the subwords continued by the next subword
+pub const SUBWORD_MARKER: &str = "@@";
+
+/// Model header set if its tokens are subwords
+pub const SUBWORDS_HEADER: &str = "subwords";
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(from = "Vec<(String, String)>", into = "Vec<(String, String)>")]
+/// Byte-pair encoding subwords tokenizer
+///
+/// Words are split into subwords, where every subword
+/// except the last one ends with `@@`:
+/// `unbelievable` -> `un@@ believ@@ able`.
+pub struct Bpe {
+    merges: Vec<(String, String)>,
+
+    /// pair -> merge priority
+    ranks: HashMap<(String, String), usize>
+}
+
+impl From<Vec<(String, String)>> for Bpe {
+    fn from(merges: Vec<(String, String)>) -> Self {
+        let ranks = merges.iter()
+            .cloned()
+            .enumerate()
+            .map(|(rank, pair)| (pair, rank))
+            .collect();
+
+        Self {
+            merges,
+            ranks
+        }
+    }
+}
+
+impl From<Bpe> for Vec<(String, String)> {
+    #[inline]
+    fn from(bpe: Bpe) -> Self {
+        bpe.merges
+    }
+}
+
+/// Split the word into marked characters
+fn split_chars(word: &str) -> Vec<String> {
+    let mut chars = word.chars()
+        .map(|char| format!("{char}{SUBWORD_MARKER}"))
+        .collect::<Vec<_>>();
+
+    if let Some(last) = chars.last_mut() {
+        last.truncate(last.len() - SUBWORD_MARKER.len());
+    }
+
+    chars
+}
+
+/// Merge the marked subword with the following one
+fn merge_pair(left: &str, right: &str) -> String {
+    format!("{}{right}", left.strip_suffix(SUBWORD_MARKER).unwrap_or(left))
+}
+
+/// Merge all non-overlapping occurrences of the pair
+fn merge_symbols(symbols: &[u32], pair: (u32, u32), merged: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(symbols.len());
+
+    let mut i = 0;
+
+    while i < symbols.len() {
+        if i + 1 < symbols.len() && (symbols[i], symbols[i + 1]) == pair {
+            result.push(merged);
+
+            i += 2;
+        } else {
+            result.push(symbols[i]);
+
+            i += 1;
+        }
+    }
+
+    result
+}
+
+impl Bpe {
+    /// Learn merges from the messages until the vocabulary
+    /// reaches the given size or no pair repeats
+    pub fn train(messages: &Messages, vocab_size: usize) -> Self {
+        let mut frequencies = HashMap::<&str, u64>::new();
+
+        for word in messages.messages().iter().flatten() {
+            *frequencies.entry(word).or_default() += 1;
+        }
+
+        // Intern symbols to compare numbers instead of strings
+        let mut symbols = Vec::<String>::new();
+        let mut symbol_ids = HashMap::<String, u32>::new();
+
+        let mut intern = |symbol: String, symbols: &mut Vec<String>| {
+            *symbol_ids.entry(symbol.clone()).or_insert_with(|| {
+                symbols.push(symbol);
+
+                symbols.len() as u32 - 1
+            })
+        };
+
+        let mut words = frequencies.into_iter()
+            .map(|(word, frequency)| {
+                let word = split_chars(word).into_iter()
+                    .map(|symbol| intern(symbol, &mut symbols))
+                    .collect::<Vec<_>>();
+
+                (word, frequency)
+            })
+            .collect::<Vec<_>>();
+
+        let mut pair_counts = HashMap::<(u32, u32), u64>::new();
+        let mut pair_words = HashMap::<(u32, u32), HashSet<usize>>::new();
+
+        for (i, (word, frequency)) in words.iter().enumerate() {
+            for pair in word.windows(2) {
+                *pair_counts.entry((pair[0], pair[1])).or_default() += frequency;
+
+                pair_words.entry((pair[0], pair[1])).or_default().insert(i);
+            }
+        }
+
+        // Outdated entries are skipped when their count doesn't match
+        let mut queue = pair_counts.iter()
+            .map(|(pair, count)| (*count, Reverse(*pair)))
+            .collect::<BinaryHeap<_>>();
+
+        let mut merges = Vec::new();
+
+        while symbols.len() < vocab_size {
+            let Some((count, Reverse(pair))) = queue.pop() else {
+                break;
+            };
+
+            if pair_counts.get(&pair) != Some(&count) {
+                continue;
+            }
+
+            if count < 2 {
+                break;
+            }
+
+            let (left, right) = (&symbols[pair.0 as usize], &symbols[pair.1 as usize]);
+
+            merges.push((left.clone(), right.clone()));
+
+            let merged = intern(merge_pair(left, right), &mut symbols);
+
+            let mut changed = HashSet::new();
+
+            for i in pair_words.remove(&pair).unwrap_or_default() {
+                let (word, frequency) = &mut words[i];
+
+                let new_word = merge_symbols(word, pair, merged);
+
+                for old in word.windows(2) {
+                    let old = (old[0], old[1]);
+
+                    if let Some(count) = pair_counts.get_mut(&old) {
+                        *count = count.saturating_sub(*frequency);
+                    }
+
+                    changed.insert(old);
+                }
+
+                for new in new_word.windows(2) {
+                    let new = (new[0], new[1]);
+
+                    *pair_counts.entry(new).or_default() += *frequency;
+
+                    pair_words.entry(new).or_default().insert(i);
+
+                    changed.insert(new);
+                }
+
+                *word = new_word;
+            }
+
+            pair_counts.remove(&pair);
+
+            for pair in changed {
+                if let Some(count) = pair_counts.get(&pair) {
+                    queue.push((*count, Reverse(pair)));
+                }
+            }
+        }
+
+        Self::from(merges)
+    }
+
+    #[inline]
+    /// Learned merges in their priority order
+    pub fn merges(&self) -> &[(String, String)] {
+        &self.merges
+    }
+
+    /// Split the word into subwords
+    pub fn encode_word(&self, word: &str) -> Vec<String> {
+        let mut symbols = split_chars(word);
+
+        loop {
+            let best = symbols.windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.ranks.get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+
+            let Some((_, i)) = best else {
+                break;
+            };
+
+            let merged = merge_pair(&symbols[i], &symbols[i + 1]);
+
+            symbols.splice(i..i + 2, [merged]);
+        }
+
+        symbols
+    }
+
+    /// Split words of all the messages into subwords
+    pub fn encode(&self, messages: &Messages) -> Messages {
+        let mut cache = HashMap::<&str, Vec<String>>::new();
+
+        let messages = messages.messages()
+            .iter()
+            .map(|message| {
+                message.iter()
+                    .flat_map(|word| {
+                        cache.entry(word)
+                            .or_insert_with(|| self.encode_word(word))
+                            .clone()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Messages {
+            messages
+        }
+    }
+}
+
+/// Join subwords back into words
+pub fn join_subwords<T: AsRef<str>>(subwords: &[T]) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+
+    for subword in subwords {
+        let subword = subword.as_ref();
+
+        match subword.strip_suffix(SUBWORD_MARKER) {
+            Some(subword) => word.push_str(subword),
+
+            None => {
+                word.push_str(subword);
+
+                words.push(std::mem::take(&mut word));
+            }
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+impl Tokens {
+    /// Split the word into the longest known subwords
+    ///
+    /// Returns `None` if some part of the word has no subword.
+    pub fn find_subwords(&self, word: &str) -> Option<Vec<u64>> {
+        let mut tokens = Vec::new();
+        let mut rest = word;
+
+        while !rest.is_empty() {
+            let ends = rest.char_indices()
+                .map(|(i, _)| i)
+                .skip(1)
+                .chain([rest.len()])
+                .collect::<Vec<_>>();
+
+            let token = ends.into_iter()
+                .rev()
+                .find_map(|end| {
+                    let token = if end == rest.len() {
+                        self.find_token(rest)
+                    } else {
+                        self.find_token(format!("{}{SUBWORD_MARKER}", &rest[..end]))
+                    };
+
+                    token.map(|token| (token, end))
+                });
+
+            let (token, end) = token?;
+
+            tokens.push(token);
+
+            rest = &rest[end..];
+        }
+
+        Some(tokens)
+    }
+}
+
+impl Model {
+    #[inline]
+    /// Check if tokens of the model are subwords
+    pub fn has_subwords(&self) -> bool {
+        self.headers.contains_key(SUBWORDS_HEADER) ||
+            self.headers.get(TOKENIZER_HEADER).is_some_and(|name| name == TokenizerKind::Bpe.name())
+    }
+}
+
+mod tests {
+    #[test]
+    fn bpe() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bpe::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("lower lowest newer newest"),
+            String::from("low lower new newer"),
+            String::from("wider widest")
+        ]);
+
+        let bpe = Bpe::train(&messages, 30);
+
+        assert!(!bpe.merges().is_empty());
+        assert_eq!(bpe.merges()[0], (String::from("w@@"), String::from("e@@")));
+
+        let encoded = bpe.encode(&messages);
+
+        for (message, encoded) in messages.messages().iter().zip(encoded.messages()) {
+            assert!(encoded.len() >= message.len());
+            assert_eq!(&join_subwords(encoded), message);
+        }
+
+        // Postcard stores only the merges
+        let bpe = postcard::from_bytes::<Bpe>(&postcard::to_allocvec(&bpe)?)?;
+
+        assert_eq!(bpe.encode(&messages).messages(), encoded.messages());
+
+        let tokens = Tokens::parse_from_messages(&encoded);
+
+        // Unseen word made of the known subwords
+        let subwords = tokens.find_subwords("lonew").unwrap();
+
+        let words = subwords.iter()
+            .map(|token| tokens.find_word(*token).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(words, ["lo@@", "ne@@", "w"]);
+        assert_eq!(join_subwords(&words), ["lonew"]);
+
+        assert!(tokens.find_subwords("xyz").is_none());
+
+        Ok(())
+    }
+}
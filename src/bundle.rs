@@ -0,0 +1,544 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::prelude::{
+    Messages,
+    Tokens,
+    TokenizedMessages,
+    Dataset,
+    ManifestEntry,
+    Model,
+    Evaluation,
+    Bpe
+};
+
+use crate::tokens::LegacyTokens;
+use crate::Error;
+
+/// First bytes of the zstd frame
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// First bytes of the bundles with format header
+pub const BUNDLE_MAGIC: [u8; 4] = *b"MRKV";
+
+/// Current version of the bundles format
+///
+/// Version 0 is used for the bundles written without format header.
+/// Version 2 stores the dialogues of the datasets.
+/// Version 3 stores names of the dataset messages sources.
+/// Version 4 stores occurrences of the tokens.
+pub const FORMAT_VERSION: u8 = 4;
+
+/// Compression level of the written bundles
+///
+/// 0 disables compression.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Type of the value stored in the bundle
+pub enum BundleKind {
+    Messages,
+    Tokens,
+    TokenizedMessages,
+    Dataset,
+    Model,
+    Evaluation,
+    Bpe,
+    MappedModel
+}
+
+impl BundleKind {
+    /// Byte storing the kind in the format header
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Self::Messages          => 1,
+            Self::Tokens            => 2,
+            Self::TokenizedMessages => 3,
+            Self::Dataset           => 4,
+            Self::Model             => 5,
+            Self::Evaluation        => 6,
+            Self::Bpe               => 7,
+            Self::MappedModel       => 8
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::Messages),
+            2 => Some(Self::Tokens),
+            3 => Some(Self::TokenizedMessages),
+            4 => Some(Self::Dataset),
+            5 => Some(Self::Model),
+            6 => Some(Self::Evaluation),
+            7 => Some(Self::Bpe),
+            8 => Some(Self::MappedModel),
+
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for BundleKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Messages          => write!(f, "messages"),
+            Self::Tokens            => write!(f, "tokens"),
+            Self::TokenizedMessages => write!(f, "tokenized messages"),
+            Self::Dataset           => write!(f, "dataset"),
+            Self::Model             => write!(f, "model"),
+            Self::Evaluation        => write!(f, "evaluation"),
+            Self::Bpe               => write!(f, "subwords tokenizer"),
+            Self::MappedModel       => write!(f, "mapped model")
+        }
+    }
+}
+
+/// Value which can be stored in the bundle file
+pub trait Bundle: Serialize + DeserializeOwned {
+    const KIND: BundleKind;
+
+    #[inline]
+    /// Decode the value from the postcard bytes of the bundle
+    /// written with the given format version
+    fn decode(payload: &[u8], _version: u8) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(payload)
+    }
+}
+
+impl Bundle for Messages {
+    const KIND: BundleKind = BundleKind::Messages;
+}
+
+impl Bundle for Tokens {
+    const KIND: BundleKind = BundleKind::Tokens;
+
+    fn decode(payload: &[u8], version: u8) -> Result<Self, postcard::Error> {
+        if version >= 4 {
+            return postcard::from_bytes(payload);
+        }
+
+        postcard::from_bytes::<LegacyTokens>(payload).map(Tokens::from)
+    }
+}
+
+impl Bundle for TokenizedMessages {
+    const KIND: BundleKind = BundleKind::TokenizedMessages;
+}
+
+impl Bundle for Dataset {
+    const KIND: BundleKind = BundleKind::Dataset;
+
+    fn decode(payload: &[u8], version: u8) -> Result<Self, postcard::Error> {
+        if version >= 4 {
+            return postcard::from_bytes(payload);
+        }
+
+        type Sources = Vec<(TokenizedMessages, u64)>;
+
+        // Older datasets have no tokens counts and sources names,
+        // and the oldest ones have no dialogues and provenance
+        let (messages, tokens, provenance, dialogues, sources) = match version {
+            3 => postcard::from_bytes::<(Sources, LegacyTokens, Vec<ManifestEntry>, Vec<usize>, Vec<String>)>(payload)?,
+
+            2 => {
+                let (messages, tokens, provenance, dialogues) = postcard::from_bytes::<(Sources, LegacyTokens, Vec<ManifestEntry>, Vec<usize>)>(payload)?;
+
+                (messages, tokens, provenance, dialogues, Vec::new())
+            }
+
+            1 => {
+                let (messages, tokens, provenance) = postcard::from_bytes::<(Sources, LegacyTokens, Vec<ManifestEntry>)>(payload)?;
+
+                (messages, tokens, provenance, Vec::new(), Vec::new())
+            }
+
+            // Bundles without format header could be written
+            // before the provenance was stored as well
+            _ => match postcard::from_bytes::<(Sources, LegacyTokens, Vec<ManifestEntry>)>(payload) {
+                Ok((messages, tokens, provenance)) => (messages, tokens, provenance, Vec::new(), Vec::new()),
+
+                Err(_) => {
+                    let (messages, tokens) = postcard::from_bytes::<(Sources, LegacyTokens)>(payload)?;
+
+                    (messages, tokens, Vec::new(), Vec::new(), Vec::new())
+                }
+            }
+        };
+
+        Ok(Self {
+            messages,
+            tokens: tokens.into(),
+            provenance,
+            dialogues,
+            sources
+        })
+    }
+}
+
+impl Bundle for Model {
+    const KIND: BundleKind = BundleKind::Model;
+}
+
+impl Bundle for Evaluation {
+    const KIND: BundleKind = BundleKind::Evaluation;
+}
+
+impl Bundle for Bpe {
+    const KIND: BundleKind = BundleKind::Bpe;
+}
+
+#[inline]
+/// Check if the bytes are compressed by zstd
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZSTD_MAGIC)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn zstd_decode(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::decode_all(bytes)
+}
+
+#[cfg(target_arch = "wasm32")]
+/// Decode with the pure Rust decoder since zstd can't be built for wasm
+fn zstd_decode(mut bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut decoder = ruzstd::StreamingDecoder::new(&mut bytes)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+    let mut decoded = Vec::new();
+
+    decoder.read_to_end(&mut decoded)?;
+
+    Ok(decoded)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn zstd_encode(bytes: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(bytes, level)
+}
+
+#[cfg(target_arch = "wasm32")]
+/// There's no pure Rust encoder, so wasm writes plain bundles
+fn zstd_encode(bytes: &[u8], _level: i32) -> std::io::Result<Vec<u8>> {
+    Ok(bytes.to_vec())
+}
+
+/// Decompress the bytes if they're compressed by zstd
+///
+/// Plain postcard bundles are returned as is.
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    if is_compressed(bytes) {
+        Ok(Cow::Owned(zstd_decode(bytes).map_err(Error::CorruptedBundle)?))
+    } else {
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+/// Get kind and format version of the bundle
+///
+/// Bundles without format header have no kind and version 0.
+pub fn format_header(bytes: &[u8]) -> Result<(Option<BundleKind>, u8), Error> {
+    if !bytes.starts_with(&BUNDLE_MAGIC) {
+        return Ok((None, 0));
+    }
+
+    let (Some(kind), Some(version)) = (bytes.get(4), bytes.get(5)) else {
+        return Err(Error::InvalidBundleHeader);
+    };
+
+    let Some(kind) = BundleKind::from_byte(*kind) else {
+        return Err(Error::InvalidBundleHeader);
+    };
+
+    Ok((Some(kind), *version))
+}
+
+/// Get decompressed postcard bytes of the bundle of the given kind
+///
+/// Bundles without format header are accepted as written by older
+/// crate versions. Fails if the bundle has another kind or was
+/// written with a newer format version.
+pub fn payload(bytes: &[u8], expected: BundleKind) -> Result<Cow<'_, [u8]>, Error> {
+    let (kind, version) = format_header(bytes)?;
+
+    if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion {
+            found: version,
+            supported: FORMAT_VERSION
+        });
+    }
+
+    match kind {
+        Some(kind) if kind != expected => Err(Error::BundleKindMismatch {
+            expected,
+            found: kind
+        }),
+
+        Some(_) => decompress(&bytes[BUNDLE_MAGIC.len() + 2..]),
+
+        None => decompress(bytes)
+    }
+}
+
+/// Serialize the value to the bundle bytes compressed with the given level
+///
+/// Level 0 stores plain postcard bytes after the format header.
+/// Bundles are never compressed on wasm targets.
+pub fn to_bytes<T: Bundle>(value: &T, level: i32) -> Result<Vec<u8>, Error> {
+    let payload = postcard::to_allocvec(value)?;
+
+    let mut bytes = Vec::with_capacity(payload.len() + BUNDLE_MAGIC.len() + 2);
+
+    bytes.extend_from_slice(&BUNDLE_MAGIC);
+    bytes.push(T::KIND.to_byte());
+    bytes.push(FORMAT_VERSION);
+
+    if level == 0 {
+        bytes.extend(payload);
+    } else {
+        bytes.extend(zstd_encode(&payload, level)?);
+    }
+
+    Ok(bytes)
+}
+
+/// Deserialize the value from the bundle bytes
+///
+/// Bundles without format header which fail to decode are reported
+/// as written by an incompatible crate version.
+pub fn from_bytes<T: Bundle>(bytes: &[u8]) -> Result<T, Error> {
+    let (_, version) = format_header(bytes)?;
+
+    match T::decode(&payload(bytes, T::KIND)?, version) {
+        Ok(value) => Ok(value),
+
+        Err(err) if version == 0 => Err(Error::LegacyBundle {
+            kind: T::KIND,
+            source: err
+        }),
+
+        Err(err) => Err(err.into())
+    }
+}
+
+#[inline]
+/// Read bundle from the file
+pub fn read<T: Bundle>(path: impl AsRef<Path>) -> Result<T, Error> {
+    from_bytes(&std::fs::read(path)?)
+}
+
+#[inline]
+/// Write bundle to the file compressed with the given level
+///
+/// Level 0 stores plain postcard bytes after the format header.
+pub fn write<T: Bundle>(path: impl AsRef<Path>, value: &T, level: i32) -> Result<(), Error> {
+    std::fs::write(path, to_bytes(value, level)?)?;
+
+    Ok(())
+}
+
+#[inline]
+/// Serialize the value to pretty JSON
+///
+/// JSON has no format header, so the value's type
+/// must be known when it's converted back.
+pub fn to_json<T: Bundle>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(serde_json::to_vec_pretty(value)?)
+}
+
+#[inline]
+/// Deserialize the value from JSON
+pub fn from_json<T: Bundle>(bytes: &[u8]) -> Result<T, Error> {
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+mod tests {
+    #[test]
+    fn compression() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bundle::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("hello world"),
+            String::from("hello world")
+        ]);
+
+        let plain = to_bytes(&messages, 0)?;
+        let compressed = to_bytes(&messages, DEFAULT_COMPRESSION_LEVEL)?;
+
+        assert!(is_compressed(&compressed[BUNDLE_MAGIC.len() + 2..]));
+
+        assert_eq!(from_bytes::<Messages>(&plain)?.messages(), messages.messages());
+        assert_eq!(from_bytes::<Messages>(&compressed)?.messages(), messages.messages());
+
+        // Models are detected as well
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, false);
+
+        let restored = Model::from_bytes(&to_bytes(&model, 19)?)?;
+
+        assert_eq!(restored.tokens().len(), model.tokens().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn format_header() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bundle::*;
+        use crate::Error;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world")
+        ]);
+
+        let bytes = to_bytes(&messages, 0)?;
+
+        assert_eq!(super::format_header(&bytes)?, (Some(BundleKind::Messages), FORMAT_VERSION));
+
+        // Bundles of another kind are rejected
+        assert!(matches!(from_bytes::<Tokens>(&bytes), Err(Error::BundleKindMismatch {
+            expected: BundleKind::Tokens,
+            found: BundleKind::Messages
+        })));
+
+        // Bundles written by newer versions are rejected
+        let mut newer = bytes.clone();
+
+        newer[5] = FORMAT_VERSION + 1;
+
+        assert!(matches!(from_bytes::<Messages>(&newer), Err(Error::UnsupportedFormatVersion { .. })));
+
+        // Bundles without header are read as legacy ones
+        let legacy = postcard::to_allocvec(&messages)?;
+
+        assert_eq!(super::format_header(&legacy)?, (None, 0));
+        assert_eq!(from_bytes::<Messages>(&legacy)?.messages(), messages.messages());
+
+        let legacy = zstd::encode_all(legacy.as_slice(), 3)?;
+
+        assert_eq!(from_bytes::<Messages>(&legacy)?.messages(), messages.messages());
+
+        assert!(matches!(from_bytes::<Dataset>(&[1, 2, 3]), Err(Error::LegacyBundle { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_dataset() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bundle::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world")
+        ]);
+
+        let parsed = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &parsed)?;
+
+        // Tokens written before the words were counted
+        let tokens = (&parsed.token_word, &parsed.word_token);
+
+        let header = |kind: BundleKind, version: u8, payload: Vec<u8>| {
+            let mut bytes = BUNDLE_MAGIC.to_vec();
+
+            bytes.push(kind.to_byte());
+            bytes.push(version);
+            bytes.extend(payload);
+
+            bytes
+        };
+
+        let bytes = header(BundleKind::Tokens, 3, postcard::to_allocvec(&tokens)?);
+
+        let legacy = from_bytes::<Tokens>(&bytes)?;
+
+        assert_eq!(legacy.find_token("hello"), parsed.find_token("hello"));
+        assert!(!legacy.has_counts());
+
+        // Datasets written before the sources names
+        let bytes = header(BundleKind::Dataset, 2, postcard::to_allocvec(&(vec![(tokenized.clone(), 2_u64)], tokens, Vec::<ManifestEntry>::new(), vec![0_usize]))?);
+
+        let dataset = from_bytes::<Dataset>(&bytes)?;
+
+        assert_eq!(dataset.messages()[0].1, 2);
+        assert_eq!(dataset.dialogues(), [0]);
+        assert_eq!(dataset.source_name(0), None);
+
+        // Datasets written before the dialogues
+        let bytes = header(BundleKind::Dataset, 1, postcard::to_allocvec(&(vec![(tokenized.clone(), 3_u64)], tokens, Vec::<ManifestEntry>::new()))?);
+
+        let dataset = from_bytes::<Dataset>(&bytes)?;
+
+        assert_eq!(dataset.messages()[0].1, 3);
+        assert!(dataset.dialogues().is_empty());
+
+        // Datasets written before the tokens counts
+        let sources = vec![String::from("chat")];
+
+        let bytes = header(BundleKind::Dataset, 3, postcard::to_allocvec(&(vec![(tokenized.clone(), 1_u64)], tokens, Vec::<ManifestEntry>::new(), Vec::<usize>::new(), sources))?);
+
+        let dataset = from_bytes::<Dataset>(&bytes)?;
+
+        assert_eq!(dataset.source_name(0), Some("chat"));
+        assert_eq!(dataset.tokens().len(), 2);
+
+        // Datasets written before the format header and provenance
+        let bytes = postcard::to_allocvec(&(vec![(tokenized, 4_u64)], tokens))?;
+
+        let dataset = from_bytes::<Dataset>(&bytes)?;
+
+        assert_eq!(dataset.messages()[0].1, 4);
+        assert!(dataset.provenance().is_empty());
+        assert_eq!(dataset.tokens().find_token("world"), parsed.find_token("world"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn json() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bundle::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("hello there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let dataset = Dataset::default()
+            .with_messages(tokenized.clone(), 1)
+            .with_tokens(tokens.clone());
+
+        let model = Model::build_with_order(dataset.clone(), 3);
+
+        assert_eq!(from_json::<Messages>(&to_json(&messages)?)?.messages(), messages.messages());
+        assert_eq!(from_json::<Tokens>(&to_json(&tokens)?)?.len(), tokens.len());
+        assert_eq!(from_json::<TokenizedMessages>(&to_json(&tokenized)?)?.messages(), tokenized.messages());
+        assert_eq!(from_json::<Dataset>(&to_json(&dataset)?)?.messages().len(), dataset.messages().len());
+
+        let restored = from_json::<Model>(&to_json(&model)?)?;
+
+        assert_eq!(restored.transitions().unigrams, model.transitions().unigrams);
+        assert_eq!(restored.transitions().trigrams, model.transitions().trigrams);
+
+        // Converted model generates the same text
+        let hello = model.tokens().find_token("hello").unwrap();
+
+        assert_eq!(restored.probability(&[hello], hello, &Smoothing::default()), model.probability(&[hello], hello, &Smoothing::default()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde_json::json;
+
+use tungstenite::{Message as Frame, WebSocket};
+use tungstenite::stream::MaybeTlsStream;
+
+use crate::prelude::{
+    GenerationParams,
+    CandidateCache
+};
+
+use super::{BotContext, RETRY_DELAY, truncate_message};
+use super::super::repl::set_param;
+
+/// Address of the Discord gateway
+pub const GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+
+/// Address of the Discord REST API
+pub const API_URL: &str = "https://discord.com/api/v10";
+
+/// Guild messages, direct messages and message content intents
+pub const INTENTS: u64 = (1 << 9) | (1 << 12) | (1 << 15);
+
+/// Maximal length of the message in characters
+pub const MAX_MESSAGE_LEN: usize = 2000;
+
+/// How often to check if the heartbeat should be sent
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, serde::Deserialize)]
+struct Payload {
+    op: u8,
+
+    #[serde(default)]
+    d: serde_json::Value,
+
+    s: Option<u64>,
+    t: Option<String>
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct User {
+    id: String,
+
+    #[serde(default)]
+    bot: bool
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Message {
+    id: String,
+    channel_id: String,
+    author: User,
+    content: String,
+
+    #[serde(default)]
+    mentions: Vec<User>
+}
+
+/// Parse generation params of the answered channels
+///
+/// Channels with params are answered even if not listed in `channels`.
+/// Returns empty map if mentions in all the channels should be answered.
+pub fn channels_params(channels: &[String], params: &[String], base: &GenerationParams) -> anyhow::Result<HashMap<String, GenerationParams>> {
+    let mut channels = channels.iter()
+        .map(|channel| (channel.clone(), *base))
+        .collect::<HashMap<_, _>>();
+
+    for param in params {
+        let Some((channel, (name, value))) = param.split_once(':').and_then(|(channel, param)| Some((channel, param.split_once('=')?))) else {
+            anyhow::bail!("Invalid channel param: {param}, expected <channel>:<param>=<value>");
+        };
+
+        let params = channels.entry(channel.to_string())
+            .or_insert(*base);
+
+        set_param(params, name, value)?;
+    }
+
+    Ok(channels)
+}
+
+/// Get text of the message mentioning the bot without the mentions
+fn mentioned_text(message: &Message, me: &str) -> Option<String> {
+    if message.author.bot || !message.mentions.iter().any(|user| user.id == me) {
+        return None;
+    }
+
+    let text = message.content
+        .replace(&format!("<@{me}>"), "")
+        .replace(&format!("<@!{me}>"), "");
+
+    Some(text)
+}
+
+fn send(socket: &mut Socket, payload: serde_json::Value) -> anyhow::Result<()> {
+    socket.send(Frame::text(payload.to_string()))?;
+
+    Ok(())
+}
+
+/// Read the next gateway payload
+///
+/// Returns `None` if nothing was received during the poll interval.
+fn read(socket: &mut Socket) -> anyhow::Result<Option<Payload>> {
+    match socket.read() {
+        Ok(Frame::Text(text)) => Ok(Some(serde_json::from_str(&text)?)),
+        Ok(Frame::Close(frame)) => anyhow::bail!("Gateway closed the connection: {frame:?}"),
+        Ok(_) => Ok(None),
+
+        Err(tungstenite::Error::Io(err)) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => Ok(None),
+
+        Err(err) => Err(err.into())
+    }
+}
+
+struct Session<'a> {
+    context: &'a BotContext,
+    channels: &'a HashMap<String, GenerationParams>,
+    agent: ureq::Agent,
+    token: &'a str,
+    rng: ChaCha8Rng,
+    cache: CandidateCache,
+
+    /// Id of the bot user
+    me: Option<String>
+}
+
+impl Session<'_> {
+    /// Answer the message if it mentions the bot in the answered channel
+    fn handle_message(&mut self, message: Message) {
+        let Some(me) = &self.me else {
+            return;
+        };
+
+        let params = match self.channels.get(&message.channel_id) {
+            Some(params) => params,
+            None if self.channels.is_empty() => &self.context.params,
+            None => return
+        };
+
+        let Some(text) = mentioned_text(&message, me) else {
+            return;
+        };
+
+        let Some(reply) = self.context.reply(&text, params, &mut self.rng, &mut self.cache) else {
+            return;
+        };
+
+        let sent = self.agent.post(&format!("{API_URL}/channels/{}/messages", message.channel_id))
+            .set("Authorization", &format!("Bot {}", self.token))
+            .send_json(json!({
+                "content": truncate_message(reply, MAX_MESSAGE_LEN),
+                "message_reference": {
+                    "message_id": message.id,
+                    "fail_if_not_exists": false
+                },
+                "allowed_mentions": {
+                    "parse": []
+                }
+            }));
+
+        match sent {
+            Ok(_) => (),
+
+            Err(ureq::Error::Status(status, response)) => {
+                eprintln!("Failed to send message: {status} {}", response.into_string().unwrap_or_default());
+            }
+
+            Err(ureq::Error::Transport(err)) => eprintln!("Failed to send message: {}", err.kind())
+        }
+    }
+
+    /// Answer messages until the gateway asks to reconnect
+    fn run(&mut self) -> anyhow::Result<()> {
+        let (mut socket, _) = tungstenite::connect(GATEWAY_URL)?;
+
+        // Heartbeats are sent between the reads
+        match socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(POLL_INTERVAL))?,
+            MaybeTlsStream::Rustls(stream) => stream.get_ref().set_read_timeout(Some(POLL_INTERVAL))?,
+
+            _ => ()
+        }
+
+        let heartbeat_interval = loop {
+            if let Some(payload) = read(&mut socket)? {
+                if payload.op == 10 {
+                    break Duration::from_millis(payload.d["heartbeat_interval"].as_u64().unwrap_or(40000));
+                }
+            }
+        };
+
+        send(&mut socket, json!({
+            "op": 2,
+            "d": {
+                "token": self.token,
+                "intents": INTENTS,
+                "properties": {
+                    "os": std::env::consts::OS,
+                    "browser": "markov-chains",
+                    "device": "markov-chains"
+                }
+            }
+        }))?;
+
+        let mut sequence = None;
+        let mut last_heartbeat = Instant::now();
+
+        loop {
+            if last_heartbeat.elapsed() >= heartbeat_interval {
+                send(&mut socket, json!({ "op": 1, "d": sequence }))?;
+
+                last_heartbeat = Instant::now();
+            }
+
+            let Some(payload) = read(&mut socket)? else {
+                continue;
+            };
+
+            if payload.s.is_some() {
+                sequence = payload.s;
+            }
+
+            match (payload.op, payload.t.as_deref()) {
+                (0, Some("READY")) => {
+                    let me = serde_json::from_value::<User>(payload.d["user"].clone())?;
+
+                    println!("Logged in as {}", me.id);
+
+                    self.me = Some(me.id);
+                }
+
+                (0, Some("MESSAGE_CREATE")) => {
+                    match serde_json::from_value::<Message>(payload.d) {
+                        Ok(message) => self.handle_message(message),
+                        Err(err) => eprintln!("Failed to parse message: {err}")
+                    }
+                }
+
+                // Heartbeat request
+                (1, _) => {
+                    send(&mut socket, json!({ "op": 1, "d": sequence }))?;
+
+                    last_heartbeat = Instant::now();
+                }
+
+                // Reconnect request and invalid session
+                (7 | 9, _) => return Ok(()),
+
+                _ => ()
+            }
+        }
+    }
+}
+
+/// Answer mentions until the process is stopped
+///
+/// Connection is restarted if the gateway closes it or asks to reconnect.
+pub fn run(context: BotContext, token: &str, channels: HashMap<String, GenerationParams>) -> anyhow::Result<()> {
+    let rng = match context.params.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy()
+    };
+
+    let mut session = Session {
+        context: &context,
+        channels: &channels,
+        agent: ureq::Agent::new(),
+        token,
+        rng,
+        cache: CandidateCache::default(),
+        me: None
+    };
+
+    loop {
+        if let Err(err) = session.run() {
+            eprintln!("Gateway error: {err}");
+        }
+
+        std::thread::sleep(RETRY_DELAY);
+    }
+}
+
+mod tests {
+    #[test]
+    fn channels_params() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let base = GenerationParams::default();
+
+        let channels = super::channels_params(&[String::from("1")], &[
+            String::from("2:max-len=40"),
+            String::from("2:temperature=0.5")
+        ], &base)?;
+
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels["1"].max_len, base.max_len);
+        assert_eq!(channels["2"].max_len, 40);
+        assert_eq!(channels["2"].temperature, 0.5);
+
+        assert!(super::channels_params(&[], &[String::from("2:max-len")], &base).is_err());
+        assert!(super::channels_params(&[], &[String::from("2:unknown=1")], &base).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mentioned_text() -> anyhow::Result<()> {
+        use super::*;
+
+        let message = |author: &str, bot: bool, mentions: &[&str], content: &str| -> anyhow::Result<Message> {
+            let mentions = mentions.iter()
+                .map(|id| json!({ "id": id }))
+                .collect::<Vec<_>>();
+
+            Ok(serde_json::from_value(json!({
+                "id": "100",
+                "channel_id": "10",
+                "author": { "id": author, "bot": bot },
+                "content": content,
+                "mentions": mentions
+            }))?)
+        };
+
+        assert_eq!(mentioned_text(&message("5", false, &["1"], "<@1> hello")?, "1").as_deref(), Some(" hello"));
+        assert_eq!(mentioned_text(&message("5", false, &["1"], "hi <@!1>")?, "1").as_deref(), Some("hi "));
+        assert_eq!(mentioned_text(&message("5", false, &["2"], "<@2> hello")?, "1"), None);
+        assert_eq!(mentioned_text(&message("6", true, &["1"], "<@1> hello")?, "1"), None);
+
+        Ok(())
+    }
+}
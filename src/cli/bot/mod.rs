@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use rand::RngCore;
+
+use crate::prelude::{
+    GenerationParams,
+    Model,
+    CandidateCache,
+    Tokenizer
+};
+
+use super::model::{generate_text, BanList};
+
+mod telegram;
+mod discord;
+
+/// Delay before retrying failed requests to the chat API
+pub const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Subcommand)]
+pub enum CliBotCommand {
+    /// Run long-polling Telegram bot
+    ///
+    /// Private messages, mentions and replies to the bot's messages are
+    /// always answered, other group messages with `--reply-chance`.
+    Telegram {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(long)]
+        /// Token of the bot given by @BotFather
+        token: String,
+
+        #[arg(long, default_value_t = 1.0)]
+        /// Probability to answer group messages not addressed to the bot
+        reply_chance: f64,
+
+        #[command(flatten)]
+        bot: BotArgs
+    },
+
+    /// Run Discord bot answering mentions
+    ///
+    /// Requires the message content intent enabled
+    /// in the bot settings of the developer portal.
+    Discord {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(long)]
+        /// Token of the bot from the developer portal
+        token: String,
+
+        #[arg(long)]
+        /// Id of the channel to answer in
+        ///
+        /// Mentions in all the channels are answered if not specified.
+        channel: Vec<String>,
+
+        #[arg(long)]
+        /// Generation param of the channel
+        ///
+        /// `--channel-param <channel>:<param>=<value>`, e.g.
+        /// `--channel-param 1234:max-len=40`. Params are named
+        /// like the command line flags.
+        channel_param: Vec<String>,
+
+        #[command(flatten)]
+        bot: BotArgs
+    }
+}
+
+#[derive(clap::Args)]
+pub struct BotArgs {
+    #[arg(long)]
+    /// Concatenate generated tokens without spaces
+    no_space_join: bool,
+
+    #[arg(long)]
+    /// Replace unknown words of the messages by the closest known ones
+    /// within this edit distance divided by the words length
+    ///
+    /// Unknown words are skipped if not set. 0.34 allows
+    /// about one typo per three characters.
+    max_typo_distance: Option<f64>,
+
+    #[command(flatten)]
+    ban: BanList,
+
+    #[command(flatten)]
+    params: GenerationParams
+}
+
+/// Shared state of the chat bots
+pub struct BotContext {
+    pub model: Model,
+    pub separator: &'static str,
+    pub max_typo_distance: Option<f64>,
+    pub banned: HashSet<u64>,
+    pub params: GenerationParams
+}
+
+impl BotContext {
+    pub fn new(model: Model, args: &BotArgs) -> anyhow::Result<Self> {
+        Ok(Self {
+            banned: args.ban.tokens(&model)?,
+            model,
+            separator: if args.no_space_join { "" } else { " " },
+            max_typo_distance: args.max_typo_distance,
+            params: args.params
+        })
+    }
+
+    /// Generate reply continuing the last known word of the message
+    ///
+    /// With the `dialogue` param the reply is generated to all the
+    /// known words instead. Messages without known words are answered
+    /// by a text started from the messages openers. Returns `None`
+    /// if nothing was generated.
+    pub fn reply(&self, message: &str, params: &GenerationParams, rng: &mut impl RngCore, cache: &mut CandidateCache) -> Option<String> {
+        let mut request = known_tokens(&self.model, message, self.max_typo_distance);
+
+        if !params.dialogue {
+            request.drain(..request.len().saturating_sub(1));
+        }
+
+        let (text, error) = generate_text(&self.model, request, params, rng, cache, &self.banned, self.separator, None);
+
+        if let Some(error) = error {
+            eprintln!("Failed to generate reply: {error}");
+        }
+
+        (!text.trim().is_empty()).then_some(text)
+    }
+}
+
+/// Cut the message to the chat's length limit
+pub fn truncate_message(mut text: String, max_chars: usize) -> String {
+    if let Some((index, _)) = text.char_indices().nth(max_chars) {
+        text.truncate(index);
+    }
+
+    text
+}
+
+/// Get tokens of the message words known to the model, ignoring case
+///
+/// Unknown words are replaced by the closest known ones
+/// if `max_typo_distance` is set, see `Tokens::find_similar_word`.
+fn known_tokens(model: &Model, message: &str, max_typo_distance: Option<f64>) -> Vec<u64> {
+    let subwords = model.has_subwords();
+
+    model.tokenizer()
+        .tokenize(message)
+        .iter()
+        .filter_map(|word| {
+            model.tokens()
+                .find_token_ignore_case(word)
+                .or_else(|| {
+                    let max_distance = max_typo_distance.filter(|_| !subwords)?;
+
+                    model.tokens()
+                        .find_similar_word(word, max_distance)
+                        .map(|(_, token)| token)
+                })
+        })
+        .collect()
+}
+
+impl CliBotCommand {
+    #[inline]
+    pub fn execute(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Telegram { model, token, reply_chance, bot } => {
+                if !(0.0..=1.0).contains(reply_chance) {
+                    anyhow::bail!("Reply chance must be in [0.0, 1.0] range");
+                }
+
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                println!("Starting bot...");
+
+                telegram::run(BotContext::new(model, bot)?, token, *reply_chance)?;
+            }
+
+            Self::Discord { model, token, channel, channel_param, bot } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                let context = BotContext::new(model, bot)?;
+                let channels = discord::channels_params(channel, channel_param, &context.params)?;
+
+                println!("Starting bot...");
+
+                discord::run(context, token, channels)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn known_tokens() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("hello there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        assert_eq!(super::known_tokens(&model, "Hello unknown WORLD", None), [token("hello"), token("world")]);
+        assert!(super::known_tokens(&model, "nothing known", None).is_empty());
+
+        assert!(super::known_tokens(&model, "helo wrld", None).is_empty());
+        assert_eq!(super::known_tokens(&model, "helo wrld", Some(DEFAULT_TYPO_DISTANCE)), [token("hello"), token("world")]);
+
+        assert_eq!(super::truncate_message(String::from("привет"), 3), "при");
+        assert_eq!(super::truncate_message(String::from("hi"), 3), "hi");
+
+        Ok(())
+    }
+}
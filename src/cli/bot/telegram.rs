@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::prelude::CandidateCache;
+
+use super::{BotContext, RETRY_DELAY, truncate_message};
+
+/// Address of the Telegram Bot API
+pub const API_URL: &str = "https://api.telegram.org";
+
+/// Seconds to wait for new updates in a single request
+pub const POLL_TIMEOUT: u64 = 30;
+
+/// Maximal length of the message in characters
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+#[derive(Debug, serde::Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct User {
+    id: i64,
+    username: Option<String>
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Chat {
+    id: i64,
+
+    #[serde(rename = "type")]
+    kind: String
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    from: Option<User>,
+    text: Option<String>,
+    reply_to_message: Option<Box<Message>>
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>
+}
+
+struct TelegramApi {
+    agent: ureq::Agent,
+    url: String
+}
+
+impl TelegramApi {
+    fn new(token: &str) -> Self {
+        // Long polling requests must not time out before the API answers
+        let agent = ureq::AgentBuilder::new()
+            .timeout_read(Duration::from_secs(POLL_TIMEOUT + 10))
+            .build();
+
+        Self {
+            agent,
+            url: format!("{API_URL}/bot{token}")
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, body: serde_json::Value) -> anyhow::Result<T> {
+        let response = match self.agent.post(&format!("{}/{method}", self.url)).send_json(body) {
+            Ok(response) => response,
+
+            // API errors are described in the response body
+            Err(ureq::Error::Status(_, response)) => response,
+
+            // Transport errors contain the url with the token
+            Err(ureq::Error::Transport(err)) => anyhow::bail!("Failed to call {method}: {}", err.kind())
+        };
+
+        let response = response.into_json::<ApiResponse<T>>()?;
+
+        match response.result {
+            Some(result) if response.ok => Ok(result),
+
+            _ => anyhow::bail!("Telegram API error in {method}: {}", response.description.unwrap_or_default())
+        }
+    }
+}
+
+/// Get text of the message addressed to the bot
+///
+/// Private messages, replies to the bot's messages and messages
+/// mentioning the bot are addressed to it. Mentions are removed.
+fn addressed_text(message: &Message, me: &User) -> Option<String> {
+    let text = message.text.as_deref()?;
+
+    if message.chat.kind == "private" {
+        return Some(text.to_string());
+    }
+
+    let reply_to_me = message.reply_to_message.as_ref()
+        .and_then(|message| message.from.as_ref())
+        .is_some_and(|user| user.id == me.id);
+
+    let mention = me.username.as_ref()
+        .map(|username| format!("@{username}"))
+        .filter(|mention| text.contains(mention));
+
+    match mention {
+        Some(mention) => Some(text.replace(&mention, "")),
+        None if reply_to_me => Some(text.to_string()),
+        None => None
+    }
+}
+
+/// Answer messages until the process is stopped
+pub fn run(context: BotContext, token: &str, reply_chance: f64) -> anyhow::Result<()> {
+    let api = TelegramApi::new(token);
+
+    let me = api.call::<User>("getMe", json!({}))?;
+
+    println!("Logged in as @{}", me.username.as_deref().unwrap_or("unknown"));
+
+    let mut rng = match context.params.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy()
+    };
+
+    let mut cache = CandidateCache::default();
+    let mut offset = 0;
+
+    loop {
+        let updates = api.call::<Vec<Update>>("getUpdates", json!({
+            "offset": offset,
+            "timeout": POLL_TIMEOUT,
+            "allowed_updates": ["message"]
+        }));
+
+        let updates = match updates {
+            Ok(updates) => updates,
+
+            Err(err) => {
+                eprintln!("{err}");
+
+                std::thread::sleep(RETRY_DELAY);
+
+                continue;
+            }
+        };
+
+        for update in updates {
+            offset = offset.max(update.update_id + 1);
+
+            let Some(message) = update.message else {
+                continue;
+            };
+
+            // Bot commands are not prompts
+            let Some(text) = message.text.as_deref().filter(|text| !text.starts_with('/')) else {
+                continue;
+            };
+
+            let addressed = addressed_text(&message, &me);
+
+            if addressed.is_none() && !rng.gen_bool(reply_chance) {
+                continue;
+            }
+
+            let text = addressed.as_deref().unwrap_or(text);
+
+            let Some(reply) = context.reply(text, &context.params, &mut rng, &mut cache) else {
+                continue;
+            };
+
+            let sent = api.call::<Message>("sendMessage", json!({
+                "chat_id": message.chat.id,
+                "text": truncate_message(reply, MAX_MESSAGE_LEN),
+                "reply_parameters": {
+                    "message_id": message.message_id,
+                    "allow_sending_without_reply": true
+                }
+            }));
+
+            if let Err(err) = sent {
+                eprintln!("{err}");
+            }
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn addressed_text() -> anyhow::Result<()> {
+        use super::*;
+
+        let me = User {
+            id: 1,
+            username: Some(String::from("markov_bot"))
+        };
+
+        let message = |chat: &str, text: &str, reply_to: Option<i64>| -> anyhow::Result<Message> {
+            let reply_to = reply_to.map(|id| json!({
+                "message_id": 1,
+                "chat": { "id": 10, "type": chat },
+                "from": { "id": id }
+            }));
+
+            Ok(serde_json::from_value(json!({
+                "message_id": 2,
+                "chat": { "id": 10, "type": chat },
+                "from": { "id": 5, "username": "user" },
+                "text": text,
+                "reply_to_message": reply_to
+            }))?)
+        };
+
+        assert_eq!(addressed_text(&message("private", "hello", None)?, &me).as_deref(), Some("hello"));
+        assert_eq!(addressed_text(&message("group", "hello", None)?, &me), None);
+        assert_eq!(addressed_text(&message("group", "hello", Some(5))?, &me), None);
+        assert_eq!(addressed_text(&message("group", "hello", Some(1))?, &me).as_deref(), Some("hello"));
+        assert_eq!(addressed_text(&message("supergroup", "@markov_bot hello", None)?, &me).as_deref(), Some(" hello"));
+
+        Ok(())
+    }
+}
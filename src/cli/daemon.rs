@@ -0,0 +1,315 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::prelude::{
+    Messages,
+    CandidateCache
+};
+
+use super::server::{ServerContext, GenerateRequest, GenerateResponse};
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+
+/// JSON is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+
+/// Requested method doesn't exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Params of the method are invalid
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// Generation failed
+pub const GENERATION_ERROR: i64 = -32000;
+
+#[derive(Debug, serde::Deserialize)]
+struct RpcRequest {
+    /// Requests without id are notifications which are not answered
+    id: Option<Value>,
+    method: String,
+
+    #[serde(default)]
+    params: Value
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RpcError {
+    code: i64,
+    message: String
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ScoreRequest {
+    text: String
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ScoreResponse {
+    tokens: u64,
+    unseen: u64,
+    log_probability: f64,
+    cross_entropy: f64,
+    perplexity: f64
+}
+
+fn error(code: i64, message: impl ToString) -> RpcError {
+    RpcError {
+        code,
+        message: message.to_string()
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Omitted params are the same as the empty ones
+    let params = match params {
+        Value::Null => Value::Object(Default::default()),
+        params => params
+    };
+
+    serde_json::from_value(params)
+        .map_err(|err| error(INVALID_PARAMS, format!("Invalid params: {err}")))
+}
+
+fn call(context: &ServerContext, method: &str, params: Value, cache: &mut CandidateCache) -> Result<Value, RpcError> {
+    match method {
+        "generate" => {
+            let request = parse_params::<GenerateRequest>(params)?;
+
+            let Some(request) = context.prepare(&request) else {
+                return Err(error(INVALID_PARAMS, "Prompt has words unknown to the model"));
+            };
+
+            match context.generate(request, cache) {
+                (_, Some(err)) => Err(error(GENERATION_ERROR, err)),
+                (text, None) => Ok(serde_json::json!(GenerateResponse { text }))
+            }
+        }
+
+        "score" => {
+            let request = parse_params::<ScoreRequest>(params)?;
+
+            let evaluation = context.model.evaluate(&Messages::parse_from_lines(&[request.text]));
+
+            if evaluation.skipped > 0 {
+                return Err(error(INVALID_PARAMS, "Text has words unknown to the model"));
+            }
+
+            Ok(serde_json::json!(ScoreResponse {
+                tokens: evaluation.tokens,
+                unseen: evaluation.unseen,
+                log_probability: evaluation.log_probability,
+                cross_entropy: evaluation.cross_entropy(),
+                perplexity: evaluation.perplexity()
+            }))
+        }
+
+        _ => Err(error(METHOD_NOT_FOUND, format!("Unknown method: {method}")))
+    }
+}
+
+/// Answer single JSON-RPC request line
+///
+/// Returns `None` for notifications.
+fn handle_line(context: &ServerContext, line: &str, cache: &mut CandidateCache) -> Option<String> {
+    let response = |id, result: Result<Value, RpcError>| {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error))
+        };
+
+        let response = RpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error
+        };
+
+        serde_json::to_string(&response).ok()
+    };
+
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(request) => request,
+        Err(err) => return response(Value::Null, Err(error(PARSE_ERROR, format!("Invalid JSON: {err}"))))
+    };
+
+    let request = match serde_json::from_value::<RpcRequest>(request) {
+        Ok(request) => request,
+        Err(err) => return response(Value::Null, Err(error(INVALID_REQUEST, format!("Invalid request: {err}"))))
+    };
+
+    let result = call(context, &request.method, request.params, cache);
+
+    response(request.id?, result)
+}
+
+/// Answer newline-delimited requests until the reader is closed
+fn handle_stream(context: &ServerContext, reader: impl BufRead, mut writer: impl Write) -> std::io::Result<()> {
+    let mut cache = CandidateCache::default();
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = handle_line(context, &line, &mut cache) {
+            writeln!(writer, "{response}")?;
+
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer requests from stdin until it is closed
+pub fn serve_stdio(context: ServerContext) -> anyhow::Result<()> {
+    handle_stream(&context, std::io::stdin().lock(), std::io::stdout().lock())?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Answer requests of the socket clients until the process is stopped
+///
+/// Every client is handled in its own thread.
+pub fn serve_socket(context: ServerContext, path: &Path) -> anyhow::Result<()> {
+    use std::io::BufReader;
+    use std::os::unix::net::UnixListener;
+    use std::os::unix::fs::FileTypeExt;
+    use std::sync::Arc;
+
+    // Remove the socket left by the previous daemon, but never other files
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => anyhow::bail!("{} already exists and is not a socket", path.display()),
+
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err.into())
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let context = Arc::new(context);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+
+            Err(err) => {
+                eprintln!("Failed to accept client: {err}");
+
+                continue;
+            }
+        };
+
+        let context = context.clone();
+
+        std::thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(reader) => BufReader::new(reader),
+
+                Err(err) => {
+                    eprintln!("Failed to read client: {err}");
+
+                    return;
+                }
+            };
+
+            if let Err(err) = handle_stream(&context, reader, stream) {
+                eprintln!("Failed to answer client: {err}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve_socket(_context: ServerContext, _path: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("Unix sockets are not supported on this platform")
+}
+
+mod tests {
+    #[test]
+    fn rpc_requests() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+        use super::*;
+        use super::super::model::UnknownWordsArgs;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("hello there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let context = ServerContext {
+            model: Model::build(dataset, true, true),
+            template: PromptTemplate::new(PROMPT_PLACEHOLDER),
+            separator: " ",
+            unknown_words: UnknownWordsArgs::default(),
+            banned: HashSet::new(),
+            params: GenerationParams::default(),
+            bounds: GenerationBounds::default()
+        };
+
+        let input = [
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "generate", "params": {"prompt": "hello", "seed": 1}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "score", "params": {"text": "hello world"}}"#,
+            r#"{"jsonrpc": "2.0", "method": "generate"}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "score"}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "score", "params": {"text": "unknown"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 5, "method": "unknown"}"#,
+            "not json"
+        ];
+
+        let mut output = Vec::new();
+
+        handle_stream(&context, input.join("\n").as_bytes(), &mut output)?;
+
+        let responses = String::from_utf8(output)?
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Notification is not answered
+        assert_eq!(responses.len(), 6);
+
+        assert_eq!(responses[0]["id"], 1);
+        assert!(responses[0]["result"]["text"].as_str().unwrap().starts_with("hello "));
+
+        // <START> -> hello -> world (1/2) -> <END>
+        assert_eq!(responses[1]["result"]["tokens"], 3);
+        assert!((responses[1]["result"]["log_probability"].as_f64().unwrap() - 0.5_f64.ln()).abs() < 1e-9);
+
+        assert_eq!(responses[2]["error"]["code"], INVALID_PARAMS);
+        assert_eq!(responses[3]["error"]["code"], INVALID_PARAMS);
+        assert_eq!(responses[4]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[5]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[5]["id"], Value::Null);
+
+        Ok(())
+    }
+}
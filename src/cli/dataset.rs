@@ -1,14 +1,31 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Subcommand;
+use globset::GlobSet;
 
 use crate::prelude::{
     TokenizedMessages,
     Tokens,
-    Dataset
+    Dataset,
+    ManifestEntry,
+    UNK_TOKEN_NAME
 };
 
-use super::search_files;
+use crate::bundle;
+
+use super::{search_files, read_manifest, find_word_tokens, progress, convert_bundle, ConvertFormat};
+use super::messages::{read_blocklist, BlockMode};
+
+/// Name of the messages source, file name of its bundle by default
+fn source_name(path: &Path, name: Option<&str>) -> String {
+    match name {
+        Some(name) => name.to_string(),
+
+        None => path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
 
 #[derive(Subcommand)]
 pub enum CliDatasetCommand {
@@ -26,6 +43,23 @@ pub enum CliDatasetCommand {
         /// Messages weight in the dataset
         weight: u64,
 
+        #[arg(long)]
+        /// Name of the messages source, used by `set-weight`
+        ///
+        /// File name of the messages bundle without extension by default.
+        name: Option<String>,
+
+        #[arg(long)]
+        /// Treat every message as a reply to the previous one
+        ///
+        /// Models built from the dataset can reply to the prompts
+        /// using the `--dialogue` generation param.
+        dialogue: bool,
+
+        #[arg(long)]
+        /// Paths to the manifests to store in the dataset provenance
+        manifest: Vec<PathBuf>,
+
         #[arg(short, long)]
         /// Path to the dataset output
         output: PathBuf
@@ -45,12 +79,39 @@ pub enum CliDatasetCommand {
         /// Messages weight
         weight: u64,
 
+        #[arg(long)]
+        /// Name of the messages source, used by `set-weight`
+        ///
+        /// File name of the messages bundle without extension by default.
+        name: Option<String>,
+
+        #[arg(long)]
+        /// Treat every message as a reply to the previous one
+        ///
+        /// Models built from the dataset can reply to the prompts
+        /// using the `--dialogue` generation param.
+        dialogue: bool,
+
+        #[arg(short, long)]
+        /// Path to the tokens bundle used to tokenize the messages
+        ///
+        /// The bundle is merged into the dataset's tokens and
+        /// messages are translated to the merged tokens.
+        tokens: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Paths to the manifests to store in the dataset provenance
+        manifest: Vec<PathBuf>,
+
         #[arg(short, long)]
         /// Path to the dataset output
         output: PathBuf
     },
 
     /// Extend existing dataset with the tokenized messages
+    ///
+    /// Fails if the tokens bundles assign any word a token
+    /// different from the dataset's one.
     AddTokens {
         #[arg(short, long)]
         /// Path to the dataset bundle
@@ -65,6 +126,126 @@ pub enum CliDatasetCommand {
         output: PathBuf
     },
 
+    /// Translate tokens of the dataset messages tokenized
+    /// with another tokens bundle to the dataset's tokens
+    Remap {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the tokens bundle used to tokenize the messages
+        tokens: PathBuf,
+
+        #[arg(long)]
+        /// Indexes of the messages bundles in the dataset to translate
+        ///
+        /// All the messages are translated if not specified.
+        index: Vec<usize>,
+
+        #[arg(short, long)]
+        /// Path to the dataset output
+        output: PathBuf
+    },
+
+    /// Replace rare tokens of the dataset by the `<UNK>` word
+    PruneVocab {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Minimal amount of occurrences of the kept words
+        min_count: u64,
+
+        #[arg(long, default_value_t = String::from(UNK_TOKEN_NAME))]
+        /// Word replacing the pruned words
+        unk: String,
+
+        #[arg(short, long)]
+        /// Path to the dataset output
+        output: PathBuf
+    },
+
+    /// List messages bundles of the dataset with their names and weights
+    ListSources {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf
+    },
+
+    /// Change weight of the dataset messages bundles
+    SetWeight {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Name of the messages bundles, or `#index` of one of them
+        source: String,
+
+        #[arg(short, long)]
+        /// New weight of the messages
+        weight: u64,
+
+        #[arg(short, long)]
+        /// Path to the dataset output
+        output: PathBuf
+    },
+
+    /// Remove words from the dataset tokens and messages
+    ///
+    /// Words around the removed ones become adjacent.
+    RemoveWord {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(short, long, required = true)]
+        /// Words to remove
+        word: Vec<String>,
+
+        #[arg(long)]
+        /// Also remove variants of the words differing
+        /// by case and punctuation around them
+        variants: bool,
+
+        #[arg(short, long)]
+        /// Path to the dataset output
+        output: PathBuf
+    },
+
+    /// Drop or mask messages of the dataset containing blocked words
+    ///
+    /// Blocked words are removed from the dataset tokens.
+    Filter {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Path to the file with a blocked word per line
+        ///
+        /// Words are matched ignoring case and punctuation around
+        /// them, lines starting with `#` are skipped.
+        blockwords: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = BlockMode::Drop)]
+        /// What to do with the messages containing blocked words
+        mode: BlockMode,
+
+        #[arg(short, long)]
+        /// Path to the dataset output
+        output: PathBuf
+    },
+
+    /// List files used to create the dataset
+    Provenance {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf
+    },
+
     /// Check the word appearance in the dataset
     CheckWord {
         #[arg(short, long)]
@@ -74,53 +255,128 @@ pub enum CliDatasetCommand {
         #[arg(short, long)]
         /// Word to check
         word: String
+    },
+
+    /// Print summary of the dataset contents
+    Stats {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(long, default_value_t = 20)]
+        /// Amount of the most frequent words to print
+        top: usize
+    },
+
+    /// Write messages of the dataset to a text file one per line
+    ///
+    /// Tokens are converted back to words by the dataset tokens.
+    Export {
+        #[arg(short, long)]
+        /// Path to the dataset bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the text output
+        output: PathBuf
+    },
+
+    /// Convert dataset bundle to JSON and back
+    Convert {
+        #[arg(short, long)]
+        /// Path to the dataset bundle or JSON file
+        path: PathBuf,
+
+        #[arg(long, value_enum)]
+        /// Format of the output
+        to: ConvertFormat,
+
+        #[arg(short, long)]
+        /// Path to the converted output
+        output: PathBuf
     }
 }
 
 impl CliDatasetCommand {
     #[inline]
-    pub fn execute(&self) -> anyhow::Result<()> {
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
         match self {
-            Self::Create { messages, tokens, weight, output } => {
+            Self::Create { messages, tokens, weight, name, dialogue, manifest, output } => {
                 println!("Reading tokenized messages bundle...");
 
-                let tokenized_messages = postcard::from_bytes::<TokenizedMessages>(&std::fs::read(messages)?)?;
+                let tokenized_messages = bundle::read::<TokenizedMessages>(messages)?;
+
+                let mut provenance = vec![ManifestEntry::from_file(messages)?];
+
+                for path in manifest {
+                    provenance.extend(read_manifest(path)?);
+                }
 
                 println!("Reading tokens bundle...");
 
-                let tokens = postcard::from_bytes::<Tokens>(&std::fs::read(tokens)?)?;
+                let tokens = bundle::read::<Tokens>(tokens)?;
 
                 println!("Creating dataset...");
 
-                let dataset = Dataset::default()
+                let mut dataset = Dataset::default()
                     .with_messages(tokenized_messages, *weight)
-                    .with_tokens(tokens);
+                    .name_source(0, source_name(messages, name.as_deref()))
+                    .with_tokens(tokens)
+                    .with_provenance(provenance);
+
+                if *dialogue {
+                    dataset = dataset.mark_dialogue(0);
+                }
 
                 println!("Storing dataset bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&dataset)?)?;
+                bundle::write(output, &dataset, compression_level)?;
 
                 println!("Done");
             }
 
-            Self::AddMessages { path, messages, weight, output } => {
+            Self::AddMessages { path, messages, weight, name, dialogue, tokens, manifest, output } => {
                 println!("Reading dataset bundle...");
 
-                let mut dataset = postcard::from_bytes::<Dataset>(&std::fs::read(path)?)?;
+                let mut dataset = bundle::read::<Dataset>(path)?;
+
+                let tokens = match tokens {
+                    Some(tokens) => {
+                        println!("Reading tokens bundle...");
+
+                        Some(bundle::read::<Tokens>(tokens)?)
+                    }
+
+                    None => None
+                };
 
                 println!("Reading tokenized messages bundles...");
 
-                for path in search_files(messages) {
-                    println!("Reading {:?}...", path);
+                for path in progress::files(&search_files(messages, exclude)?, "Reading") {
+                    let tokenized_messages = bundle::read::<TokenizedMessages>(&path)?;
+                    let index = dataset.messages().len();
+
+                    dataset = match &tokens {
+                        Some(tokens) => dataset.with_tokenized_messages(tokenized_messages, tokens.clone(), *weight),
+                        None => dataset.with_messages(tokenized_messages, *weight)
+                    };
 
-                    let tokenized_messages = postcard::from_bytes::<TokenizedMessages>(&std::fs::read(path)?)?;
+                    dataset = dataset.name_source(index, source_name(path, name.as_deref()));
+
+                    if *dialogue {
+                        dataset = dataset.mark_dialogue(index);
+                    }
 
-                    dataset = dataset.with_messages(tokenized_messages, *weight);
+                    dataset = dataset.with_provenance([ManifestEntry::from_file(path)?]);
+                }
+
+                for path in manifest {
+                    dataset = dataset.with_provenance(read_manifest(path)?);
                 }
 
                 println!("Storing dataset bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&dataset)?)?;
+                bundle::write(output, &dataset, compression_level)?;
 
                 println!("Done");
             }
@@ -128,29 +384,181 @@ impl CliDatasetCommand {
             Self::AddTokens { path, tokens, output } => {
                 println!("Reading dataset bundle...");
 
-                let mut dataset = postcard::from_bytes::<Dataset>(&std::fs::read(path)?)?;
+                let mut dataset = bundle::read::<Dataset>(path)?;
 
                 println!("Reading tokens bundles...");
 
-                for path in search_files(tokens) {
-                    println!("Reading {:?}...", path);
+                for path in progress::files(&search_files(tokens, exclude)?, "Reading") {
+                    let tokens = bundle::read::<Tokens>(path)?;
+
+                    dataset = dataset.try_with_tokens(tokens)?;
+                }
+
+                println!("Storing dataset bundle...");
+
+                bundle::write(output, &dataset, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Remap { path, tokens, index, output } => {
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                println!("Reading tokens bundle...");
+
+                let tokens = bundle::read::<Tokens>(tokens)?;
+
+                println!("Translating tokens...");
+
+                let dataset = dataset.with_tokens(tokens.clone());
+                let remap = tokens.remap_to(dataset.tokens())?;
+
+                println!("  Changed tokens: {}", remap.len());
+
+                let dataset = if index.is_empty() {
+                    let indexes = 0..dataset.messages().len();
+
+                    dataset.remap(indexes, &remap)
+                } else {
+                    dataset.remap(index.iter().copied(), &remap)
+                };
+
+                println!("Storing dataset bundle...");
+
+                bundle::write(output, &dataset, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::PruneVocab { path, min_count, unk, output } => {
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                println!("Pruning tokens...");
+
+                let total = dataset.tokens().len();
 
-                    let tokens = postcard::from_bytes::<Tokens>(&std::fs::read(path)?)?;
+                let dataset = dataset.prune_vocab(*min_count, unk);
 
-                    dataset = dataset.with_tokens(tokens);
+                println!("  Tokens: {} of {total}", dataset.tokens().len());
+
+                println!("Storing dataset bundle...");
+
+                bundle::write(output, &dataset, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::ListSources { path } => {
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                for (i, (messages, weight)) in dataset.messages().iter().enumerate() {
+                    let name = dataset.source_name(i).unwrap_or("<unnamed>");
+
+                    let kind = if dataset.dialogues().contains(&i) {
+                        ", dialogue"
+                    } else {
+                        ""
+                    };
+
+                    println!("#{i}: {name}, {} messages, weight {weight}{kind}", messages.messages().len());
+                }
+            }
+
+            Self::SetWeight { path, source, weight, output } => {
+                println!("Reading dataset bundle...");
+
+                let mut dataset = bundle::read::<Dataset>(path)?;
+
+                let changed = dataset.set_source_weight(source, *weight);
+
+                if changed == 0 {
+                    anyhow::bail!("Dataset has no messages source {source:?}, see `dataset list-sources`");
                 }
 
+                println!("  Changed sources: {changed}");
+
                 println!("Storing dataset bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&dataset)?)?;
+                bundle::write(output, &dataset, compression_level)?;
 
                 println!("Done");
             }
 
+            Self::RemoveWord { path, word, variants, output } => {
+                println!("Reading dataset bundle...");
+
+                let mut dataset = bundle::read::<Dataset>(path)?;
+
+                let tokens = find_word_tokens(dataset.tokens(), word, *variants)?;
+
+                println!("Removing words...");
+
+                let mut words = tokens.iter()
+                    .filter_map(|token| dataset.tokens().find_word(*token))
+                    .collect::<Vec<_>>();
+
+                words.sort_unstable();
+
+                for word in words {
+                    println!("  {word}");
+                }
+
+                let (occurrences, words) = dataset.remove_tokens(&tokens);
+
+                println!("Removed {words} words and {occurrences} occurrences");
+
+                println!("Storing dataset bundle...");
+
+                bundle::write(output, &dataset, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Filter { path, blockwords, mode, output } => {
+                let blocklist = read_blocklist(blockwords)?;
+
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                let messages = dataset.stats().messages;
+                let tokens = dataset.tokens().len();
+
+                let dataset = match mode {
+                    BlockMode::Drop => dataset.drop_blocked(&blocklist),
+                    BlockMode::Mask => dataset.mask_blocked(&blocklist)
+                };
+
+                println!("  Messages: {} of {messages}", dataset.stats().messages);
+                println!("  Tokens: {} of {tokens}", dataset.tokens().len());
+
+                println!("Storing dataset bundle...");
+
+                bundle::write(output, &dataset, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Provenance { path } => {
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                println!();
+
+                for entry in dataset.provenance() {
+                    println!("  {}  {:>12}  {:?}", entry.hash, entry.size, entry.path);
+                }
+            }
+
             Self::CheckWord { path, word } => {
                 println!("Reading dataset bundle...");
 
-                let dataset = postcard::from_bytes::<Dataset>(&std::fs::read(path)?)?;
+                let dataset = bundle::read::<Dataset>(path)?;
 
                 println!("Checking word appearance...");
 
@@ -183,6 +591,74 @@ impl CliDatasetCommand {
                 println!("  Importance: {importance}");
                 println!("   Frequency: {:.5}%", distinct_num as f64 / total_messages as f64 * 100.0);
             }
+
+            Self::Stats { path, top } => {
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                println!("Calculating statistics...");
+
+                let stats = dataset.stats();
+
+                println!();
+                println!("      Messages: {}", stats.messages);
+                println!("        Tokens: {}", stats.tokens);
+                println!("    Vocabulary: {}", stats.vocabulary);
+                println!();
+                println!("Average length: {:.2}", stats.average_len());
+                println!(" Median length: {}", stats.percentile_len(50.0));
+                println!("   90th length: {}", stats.percentile_len(90.0));
+                println!("   99th length: {}", stats.percentile_len(99.0));
+                println!("    Max length: {}", stats.percentile_len(100.0));
+
+                println!();
+                println!("  Sources:");
+                println!();
+
+                for (i, (messages, weight)) in stats.sources.iter().enumerate() {
+                    match dataset.source_name(i) {
+                        Some(name) => println!("    #{i} {name}: {messages} messages, weight {weight}"),
+                        None => println!("    #{i}: {messages} messages, weight {weight}")
+                    }
+                }
+
+                if *top > 0 {
+                    println!();
+                    println!("  Top words:");
+                    println!();
+
+                    for (token, count) in stats.top_tokens(*top) {
+                        let word = dataset.tokens()
+                            .find_word(token)
+                            .map(String::from)
+                            .unwrap_or_else(|| format!("<{token}>"));
+
+                        println!("    {count:>10}  {word}");
+                    }
+                }
+            }
+
+            Self::Export { path, output } => {
+                println!("Reading dataset bundle...");
+
+                let dataset = bundle::read::<Dataset>(path)?;
+
+                println!("Exporting messages...");
+
+                let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+
+                for (messages, _) in dataset.messages() {
+                    messages.detokenize(dataset.tokens())?
+                        .write_lines(&mut file)?;
+                }
+
+                println!("Done");
+            }
+
+            Self::Convert { path, to, output } => {
+                convert_bundle::<Dataset>(path, *to, output, compression_level)?;
+            }
         }
 
         Ok(())
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::prelude::Model;
+
+#[derive(Args)]
+pub struct CliDoctorCommand {
+    #[arg(short, long)]
+    /// Path to the model
+    model: PathBuf
+}
+
+impl CliDoctorCommand {
+    pub fn execute(&self) -> anyhow::Result<()> {
+        println!("Reading model...");
+
+        let model = Model::load(&self.model)?;
+
+        println!("Inspecting model...");
+        println!();
+
+        let diagnostics = model.diagnose();
+
+        if diagnostics.is_empty() {
+            println!("  No problems found");
+        }
+
+        for diagnostic in &diagnostics {
+            let severity = if diagnostic.is_error() {
+                "error"
+            } else {
+                "warning"
+            };
+
+            println!("  [{severity}] {diagnostic}");
+            println!("            fix: {}", diagnostic.suggestion());
+        }
+
+        println!();
+
+        if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+            anyhow::bail!("Model has errors");
+        }
+
+        println!("Done");
+
+        Ok(())
+    }
+}
@@ -1,14 +1,86 @@
 use std::path::PathBuf;
 
-use clap::Subcommand;
+use clap::{Subcommand, ValueEnum};
+use globset::GlobSet;
 
 use crate::prelude::{
     Messages,
+    DiscordFilter,
+    Punctuation,
+    DEFAULT_PUNCTUATION,
+    Bpe,
+    CharTokenizer,
+    Anonymizer,
     Tokens,
-    TokenizedMessages
+    TokenizedMessages,
+    MessageFilter,
+    Blocklist,
+    SplitMode,
+    DEFAULT_SIMILARITY
 };
 
-use super::search_files;
+use crate::discord::read_discord_export;
+use crate::bundle;
+
+use super::{search_files, write_manifest, progress, convert_bundle, ConvertFormat};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessagesFormat {
+    #[default]
+    /// Plain text or json string lines
+    Lines,
+
+    /// Newline-delimited JSON objects
+    Jsonl
+}
+
+/// Remove near-duplicate messages printing how many were removed
+fn dedup_similar(messages: Messages, similarity: f64) -> anyhow::Result<Messages> {
+    if similarity <= 0.0 || similarity > 1.0 {
+        anyhow::bail!("Similarity must be in (0.0, 1.0] range");
+    }
+
+    println!("Removing near-duplicates...");
+
+    let total = messages.messages().len();
+
+    let messages = progress::spin(|| messages.dedup_similar(similarity));
+
+    println!("Removed {} of {total} messages", total - messages.messages().len());
+
+    Ok(messages)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScrubMode {
+    #[default]
+    /// Replace personal data by placeholder words
+    Replace,
+
+    /// Drop messages containing personal data
+    Drop
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlockMode {
+    #[default]
+    /// Drop messages containing blocked words
+    Drop,
+
+    /// Replace blocked words by the `<BLOCKED>` word
+    Mask
+}
+
+/// Read the blocked words list with a word per line
+pub(super) fn read_blocklist(path: &PathBuf) -> anyhow::Result<Blocklist> {
+    println!("Reading blocked words...");
+
+    let blocklist = Blocklist::parse(&std::fs::read_to_string(path)?);
+
+    println!("  Blocked words: {}", blocklist.len());
+
+    Ok(blocklist)
+}
 
 #[derive(Subcommand)]
 pub enum CliMessagesCommand {
@@ -16,8 +88,178 @@ pub enum CliMessagesCommand {
     Parse {
         #[arg(short, long)]
         /// Paths to the messages list
+        ///
+        /// `.gz`, `.zst` and `.xz` files are decompressed.
         path: Vec<PathBuf>,
 
+        #[arg(long, value_enum, default_value_t = MessagesFormat::Lines)]
+        /// Format of the messages files
+        format: MessagesFormat,
+
+        #[arg(long, required_if_eq("format", "jsonl"))]
+        /// Dot-separated path to the message text in JSONL objects
+        ///
+        /// Numeric segments index arrays, e.g. `replies.0.text`.
+        field: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = SplitMode::Lines)]
+        /// How the lines files are split into messages
+        ///
+        /// Use `sentences` for books and articles
+        /// which aren't written one message per line.
+        split: SplitMode,
+
+        #[arg(long)]
+        /// Split messages longer than this amount of words
+        /// into overlapping windows of this many words
+        ///
+        /// Keeps very long lines of documents from becoming
+        /// single gigantic messages which dominate the chain.
+        chunk_words: Option<usize>,
+
+        #[arg(long, default_value_t = 0, requires = "chunk_words")]
+        /// Amount of words shared by the adjacent windows
+        chunk_overlap: usize,
+
+        #[arg(long)]
+        /// Path to the manifest of the parsed files
+        ///
+        /// Can be stored in the dataset provenance later.
+        manifest: Option<PathBuf>,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PUNCTUATION)]
+        /// Split these punctuation characters from the words into separate tokens
+        ///
+        /// Uses `.,!?;:"()[]…` if no characters are given. Build the model
+        /// with the same `--punctuation` to re-attach them when generating.
+        split_punctuation: Option<String>,
+
+        #[arg(long, conflicts_with = "split_punctuation")]
+        /// Split messages into characters with spaces between the words
+        ///
+        /// Build the model with `--tokenizer chars` to join
+        /// them back and split prompts the same way.
+        chars: bool,
+
+        #[arg(long, default_value_t = false)]
+        /// Keep only the first occurrence of repeated messages
+        ///
+        /// By default repeated messages are kept so they
+        /// weigh proportionally to their frequency.
+        dedup: bool,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = "0.8")]
+        /// Drop messages whose word pairs overlap with a previous
+        /// message at least by this share
+        ///
+        /// Removes copypasta and repeated bot output which skew
+        /// the transitions. Uses 0.8 if no value is given.
+        dedup_similarity: Option<f64>,
+
+        #[arg(long)]
+        /// Skip messages with fewer words
+        min_words: Option<usize>,
+
+        #[arg(long)]
+        /// Skip messages with more words
+        max_words: Option<usize>,
+
+        #[arg(long)]
+        /// Keep only messages matching any of these regex patterns
+        ///
+        /// Patterns are matched against the lowercased words
+        /// joined by spaces, unless `--preserve-case` is set.
+        include_regex: Vec<String>,
+
+        #[arg(long)]
+        /// Skip messages matching any of these regex patterns,
+        /// e.g. `^!` for bot commands
+        exclude_regex: Vec<String>,
+
+        #[arg(long, default_value_t = false)]
+        /// Scrub emails, credit card like numbers, phone numbers
+        /// and user mentions before any other processing
+        ///
+        /// They're replaced by `<EMAIL>`, `<CARD>`, `<PHONE>`
+        /// and `<USER>` words unless `--scrub-mode drop` is used.
+        scrub: bool,
+
+        #[arg(long, value_enum, default_value_t = ScrubMode::Replace, requires = "scrub")]
+        /// What to do with the scrubbed personal data
+        scrub_mode: ScrubMode,
+
+        #[arg(long, default_value_t = false)]
+        /// Collapse URLs, emails and user mentions
+        /// into `<URL>`, `<EMAIL>` and `<USER>` words
+        special_tokens: bool,
+
+        #[arg(long)]
+        /// Collapse matches of the pattern into the placeholder word,
+        /// in `<PLACEHOLDER>=regex` format
+        ///
+        /// Register placeholders with `tokens parse --special`.
+        special: Vec<String>,
+
+        #[arg(long, default_value_t = false)]
+        /// Keep original case of the words instead of lowercasing them
+        preserve_case: bool,
+
+        #[arg(short, long)]
+        /// Path to the bundle output
+        output: PathBuf
+    },
+
+    /// Parse messages from DiscordChatExporter JSON or CSV dumps
+    ParseDiscord {
+        #[arg(short, long)]
+        /// Paths to the dumps, `.csv` files are parsed as CSV exports
+        ///
+        /// `.gz`, `.zst` and `.xz` files are decompressed.
+        path: Vec<PathBuf>,
+
+        #[arg(long, default_value_t = false)]
+        /// Skip messages of the bots
+        ///
+        /// CSV exports have no bots information.
+        drop_bots: bool,
+
+        #[arg(long, default_value_t = false)]
+        /// Remove user, role and channel mentions
+        strip_mentions: bool,
+
+        #[arg(long, default_value_t = false)]
+        /// Remove custom emoji
+        strip_emoji: bool,
+
+        #[arg(long)]
+        /// Keep only messages of the channel id or name
+        ///
+        /// CSV exports have no channels information
+        /// and are skipped by this filter.
+        channel: Vec<String>,
+
+        #[arg(long)]
+        /// Keep only messages of the author id, name or nickname
+        author: Vec<String>,
+
+        #[arg(long)]
+        /// Path to the manifest of the parsed files
+        manifest: Option<PathBuf>,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PUNCTUATION)]
+        /// Split these punctuation characters from the words into separate tokens
+        ///
+        /// Uses `.,!?;:"()[]…` if no characters are given. Build the model
+        /// with the same `--punctuation` to re-attach them when generating.
+        split_punctuation: Option<String>,
+
+        #[arg(long, default_value_t = false)]
+        /// Keep only the first occurrence of repeated messages
+        ///
+        /// By default repeated messages are kept so they
+        /// weigh proportionally to their frequency.
+        dedup: bool,
+
         #[arg(short, long)]
         /// Path to the bundle output
         output: PathBuf
@@ -29,11 +271,154 @@ pub enum CliMessagesCommand {
         /// Paths to the messages bundles
         path: Vec<PathBuf>,
 
+        #[arg(long, default_value_t = false)]
+        /// Keep only the first occurrence of repeated messages
+        dedup: bool,
+
         #[arg(short, long)]
         /// Path to the merged messages bundle
         output: PathBuf
     },
 
+    /// Remove near-duplicate messages from the bundle
+    ///
+    /// Keeps the first of the messages whose word pairs overlap
+    /// at least by the similarity share, so copypasta and repeated
+    /// bot output don't skew the transitions.
+    Dedup {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(long, default_value_t = DEFAULT_SIMILARITY)]
+        /// Minimal Jaccard similarity of the word pairs of near-duplicates
+        ///
+        /// 1.0 removes exact duplicates only.
+        similarity: f64,
+
+        #[arg(short, long)]
+        /// Path to the deduplicated messages bundle
+        output: PathBuf
+    },
+
+    /// Drop or mask messages containing blocked words
+    Filter {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Path to the file with a blocked word per line
+        ///
+        /// Words are matched ignoring case and punctuation around
+        /// them, lines starting with `#` are skipped.
+        blockwords: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = BlockMode::Drop)]
+        /// What to do with the messages containing blocked words
+        mode: BlockMode,
+
+        #[arg(short, long)]
+        /// Path to the filtered messages bundle
+        output: PathBuf
+    },
+
+    /// Print statistics of the messages bundle
+    Stats {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(long, default_value_t = 20)]
+        /// Amount of the most frequent words to print
+        top: usize
+    },
+
+    /// Print random messages of the bundle
+    Sample {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(short, long, default_value_t = 20)]
+        /// Amount of messages to print
+        count: usize,
+
+        #[arg(long)]
+        /// Seed of the random generator to print the same messages
+        seed: Option<u64>
+    },
+
+    /// Report words which differ only by case, quotes or dashes
+    Normalize {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the messages bundle with merged variants
+        ///
+        /// Each word is replaced by its most frequent variant.
+        output: Option<PathBuf>
+    },
+
+    /// Replace names, handles, phone numbers and emails by placeholders
+    Anonymize {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Path to the names list, one name per line
+        names: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Additional pattern in `<PLACEHOLDER>=regex` format
+        pattern: Vec<String>,
+
+        #[arg(long, default_value_t = false)]
+        /// Do not use built-in emails, handles and phone numbers patterns
+        no_default_patterns: bool,
+
+        #[arg(short, long)]
+        /// Path to the anonymized messages bundle
+        output: PathBuf
+    },
+
+    /// Split words of the messages bundle into subwords
+    EncodeBpe {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the tokenizer trained by `tokens train-bpe`
+        bpe: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the encoded messages bundle
+        output: PathBuf
+    },
+
+    /// Translate tokenized messages bundle to another tokens bundle
+    Remap {
+        #[arg(short, long)]
+        /// Path to the tokenized messages bundle
+        messages: PathBuf,
+
+        #[arg(long)]
+        /// Path to the tokens bundle used to tokenize the messages
+        from: PathBuf,
+
+        #[arg(long)]
+        /// Path to the target tokens bundle, e.g. merged by `tokens merge`
+        to: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the tokenized messages bundle output
+        output: PathBuf
+    },
+
     /// Tokenize messages bundle
     Tokenize {
         #[arg(short, long)]
@@ -44,73 +429,511 @@ pub enum CliMessagesCommand {
         /// Path to the tokens bundle
         tokens: PathBuf,
 
+        #[arg(long)]
+        /// Replace words unknown to the tokens bundle by this word,
+        /// e.g. `<UNK>` of the pruned tokens bundle
+        unk: Option<String>,
+
         #[arg(short, long)]
         /// Path to the tokenized messages bundle
         output: PathBuf
+    },
+
+    /// Write messages of the bundle to a text file one per line
+    Export {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the text output
+        output: PathBuf
+    },
+
+    /// Convert messages bundle to JSON and back
+    Convert {
+        #[arg(short, long)]
+        /// Path to the messages bundle or JSON file
+        path: PathBuf,
+
+        #[arg(long)]
+        /// Convert tokenized messages bundle instead of the plain one
+        tokenized: bool,
+
+        #[arg(long, value_enum)]
+        /// Format of the output
+        to: ConvertFormat,
+
+        #[arg(short, long)]
+        /// Path to the converted output
+        output: PathBuf
     }
 }
 
 impl CliMessagesCommand {
     #[inline]
-    pub fn execute(&self) -> anyhow::Result<()> {
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
         match self {
-            Self::Parse { path, output } => {
+            Self::Parse { path, format, field, split, chunk_words, chunk_overlap, manifest, split_punctuation, chars, dedup, dedup_similarity, min_words, max_words, include_regex, exclude_regex, scrub, scrub_mode, special_tokens, special, preserve_case, output } => {
+                let filter = |word: &str| if *preserve_case {
+                    word.to_string()
+                } else {
+                    word.to_lowercase()
+                };
+
+                if *format == MessagesFormat::Jsonl && *split == SplitMode::Sentences {
+                    anyhow::bail!("Sentences splitting is supported for the lines format only");
+                }
+
+                if let Some(window) = chunk_words {
+                    if *window == 0 || chunk_overlap >= window {
+                        anyhow::bail!("Chunk overlap must be less than the chunk size");
+                    }
+                }
+
+                let mut messages = Messages::default();
+
+                println!("Parsing messages...");
+
+                let paths = search_files(path, exclude)?;
+
+                for path in progress::files(&paths, "Parsing") {
+                    let parsed = match (format, field) {
+                        (MessagesFormat::Jsonl, Some(field)) => Messages::parse_from_jsonl_with_filter(path, field, filter)?,
+                        (MessagesFormat::Jsonl, None) => anyhow::bail!("JSONL format requires --field"),
+
+                        (MessagesFormat::Lines, _) => match split {
+                            SplitMode::Lines => Messages::parse_from_messages_with_filter(path, filter)?,
+                            SplitMode::Sentences => Messages::parse_from_sentences_with_filter(path, filter)?
+                        }
+                    };
+
+                    messages = messages.merge(parsed);
+                }
+
+                if let Some(manifest) = manifest {
+                    println!("Storing manifest...");
+
+                    write_manifest(manifest, &paths)?;
+                }
+
+                if *scrub {
+                    println!("Scrubbing personal data...");
+
+                    let total = messages.messages().len();
+
+                    messages = match scrub_mode {
+                        ScrubMode::Replace => messages.anonymize(&Anonymizer::scrubber()),
+                        ScrubMode::Drop => messages.drop_anonymized(&Anonymizer::scrubber())
+                    };
+
+                    if *scrub_mode == ScrubMode::Drop {
+                        println!("Dropped {} of {total} messages", total - messages.messages().len());
+                    }
+                }
+
+                if let Some(window) = chunk_words {
+                    println!("Chunking messages...");
+
+                    let total = messages.messages().len();
+
+                    messages = messages.chunk(*window, *chunk_overlap);
+
+                    println!("  Messages: {} from {total}", messages.messages().len());
+                }
+
+                let mut filter = MessageFilter {
+                    min_words: *min_words,
+                    max_words: *max_words,
+                    ..MessageFilter::default()
+                };
+
+                for pattern in include_regex {
+                    filter = filter.with_include(pattern)?;
+                }
+
+                for pattern in exclude_regex {
+                    filter = filter.with_exclude(pattern)?;
+                }
+
+                let total = messages.messages().len();
+
+                messages = messages.filter(&filter);
+
+                if messages.messages().len() < total {
+                    println!("Filtered out {} of {total} messages", total - messages.messages().len());
+                }
+
+                let mut placeholders = if *special_tokens {
+                    Anonymizer::special_tokens()
+                } else {
+                    Anonymizer::empty()
+                };
+
+                for pattern in special {
+                    let Some((placeholder, pattern)) = pattern.split_once('=') else {
+                        anyhow::bail!("Pattern must be in `<PLACEHOLDER>=regex` format: {pattern}");
+                    };
+
+                    placeholders = placeholders.with_pattern(pattern, placeholder)?;
+                }
+
+                if placeholders.placeholders().next().is_some() {
+                    println!("Replacing special tokens...");
+
+                    messages = messages.anonymize(&placeholders);
+                }
+
+                if let Some(chars) = split_punctuation {
+                    messages = messages.split_punctuation(&Punctuation::new(chars));
+                }
+
+                if *chars {
+                    messages = messages.tokenize(&CharTokenizer);
+                }
+
+                if *dedup {
+                    messages = messages.dedup();
+                }
+
+                if let Some(similarity) = dedup_similarity {
+                    messages = dedup_similar(messages, *similarity)?;
+                }
+
+                println!("Storing messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::ParseDiscord { path, drop_bots, strip_mentions, strip_emoji, channel, author, manifest, split_punctuation, dedup, output } => {
+                let filter = DiscordFilter {
+                    drop_bots: *drop_bots,
+                    strip_mentions: *strip_mentions,
+                    strip_emoji: *strip_emoji,
+                    channels: channel.clone(),
+                    authors: author.clone()
+                };
+
                 let mut messages = Messages::default();
 
                 println!("Parsing messages...");
 
-                for path in search_files(path) {
-                    println!("Parsing {:?}...", path);
+                let paths = search_files(path, exclude)?;
+
+                for path in progress::files(&paths, "Parsing") {
+                    let dump = read_discord_export(path)?;
+
+                    messages = messages.merge(Messages::parse_from_discord(&dump, &filter));
+                }
+
+                if let Some(manifest) = manifest {
+                    println!("Storing manifest...");
 
-                    messages = messages.merge(Messages::parse_from_messages(path)?);
+                    write_manifest(manifest, &paths)?;
+                }
+
+                if let Some(chars) = split_punctuation {
+                    messages = messages.split_punctuation(&Punctuation::new(chars));
+                }
+
+                if *dedup {
+                    messages = messages.dedup();
                 }
 
                 println!("Storing messages bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&messages)?)?;
+                bundle::write(output, &messages, compression_level)?;
 
                 println!("Done");
             }
 
-            Self::Merge { path, output } => {
+            Self::Merge { path, dedup, output } => {
                 let mut messages = Messages::default();
 
                 println!("Reading messages bundles...");
 
-                for path in search_files(path) {
-                    println!("Reading {:?}...", path);
-
-                    let bundle = postcard::from_bytes::<Messages>(&std::fs::read(path)?)?;
+                for path in progress::files(&search_files(path, exclude)?, "Reading") {
+                    let bundle = bundle::read::<Messages>(path)?;
 
                     messages = messages.merge(bundle);
                 }
 
+                if *dedup {
+                    messages = messages.dedup();
+                }
+
                 println!("Storing merged messages bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&messages)?)?;
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Dedup { path, similarity, output } => {
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                let messages = dedup_similar(messages, *similarity)?;
+
+                println!("Storing messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Filter { path, blockwords, mode, output } => {
+                let blocklist = read_blocklist(blockwords)?;
+
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                let blocked = messages.messages().iter()
+                    .filter(|message| blocklist.is_match(message))
+                    .count();
+
+                let messages = match mode {
+                    BlockMode::Drop => messages.drop_blocked(&blocklist),
+                    BlockMode::Mask => messages.mask_blocked(&blocklist)
+                };
+
+                match mode {
+                    BlockMode::Drop => println!("Dropped {blocked} messages"),
+                    BlockMode::Mask => println!("Masked {blocked} messages")
+                }
+
+                println!("Storing messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Stats { path, top } => {
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                println!("Calculating statistics...");
+
+                let stats = messages.stats();
+
+                println!();
+                println!("      Messages: {}", stats.messages);
+                println!("        Unique: {}", stats.unique_messages);
+                println!("         Words: {}", stats.words);
+                println!("    Vocabulary: {}", stats.vocabulary);
+                println!("    Singletons: {}", stats.singletons);
+                println!();
+                println!("Average length: {:.2}", stats.average_len());
+                println!(" Median length: {}", stats.percentile_len(50.0));
+                println!("   90th length: {}", stats.percentile_len(90.0));
+                println!("   99th length: {}", stats.percentile_len(99.0));
+                println!("    Max length: {}", stats.percentile_len(100.0));
+
+                println!();
+                println!("  Lengths:");
+                println!();
+
+                let histogram = stats.length_histogram();
+
+                let max = histogram.iter()
+                    .map(|(_, _, count)| *count)
+                    .max()
+                    .unwrap_or_default()
+                    .max(1);
+
+                for (min, max_len, count) in histogram {
+                    let bar = "#".repeat((count * 40).div_ceil(max));
+
+                    println!("    {:>13}  {count:>10}  {bar}", format!("{min}-{max_len}"));
+                }
+
+                if *top > 0 {
+                    println!();
+                    println!("  Top words:");
+                    println!();
+
+                    let mut counts = messages.word_counts()
+                        .into_iter()
+                        .collect::<Vec<_>>();
+
+                    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+                    for (word, count) in counts.into_iter().take(*top) {
+                        println!("    {count:>10}  {word}");
+                    }
+                }
+            }
+
+            Self::Sample { path, count, seed } => {
+                let messages = bundle::read::<Messages>(path)?;
+
+                for message in messages.sample(*count, *seed) {
+                    println!("{}", message.join(" "));
+                }
+            }
+
+            Self::Normalize { path, output } => {
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                println!("Searching spelling variants...");
+
+                let report = messages.normalization_report();
+
+                println!();
+
+                for group in &report {
+                    let variants = group.variants.iter()
+                        .map(|(word, count)| format!("{word} ({count})"))
+                        .collect::<Vec<_>>();
+
+                    println!("  {} : {}", group.normalized, variants.join(", "));
+                }
+
+                println!();
+                println!("  Total groups: {}", report.len());
+
+                if let Some(output) = output {
+                    println!();
+                    println!("Merging spelling variants...");
+
+                    let messages = messages.merge_normalized();
+
+                    println!("Storing messages bundle...");
+
+                    bundle::write(output, &messages, compression_level)?;
+
+                    println!("Done");
+                }
+            }
+
+            Self::Anonymize { path, names, pattern, no_default_patterns, output } => {
+                let mut anonymizer = if *no_default_patterns {
+                    Anonymizer::empty()
+                } else {
+                    Anonymizer::default()
+                };
+
+                for pattern in pattern {
+                    let Some((placeholder, pattern)) = pattern.split_once('=') else {
+                        anyhow::bail!("Pattern must be in `<PLACEHOLDER>=regex` format: {pattern}");
+                    };
+
+                    anonymizer = anonymizer.with_pattern(pattern, placeholder)?;
+                }
+
+                if let Some(names) = names {
+                    println!("Reading names list...");
+
+                    anonymizer = anonymizer.with_names(std::fs::read_to_string(names)?.lines())?;
+                }
+
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                println!("Anonymizing messages...");
+
+                let messages = messages.anonymize(&anonymizer);
+
+                println!("Storing messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
 
                 println!("Done");
             }
 
-            Self::Tokenize { messages, tokens, output } => {
+            Self::EncodeBpe { path, bpe, output } => {
                 println!("Reading messages bundle...");
 
-                let messages = postcard::from_bytes::<Messages>(&std::fs::read(messages)?)?;
+                let messages = bundle::read::<Messages>(path)?;
+
+                println!("Reading tokenizer...");
+
+                let bpe = bundle::read::<Bpe>(bpe)?;
+
+                println!("Encoding messages...");
+
+                let messages = bpe.encode(&messages);
+
+                println!("Storing messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Remap { messages, from, to, output } => {
+                println!("Reading tokenized messages bundle...");
+
+                let messages = bundle::read::<TokenizedMessages>(messages)?;
+
+                println!("Reading tokens bundles...");
+
+                let from = bundle::read::<Tokens>(from)?;
+                let to = bundle::read::<Tokens>(to)?;
+
+                println!("Translating tokens...");
+
+                let remap = from.remap_to(&to)?;
+
+                println!("  Changed tokens: {}", remap.len());
+
+                let messages = messages.remap(&remap);
+
+                println!("Storing tokenized messages bundle...");
+
+                bundle::write(output, &messages, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Tokenize { messages, tokens, unk, output } => {
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(messages)?;
 
                 println!("Reading tokens bundle...");
                 
-                let tokens = postcard::from_bytes::<Tokens>(&std::fs::read(tokens)?)?;
+                let tokens = bundle::read::<Tokens>(tokens)?;
 
                 println!("Tokenizing messages...");
 
-                let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+                let tokenized = progress::spin(|| TokenizedMessages::tokenize_message_with_unk(&messages, &tokens, unk.as_deref()))?;
 
                 println!("Storing tokenized messages bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&tokenized)?)?;
+                bundle::write(output, &tokenized, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Export { path, output } => {
+                println!("Reading messages bundle...");
+
+                let messages = bundle::read::<Messages>(path)?;
+
+                println!("Exporting messages...");
+
+                messages.write_lines(std::io::BufWriter::new(std::fs::File::create(output)?))?;
 
                 println!("Done");
             }
+
+            Self::Convert { path, tokenized, to, output } => {
+                if *tokenized {
+                    convert_bundle::<TokenizedMessages>(path, *to, output, compression_level)?;
+                } else {
+                    convert_bundle::<Messages>(path, *to, output, compression_level)?;
+                }
+            }
         }
 
         Ok(())
@@ -1,25 +1,122 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+
+use crate::prelude::{
+    ManifestEntry,
+    Tokens,
+    Blocklist
+};
+use crate::bundle::{self, Bundle, DEFAULT_COMPRESSION_LEVEL};
 
 mod messages;
 mod tokens;
 mod dataset;
 mod model;
+mod train;
+mod verify;
+mod doctor;
+mod server;
+mod daemon;
+mod repl;
+
+#[cfg(feature = "bots")]
+mod bot;
+mod progress;
 
 use messages::CliMessagesCommand;
 use tokens::CliTokensCommand;
 use dataset::CliDatasetCommand;
 use model::CliModelCommand;
+use train::CliTrainCommand;
+use verify::CliVerifyCommand;
+use doctor::CliDoctorCommand;
+
+#[inline]
+fn glob(pattern: &str) -> anyhow::Result<Glob> {
+    Ok(GlobBuilder::new(pattern).literal_separator(true).build()?)
+}
+
+/// Build set of the files and directories skipped by `search_files`
+///
+/// Patterns are matched against both the whole path
+/// and the file name, so `old` skips all the `old` directories
+/// and `logs/**/*.tmp` only the files in `logs`.
+pub fn build_exclude(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut exclude = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        exclude.add(glob(pattern)?);
+    }
+
+    Ok(exclude.build()?)
+}
 
-pub fn search_files(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Vec<PathBuf> {
+#[inline]
+fn is_excluded(exclude: &GlobSet, path: &Path) -> bool {
+    exclude.is_match(path) || path.file_name().is_some_and(|name| exclude.is_match(name))
+}
+
+/// Search files by the paths, directories and glob patterns
+///
+/// Directories are searched recursively. Glob patterns like
+/// `logs/**/*.txt` are expanded from their directory without
+/// special characters. Files and directories matching the exclude
+/// set are skipped. Files are returned sorted by their paths.
+pub fn search_files(paths: impl IntoIterator<Item = impl Into<PathBuf>>, exclude: &GlobSet) -> anyhow::Result<Vec<PathBuf>> {
     let mut files = Vec::new();
 
-    let mut paths = paths.into_iter()
-        .map(|path| path.into())
-        .collect::<Vec<PathBuf>>();
+    for path in paths {
+        let path: PathBuf = path.into();
+
+        let is_pattern = path.to_string_lossy()
+            .contains(['*', '?', '[', '{']);
+
+        if !is_pattern {
+            files.extend(walk_files(path, exclude));
+
+            continue;
+        }
+
+        let pattern = glob(&path.to_string_lossy())?.compile_matcher();
+
+        // Walk the longest directory without special characters
+        let base = path.components()
+            .take_while(|component| !component.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']))
+            .collect::<PathBuf>();
+
+        let found = if base.as_os_str().is_empty() {
+            walk_files(PathBuf::from("."), exclude)
+                .into_iter()
+                .map(|path| path.strip_prefix(".").map(Path::to_path_buf).unwrap_or(path))
+                .collect()
+        } else {
+            walk_files(base, exclude)
+        };
+
+        files.extend(found.into_iter().filter(|path| pattern.is_match(path)));
+    }
+
+    // Keep the order independent from the filesystem
+    files.sort();
+    files.dedup();
+
+    Ok(files)
+}
+
+/// Find all the files in the directory recursively
+fn walk_files(path: PathBuf, exclude: &GlobSet) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut paths = vec![path];
 
     while let Some(path) = paths.pop() {
+        if is_excluded(exclude, &path) {
+            continue;
+        }
+
         if path.is_file() {
             files.push(path);
         }
@@ -38,9 +135,106 @@ pub fn search_files(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Vec<
     files
 }
 
+/// Find tokens of the words
+///
+/// With `variants` words are matched ignoring case and punctuation
+/// around them, so `alice` finds `Alice,` and `alice!` too.
+/// Fails if some word or all the variants are unknown.
+pub fn find_word_tokens(tokens: &Tokens, words: &[String], variants: bool) -> anyhow::Result<HashSet<u64>> {
+    if variants {
+        let found = Blocklist::new(words).find_tokens(tokens);
+
+        if found.is_empty() {
+            anyhow::bail!("Could not find any variant of the words");
+        }
+
+        return Ok(found);
+    }
+
+    words.iter()
+        .map(|word| tokens.find_token(word).ok_or_else(|| anyhow::anyhow!("Could not find token for word: {word}")))
+        .collect()
+}
+
+/// Describe the files with their sizes and hashes
+pub fn build_manifest(files: &[PathBuf]) -> anyhow::Result<Vec<ManifestEntry>> {
+    Ok(files.par_iter()
+        .map(ManifestEntry::from_file)
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[inline]
+pub fn read_manifest(path: impl AsRef<Path>) -> anyhow::Result<Vec<ManifestEntry>> {
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+#[inline]
+pub fn write_manifest(path: impl AsRef<Path>, files: &[PathBuf]) -> anyhow::Result<()> {
+    std::fs::write(path, serde_json::to_vec_pretty(&build_manifest(files)?)?)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertFormat {
+    #[default]
+    /// Pretty JSON which can be inspected and edited by hand
+    Json,
+
+    /// Postcard bundle used by the other commands
+    Bundle
+}
+
+/// Convert the bundle to JSON or the JSON file back to the bundle
+pub fn convert_bundle<T: Bundle>(path: &Path, to: ConvertFormat, output: &Path, compression_level: i32) -> anyhow::Result<()> {
+    match to {
+        ConvertFormat::Json => {
+            println!("Reading {} bundle...", T::KIND);
+
+            let value = bundle::read::<T>(path)?;
+
+            println!("Storing JSON...");
+
+            std::fs::write(output, bundle::to_json(&value)?)?;
+        }
+
+        ConvertFormat::Bundle => {
+            println!("Reading JSON...");
+
+            let value = bundle::from_json::<T>(&std::fs::read(path)?)?;
+
+            println!("Storing {} bundle...", T::KIND);
+
+            bundle::write(output, &value, compression_level)?;
+        }
+    }
+
+    println!("Done");
+
+    Ok(())
+}
+
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Cli {
+    #[arg(long, global = true, default_value_t = DEFAULT_COMPRESSION_LEVEL)]
+    /// Zstd compression level of the written bundles
+    ///
+    /// 0 stores uncompressed bundles. Compressed and uncompressed
+    /// bundles are detected automatically when read.
+    compression_level: i32,
+
+    #[arg(short, long, global = true)]
+    /// Don't show progress bars of the long operations
+    quiet: bool,
+
+    #[arg(long, global = true)]
+    /// Skip input files and directories matching the glob pattern
+    ///
+    /// Patterns are matched against the whole path
+    /// and the file name, e.g. `*.tmp` or `logs/old/**`.
+    exclude: Vec<String>,
+
     #[command(subcommand)]
     command: Commands
 }
@@ -48,11 +242,16 @@ pub struct Cli {
 impl Cli {
     #[inline]
     pub fn execute(&self) -> anyhow::Result<()> {
-        self.command.execute()
+        progress::set_quiet(self.quiet);
+
+        let exclude = build_exclude(&self.exclude)?;
+
+        self.command.execute(self.compression_level, &exclude)
     }
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     /// Work with messages
     Messages {
@@ -76,17 +275,84 @@ pub enum Commands {
     Model {
         #[command(subcommand)]
         action: CliModelCommand
-    }
+    },
+
+    /// Build language model from plain messages files in one step
+    Train(CliTrainCommand),
+
+    /// Check consistency of the messages, tokens, dataset and model bundles
+    Verify(CliVerifyCommand),
+
+    /// Report common quality problems of the model with suggested fixes
+    Doctor(CliDoctorCommand)
 }
 
 impl Commands {
     #[inline]
-    pub fn execute(&self) -> anyhow::Result<()> {
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
         match self {
-            Self::Messages { action } => action.execute(),
-            Self::Tokens { action } => action.execute(),
-            Self::Dataset { action } => action.execute(),
-            Self::Model { action } => action.execute()
+            Self::Messages { action } => action.execute(compression_level, exclude),
+            Self::Tokens { action } => action.execute(compression_level, exclude),
+            Self::Dataset { action } => action.execute(compression_level, exclude),
+            Self::Model { action } => action.execute(compression_level, exclude),
+            Self::Train(command) => command.execute(compression_level, exclude),
+            Self::Verify(command) => command.execute(),
+            Self::Doctor(command) => command.execute()
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn verify_cli() {
+        use clap::CommandFactory;
+
+        super::Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn search_files() -> anyhow::Result<()> {
+        use globset::GlobSet;
+
+        use super::*;
+
+        let root = std::env::temp_dir().join(format!("markov-chains-search-{}", std::process::id()));
+
+        for file in ["a.txt", "b.json", "logs/c.txt", "logs/old/d.txt", "logs/old/e.tmp"] {
+            let path = root.join(file);
+
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, file)?;
         }
+
+        let search = |paths: &[&str], exclude: &[&str]| -> anyhow::Result<Vec<String>> {
+            let mut set = GlobSetBuilder::new();
+
+            for pattern in exclude {
+                set.add(glob(&root.join(pattern).to_string_lossy())?);
+                set.add(glob(pattern)?);
+            }
+
+            let files = search_files(paths.iter().map(|path| root.join(path)), &set.build()?)?;
+
+            Ok(files.iter()
+                .map(|path| path.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect())
+        };
+
+        assert_eq!(search(&[""], &[])?, ["a.txt", "b.json", "logs/c.txt", "logs/old/d.txt", "logs/old/e.tmp"]);
+        assert_eq!(search(&["*.txt"], &[])?, ["a.txt"]);
+        assert_eq!(search(&["**/*.txt"], &[])?, ["a.txt", "logs/c.txt", "logs/old/d.txt"]);
+        assert_eq!(search(&["logs/*", "a.txt"], &[])?, ["a.txt", "logs/c.txt"]);
+
+        assert_eq!(search(&[""], &["old"])?, ["a.txt", "b.json", "logs/c.txt"]);
+        assert_eq!(search(&["logs"], &["*.tmp"])?, ["logs/c.txt", "logs/old/d.txt"]);
+        assert_eq!(search(&["**/*.txt"], &["logs/**"])?, ["a.txt"]);
+
+        assert_eq!(search_files([root.join("missing")], &GlobSet::empty())?, Vec::<PathBuf>::new());
+
+        std::fs::remove_dir_all(&root)?;
+
+        Ok(())
     }
 }
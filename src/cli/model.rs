@@ -1,18 +1,182 @@
-use std::path::PathBuf;
-use std::io::Write;
-
-use clap::Subcommand;
+use std::path::{Path, PathBuf};
+use std::io::{Write, BufWriter};
+use std::fs::File;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use clap::{Args, Subcommand, ValueEnum};
+use globset::GlobSet;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
 
 use crate::prelude::{
     Messages,
     Tokens,
     TokenizedMessages,
     Dataset,
+    MAX_ORDER,
     GenerationParams,
-    Model
+    GenerationBounds,
+    Smoothing,
+    Model,
+    MappedModel,
+    MappedGenerator,
+    ChainFormat,
+    StreamingBuilder,
+    Evaluation,
+    Classifier,
+    Classification,
+    CHECKSUM_HEADER,
+    CandidateCache,
+    TokenProbability,
+    PromptTemplate,
+    PROMPT_PLACEHOLDER,
+    UNK_TOKEN_NAME,
+    DEFAULT_TYPO_DISTANCE,
+    Punctuation,
+    DEFAULT_PUNCTUATION,
+    PUNCTUATION_HEADER,
+    Bpe,
+    SUBWORD_MARKER,
+    SUBWORDS_HEADER,
+    Tokenizer,
+    TokenizerKind,
+    CharTokenizer,
+    TOKENIZER_HEADER,
+    NearDuplicates,
+    ModelEnsemble,
+    EnsembleGenerator
 };
 
-use super::search_files;
+use crate::bpe::join_subwords;
+use crate::bundle;
+use crate::Error;
+
+use super::{search_files, write_manifest, find_word_tokens, progress, ConvertFormat};
+use super::server::{serve, ServerContext};
+use super::daemon::{serve_stdio, serve_socket};
+use super::repl::{ReplCommand, REPL_HELP, set_param, format_params};
+
+#[cfg(feature = "bots")]
+use super::bot::CliBotCommand;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UnknownWords {
+    #[default]
+    /// Don't generate anything for the prompt
+    Reject,
+
+    /// Remove unknown words from the prompt
+    Skip,
+
+    /// Replace unknown words by the `<UNK>` word of pruned models
+    Unk,
+
+    /// Replace unknown words by the closest known ones by edit distance
+    ///
+    /// Words without variants within `--max-typo-distance` are removed.
+    Closest
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Args)]
+pub struct UnknownWordsArgs {
+    #[arg(long, value_enum, default_value_t = UnknownWords::Reject)]
+    /// How to handle prompt words unknown to the model
+    pub unknown_words: UnknownWords,
+
+    #[arg(long, default_value_t = DEFAULT_TYPO_DISTANCE)]
+    /// Maximal edit distance to the closest known word divided
+    /// by the words length, used with `--unknown-words closest`
+    ///
+    /// 0.34 allows about one typo per three characters,
+    /// a single typo is allowed in shorter words.
+    pub max_typo_distance: f64
+}
+
+impl Default for UnknownWordsArgs {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            unknown_words: UnknownWords::default(),
+            max_typo_distance: DEFAULT_TYPO_DISTANCE
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    /// GraphViz DOT language
+    Dot,
+
+    /// JSON with nodes and edges lists, e.g. for D3
+    Json
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ModelFormat {
+    #[default]
+    /// Postcard bundle loaded into memory
+    Bundle,
+
+    /// Sorted tables queried from the memory-mapped file
+    ///
+    /// Loads instantly, but only supports plain sampling
+    /// in `model generate`.
+    Mmap
+}
+
+impl ModelFormat {
+    /// Store the model in this format
+    ///
+    /// Checksum of the model is updated in its headers.
+    pub fn write(&self, path: &Path, model: Model, compression_level: i32) -> anyhow::Result<()> {
+        let model = model.with_checksum()?;
+
+        match self {
+            Self::Bundle => bundle::write(path, &model, compression_level)?,
+            Self::Mmap => MappedModel::write(&model, path)?
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+pub struct BanList {
+    #[arg(long)]
+    /// Word which must never be generated, ignoring case
+    ban: Vec<String>,
+
+    #[arg(long)]
+    /// Path to the list of banned words, one word per line
+    ban_file: Option<PathBuf>
+}
+
+impl BanList {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ban.is_empty() && self.ban_file.is_none()
+    }
+
+    /// Get tokens of the banned words known to the model
+    pub fn tokens(&self, model: &Model) -> anyhow::Result<HashSet<u64>> {
+        let mut words = self.ban.clone();
+
+        if let Some(path) = &self.ban_file {
+            words.extend(std::fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|word| !word.is_empty())
+                .map(String::from));
+        }
+
+        Ok(model.tokens.find_all_ignore_case(words))
+    }
+}
 
 #[derive(Subcommand)]
 pub enum CliModelCommand {
@@ -30,12 +194,49 @@ pub enum CliModelCommand {
         /// Build trigrams transitions table
         trigrams: bool,
 
+        #[arg(long, conflicts_with_all = ["bigrams", "trigrams"])]
+        /// Build transitions tables of all the orders up to this one
+        ///
+        /// Supported orders are 1 to 5.
+        order: Option<usize>,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PUNCTUATION)]
+        /// Punctuation characters split from the words by the messages parser
+        ///
+        /// Stored in the model to re-attach punctuation when generating.
+        punctuation: Option<String>,
+
+        #[arg(long)]
+        /// Dataset tokens are subwords encoded by `messages encode-bpe`
+        ///
+        /// Subwords are joined into words when generating.
+        subwords: bool,
+
+        #[arg(long, value_enum)]
+        /// Tokenizer which split words of the dataset messages
+        ///
+        /// Stored in the model so prompts are split the same way.
+        /// Defaults to `punctuation` or `bpe` with the corresponding
+        /// flags, and `whitespace` otherwise.
+        tokenizer: Option<TokenizerKind>,
+
+        #[arg(long)]
+        /// Fail if the dataset tokens are random
+        ///
+        /// Models built from the same dataset with tokens
+        /// parsed by `--deterministic` are byte-identical.
+        deterministic: bool,
+
         #[arg(long)]
         /// Header to add to the model
         /// 
         /// `--header key=value`
         header: Vec<String>,
 
+        #[arg(long, value_enum, default_value_t = ModelFormat::Bundle)]
+        /// Format of the stored model
+        format: ModelFormat,
+
         #[arg(short, long)]
         /// Path to the model output
         output: PathBuf
@@ -47,6 +248,10 @@ pub enum CliModelCommand {
         /// Path to the plain messages file
         messages: Vec<PathBuf>,
 
+        #[arg(long)]
+        /// Path to the manifest of the parsed files
+        manifest: Option<PathBuf>,
+
         #[arg(long)]
         /// Build bigrams transitions table
         bigrams: bool,
@@ -55,12 +260,64 @@ pub enum CliModelCommand {
         /// Build trigrams transitions table
         trigrams: bool,
 
+        #[arg(long, conflicts_with_all = ["bigrams", "trigrams"])]
+        /// Build transitions tables of all the orders up to this one
+        ///
+        /// Supported orders are 1 to 5.
+        order: Option<usize>,
+
+        #[arg(long)]
+        /// Read, tokenize and count messages line by line
+        ///
+        /// Uses much less memory on huge corpora, but can't
+        /// process files in parallel.
+        streaming: bool,
+
+        #[arg(long)]
+        /// Count repeated messages only once
+        dedup: bool,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PUNCTUATION)]
+        /// Split these punctuation characters from the words into separate tokens
+        ///
+        /// Uses `.,!?;:"()[]…` if no characters are given.
+        split_punctuation: Option<String>,
+
+        #[arg(long)]
+        /// Derive tokens from the words' hashes instead of random numbers
+        ///
+        /// Models built from the same messages are byte-identical.
+        deterministic: bool,
+
+        #[arg(long, default_value_t = false)]
+        /// Keep original case of the words instead of lowercasing them
+        ///
+        /// Prompts are still matched ignoring case.
+        preserve_case: bool,
+
+        #[arg(long, conflicts_with = "streaming")]
+        /// Split words into byte-pair encoding subwords
+        /// with vocabulary of this size
+        bpe_vocab_size: Option<usize>,
+
+        #[arg(long, value_enum)]
+        /// Tokenizer splitting words of the messages
+        ///
+        /// Defaults to `punctuation` or `bpe` with the corresponding
+        /// flags, and `whitespace` otherwise. Punctuation tokenizer
+        /// splits `.,!?;:"()[]…` unless other characters are given.
+        tokenizer: Option<TokenizerKind>,
+
         #[arg(long)]
         /// Header to add to the model
         /// 
         /// `--header key=value`
         header: Vec<String>,
 
+        #[arg(long, value_enum, default_value_t = ModelFormat::Bundle)]
+        /// Format of the stored model
+        format: ModelFormat,
+
         #[arg(short, long)]
         /// Path to the model output
         output: PathBuf
@@ -72,219 +329,2162 @@ pub enum CliModelCommand {
         /// Path to the model
         model: PathBuf,
 
+        #[arg(long, default_value_t = String::from(PROMPT_PLACEHOLDER))]
+        /// Template of the prompt
+        ///
+        /// `{prompt}` is replaced by the entered text. If nothing
+        /// is placed before the prompt and the entered text is empty,
+        /// the first token is sampled from the messages openers.
+        template: String,
+
+        #[arg(long)]
+        /// Concatenate generated tokens without spaces
+        ///
+        /// Useful for char-level or subword models.
+        no_space_join: bool,
+
+        #[command(flatten)]
+        unknown_words: UnknownWordsArgs,
+
+        #[arg(short, long)]
+        /// Print probability of every generated token
+        /// and the order of the table which predicted it
+        verbose: bool,
+
+        #[arg(long)]
+        /// Path to the prompts history file
+        ///
+        /// Defaults to `.markov-chains-history` in the home directory.
+        history: Option<PathBuf>,
+
+        #[command(flatten)]
+        ban: BanList,
+
         #[command(flatten)]
         params: GenerationParams
-    }
-}
+    },
 
-impl CliModelCommand {
-    #[inline]
-    pub fn execute(&self) -> anyhow::Result<()> {
-        match self {
-            Self::Build { dataset, bigrams, trigrams, header, output } => {
-                println!("Reading dataset bundle...");
+    /// Generate completions of the prompt and exit
+    Generate {
+        #[arg(short, long, required = true)]
+        /// Path to the model
+        ///
+        /// Several models are mixed at generation time by their
+        /// weights given after a colon, e.g. `-m generic.bin:0.7
+        /// -m persona.bin:0.3`. Weight of a model defaults to 1.
+        model: Vec<PathBuf>,
+
+        #[arg(short, long, default_value_t = String::new())]
+        /// Prompt to continue
+        ///
+        /// If empty, the first token is sampled from the messages openers.
+        prompt: String,
+
+        #[arg(long, default_value_t = String::from(PROMPT_PLACEHOLDER))]
+        /// Template of the prompt
+        template: String,
+
+        #[arg(long, conflicts_with_all = ["prompt", "template"])]
+        /// Beginning of the text to fill in until the suffix
+        ///
+        /// If empty, the text starts with the suffix.
+        prefix: Option<String>,
+
+        #[arg(long, conflicts_with_all = ["prompt", "template"])]
+        /// End of the text connected with the prefix
+        ///
+        /// If empty, the text continues the prefix till its end.
+        /// Generated tokens are limited by `--max-len`.
+        suffix: Option<String>,
+
+        #[arg(short = 'n', long, alias = "n", default_value_t = 1)]
+        /// Amount of completions to generate
+        count: usize,
+
+        #[arg(long, num_args = 0..=1, default_missing_value = "0.8")]
+        /// Discard completions whose word pairs overlap with a previous
+        /// completion at least by this share
+        ///
+        /// Up to 10 completions per requested one are generated to find
+        /// diverse ones, so fewer completions can be printed.
+        /// Uses 0.8 if no value is given.
+        dedup_similarity: Option<f64>,
+
+        #[arg(long, conflicts_with_all = ["prefix", "suffix"])]
+        /// Find the most probable completions using beam search of this width
+        ///
+        /// Prints at most `--count` best completions prefixed by their
+        /// log-probabilities, separated by a tab. Random sampling
+        /// parameters like temperature are ignored.
+        beam_width: Option<usize>,
 
-                let messages = postcard::from_bytes::<Dataset>(&std::fs::read(dataset)?)?;
+        #[arg(long)]
+        /// Concatenate generated tokens without spaces
+        no_space_join: bool,
 
-                println!("Building model...");
+        #[command(flatten)]
+        unknown_words: UnknownWordsArgs,
 
-                let mut model = Model::build(messages, *bigrams, *trigrams);
+        #[arg(short, long, conflicts_with_all = ["beam_width", "prefix", "suffix"])]
+        /// Print probability of every generated token
+        /// and the order of the table which predicted it
+        ///
+        /// Probabilities are printed after each completion,
+        /// one token per line.
+        verbose: bool,
 
-                for header in header {
-                    if let Some((key, value)) = header.split_once('=') {
-                        model = model.with_header(key, value);
-                    }
-                }
+        #[arg(short, long)]
+        /// Path to the output file
+        ///
+        /// Completions are printed to stdout if not specified,
+        /// one per line.
+        output: Option<PathBuf>,
 
-                println!("Storing model...");
+        #[command(flatten)]
+        ban: BanList,
 
-                std::fs::write(output, postcard::to_allocvec(&model)?)?;
+        #[command(flatten)]
+        params: GenerationParams
+    },
 
-                println!("Done");
-            }
+    /// Generate many independent texts in parallel
+    ///
+    /// Every text uses its own random numbers generator, so with
+    /// the `--seed` the samples don't depend on the amount of threads.
+    Sample {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-            Self::FromScratch { messages: paths, bigrams, trigrams, header, output } => {
-                println!("Parsing messages...");
+        #[arg(short = 'n', long, default_value_t = 1000)]
+        /// Amount of texts to generate
+        count: usize,
 
-                let mut messages = Messages::default();
+        #[arg(long)]
+        /// Path to the file with prompts, one per line
+        ///
+        /// Prompts are used in turn. If not specified, the first
+        /// token of every text is sampled from the messages openers.
+        prompts: Option<PathBuf>,
 
-                for path in search_files(paths) {
-                    println!("Parsing {:?}...", path);
+        #[arg(long, default_value_t = String::from(PROMPT_PLACEHOLDER))]
+        /// Template of the prompts
+        template: String,
 
-                    let parsed = Messages::parse_from_messages(path)?;
+        #[arg(long)]
+        /// Concatenate generated tokens without spaces
+        no_space_join: bool,
 
-                    messages = messages.merge(parsed);
-                }
+        #[command(flatten)]
+        unknown_words: UnknownWordsArgs,
 
-                println!("Generating tokens...");
+        #[arg(short, long)]
+        /// Path to the output file
+        ///
+        /// Texts are printed to stdout if not specified, one per line.
+        output: Option<PathBuf>,
 
-                let tokens = Tokens::parse_from_messages(&messages);
+        #[command(flatten)]
+        ban: BanList,
 
-                println!("Tokenizing messages...");
+        #[command(flatten)]
+        params: GenerationParams
+    },
 
-                let tokenized_messages = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+    /// Serve HTTP API for text generation
+    ///
+    /// `POST /generate` accepts JSON with `prompt` and optional
+    /// `temperature`, `max_len`, `seed` and `top_k` overrides
+    /// and returns JSON with the generated `text`.
+    ///
+    /// `POST /generate/stream` accepts the same JSON and streams
+    /// server-sent events: `delta` with the next piece of the `text`
+    /// replacing `rewind` last characters of the previous ones
+    /// as soon as a token is generated, then `done` with the whole
+    /// `text` or `error`.
+    Serve {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                println!("Creating dataset...");
+        #[arg(short, long, default_value_t = String::from("127.0.0.1:8080"))]
+        /// Address to listen on
+        bind: String,
 
-                let dataset = Dataset::default()
-                    .with_messages(tokenized_messages, 1)
-                    .with_tokens(tokens);
+        #[arg(long, default_value_t = 0)]
+        /// Amount of threads handling requests
+        ///
+        /// 0 means the amount of available CPU cores.
+        threads: usize,
 
-                println!("Building model...");
+        #[arg(long, default_value_t = String::from(PROMPT_PLACEHOLDER))]
+        /// Template of the prompt
+        template: String,
 
-                let mut model = Model::build(dataset, *bigrams, *trigrams);
+        #[arg(long)]
+        /// Concatenate generated tokens without spaces
+        no_space_join: bool,
 
-                for header in header {
-                    if let Some((key, value)) = header.split_once('=') {
-                        model = model.with_header(key, value);
-                    }
-                }
+        #[command(flatten)]
+        unknown_words: UnknownWordsArgs,
 
-                println!("Storing model...");
+        #[command(flatten)]
+        ban: BanList,
 
-                std::fs::write(output, postcard::to_allocvec(&model)?)?;
+        #[command(flatten)]
+        params: GenerationParams,
 
-                println!("Done");
-            }
+        #[command(flatten)]
+        bounds: GenerationBounds
+    },
 
-            Self::Load { model, params } => {
-                println!("Reading model...");
+    /// Answer JSON-RPC requests keeping the model loaded
+    ///
+    /// Requests and responses are JSON-RPC 2.0 objects, one per line.
+    /// `generate` accepts the same params as `serve` and returns the
+    /// generated `text`, `score` accepts `text` and returns its
+    /// `log_probability`, `cross_entropy` and `perplexity`.
+    Daemon {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                let model = postcard::from_bytes::<Model>(&std::fs::read(model)?)?;
+        #[arg(short, long)]
+        /// Path to the Unix socket to listen on
+        ///
+        /// Requests are read from stdin if not specified.
+        socket: Option<PathBuf>,
 
-                println!("Starting model...");
+        #[arg(long, default_value_t = String::from(PROMPT_PLACEHOLDER))]
+        /// Template of the prompt
+        template: String,
 
-                let stdin = std::io::stdin();
-                let mut stdout = std::io::stdout();
+        #[arg(long)]
+        /// Concatenate generated tokens without spaces
+        no_space_join: bool,
 
-                let chains = (
-                    model.transitions.trigrams_len()
-                        .map(|len| len.to_string())
-                        .unwrap_or(String::from("N/A")),
+        #[command(flatten)]
+        unknown_words: UnknownWordsArgs,
 
-                    model.transitions.bigrams_len()
-                        .map(|len| len.to_string())
-                        .unwrap_or(String::from("N/A")),
+        #[command(flatten)]
+        ban: BanList,
 
-                    model.transitions.unigrams_len()
-                );
+        #[command(flatten)]
+        params: GenerationParams,
 
-                let avg_paths = (
-                    model.transitions.calc_avg_trigram_paths()
-                        .map(|avg| format!("{:.4}", avg))
-                        .unwrap_or(String::from("N/A")),
+        #[command(flatten)]
+        bounds: GenerationBounds
+    },
 
-                    model.transitions.calc_avg_bigram_paths()
-                        .map(|avg| format!("{:.4}", avg))
-                        .unwrap_or(String::from("N/A")),
+    #[cfg(feature = "bots")]
+    /// Run chat bot answering messages with generated texts
+    Bot {
+        #[command(subcommand)]
+        bot: CliBotCommand
+    },
 
-                    format!("{:.4}", model.transitions.calc_avg_unigram_paths())
-                );
+    /// Scale down transitions counts of the model
+    ///
+    /// Transitions with counts falling below 1 are removed.
+    Decay {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                let variety = (
-                    model.transitions.calc_trigram_variety()
-                        .map(|variety| format!("{:.4}%", variety * 100.0))
-                        .unwrap_or(String::from("N/A")),
+        #[arg(short, long, value_parser = decay_factor)]
+        /// Multiplier of the transitions counts, in (0.0, 1.0] range
+        factor: f64,
 
-                    model.transitions.calc_bigram_variety()
-                        .map(|variety| format!("{:.4}%", variety * 100.0))
-                        .unwrap_or(String::from("N/A")),
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    },
 
-                    format!("{:.4}%", model.transitions.calc_unigram_variety() * 100.0)
-                );
+    /// Convert the model written by an older crate version to the current format
+    ///
+    /// Fails if the model can't be decoded, in which
+    /// case it has to be rebuilt from the messages.
+    Migrate {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                let model_name = model.headers()
-                    .get("name")
-                    .map(|name| name.as_str())
-                    .unwrap_or("model");
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    },
 
-                println!();
-                println!("  Model loaded:");
-                println!();
-                println!("    Total tokens  :  {}", model.tokens.len());
-                println!("    Chains        :  {} / {} / {}", chains.0, chains.1, chains.2);
-                println!("    Avg paths     :  {} / {} / {}", avg_paths.0, avg_paths.1, avg_paths.2);
-                println!("    Variety       :  {} / {} / {}", variety.0, variety.1, variety.2);
+    /// Check that the model file is not truncated or corrupted
+    ///
+    /// Transitions and tokens are compared with the checksum stored
+    /// when the model was written. Models without checksum are only
+    /// checked to be decodable.
+    Verify {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf
+    },
 
-                if !model.headers().is_empty() {
-                    println!();
-                    println!("  Headers:");
-                    println!();
+    /// Remove rare transitions to shrink the model
+    ///
+    /// Words left without transitions are removed as well.
+    Prune {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                    let max_len = model.headers()
-                        .keys()
-                        .map(|key| key.len())
-                        .max()
-                        .unwrap_or(0);
+        #[arg(long)]
+        /// Minimal count of the kept transitions
+        min_count: u64,
 
-                    for (key, value) in model.headers() {
-                        let offset = " ".repeat(max_len - key.len());
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    },
 
-                        println!("    [{key}]{offset} : {value}");
-                    }
-                }
+    /// Remove words from the model vocabulary and transitions
+    ///
+    /// Transitions from and to the ngrams with the words are
+    /// dropped, so texts may end where the words were.
+    RemoveToken {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                println!();
+        #[arg(short, long, required = true)]
+        /// Words to remove
+        word: Vec<String>,
 
-                loop {
-                    let mut request = String::new();
+        #[arg(long)]
+        /// Also remove variants of the words differing
+        /// by case and punctuation around them
+        variants: bool,
 
-                    stdout.write_all(b"> ")?;
-                    stdout.flush()?;
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    },
 
-                    stdin.read_line(&mut request)?;
+    /// Learn transitions of new messages without rebuilding the model
+    ///
+    /// Messages are tokenized with the model's vocabulary, new words
+    /// are added to it. Messages are split by the tokenizer the model
+    /// was built with. Models with subwords can't be updated.
+    Update {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                    let request = request.split_whitespace()
-                        .filter(|word| !word.is_empty())
-                        .map(|word| word.to_lowercase())
-                        .map(|word| model.tokens.find_token(word))
-                        .collect::<Option<Vec<_>>>();
+        #[arg(long)]
+        /// Paths to the plain messages files
+        messages: Vec<PathBuf>,
 
-                    let Some(request) = request else {
-                        continue;
-                    };
+        #[arg(long, default_value_t = 1)]
+        /// Weight of the new messages' transitions
+        weight: u64,
 
-                    if request.is_empty() {
-                        continue;
-                    }
+        #[arg(long)]
+        /// Keep original case of the words
+        ///
+        /// Use if the model was built with `--preserve-case`.
+        preserve_case: bool,
 
-                    stdout.write_all(format!("\n  {model_name}: ").as_bytes())?;
-                    stdout.flush()?;
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    },
 
-                    for token in &request {
-                        stdout.write_all(model.tokens.find_word(*token).unwrap().as_bytes())?;
-                        stdout.write_all(b" ")?;
-                        stdout.flush()?;
-                    }
+    /// Evaluate language model perplexity on the plain messages files
+    Perplexity {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
 
-                    for token in model.generate(request, params) {
-                        match token {
-                            Ok(token) => {
-                                let Some(word) = model.tokens.find_word(token) else {
-                                    print!("\n\n  Failed to find word for token: {token}");
+        #[arg(long)]
+        /// Paths to the plain messages files
+        messages: Vec<PathBuf>,
 
-                                    break;
-                                };
+        #[arg(short, long)]
+        /// Path to the directory with cached per-file results
+        ///
+        /// Files which were not changed since the last evaluation
+        /// of the same model will not be evaluated again.
+        cache: Option<PathBuf>,
 
-                                stdout.write_all(word.as_bytes())?;
-                                stdout.write_all(b" ")?;
-                                stdout.flush()?;
-                            }
+        #[command(flatten)]
+        smoothing: Smoothing
+    },
 
-                            Err(err) => {
-                                print!("\n\n  Failed to generate: {err}");
+    /// Classify texts by the model which gives them the highest likelihood
+    ///
+    /// Every non-empty line of the input is a text. Prints the name
+    /// of the winning model, natural log-odds of the winner against
+    /// the runner-up and the text, separated by tabs.
+    Classify {
+        #[arg(short, long, required = true)]
+        /// Paths to the models, at least two
+        ///
+        /// Models are named by their file names.
+        model: Vec<PathBuf>,
+
+        #[arg(short, long, default_value = "-")]
+        /// Path to the file with texts, `-` for stdin
+        input: PathBuf,
+
+        #[arg(long, default_value_t = -10.0, allow_hyphen_values = true)]
+        /// Natural log-probability of every unknown word
+        /// and unseen transition of the text
+        unseen_log_probability: f64,
 
-                                break;
-                            }
-                        }
-                    }
+        #[arg(short, long)]
+        /// Print log-likelihood of the text under every model
+        verbose: bool,
 
-                    stdout.write_all(b"\n\n")?;
+        #[command(flatten)]
+        smoothing: Smoothing
+    },
+
+    /// Identify language of the texts using per-language models
+    ///
+    /// Every file of the models directory is a model of the language
+    /// named by the file name, e.g. `en.bin`. Prints the language
+    /// of every non-empty line of the input, the probability of it
+    /// among the known languages and the text, separated by tabs.
+    DetectLanguage {
+        #[arg(short, long)]
+        /// Path to the directory with language models
+        models: PathBuf,
+
+        #[arg(short, long, default_value = "-")]
+        /// Path to the file with texts, `-` for stdin
+        input: PathBuf,
+
+        #[arg(long, default_value_t = -10.0, allow_hyphen_values = true)]
+        /// Natural log-probability of every unknown word
+        /// and unseen transition of the text
+        unseen_log_probability: f64,
+
+        #[arg(short, long)]
+        /// Print log-likelihood of the text under every model
+        verbose: bool,
+
+        #[command(flatten)]
+        smoothing: Smoothing
+    },
+
+    /// Convert model to JSON and back
+    ///
+    /// Models of older formats should be migrated first.
+    Convert {
+        #[arg(short, long)]
+        /// Path to the model or JSON file
+        model: PathBuf,
+
+        #[arg(long, value_enum)]
+        /// Format of the output
+        to: ConvertFormat,
+
+        #[arg(short, long)]
+        /// Path to the converted output
+        output: PathBuf
+    },
+
+    /// Export model as an ARPA language model
+    ///
+    /// Transitions tables of the order `n` are written as `n + 1`-grams
+    /// with log10 probabilities and backoff weights.
+    ExportArpa {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(short, long)]
+        /// Path to the ARPA file
+        output: PathBuf
+    },
+
+    /// Print the most frequent continuations of the words
+    /// in every transitions table which has them
+    Inspect {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(short, long)]
+        /// Words to continue, separated by spaces
+        ///
+        /// Tables of the orders up to the amount of words are used.
+        word: String,
+
+        #[arg(long, default_value_t = 10)]
+        /// Amount of the continuations to print from every table
+        top: usize
+    },
+
+    /// Suggest the most probable next words of the prefix
+    ///
+    /// Prints every word with its probability, separated by a tab,
+    /// the most probable first. Empty prefix suggests message openers.
+    Suggest {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(short, long, default_value = "")]
+        /// Words to continue, separated by spaces
+        prefix: String,
+
+        #[arg(long, default_value_t = 5)]
+        /// Amount of words to suggest, 0 means no limit
+        top: usize,
+
+        #[command(flatten)]
+        smoothing: Smoothing
+    },
+
+    /// Export subgraph of the unigram transitions around the word
+    ExportGraph {
+        #[arg(short, long)]
+        /// Path to the model
+        model: PathBuf,
+
+        #[arg(short, long)]
+        /// Word to start the graph from
+        word: String,
+
+        #[arg(long, default_value_t = 2)]
+        /// Maximal amount of transitions from the word
+        depth: usize,
+
+        #[arg(long, default_value_t = 10)]
+        /// Amount of the most frequent continuations to follow
+        /// from every word, 0 means no limit
+        top: usize,
+
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        /// Format of the graph
+        format: GraphFormat,
+
+        #[arg(short, long)]
+        /// Path to the graph output
+        output: PathBuf
+    },
+
+    /// Import chain model made by another tool
+    ///
+    /// Order of the model is the state size of the chain.
+    Import {
+        /// Path to the JSON chain
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ChainFormat::Markovify)]
+        /// Format of the chain
+        format: ChainFormat,
+
+        #[arg(short, long)]
+        /// Path to the model output
+        output: PathBuf
+    }
+}
+
+#[inline]
+/// Random numbers generator seeded by the given seed or the entropy
+fn seeded_rng(seed: Option<u64>) -> ChaCha8Rng {
+    match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy()
+    }
+}
+
+/// Default path to the REPL prompts history file in the home directory
+fn default_history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".markov-chains-history"))
+}
+
+/// Escape control characters of the word so it can't break the terminal
+pub(super) fn printable_word(word: &str) -> Cow<'_, str> {
+    if !word.chars().any(char::is_control) {
+        return Cow::Borrowed(word);
+    }
+
+    let word = word.chars()
+        .map(|char| {
+            if char.is_control() {
+                char.escape_default().to_string()
+            } else {
+                char.to_string()
+            }
+        })
+        .collect::<String>();
+
+    Cow::Owned(word)
+}
+
+/// Convert the text to tokens of the model using its tokenizer
+///
+/// Unknown words are handled according to `unknown` with a warning
+/// printed to stderr. Returns `None` if the words are rejected.
+fn text_tokens(model: &Model, text: &str, unknown: UnknownWordsArgs) -> Option<Vec<u64>> {
+    let words = model.tokenizer().tokenize(text);
+
+    let subwords = model.has_subwords();
+
+    let mut tokens = Vec::with_capacity(words.len());
+
+    for word in words {
+        if let Some(token) = model.tokens.find_token_ignore_case(&word) {
+            tokens.push(token);
+
+            continue;
+        }
+
+        match unknown.unknown_words {
+            UnknownWords::Reject => {
+                eprintln!("Warning: unknown word {word:?}, prompt is ignored");
+
+                return None;
+            }
+
+            UnknownWords::Skip => eprintln!("Warning: unknown word {word:?} is skipped"),
+
+            UnknownWords::Unk => match model.tokens.find_token(UNK_TOKEN_NAME) {
+                Some(token) => {
+                    eprintln!("Warning: unknown word {word:?} is replaced by {UNK_TOKEN_NAME}");
+
+                    tokens.push(token);
+                }
+
+                None => eprintln!("Warning: unknown word {word:?} is skipped, model has no {UNK_TOKEN_NAME} word")
+            }
+
+            UnknownWords::Closest => {
+                let closest = if subwords {
+                    None
+                } else {
+                    model.tokens.find_similar_word(&word, unknown.max_typo_distance)
+                };
+
+                match closest {
+                    Some((closest, token)) => {
+                        eprintln!("Warning: unknown word {word:?} is replaced by {closest:?}");
+
+                        tokens.push(token);
+                    }
+
+                    None => eprintln!("Warning: unknown word {word:?} has no close variants and is skipped")
+                }
+            }
+        }
+    }
+
+    Some(tokens)
+}
+
+/// Convert the prompt to tokens using the template
+///
+/// Returns `None` if the prompt has rejected unknown words.
+pub(super) fn prompt_tokens(model: &Model, template: &PromptTemplate, prompt: &str, unknown: UnknownWordsArgs, rng: &mut impl RngCore) -> Option<Vec<u64>> {
+    let (prefix, _) = template.parts(prompt);
+
+    let mut request = text_tokens(model, &template.apply(prompt), unknown)?;
+
+    // Start with a random opener if the prompt is empty but the template
+    // has words after it, otherwise let the generator sample the opener
+    if !request.is_empty() && model.tokenizer().tokenize(&prefix).is_empty() {
+        request.insert(0, model.sample_start_token_with_rng(rng)?);
+    }
+
+    Some(request)
+}
+
+/// Convert the prompt to tokens using the template
+/// without sampling an opener for the empty prompt
+///
+/// Returns `None` if the prompt has rejected unknown words.
+fn template_tokens(model: &Model, template: &PromptTemplate, prompt: &str, unknown: UnknownWordsArgs) -> Option<Vec<u64>> {
+    text_tokens(model, &template.apply(prompt), unknown)
+}
+
+/// Get tokenizer of the built model and its punctuation characters
+///
+/// Tokenizer defaults to the one enabled by the punctuation
+/// or subwords flags, punctuation characters default to
+/// the standard ones for the punctuation tokenizer.
+fn resolve_tokenizer(tokenizer: Option<TokenizerKind>, punctuation: &Option<String>, subwords: bool) -> anyhow::Result<(TokenizerKind, Option<String>)> {
+    let tokenizer = tokenizer.unwrap_or(if subwords {
+        TokenizerKind::Bpe
+    } else if punctuation.is_some() {
+        TokenizerKind::Punctuation
+    } else {
+        TokenizerKind::Whitespace
+    });
+
+    match tokenizer {
+        TokenizerKind::Whitespace if subwords || punctuation.is_some() => {
+            anyhow::bail!("Whitespace tokenizer can't split punctuation or subwords")
+        }
+
+        TokenizerKind::Chars if subwords || punctuation.is_some() => {
+            anyhow::bail!("Chars tokenizer can't split punctuation or subwords")
+        }
+
+        TokenizerKind::Punctuation if subwords => {
+            anyhow::bail!("Punctuation tokenizer can't split subwords, use the bpe one")
+        }
+
+        TokenizerKind::Punctuation if punctuation.is_none() => {
+            Ok((tokenizer, Some(String::from(DEFAULT_PUNCTUATION))))
+        }
+
+        _ => Ok((tokenizer, punctuation.clone()))
+    }
+}
+
+/// Store tokenizer of the model in its headers
+fn with_tokenizer_headers(mut model: Model, tokenizer: TokenizerKind, punctuation: Option<String>) -> Model {
+    if let Some(chars) = punctuation {
+        model = model.with_header(PUNCTUATION_HEADER, chars);
+    }
+
+    // Kept for the older versions which don't know the tokenizer header
+    if tokenizer == TokenizerKind::Bpe {
+        model = model.with_header(SUBWORDS_HEADER, SUBWORD_MARKER);
+    }
+
+    model.with_header(TOKENIZER_HEADER, tokenizer.name())
+}
+
+/// Join words of the model into the text
+pub(super) fn join_words(model: &Model, words: Vec<Cow<'_, str>>, separator: &str) -> String {
+    if separator == " " || model.tokenizer_kind() == TokenizerKind::Chars {
+        model.join_words(&words)
+    } else if model.has_subwords() {
+        join_subwords(&words).join(separator)
+    } else {
+        words.join(separator)
+    }
+}
+
+/// Generate printable text continuing the request
+///
+/// Returns the text including the request and the error
+/// which interrupted the generation. Probabilities of the
+/// generated tokens are stored if the vector is given.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn generate_text<R: RngCore>(
+    model: &Model,
+    request: Vec<u64>,
+    params: &GenerationParams,
+    rng: R,
+    cache: &mut CandidateCache,
+    banned: &HashSet<u64>,
+    separator: &str,
+    mut probabilities: Option<&mut Vec<TokenProbability>>
+) -> (String, Option<String>) {
+    let mut words = Vec::new();
+    let mut error = None;
+
+    // Replies don't include the request
+    if !params.dialogue {
+        for token in &request {
+            words.push(printable_word(model.tokens.find_word(*token).unwrap()));
+        }
+    }
+
+    let generator = model.generate_with_rng(request, params, rng)
+        .with_cache(cache)
+        .with_banned(banned);
+
+    // Probabilities are calculated only when needed since
+    // smoothed ones require the model statistics
+    type Tokens<'a> = Box<dyn Iterator<Item = Result<(u64, Option<TokenProbability>), Error>> + 'a>;
+
+    let tokens: Tokens = if probabilities.is_some() {
+        Box::new(generator.with_probabilities().map(|step| step.map(|step| (step.token, Some(step)))))
+    } else {
+        Box::new(generator.map(|token| token.map(|token| (token, None))))
+    };
+
+    for token in tokens {
+        match token {
+            Ok((token, probability)) => {
+                if let (Some(probabilities), Some(probability)) = (probabilities.as_deref_mut(), probability) {
+                    probabilities.push(probability);
+                }
+
+                let Some(word) = model.tokens.find_word(token) else {
+                    error = Some(format!("Failed to find word for token: {token}"));
+
+                    break;
+                };
+
+                words.push(printable_word(word));
+            }
+
+            Err(err) => {
+                error = Some(format!("Failed to generate: {err}"));
+
+                break;
+            }
+        }
+    }
+
+    (join_words(model, words, separator), error)
+}
+
+/// Format probabilities of the generated tokens, one token per line
+fn format_probabilities(model: &Model, probabilities: &[TokenProbability]) -> String {
+    let words = probabilities.iter()
+        .map(|step| printable_word(model.tokens.find_word(step.token).unwrap_or("?")))
+        .collect::<Vec<_>>();
+
+    let max_len = words.iter()
+        .map(|word| word.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut output = String::new();
+
+    for (word, step) in words.iter().zip(probabilities) {
+        let offset = " ".repeat(max_len - word.chars().count());
+
+        let order = match step.order {
+            0 => String::from("unseen"),
+            order => format!("order {order}")
+        };
+
+        output.push_str(&format!("    {word}{offset}  {:.4}  {order}\n", step.probability));
+    }
+
+    output
+}
+
+/// Split the model path and its weight given after a colon
+///
+/// Paths of the existing files are not split.
+fn weighted_model(path: &Path) -> (PathBuf, f64) {
+    if !path.exists() {
+        if let Some((model, weight)) = path.to_string_lossy().rsplit_once(':') {
+            if let Ok(weight) = weight.parse::<f64>() {
+                return (PathBuf::from(model), weight);
+            }
+        }
+    }
+
+    (path.to_path_buf(), 1.0)
+}
+
+/// Maximal amount of generated completions per requested one
+/// when the near-duplicates are discarded
+const DEDUP_ATTEMPTS: usize = 10;
+
+/// Check if the completion is not similar to the previous ones
+///
+/// Completions are always kept if the near-duplicates are not discarded.
+fn is_diverse(near_duplicates: &mut Option<NearDuplicates>, completion: &str) -> bool {
+    let Some(near_duplicates) = near_duplicates else {
+        return true;
+    };
+
+    near_duplicates.insert(&completion.split_whitespace().collect::<Vec<_>>())
+}
+
+/// Write completions to the file or to stdout, one per line
+fn write_completions(output: Option<&Path>, completions: &[String]) -> anyhow::Result<()> {
+    match output {
+        Some(output) => {
+            let mut file = std::fs::File::create(output)?;
+
+            for completion in completions {
+                writeln!(file, "{completion}")?;
+            }
+        }
+
+        None => {
+            let mut stdout = std::io::stdout().lock();
+
+            for completion in completions {
+                writeln!(stdout, "{completion}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Classify every non-empty line of the input file or stdin
+///
+/// Prints the winning label, the score formatted by `score`
+/// and the text, separated by tabs.
+fn classify_texts(classifier: &Classifier, input: &Path, verbose: bool, score: impl Fn(&Classification) -> String) -> anyhow::Result<()> {
+    let input = if input.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(input)?
+    };
+
+    let mut stdout = std::io::stdout().lock();
+
+    for text in input.lines().filter(|line| !line.trim().is_empty()) {
+        let words = Messages::parse_from_lines(&[text.to_string()])
+            .messages()
+            .first()
+            .cloned()
+            .unwrap_or_default();
+
+        let Some(result) = classifier.classify(&words) else {
+            continue;
+        };
+
+        writeln!(stdout, "{}\t{}\t{text}", result.label, score(&result))?;
+
+        if verbose {
+            for (name, score) in result.scores {
+                writeln!(stdout, "  {name}: {score:.4}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate printable text connecting the prefix with the suffix
+///
+/// Returns `None` if the suffix can't be reached from the prefix.
+fn infill_text(model: &Model, prefix: &[u64], suffix: &[u64], params: &GenerationParams, rng: &mut impl RngCore, separator: &str) -> Result<Option<String>, Error> {
+    let Some(middle) = model.infill_with_rng(prefix, suffix, params, rng)? else {
+        return Ok(None);
+    };
+
+    let words = prefix.iter()
+        .chain(&middle)
+        .chain(suffix)
+        .filter_map(|token| model.tokens.find_word(*token))
+        .map(printable_word)
+        .collect();
+
+    Ok(Some(join_words(model, words, separator)))
+}
+
+/// Convert the model to JSON or the JSON file back to the model
+///
+/// Models are read with `Model::load` so legacy and corrupted models
+/// are handled as by the other commands. Checksum of the converted
+/// model is recalculated since its JSON could be edited by hand.
+fn convert_model(path: &Path, to: ConvertFormat, output: &Path, compression_level: i32) -> anyhow::Result<()> {
+    match to {
+        ConvertFormat::Json => {
+            println!("Reading model...");
+
+            let model = Model::load(path)?;
+
+            println!("Storing JSON...");
+
+            std::fs::write(output, bundle::to_json(&model)?)?;
+        }
+
+        ConvertFormat::Bundle => {
+            println!("Reading JSON...");
+
+            let model = bundle::from_json::<Model>(&std::fs::read(path)?)?;
+
+            if model.transitions().unigrams_len() == 0 {
+                return Err(Error::EmptyModel.into());
+            }
+
+            println!("Storing model...");
+
+            ModelFormat::Bundle.write(output, model, compression_level)?;
+        }
+    }
+
+    println!("Done");
+
+    Ok(())
+}
+
+/// Parse decay factor from the (0.0, 1.0] range
+///
+/// Factor 0 would remove all the transitions.
+fn decay_factor(value: &str) -> Result<f64, String> {
+    let factor = value.parse::<f64>()
+        .map_err(|err| err.to_string())?;
+
+    if factor.is_nan() || factor <= 0.0 || factor > 1.0 {
+        return Err(String::from("Decay factor must be in (0.0, 1.0] range"));
+    }
+
+    Ok(factor)
+}
+
+/// Hash file's path, size and modification time
+fn fingerprint_file(path: &Path, hasher: &mut blake3::Hasher) -> anyhow::Result<()> {
+    let metadata = path.metadata()?;
+    let path = path.canonicalize()?;
+
+    let modified = metadata.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    // Path length separates it from the following bytes
+    hasher.update(&(path.as_os_str().len() as u64).to_le_bytes());
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(&metadata.len().to_le_bytes());
+    hasher.update(&modified.as_nanos().to_le_bytes());
+
+    Ok(())
+}
+
+impl CliModelCommand {
+    #[inline]
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
+        match self {
+            Self::Build { dataset, bigrams, trigrams, order, punctuation, subwords, tokenizer, deterministic, header, format, output } => {
+                if order.is_some_and(|order| !(1..=MAX_ORDER).contains(&order)) {
+                    anyhow::bail!("Order must be in [1, {MAX_ORDER}] range");
+                }
+
+                let (tokenizer, punctuation) = resolve_tokenizer(*tokenizer, punctuation, *subwords)?;
+
+                println!("Reading dataset bundle...");
+
+                let messages = bundle::read::<Dataset>(dataset)?;
+
+                if messages.is_empty() {
+                    return Err(Error::EmptyDataset.into());
+                }
+
+                if *deterministic && !messages.tokens.is_hashed() {
+                    anyhow::bail!("Dataset tokens are random, parse them with --deterministic to get reproducible models");
+                }
+
+                println!("Building model...");
+
+                let mut model = progress::spin(|| match order {
+                    Some(order) => Model::build_with_order(messages, *order),
+                    None => Model::build(messages, *bigrams, *trigrams)
+                });
+
+                model = with_tokenizer_headers(model, tokenizer, punctuation);
+
+                for header in header {
+                    if let Some((key, value)) = header.split_once('=') {
+                        model = model.with_header(key, value);
+                    }
+                }
+
+                println!("Storing model...");
+
+                format.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::FromScratch { messages: paths, manifest, bigrams, trigrams, order, streaming, dedup, split_punctuation, bpe_vocab_size, tokenizer, deterministic, preserve_case, header, format, output } => {
+                if order.is_some_and(|order| !(1..=MAX_ORDER).contains(&order)) {
+                    anyhow::bail!("Order must be in [1, {MAX_ORDER}] range");
+                }
+
+                let (tokenizer, split_punctuation) = resolve_tokenizer(*tokenizer, split_punctuation, bpe_vocab_size.is_some())?;
+
+                if tokenizer == TokenizerKind::Bpe && bpe_vocab_size.is_none() {
+                    anyhow::bail!("Subwords tokenizer requires --bpe-vocab-size");
+                }
+
+                if tokenizer == TokenizerKind::Chars && *streaming {
+                    anyhow::bail!("Streaming builder doesn't support the chars tokenizer");
+                }
+
+                let paths = search_files(paths, exclude)?;
+
+                let mut model = if *streaming {
+                    let mut builder = match order {
+                        Some(order) => StreamingBuilder::with_order(*order),
+                        None => StreamingBuilder::new(*bigrams, *trigrams)
+                    };
+
+                    builder = builder.with_dedup(*dedup)
+                        .with_hashed_tokens(*deterministic)
+                        .with_preserve_case(*preserve_case)
+                        .with_punctuation(split_punctuation.as_ref().map(Punctuation::new));
+
+                    println!("Building model...");
+
+                    let size = paths.iter()
+                        .filter_map(|path| path.metadata().ok())
+                        .map(|metadata| metadata.len())
+                        .sum();
+
+                    let progress = progress::bytes(size);
+
+                    for path in &paths {
+                        progress.suspend(|| println!("Reading {:?}...", path));
+
+                        let file = std::fs::File::open(path)?;
+
+                        builder.push_reader(std::io::BufReader::new(progress.wrap_read(file)))?;
+
+                        progress.set_message(format!("{} messages", builder.messages()));
+                    }
+
+                    progress.finish_and_clear();
+
+                    if builder.messages() == 0 {
+                        return Err(Error::EmptyDataset.into());
+                    }
+
+                    progress::spin(|| builder.build())
+                } else {
+                    println!("Parsing messages...");
+
+                    let mut messages = Messages::default();
+
+                    for path in progress::files(&paths, "Parsing") {
+                        let parsed = Messages::parse_from_messages_with_filter(path, |word| {
+                            if *preserve_case {
+                                word.to_string()
+                            } else {
+                                word.to_lowercase()
+                            }
+                        })?;
+
+                        messages = messages.merge(parsed);
+                    }
+
+                    if let Some(chars) = &split_punctuation {
+                        messages = messages.split_punctuation(&Punctuation::new(chars));
+                    }
+
+                    if tokenizer == TokenizerKind::Chars {
+                        messages = messages.tokenize(&CharTokenizer);
+                    }
+
+                    if *dedup {
+                        messages = messages.dedup();
+                    }
+
+                    if messages.messages().is_empty() {
+                        return Err(Error::EmptyDataset.into());
+                    }
+
+                    if let Some(vocab_size) = bpe_vocab_size {
+                        println!("Training subwords tokenizer...");
+
+                        messages = progress::spin(|| Bpe::train(&messages, *vocab_size).encode(&messages));
+                    }
+
+                    println!("Generating tokens...");
+
+                    let tokens = progress::spin(|| if *deterministic {
+                        Tokens::parse_from_messages_hashed(&messages)
+                    } else {
+                        Tokens::parse_from_messages(&messages)
+                    });
+
+                    println!("Tokenizing messages...");
+
+                    let tokenized_messages = progress::spin(|| TokenizedMessages::tokenize_message(&messages, &tokens))?;
+
+                    println!("Creating dataset...");
+
+                    let dataset = Dataset::default()
+                        .with_messages(tokenized_messages, 1)
+                        .with_tokens(tokens);
+
+                    println!("Building model...");
+
+                    progress::spin(|| match order {
+                        Some(order) => Model::build_with_order(dataset, *order),
+                        None => Model::build(dataset, *bigrams, *trigrams)
+                    })
+                };
+
+                if let Some(manifest) = manifest {
+                    println!("Storing manifest...");
+
+                    write_manifest(manifest, &paths)?;
+                }
+
+                model = with_tokenizer_headers(model, tokenizer, split_punctuation);
+
+                for header in header {
+                    if let Some((key, value)) = header.split_once('=') {
+                        model = model.with_header(key, value);
+                    }
+                }
+
+                println!("Storing model...");
+
+                format.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Load { model, template, no_space_join, unknown_words, verbose, history, ban, params } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                let banned = ban.tokens(&model)?;
+
+                println!("Starting model...");
+
+                let mut stdout = std::io::stdout();
+
+                let chains = (
+                    model.transitions.trigrams_len()
+                        .map(|len| len.to_string())
+                        .unwrap_or(String::from("N/A")),
+
+                    model.transitions.bigrams_len()
+                        .map(|len| len.to_string())
+                        .unwrap_or(String::from("N/A")),
+
+                    model.transitions.unigrams_len()
+                );
+
+                let avg_paths = (
+                    model.transitions.calc_avg_trigram_paths()
+                        .map(|avg| format!("{:.4}", avg))
+                        .unwrap_or(String::from("N/A")),
+
+                    model.transitions.calc_avg_bigram_paths()
+                        .map(|avg| format!("{:.4}", avg))
+                        .unwrap_or(String::from("N/A")),
+
+                    format!("{:.4}", model.transitions.calc_avg_unigram_paths())
+                );
+
+                let variety = (
+                    model.transitions.calc_trigram_variety()
+                        .map(|variety| format!("{:.4}%", variety * 100.0))
+                        .unwrap_or(String::from("N/A")),
+
+                    model.transitions.calc_bigram_variety()
+                        .map(|variety| format!("{:.4}%", variety * 100.0))
+                        .unwrap_or(String::from("N/A")),
+
+                    format!("{:.4}%", model.transitions.calc_unigram_variety() * 100.0)
+                );
+
+                let model_name = model.headers()
+                    .get("name")
+                    .map(|name| name.as_str())
+                    .unwrap_or("model");
+
+                println!();
+                println!("  Model loaded:");
+                println!();
+                println!("    Total tokens  :  {}", model.tokens.len());
+                println!("    Order         :  {}", model.transitions.order());
+                println!("    Chains        :  {} / {} / {}", chains.0, chains.1, chains.2);
+                println!("    Avg paths     :  {} / {} / {}", avg_paths.0, avg_paths.1, avg_paths.2);
+                println!("    Variety       :  {} / {} / {}", variety.0, variety.1, variety.2);
+
+                if !model.headers().is_empty() {
+                    println!();
+                    println!("  Headers:");
+                    println!();
+
+                    let max_len = model.headers()
+                        .keys()
+                        .map(|key| key.len())
+                        .max()
+                        .unwrap_or(0);
+
+                    for (key, value) in model.headers() {
+                        let offset = " ".repeat(max_len - key.len());
+
+                        println!("    [{key}]{offset} : {value}");
+                    }
+                }
+
+                println!();
+
+                let template = PromptTemplate::new(template);
+
+                let separator = if *no_space_join { "" } else { " " };
+
+                let mut cache = CandidateCache::default();
+
+                let mut current_params = *params;
+                let mut rng = seeded_rng(params.seed);
+
+                let history = history.clone().or_else(default_history_path);
+
+                let mut editor = DefaultEditor::new()?;
+
+                // History file doesn't exist on the first run
+                if let Some(history) = &history {
+                    let _ = editor.load_history(history);
+                }
+
+                println!("  Enter /help to see the list of commands, /exit or Ctrl+C to quit");
+                println!();
+
+                loop {
+                    let request = match editor.readline("> ") {
+                        Ok(request) => request,
+
+                        // Stop on Ctrl+C and the end of input
+                        Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+
+                        Err(err) => return Err(err.into())
+                    };
+
+                    let request = request.trim();
+
+                    if !request.is_empty() {
+                        editor.add_history_entry(request)?;
+                    }
+
+                    if let Some(command) = ReplCommand::parse(request) {
+                        match command {
+                            ReplCommand::Exit => break,
+
+                            ReplCommand::Help => println!("\n{REPL_HELP}\n"),
+                            ReplCommand::Params => println!("\n{}\n", format_params(&current_params)),
+
+                            ReplCommand::Reset => {
+                                current_params = *params;
+
+                                rng = seeded_rng(current_params.seed);
+
+                                println!("\n  Generation params were reset\n");
+                            }
+
+                            ReplCommand::Set { name, value: None } => {
+                                println!("\n  /{name} expects a value, enter /help to see the list of commands\n");
+                            }
+
+                            ReplCommand::Set { name, value: Some(value) } => {
+                                match set_param(&mut current_params, &name, &value) {
+                                    Ok(()) => {
+                                        // Same seed must generate the same text again
+                                        if name == "seed" {
+                                            rng = seeded_rng(current_params.seed);
+                                        }
+
+                                        println!("\n  {name} = {value}\n");
+                                    }
+
+                                    Err(err) => println!("\n  {err}\n")
+                                }
+                            }
+                        }
+
+                        continue;
+                    }
+
+                    let Some(request) = prompt_tokens(&model, &template, request, *unknown_words, &mut rng) else {
+                        continue;
+                    };
+
+                    let mut probabilities = Vec::new();
+
+                    let (text, error) = generate_text(&model, request, &current_params, &mut rng, &mut cache, &banned, separator, verbose.then_some(&mut probabilities));
+
+                    // Print the whole message at once
+                    let mut output = format!("\n  {model_name}: {text}");
+
+                    if *verbose {
+                        output.push_str("\n\n");
+                        output.push_str(format_probabilities(&model, &probabilities).trim_end());
+                    }
+
+                    if let Some(error) = error {
+                        output.push_str(&format!("\n\n  {error}"));
+                    }
+
+                    output.push_str("\n\n");
+
+                    stdout.write_all(output.as_bytes())?;
                     stdout.flush()?;
                 }
+
+                if let Some(history) = &history {
+                    editor.save_history(history)?;
+                }
+            }
+
+            Self::Generate { model, prompt, template, prefix, suffix, count, dedup_similarity, beam_width, no_space_join, unknown_words, verbose, output, ban, params } => {
+                if dedup_similarity.is_some_and(|similarity| similarity <= 0.0 || similarity > 1.0) {
+                    anyhow::bail!("Similarity must be in (0.0, 1.0] range");
+                }
+
+                let mut near_duplicates = dedup_similarity.map(NearDuplicates::new);
+
+                let attempts = if near_duplicates.is_some() {
+                    count * DEDUP_ATTEMPTS
+                } else {
+                    *count
+                };
+
+                if model.len() > 1 {
+                    if beam_width.is_some() || prefix.is_some() || suffix.is_some() || *verbose || !ban.is_empty() || !params.smoothing.is_none() || params.dialogue {
+                        anyhow::bail!("Beam search, infill, verbose output, banned words, smoothing and dialogue are not supported by models ensembles");
+                    }
+
+                    if params.no_repeat_ngram > 0 || params.context_window > 0 {
+                        anyhow::bail!("Repeated n-grams blocking and context window reranking are not supported by models ensembles");
+                    }
+
+                    let mut models = Vec::with_capacity(model.len());
+
+                    for path in model {
+                        let (path, weight) = weighted_model(path);
+
+                        if MappedModel::is_mapped(&path)? {
+                            anyhow::bail!("Mapped models can't be mixed: {path:?}");
+                        }
+
+                        models.push((Model::load(path)?, weight));
+                    }
+
+                    let ensemble = ModelEnsemble::new(models)?;
+
+                    let template = PromptTemplate::new(template);
+
+                    let separator = if *no_space_join { "" } else { " " };
+
+                    let Some(request) = template_tokens(&ensemble.meta, &template, prompt, *unknown_words) else {
+                        anyhow::bail!("Prompt has words unknown to the models");
+                    };
+
+                    let mut rng = seeded_rng(params.seed);
+
+                    let completions = (0..attempts)
+                        .map(|_| {
+                            let mut generator = ensemble.generate_with_rng(request.clone(), params, &mut rng);
+
+                            generator.by_ref().for_each(drop);
+
+                            let words = EnsembleGenerator::chain(&generator)
+                                .iter()
+                                .filter_map(|token| ensemble.tokens().find_word(*token))
+                                .map(printable_word)
+                                .collect();
+
+                            join_words(&ensemble.meta, words, separator)
+                        })
+                        .filter(|completion| is_diverse(&mut near_duplicates, completion))
+                        .take(*count)
+                        .collect::<Vec<_>>();
+
+                    return write_completions(output.as_deref(), &completions);
+                }
+
+                let model = &weighted_model(&model[0]).0;
+
+                if MappedModel::is_mapped(model)? {
+                    if beam_width.is_some() || prefix.is_some() || suffix.is_some() || *verbose || !ban.is_empty() || !params.smoothing.is_none() || params.dialogue {
+                        anyhow::bail!("Beam search, infill, verbose output, banned words, smoothing and dialogue are not supported by mapped models");
+                    }
+
+                    if params.no_repeat_ngram > 0 || params.context_window > 0 {
+                        anyhow::bail!("Repeated n-grams blocking and context window reranking are not supported by mapped models");
+                    }
+
+                    let model = MappedModel::open(model)?;
+
+                    let template = PromptTemplate::new(template);
+
+                    let separator = if *no_space_join { "" } else { " " };
+
+                    let Some(request) = template_tokens(&model.meta, &template, prompt, *unknown_words) else {
+                        anyhow::bail!("Prompt has words unknown to the model");
+                    };
+
+                    let mut rng = seeded_rng(params.seed);
+
+                    let completions = (0..attempts)
+                        .map(|_| {
+                            let mut generator = model.generate_with_rng(request.clone(), params, &mut rng);
+
+                            generator.by_ref().for_each(drop);
+
+                            let words = MappedGenerator::chain(&generator)
+                                .iter()
+                                .filter_map(|token| model.tokens().find_word(*token))
+                                .map(printable_word)
+                                .collect();
+
+                            join_words(&model.meta, words, separator)
+                        })
+                        .filter(|completion| is_diverse(&mut near_duplicates, completion))
+                        .take(*count)
+                        .collect::<Vec<_>>();
+
+                    return write_completions(output.as_deref(), &completions);
+                }
+
+                let model = Model::load(model)?;
+
+                let banned = ban.tokens(&model)?;
+
+                if !banned.is_empty() && (beam_width.is_some() || prefix.is_some() || suffix.is_some()) {
+                    anyhow::bail!("Banned words are not supported by beam search and infill");
+                }
+
+                if params.dialogue && (beam_width.is_some() || prefix.is_some() || suffix.is_some()) {
+                    anyhow::bail!("Dialogue is not supported by beam search and infill");
+                }
+
+                let template = PromptTemplate::new(template);
+
+                let separator = if *no_space_join { "" } else { " " };
+
+                let mut cache = CandidateCache::default();
+
+                let mut rng = seeded_rng(params.seed);
+
+                let mut completions = Vec::with_capacity(*count);
+
+                let infill = if prefix.is_some() || suffix.is_some() {
+                    let words = |text: &Option<String>| {
+                        text_tokens(&model, text.as_deref().unwrap_or_default(), *unknown_words)
+                    };
+
+                    let (Some(prefix), Some(suffix)) = (words(prefix), words(suffix)) else {
+                        anyhow::bail!("Prefix or suffix has words unknown to the model");
+                    };
+
+                    Some((prefix, suffix))
+                } else {
+                    None
+                };
+
+                if let Some(width) = beam_width {
+                    let Some(request) = template_tokens(&model, &template, prompt, *unknown_words) else {
+                        anyhow::bail!("Prompt has words unknown to the model");
+                    };
+
+                    for completion in model.beam_search(request, *width, params) {
+                        if completions.len() == *count {
+                            break;
+                        }
+
+                        let words = completion.chain.iter()
+                            .filter_map(|token| model.tokens.find_word(*token))
+                            .map(printable_word)
+                            .collect();
+
+                        let text = join_words(&model, words, separator);
+
+                        if is_diverse(&mut near_duplicates, &text) {
+                            completions.push(format!("{:.4}\t{text}", completion.score));
+                        }
+                    }
+                } else {
+                    for _ in 0..attempts {
+                        if completions.len() == *count {
+                            break;
+                        }
+
+                        if let Some((prefix, suffix)) = &infill {
+                            let text = match infill_text(&model, prefix, suffix, params, &mut rng, separator) {
+                                Ok(Some(text)) => text,
+                                Ok(None) => anyhow::bail!("Suffix can't be reached from the prefix"),
+                                Err(err) => anyhow::bail!("{err}, suffix can't be reached from the prefix within {} tokens", params.max_len)
+                            };
+
+                            if is_diverse(&mut near_duplicates, &text) {
+                                completions.push(text);
+                            }
+
+                            continue;
+                        }
+
+                        let Some(request) = prompt_tokens(&model, &template, prompt, *unknown_words, &mut rng) else {
+                            anyhow::bail!("Prompt has words unknown to the model");
+                        };
+
+                        let mut probabilities = Vec::new();
+
+                        let (mut text, error) = generate_text(&model, request, params, &mut rng, &mut cache, &banned, separator, verbose.then_some(&mut probabilities));
+
+                        if let Some(error) = error {
+                            anyhow::bail!(error);
+                        }
+
+                        if !is_diverse(&mut near_duplicates, &text) {
+                            continue;
+                        }
+
+                        if *verbose {
+                            text.push('\n');
+                            text.push_str(format_probabilities(&model, &probabilities).trim_end());
+                        }
+
+                        completions.push(text);
+                    }
+                }
+
+                write_completions(output.as_deref(), &completions)?;
+            }
+
+            Self::Sample { model, count, prompts, template, no_space_join, unknown_words, output, ban, params } => {
+                if MappedModel::is_mapped(model)? {
+                    anyhow::bail!("Sampling is not supported by mapped models");
+                }
+
+                let model = Model::load(model)?;
+
+                let banned = ban.tokens(&model)?;
+
+                let template = PromptTemplate::new(template);
+
+                let separator = if *no_space_join { "" } else { " " };
+
+                let prompts = match prompts {
+                    Some(path) => std::fs::read_to_string(path)?
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(String::from)
+                        .collect::<Vec<_>>(),
+
+                    None => vec![String::new()]
+                };
+
+                if prompts.is_empty() {
+                    anyhow::bail!("Prompts file has no prompts");
+                }
+
+                let progress = progress::items(*count as u64, "texts");
+
+                let completions = (0..*count)
+                    .into_par_iter()
+                    .map_init(CandidateCache::default, |cache, i| {
+                        // Independent stream of the seeded generator for every text
+                        let mut rng = match params.seed {
+                            Some(seed) => {
+                                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+                                rng.set_stream(i as u64);
+
+                                rng
+                            }
+
+                            None => ChaCha8Rng::from_entropy()
+                        };
+
+                        let prompt = &prompts[i % prompts.len()];
+
+                        let Some(request) = prompt_tokens(&model, &template, prompt, *unknown_words, &mut rng) else {
+                            anyhow::bail!("Prompt has words unknown to the model: {prompt}");
+                        };
+
+                        let (text, error) = generate_text(&model, request, params, &mut rng, cache, &banned, separator, None);
+
+                        if let Some(error) = error {
+                            anyhow::bail!(error);
+                        }
+
+                        progress.inc(1);
+
+                        Ok(text)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+
+                progress.finish();
+
+                write_completions(output.as_deref(), &completions)?;
+            }
+
+            Self::Serve { model, bind, threads, template, no_space_join, unknown_words, ban, params, bounds } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                let threads = match threads {
+                    0 => std::thread::available_parallelism()
+                        .map(|threads| threads.get())
+                        .unwrap_or(1),
+
+                    threads => *threads
+                };
+
+                let banned = ban.tokens(&model)?;
+
+                println!("Listening on {bind} with {threads} threads...");
+
+                serve(ServerContext {
+                    model,
+                    template: PromptTemplate::new(template),
+                    separator: if *no_space_join { "" } else { " " },
+                    unknown_words: *unknown_words,
+                    banned,
+                    params: *params,
+                    bounds: *bounds
+                }, bind, threads)?;
+            }
+
+            Self::Daemon { model, socket, template, no_space_join, unknown_words, ban, params, bounds } => {
+                // Stdout is used for the responses
+                eprintln!("Reading model...");
+
+                let model = Model::load(model)?;
+                let banned = ban.tokens(&model)?;
+
+                let context = ServerContext {
+                    model,
+                    template: PromptTemplate::new(template),
+                    separator: if *no_space_join { "" } else { " " },
+                    unknown_words: *unknown_words,
+                    banned,
+                    params: *params,
+                    bounds: *bounds
+                };
+
+                match socket {
+                    Some(socket) => {
+                        eprintln!("Listening on {socket:?}...");
+
+                        serve_socket(context, socket)?;
+                    }
+
+                    None => {
+                        eprintln!("Reading requests from stdin...");
+
+                        serve_stdio(context)?;
+                    }
+                }
+            }
+
+            #[cfg(feature = "bots")]
+            Self::Bot { bot } => bot.execute()?,
+
+            Self::Decay { model, factor, output } => {
+                println!("Reading model...");
+
+                let mut model = Model::load(model)?;
+
+                println!("Decaying transitions...");
+
+                model.decay(*factor);
+
+                // Empty models can't be loaded
+                if model.transitions().unigrams_len() == 0 {
+                    anyhow::bail!("All the transitions decayed below 1, use a larger factor");
+                }
+
+                println!("Storing model...");
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Migrate { model, output } => {
+                println!("Reading model...");
+
+                let bytes = std::fs::read(model)?;
+
+                let (_, format_version) = bundle::format_header(&bytes)?;
+
+                let model = Model::from_bytes(&bytes)?;
+
+                let version = model.headers()
+                    .get("version")
+                    .map(String::as_str)
+                    .unwrap_or("unknown");
+
+                println!("Model was built with crate version {version}, format version {format_version}");
+
+                let model = model.with_header("version", env!("CARGO_PKG_VERSION"));
+
+                println!("Storing model with format version {}...", bundle::FORMAT_VERSION);
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Verify { model } => {
+                println!("Reading model...");
+
+                if MappedModel::is_mapped(model)? {
+                    let model = MappedModel::open(model)?;
+
+                    println!("Mapped model is readable, order {}", model.order());
+
+                    return Ok(());
+                }
+
+                let bytes = std::fs::read(model)?;
+
+                let (_, format_version) = bundle::format_header(&bytes)?;
+
+                println!("Verifying model...");
+
+                // Checksum is verified before the model is decoded
+                let model = Model::from_bytes(&bytes)?;
+
+                println!("  Format version: {format_version}");
+
+                match model.headers().get(CHECKSUM_HEADER) {
+                    Some(checksum) => println!("  Checksum: {checksum}"),
+                    None => println!("  Model has no checksum, only its decoding was checked")
+                }
+
+                println!("Model is valid");
+            }
+
+            Self::Prune { model, min_count, output } => {
+                println!("Reading model...");
+
+                let mut model = Model::load(model)?;
+
+                println!("Pruning transitions...");
+
+                let (transitions, words) = model.prune(*min_count);
+
+                println!("Removed {transitions} transitions and {words} words");
+
+                println!("Storing model...");
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::RemoveToken { model, word, variants, output } => {
+                println!("Reading model...");
+
+                let mut model = Model::load(model)?;
+
+                let tokens = find_word_tokens(model.tokens(), word, *variants)?;
+
+                println!("Removing words...");
+
+                let mut words = tokens.iter()
+                    .filter_map(|token| model.tokens().find_word(*token))
+                    .collect::<Vec<_>>();
+
+                words.sort_unstable();
+
+                for word in words {
+                    println!("  {word}");
+                }
+
+                let (transitions, words) = model.remove_tokens(&tokens);
+
+                println!("Removed {transitions} transitions and {words} words");
+
+                println!("Storing model...");
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Update { model, messages: paths, weight, preserve_case, output } => {
+                println!("Reading model...");
+
+                let mut model = Model::load(model)?;
+
+                if model.has_subwords() {
+                    anyhow::bail!("Models with subwords can't be updated");
+                }
+
+                let mut messages = Messages::default();
+
+                for path in progress::files(&search_files(paths, exclude)?, "Parsing") {
+                    let parsed = Messages::parse_from_messages_with_filter(path, |word| {
+                        if *preserve_case {
+                            word.to_string()
+                        } else {
+                            word.to_lowercase()
+                        }
+                    })?;
+
+                    messages = messages.merge(parsed);
+                }
+
+                messages = messages.tokenize(&model.tokenizer());
+
+                println!("Updating model...");
+
+                let added = model.update(&messages, *weight);
+
+                println!("Learned {} messages, {added} new words", messages.messages().len());
+
+                println!("Storing model...");
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Perplexity { model: model_path, messages, cache, smoothing } => {
+                println!("Reading model...");
+
+                let model = Model::load(model_path)?;
+
+                if let Some(cache) = cache {
+                    std::fs::create_dir_all(cache)?;
+                }
+
+                println!("Evaluating messages...");
+
+                let mut total = Evaluation::default();
+
+                for path in search_files(messages, exclude)? {
+                    let cache_path = match cache {
+                        Some(cache) => {
+                            let mut hasher = blake3::Hasher::new();
+
+                            fingerprint_file(model_path, &mut hasher)?;
+                            fingerprint_file(&path, &mut hasher)?;
+
+                            hasher.update(&postcard::to_allocvec(smoothing)?);
+
+                            Some(cache.join(format!("{}.bin", hasher.finalize().to_hex())))
+                        }
+
+                        None => None
+                    };
+
+                    let cached = cache_path.as_ref()
+                        .filter(|cache_path| cache_path.is_file())
+                        .and_then(|cache_path| bundle::read::<Evaluation>(cache_path).ok());
+
+                    let evaluation = match cached {
+                        Some(evaluation) => {
+                            println!("Using cached results for {:?}...", path);
+
+                            evaluation
+                        }
+
+                        None => {
+                            println!("Evaluating {:?}...", path);
+
+                            let evaluation = model.evaluate_with_smoothing(&Messages::parse_from_messages(&path)?, smoothing);
+
+                            if let Some(cache_path) = cache_path {
+                                bundle::write(cache_path, &evaluation, compression_level)?;
+                            }
+
+                            evaluation
+                        }
+                    };
+
+                    println!("  Perplexity: {:.4} ({} messages, {} skipped)", evaluation.perplexity(), evaluation.messages, evaluation.skipped);
+
+                    total = total.merge(evaluation);
+                }
+
+                println!();
+                println!("       Messages: {}", total.messages);
+                println!("        Skipped: {}", total.skipped);
+                println!("         Tokens: {}", total.tokens);
+                println!("         Unseen: {}", total.unseen);
+                println!("Log-probability: {:.4}", total.log_probability);
+                println!("  Cross entropy: {:.4}", total.cross_entropy());
+                println!("     Perplexity: {:.4}", total.perplexity());
+            }
+
+
+            Self::Classify { model: paths, input, unseen_log_probability, verbose, smoothing } => {
+                if paths.len() < 2 {
+                    anyhow::bail!("At least two models are needed for classification");
+                }
+
+                let classifier = Classifier::load(paths, *smoothing, *unseen_log_probability)?;
+
+                classify_texts(&classifier, input, *verbose, |result| format!("{:.4}", result.log_odds))?;
+            }
+
+            Self::DetectLanguage { models, input, unseen_log_probability, verbose, smoothing } => {
+                let classifier = Classifier::load_dir(models, *smoothing, *unseen_log_probability)?;
+
+                if classifier.models().len() < 2 {
+                    anyhow::bail!("At least two language models are needed in the directory");
+                }
+
+                classify_texts(&classifier, input, *verbose, |result| format!("{:.4}", result.confidence))?;
+            }
+
+            Self::Convert { model, to, output } => {
+                convert_model(model, *to, output, compression_level)?;
+            }
+
+            Self::ExportArpa { model, output } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                println!("Exporting model...");
+
+                let mut file = BufWriter::new(File::create(output)?);
+
+                model.write_arpa(&mut file)?;
+
+                file.flush()?;
+
+                println!("Done");
+            }
+
+            Self::Inspect { model, word, top } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                let words = word.split_whitespace().collect::<Vec<_>>();
+
+                let chain = words.iter()
+                    .map(|word| model.tokens().find_token(word).ok_or_else(|| Error::UnknownWord(word.to_string())))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let tables = model.inspect(&chain);
+
+                if tables.is_empty() {
+                    println!();
+                    println!("  Words have no continuations");
+                }
+
+                for table in tables {
+                    let context = model.join_words(&words[words.len() - table.order..]);
+
+                    println!();
+                    println!("  Order {} ({context}): {} continuations, total count {}", table.order, table.continuations.len(), table.total);
+                    println!();
+
+                    for (token, count) in table.continuations.iter().take(*top) {
+                        let word = model.tokens()
+                            .find_word(*token)
+                            .map(printable_word)
+                            .unwrap_or(Cow::Borrowed(UNK_TOKEN_NAME));
+
+                        println!("    {count:>10}  {:>8.4}%  {word}", table.probability(*count) * 100.0);
+                    }
+                }
+            }
+
+            Self::Suggest { model, prefix, top, smoothing } => {
+                let model = Model::load(model)?;
+
+                let chain = prefix.split_whitespace()
+                    .map(|word| model.tokens().find_token_ignore_case(word).ok_or_else(|| Error::UnknownWord(word.to_string())))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut stdout = std::io::stdout().lock();
+
+                for suggestion in model.suggest(&chain, *top, smoothing) {
+                    if let Some(word) = model.tokens().find_word(suggestion.token) {
+                        writeln!(stdout, "{}\t{:.4}", printable_word(word), suggestion.probability)?;
+                    }
+                }
+            }
+
+            Self::ExportGraph { model, word, depth, top, format, output } => {
+                println!("Reading model...");
+
+                let model = Model::load(model)?;
+
+                let Some(token) = model.tokens().find_token(word) else {
+                    anyhow::bail!("Could not find token for word: {word}");
+                };
+
+                println!("Exporting graph...");
+
+                let graph = model.transitions_graph(token, *depth, *top);
+
+                println!("  Nodes: {}, edges: {}", graph.nodes.len(), graph.edges.len());
+
+                match format {
+                    GraphFormat::Dot => std::fs::write(output, graph.to_dot())?,
+                    GraphFormat::Json => std::fs::write(output, serde_json::to_vec_pretty(&graph)?)?
+                }
+
+                println!("Done");
+            }
+
+            Self::Import { path, format, output } => {
+                println!("Reading chain...");
+
+                let model = Model::import_chain(&std::fs::read(path)?, *format)?;
+
+                println!("Imported model of order {} with {} words", model.transitions().order(), model.tokens().len());
+
+                println!("Storing model...");
+
+                ModelFormat::Bundle.write(output, model, compression_level)?;
+
+                println!("Done");
             }
         }
 
         Ok(())
     }
 }
+
+mod tests {
+    #[test]
+    fn decay_factor() {
+        use super::decay_factor;
+
+        assert_eq!(decay_factor("0.5"), Ok(0.5));
+        assert_eq!(decay_factor("1"), Ok(1.0));
+
+        assert!(decay_factor("0").is_err());
+        assert!(decay_factor("-0.5").is_err());
+        assert!(decay_factor("1.5").is_err());
+        assert!(decay_factor("NaN").is_err());
+        assert!(decay_factor("half").is_err());
+    }
+
+    #[test]
+    fn convert_edited_model() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use super::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("hello there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let root = std::env::temp_dir().join(format!("markov-chains-convert-{}", std::process::id()));
+
+        std::fs::create_dir_all(&root)?;
+
+        let (model, json, converted) = (root.join("model"), root.join("model.json"), root.join("converted"));
+
+        ModelFormat::Bundle.write(&model, Model::build_with_order(dataset, 3), 0)?;
+
+        convert_model(&model, ConvertFormat::Json, &json, 0)?;
+
+        // Change count of some transition by hand
+        let mut value = serde_json::from_slice::<serde_json::Value>(&std::fs::read(&json)?)?;
+
+        value["transitions"]["trigrams"][0]["transitions"][0][1] = serde_json::json!(5);
+
+        std::fs::write(&json, serde_json::to_vec(&value)?)?;
+
+        convert_model(&json, ConvertFormat::Bundle, &converted, 0)?;
+
+        // Checksum of the edited model is updated
+        let original = Model::load(&model)?;
+        let edited = Model::load(&converted)?;
+
+        assert_eq!(edited.headers().get(CHECKSUM_HEADER), Some(&edited.checksum()?));
+        assert_ne!(edited.checksum()?, original.checksum()?);
+
+        // Edited model can be converted again
+        convert_model(&converted, ConvertFormat::Json, &json, 0)?;
+
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&std::fs::read(&json)?)?["transitions"], value["transitions"]);
+
+        std::fs::remove_dir_all(&root)?;
+
+        Ok(())
+    }
+}
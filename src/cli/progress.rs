@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle, ProgressFinish};
+
+/// Hide all the progress bars
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Create progress bar with the given template, hidden if `--quiet` is used
+///
+/// Bars are drawn to stderr and hidden when it's not a terminal.
+fn progress_bar(len: u64, template: &str) -> ProgressBar {
+    if QUIET.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+
+    let style = ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+
+    ProgressBar::new(len).with_style(style)
+}
+
+/// Iterate over the files, printing the action for each
+/// of them and showing progress bar of the processed ones
+pub fn files<'a>(paths: &'a [PathBuf], action: &'a str) -> impl Iterator<Item = &'a PathBuf> {
+    let progress = progress_bar(paths.len() as u64, "[{elapsed_precise}] [{bar:40}] {pos}/{len} files ({eta} left)")
+        .with_finish(ProgressFinish::AndClear);
+
+    let printer = progress.clone();
+
+    progress.wrap_iter(paths.iter())
+        .inspect(move |path| printer.suspend(|| println!("{action} {path:?}...")))
+}
+
+/// Progress bar of the read bytes, messages count is shown as its message
+pub fn bytes(len: u64) -> ProgressBar {
+    progress_bar(len, "[{elapsed_precise}] [{bar:40}] {bytes}/{total_bytes} ({eta} left) {msg}")
+}
+
+/// Progress bar of the processed items named by `items`
+pub fn items(len: u64, items: &str) -> ProgressBar {
+    progress_bar(len, &format!("[{{elapsed_precise}}] [{{bar:40}}] {{pos}}/{{len}} {items} ({{eta}} left)"))
+        .with_finish(ProgressFinish::AndClear)
+}
+
+/// Run the long operation showing a spinner with elapsed time
+pub fn spin<T>(f: impl FnOnce() -> T) -> T {
+    let progress = progress_bar(0, "{spinner} [{elapsed_precise}]");
+
+    progress.enable_steady_tick(Duration::from_millis(100));
+
+    let result = f();
+
+    progress.finish_and_clear();
+
+    result
+}
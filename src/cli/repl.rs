@@ -0,0 +1,174 @@
+use clap::ValueEnum;
+
+use crate::prelude::{
+    GenerationParams,
+    LoopAction
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Slash-command entered to the model REPL
+pub enum ReplCommand {
+    /// Stop the REPL
+    Exit,
+
+    /// Print the list of commands
+    Help,
+
+    /// Print current generation params
+    Params,
+
+    /// Restore generation params given in the command line
+    Reset,
+
+    /// Change the generation param
+    Set {
+        name: String,
+        value: Option<String>
+    }
+}
+
+impl ReplCommand {
+    /// Parse command from the entered line
+    ///
+    /// Returns `None` if the line is a prompt.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.strip_prefix('/')?.split_whitespace();
+
+        let name = parts.next()?;
+        let value = parts.next().map(String::from);
+
+        match name {
+            "exit" | "quit" => Some(Self::Exit),
+            "help"          => Some(Self::Help),
+            "params"        => Some(Self::Params),
+            "reset"         => Some(Self::Reset),
+
+            _ => Some(Self::Set {
+                name: name.to_string(),
+                value
+            })
+        }
+    }
+}
+
+/// Help message listing the REPL commands
+pub const REPL_HELP: &str = "  /help              print this message
+  /params            print current generation params
+  /<param> <value>   change generation param, e.g. /temperature 0.5 or /max-len 80
+  /reset             restore generation params given in the command line
+  /exit              quit the REPL";
+
+/// Change generation param by its command line name
+pub fn set_param(params: &mut GenerationParams, name: &str, value: &str) -> anyhow::Result<()> {
+    fn parse<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T> {
+        value.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid value of {name}: {value}"))
+    }
+
+    match name {
+        "temperature"           => params.temperature = parse(name, value)?,
+        "temperature-alpha"     => params.temperature_alpha = parse(name, value)?,
+        "repeat-penalty"        => params.repeat_penalty = parse(name, value)?,
+        "repeat-penalty-window" => params.repeat_penalty_window = parse(name, value)?,
+        "trim-least"            => params.trim_least = parse(name, value)?,
+        "trim-most"             => params.trim_most = parse(name, value)?,
+        "top-k"                 => params.top_k = parse(name, value)?,
+        "no-repeat-ngram"       => params.no_repeat_ngram = parse(name, value)?,
+        "context-window"        => params.context_window = parse(name, value)?,
+        "max-loop-period"       => params.max_loop_period = parse(name, value)?,
+        "loop-repeats"          => params.loop_repeats = parse(name, value)?,
+
+        "loop-action" => params.loop_action = LoopAction::from_str(value, true)
+            .map_err(|_| anyhow::anyhow!("Invalid value of {name}: {value}"))?,
+
+        "temperature-schedule"  => params.temperature_schedule = parse(name, value)?,
+        "min-len"               => params.min_len = parse(name, value)?,
+        "max-len"               => params.max_len = parse(name, value)?,
+        "no-bigrams"            => params.no_bigrams = parse(name, value)?,
+        "no-trigrams"           => params.no_trigrams = parse(name, value)?,
+        "no-quadgrams"          => params.no_quadgrams = parse(name, value)?,
+        "no-pentagrams"         => params.no_pentagrams = parse(name, value)?,
+        "dialogue"              => params.dialogue = parse(name, value)?,
+
+        "seed" if value == "none" => params.seed = None,
+        "seed"                    => params.seed = Some(parse(name, value)?),
+
+        _ => anyhow::bail!("Unknown command: /{name}, enter /help to see the list of commands")
+    }
+
+    Ok(())
+}
+
+/// Format generation params which can be changed in the REPL
+pub fn format_params(params: &GenerationParams) -> String {
+    let seed = params.seed
+        .map(|seed| seed.to_string())
+        .unwrap_or(String::from("none"));
+
+    let params = [
+        ("temperature", params.temperature.to_string()),
+        ("temperature-alpha", params.temperature_alpha.to_string()),
+        ("repeat-penalty", params.repeat_penalty.to_string()),
+        ("repeat-penalty-window", params.repeat_penalty_window.to_string()),
+        ("trim-least", params.trim_least.to_string()),
+        ("trim-most", params.trim_most.to_string()),
+        ("top-k", params.top_k.to_string()),
+        ("no-repeat-ngram", params.no_repeat_ngram.to_string()),
+        ("context-window", params.context_window.to_string()),
+        ("max-loop-period", params.max_loop_period.to_string()),
+        ("loop-repeats", params.loop_repeats.to_string()),
+        ("loop-action", params.loop_action.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()),
+        ("temperature-schedule", params.temperature_schedule.to_string()),
+        ("min-len", params.min_len.to_string()),
+        ("max-len", params.max_len.to_string()),
+        ("no-bigrams", params.no_bigrams.to_string()),
+        ("no-trigrams", params.no_trigrams.to_string()),
+        ("no-quadgrams", params.no_quadgrams.to_string()),
+        ("no-pentagrams", params.no_pentagrams.to_string()),
+        ("dialogue", params.dialogue.to_string()),
+        ("seed", seed)
+    ];
+
+    params.iter()
+        .map(|(name, value)| format!("  {name:<21} : {value}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+mod tests {
+    #[test]
+    fn repl_commands() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use super::*;
+
+        assert_eq!(ReplCommand::parse("hello world"), None);
+        assert_eq!(ReplCommand::parse("/exit"), Some(ReplCommand::Exit));
+        assert_eq!(ReplCommand::parse("/params"), Some(ReplCommand::Params));
+
+        assert_eq!(ReplCommand::parse("/max-len 80"), Some(ReplCommand::Set {
+            name: String::from("max-len"),
+            value: Some(String::from("80"))
+        }));
+
+        let mut params = GenerationParams::default();
+
+        set_param(&mut params, "temperature", "0.5")?;
+        set_param(&mut params, "max-len", "80")?;
+        set_param(&mut params, "seed", "42")?;
+
+        assert_eq!(params.temperature, 0.5);
+        assert_eq!(params.max_len, 80);
+        assert_eq!(params.seed, Some(42));
+
+        set_param(&mut params, "seed", "none")?;
+
+        assert_eq!(params.seed, None);
+
+        assert!(set_param(&mut params, "max-len", "long").is_err());
+        assert!(set_param(&mut params, "unknown", "1").is_err());
+
+        assert!(format_params(&params).contains("max-len               : 80"));
+
+        Ok(())
+    }
+}
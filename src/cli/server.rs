@@ -0,0 +1,398 @@
+use std::sync::Arc;
+use std::io::Read;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use tiny_http::{Server, Request, Response, Method, Header, StatusCode};
+
+use crate::prelude::{
+    Generator,
+    GenerationParams,
+    GenerationOverrides,
+    GenerationBounds,
+    Model,
+    CandidateCache,
+    PromptTemplate
+};
+
+use super::model::{prompt_tokens, generate_text, printable_word, join_words, UnknownWordsArgs};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct GenerateRequest {
+    #[serde(default)]
+    pub prompt: String,
+
+    #[serde(flatten)]
+    pub overrides: GenerationOverrides
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct GenerateResponse {
+    pub text: String
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+/// Change of the streamed text
+///
+/// `rewind` last characters of the text are replaced by the `text`.
+pub struct DeltaResponse {
+    pub text: String,
+
+    #[serde(skip_serializing_if = "is_zero")]
+    pub rewind: usize
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ErrorResponse {
+    error: String
+}
+
+/// Maximal size of the request body in bytes
+const MAX_BODY: u64 = 64 * 1024;
+
+type JsonResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+/// Prompt tokens, generation params and random numbers generator of the request
+pub type PreparedRequest = (Vec<u64>, GenerationParams, ChaCha8Rng);
+
+/// Shared state of the server threads
+pub struct ServerContext {
+    pub model: Model,
+    pub template: PromptTemplate,
+    pub separator: &'static str,
+    pub unknown_words: UnknownWordsArgs,
+    pub banned: HashSet<u64>,
+    pub params: GenerationParams,
+    pub bounds: GenerationBounds
+}
+
+impl ServerContext {
+    /// Apply the request's overrides and tokenize its prompt
+    ///
+    /// Returns `None` if the prompt has words unknown to the model.
+    pub fn prepare(&self, request: &GenerateRequest) -> Option<PreparedRequest> {
+        let params = self.params.with_overrides(&request.overrides, &self.bounds);
+
+        let mut rng = match params.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        };
+
+        let tokens = prompt_tokens(&self.model, &self.template, &request.prompt, self.unknown_words, &mut rng)?;
+
+        Some((tokens, params, rng))
+    }
+
+    /// Generate text answering the request
+    ///
+    /// Returns the generated text and the error which interrupted the generation.
+    pub fn generate(&self, request: PreparedRequest, cache: &mut CandidateCache) -> (String, Option<String>) {
+        let (tokens, params, mut rng) = request;
+
+        generate_text(&self.model, tokens, &params, &mut rng, cache, &self.banned, self.separator, None)
+    }
+}
+
+fn json_response(status: u16, body: &impl serde::Serialize) -> JsonResponse {
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("valid header");
+
+    Response::from_data(serde_json::to_vec(body).unwrap_or_default())
+        .with_status_code(status)
+        .with_header(header)
+}
+
+fn error_response(status: u16, error: impl ToString) -> JsonResponse {
+    json_response(status, &ErrorResponse {
+        error: error.to_string()
+    })
+}
+
+/// Parse generation request to the prompt tokens, params and random numbers generator
+fn parse_request(context: &ServerContext, request: &mut Request) -> Result<PreparedRequest, JsonResponse> {
+    let too_large = || error_response(413, format!("Request body is larger than {MAX_BODY} bytes"));
+
+    if request.body_length().is_some_and(|len| len as u64 > MAX_BODY) {
+        return Err(too_large());
+    }
+
+    // Content-Length is not required, so the body is limited while reading
+    let mut body = Vec::new();
+
+    if let Err(err) = request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body) {
+        return Err(error_response(400, format!("Failed to read request: {err}")));
+    }
+
+    if body.len() as u64 > MAX_BODY {
+        return Err(too_large());
+    }
+
+    let request = match serde_json::from_slice::<GenerateRequest>(&body) {
+        Ok(request) => request,
+        Err(err) => return Err(error_response(400, format!("Invalid request: {err}")))
+    };
+
+    context.prepare(&request)
+        .ok_or_else(|| error_response(400, "Prompt has words unknown to the model"))
+}
+
+fn handle_generate(context: &ServerContext, request: &mut Request, cache: &mut CandidateCache) -> JsonResponse {
+    let request = match parse_request(context, request) {
+        Ok(request) => request,
+        Err(response) => return response
+    };
+
+    match context.generate(request, cache) {
+        (_, Some(error)) => error_response(500, error),
+        (text, None) => json_response(200, &GenerateResponse { text })
+    }
+}
+
+#[inline]
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// Get change turning the previous text into the next one
+///
+/// Detokenizer may rewrite the end of the text when a word is added,
+/// e.g. the separator before punctuation, so only the common prefix
+/// of the texts is kept.
+fn text_delta(previous: &str, next: &str) -> DeltaResponse {
+    let common = previous.char_indices()
+        .zip(next.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| previous.len().min(next.len()));
+
+    // Shorter text can end in the middle of a longer character
+    let common = (0..=common).rev()
+        .find(|i| previous.is_char_boundary(*i) && next.is_char_boundary(*i))
+        .unwrap_or_default();
+
+    DeltaResponse {
+        text: next[common..].to_string(),
+        rewind: previous[common..].chars().count()
+    }
+}
+
+/// Format server-sent event
+fn event(event: &str, data: &impl serde::Serialize) -> Vec<u8> {
+    let data = serde_json::to_string(data).unwrap_or_default();
+
+    format!("event: {event}\ndata: {data}\n\n").into_bytes()
+}
+
+/// Server-sent events of the generated text
+///
+/// Sends the prompt and every generated word as `delta` events,
+/// then `done` or `error`. Tokens are generated lazily while
+/// the response is read, so generation is stopped if the
+/// client closes the connection.
+struct EventStream<'a> {
+    context: &'a ServerContext,
+
+    /// `None` when the last event is sent
+    generator: Option<Generator<'a>>,
+
+    words: Vec<Cow<'a, str>>,
+
+    /// Detokenized text sent so far
+    text: String,
+
+    /// Unread bytes of the current event
+    event: Vec<u8>,
+    position: usize
+}
+
+impl<'a> EventStream<'a> {
+    fn new(context: &'a ServerContext, tokens: Vec<u64>, params: &'a GenerationParams, rng: ChaCha8Rng, cache: &'a mut CandidateCache) -> Self {
+        // Replies don't include the prompt
+        let words = tokens.iter()
+            .filter(|_| !params.dialogue)
+            .map(|token| printable_word(context.model.tokens().find_word(*token).unwrap()))
+            .collect::<Vec<_>>();
+
+        let text = join_words(&context.model, words.clone(), context.separator);
+
+        let event = if text.is_empty() {
+            Vec::new()
+        } else {
+            event("delta", &DeltaResponse { text: text.clone(), rewind: 0 })
+        };
+
+        let generator = context.model.generate_with_rng(tokens, params, rng)
+            .with_cache(cache)
+            .with_banned(&context.banned);
+
+        Self {
+            context,
+            generator: Some(generator),
+            words,
+            text,
+            event,
+            position: 0
+        }
+    }
+
+    /// Generate the next event
+    ///
+    /// Returns `None` after the last event.
+    fn next_event(&mut self) -> Option<Vec<u8>> {
+        let model = &self.context.model;
+
+        let token = match self.generator.as_mut()?.next() {
+            Some(Ok(token)) => token,
+
+            Some(Err(err)) => {
+                self.generator = None;
+
+                return Some(event("error", &ErrorResponse {
+                    error: format!("Failed to generate: {err}")
+                }));
+            }
+
+            None => {
+                self.generator = None;
+
+                return Some(event("done", &GenerateResponse {
+                    text: self.text.clone()
+                }));
+            }
+        };
+
+        let Some(word) = model.tokens().find_word(token) else {
+            self.generator = None;
+
+            return Some(event("error", &ErrorResponse {
+                error: format!("Failed to find word for token: {token}")
+            }));
+        };
+
+        self.words.push(printable_word(word));
+
+        let text = join_words(model, self.words.clone(), self.context.separator);
+        let delta = text_delta(&self.text, &text);
+
+        self.text = text;
+
+        Some(event("delta", &delta))
+    }
+}
+
+impl Read for EventStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.event.len() {
+            match self.next_event() {
+                Some(event) => {
+                    self.event = event;
+                    self.position = 0;
+                }
+
+                None => return Ok(0)
+            }
+        }
+
+        let len = buf.len().min(self.event.len() - self.position);
+
+        buf[..len].copy_from_slice(&self.event[self.position..self.position + len]);
+
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Stream generated text as server-sent events
+///
+/// The stream has no length, so tiny_http sends it in chunks
+/// to HTTP/1.1 clients and as a whole to HTTP/1.0 ones.
+fn handle_stream(context: &ServerContext, mut request: Request, cache: &mut CandidateCache) -> std::io::Result<()> {
+    let (tokens, params, rng) = match parse_request(context, &mut request) {
+        Ok(request) => request,
+        Err(response) => return request.respond(response)
+    };
+
+    let headers = vec![
+        Header::from_bytes("Content-Type", "text/event-stream").expect("valid header"),
+        Header::from_bytes("Cache-Control", "no-cache").expect("valid header")
+    ];
+
+    let events = EventStream::new(context, tokens, &params, rng, cache);
+
+    request.respond(Response::new(StatusCode(200), headers, events, None, None))
+}
+
+/// Serve generation requests until the process is stopped
+///
+/// Every thread handles requests with its own candidates cache.
+pub fn serve(context: ServerContext, bind: &str, threads: usize) -> anyhow::Result<()> {
+    let server = Server::http(bind)
+        .map_err(|err| anyhow::anyhow!("Failed to bind {bind}: {err}"))?;
+
+    let server = Arc::new(server);
+    let context = Arc::new(context);
+
+    let threads = (0..threads.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let context = context.clone();
+
+            std::thread::spawn(move || {
+                let mut cache = CandidateCache::default();
+
+                for mut request in server.incoming_requests() {
+                    let result = match (request.method(), request.url()) {
+                        (Method::Post, "/generate/stream") => handle_stream(&context, request, &mut cache),
+
+                        (Method::Post, "/generate") => {
+                            let response = handle_generate(&context, &mut request, &mut cache);
+
+                            request.respond(response)
+                        }
+
+                        (_, "/generate" | "/generate/stream") => request.respond(error_response(405, "Method not allowed")),
+
+                        _ => request.respond(error_response(404, "Not found"))
+                    };
+
+                    if let Err(err) = result {
+                        eprintln!("Failed to respond: {err}");
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        if thread.join().is_err() {
+            anyhow::bail!("Server thread panicked");
+        }
+    }
+
+    Ok(())
+}
+
+mod tests {
+    #[test]
+    fn text_delta() {
+        use super::{text_delta, DeltaResponse};
+
+        let delta = |text: &str, rewind| DeltaResponse {
+            text: text.to_string(),
+            rewind
+        };
+
+        assert_eq!(text_delta("", "hello"), delta("hello", 0));
+        assert_eq!(text_delta("hello", "hello world"), delta(" world", 0));
+
+        // Separator before the punctuation is removed
+        assert_eq!(text_delta("hello world ", "hello world!"), delta("!", 1));
+        assert_eq!(text_delta("hello wor", "hello world"), delta("ld", 0));
+        assert_eq!(text_delta("привет", "приветик"), delta("ик", 0));
+        assert_eq!(text_delta("ёж", "еж"), delta("еж", 2));
+    }
+}
@@ -1,13 +1,32 @@
 use std::path::PathBuf;
+use std::collections::HashMap;
 
-use clap::Subcommand;
+use clap::{Subcommand, ValueEnum};
+use globset::GlobSet;
 
 use crate::prelude::{
     Messages,
-    Tokens
+    Tokens,
+    TokensRemap,
+    TokenizedMessages,
+    Dataset,
+    UNK_TOKEN_NAME,
+    Bpe
 };
 
-use super::search_files;
+use crate::bundle::{self, BundleKind};
+
+use super::{search_files, progress, convert_bundle, ConvertFormat};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TokensFormat {
+    #[default]
+    /// Tab separated token, word and count, a word per line
+    Tsv,
+
+    /// Pretty JSON of the tokens bundle
+    Json
+}
 
 #[derive(Subcommand)]
 pub enum CliTokensCommand {
@@ -17,11 +36,91 @@ pub enum CliTokensCommand {
         /// Path to the messages bundle
         path: Vec<PathBuf>,
 
+        #[arg(long)]
+        /// Derive tokens from the words' hashes instead of random numbers
+        ///
+        /// The same words always get the same tokens, so tokens
+        /// and models built from the same messages are reproducible.
+        deterministic: bool,
+
+        #[arg(long)]
+        /// Register special word with a reserved token, e.g. `<URL>`
+        ///
+        /// Special words are never pruned.
+        special: Vec<String>,
+
         #[arg(short, long)]
         /// Path to the tokens output
         output: PathBuf
     },
 
+    /// Train byte-pair encoding subwords tokenizer on messages bundles
+    ///
+    /// Apply it with `messages encode-bpe` and build the model
+    /// with `--subwords` to join subwords when generating.
+    TrainBpe {
+        #[arg(short, long)]
+        /// Path to the messages bundle
+        path: Vec<PathBuf>,
+
+        #[arg(long, default_value_t = 16000)]
+        /// Maximal amount of subwords
+        vocab_size: usize,
+
+        #[arg(short, long)]
+        /// Path to the tokenizer output
+        output: PathBuf
+    },
+
+    /// Replace rare words of the tokens bundle by the `<UNK>` word
+    ///
+    /// Tokenize messages with `messages tokenize --unk <UNK>` then.
+    Prune {
+        #[arg(short, long)]
+        /// Path to the tokens bundle
+        path: PathBuf,
+
+        #[arg(short, long)]
+        /// Paths to the messages bundles to count words in
+        ///
+        /// Counts stored in the tokens bundle are used if not given.
+        messages: Vec<PathBuf>,
+
+        #[arg(long)]
+        /// Minimal amount of occurrences of the kept words
+        min_count: u64,
+
+        #[arg(long, default_value_t = String::from(UNK_TOKEN_NAME))]
+        /// Word replacing the pruned words
+        unk: String,
+
+        #[arg(short, long)]
+        /// Path to the pruned tokens output
+        output: PathBuf
+    },
+
+    /// Show the most frequent words of the tokens bundle
+    Top {
+        #[arg(short, long)]
+        /// Path to the tokens bundle
+        path: PathBuf,
+
+        #[arg(short, long, default_value_t = 50)]
+        /// Amount of words to show
+        count: usize
+    },
+
+    /// Show the rarest words of the tokens bundle
+    Rare {
+        #[arg(short, long)]
+        /// Path to the tokens bundle
+        path: PathBuf,
+
+        #[arg(long, default_value_t = 1)]
+        /// Maximal amount of occurrences of the shown words
+        max_count: u64
+    },
+
     /// Merge tokens bundles
     Merge {
         #[arg(short, long)]
@@ -31,52 +130,288 @@ pub enum CliTokensCommand {
         #[arg(short, long)]
         /// Path to the merged tokens output
         output: PathBuf
+    },
+
+    /// Export tokens bundle to the table which can be edited by hand
+    ///
+    /// Import the edited table with `tokens import`.
+    Export {
+        #[arg(short, long)]
+        /// Path to the tokens bundle
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = TokensFormat::default())]
+        /// Format of the table
+        format: TokensFormat,
+
+        #[arg(short, long)]
+        /// Path to the table output
+        output: PathBuf
+    },
+
+    /// Import tokens bundle from the table made by `tokens export`
+    ///
+    /// Lines of the same word are merged into the first one,
+    /// words of the deleted lines are removed. Bundles tokenized
+    /// with the original tokens bundle can be translated to the
+    /// imported one with `--update`.
+    Import {
+        #[arg(short, long)]
+        /// Path to the edited table
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = TokensFormat::default())]
+        /// Format of the table
+        format: TokensFormat,
+
+        #[arg(long)]
+        /// Path to the exported tokens bundle to find the changes
+        original: Option<PathBuf>,
+
+        #[arg(long, requires = "original")]
+        /// Path to the tokenized messages or dataset bundle to translate
+        ///
+        /// The bundles are overwritten.
+        update: Vec<PathBuf>,
+
+        #[arg(short, long)]
+        /// Path to the tokens bundle output
+        output: PathBuf
+    },
+
+    /// Convert tokens bundle to JSON and back
+    Convert {
+        #[arg(short, long)]
+        /// Path to the tokens bundle or JSON file
+        path: PathBuf,
+
+        #[arg(long, value_enum)]
+        /// Format of the output
+        to: ConvertFormat,
+
+        #[arg(short, long)]
+        /// Path to the converted output
+        output: PathBuf
     }
 }
 
 impl CliTokensCommand {
     #[inline]
-    pub fn execute(&self) -> anyhow::Result<()> {
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
         match self {
-            Self::Parse { path, output } => {
+            Self::Parse { path, deterministic, special, output } => {
                 println!("Reading messages bundles...");
 
                 let mut messages = Messages::default();
 
-                for path in search_files(path) {
-                    println!("Reading {:?}...", path);
-
-                    messages = messages.merge(postcard::from_bytes::<Messages>(&std::fs::read(path)?)?);
+                for path in progress::files(&search_files(path, exclude)?, "Reading") {
+                    messages = messages.merge(bundle::read::<Messages>(path)?);
                 }
 
                 println!("Generating tokens...");
 
-                let tokens = Tokens::parse_from_messages(&messages);
+                let mut tokens = progress::spin(|| if *deterministic {
+                    Tokens::parse_from_messages_hashed(&messages)
+                } else {
+                    Tokens::parse_from_messages(&messages)
+                });
+
+                for word in special {
+                    tokens.register_special(word);
+                }
 
                 println!("Storing tokens bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&tokens)?)?;
+                bundle::write(output, &tokens, compression_level)?;
 
                 println!("Done");
             }
 
+            Self::TrainBpe { path, vocab_size, output } => {
+                println!("Reading messages bundles...");
+
+                let mut messages = Messages::default();
+
+                for path in progress::files(&search_files(path, exclude)?, "Reading") {
+                    messages = messages.merge(bundle::read::<Messages>(path)?);
+                }
+
+                println!("Training tokenizer...");
+
+                let bpe = progress::spin(|| Bpe::train(&messages, *vocab_size));
+
+                println!("Learned {} merges", bpe.merges().len());
+                println!("Storing tokenizer...");
+
+                bundle::write(output, &bpe, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Prune { path, messages, min_count, unk, output } => {
+                println!("Reading tokens bundle...");
+
+                let tokens = bundle::read::<Tokens>(path)?;
+
+                if messages.is_empty() && !tokens.has_counts() {
+                    anyhow::bail!("Tokens bundle has no words counts, provide messages bundles to count words in");
+                }
+
+                let mut counts = HashMap::new();
+
+                if messages.is_empty() {
+                    counts = tokens.counts().clone();
+                } else {
+                    println!("Reading messages bundles...");
+                }
+
+                for path in progress::files(&search_files(messages, exclude)?, "Reading") {
+                    let messages = bundle::read::<Messages>(path)?;
+
+                    for (word, count) in messages.word_counts() {
+                        if let Some(token) = tokens.find_token(word) {
+                            *counts.entry(token).or_default() += count;
+                        }
+                    }
+                }
+
+                println!("Pruning tokens...");
+
+                let total = tokens.len();
+
+                let (tokens, remap) = tokens.prune(&counts, *min_count, unk);
+
+                println!("  Pruned tokens: {} of {total}", remap.len());
+
+                println!("Storing tokens bundle...");
+
+                bundle::write(output, &tokens, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Top { path, count } => {
+                println!("Reading tokens bundle...");
+
+                let tokens = bundle::read::<Tokens>(path)?;
+
+                if !tokens.has_counts() {
+                    anyhow::bail!("Tokens bundle has no words counts, parse it again to count words");
+                }
+
+                println!();
+
+                for (word, count) in tokens.top(*count) {
+                    println!("  {count:>10}  {word}");
+                }
+            }
+
+            Self::Rare { path, max_count } => {
+                println!("Reading tokens bundle...");
+
+                let tokens = bundle::read::<Tokens>(path)?;
+
+                if !tokens.has_counts() {
+                    anyhow::bail!("Tokens bundle has no words counts, parse it again to count words");
+                }
+
+                let words = tokens.rare(*max_count);
+
+                println!();
+
+                for (word, count) in &words {
+                    println!("  {count:>10}  {word}");
+                }
+
+                println!();
+                println!("  Words: {} of {}", words.len(), tokens.len());
+            }
+
             Self::Merge { path, output } => {
                 println!("Reading tokens bundles...");
 
                 let mut tokens = Tokens::default();
 
-                for path in search_files(path) {
-                    println!("Reading {:?}...", path);
-
-                    tokens = tokens.merge(postcard::from_bytes::<Tokens>(&std::fs::read(path)?)?);
+                for path in progress::files(&search_files(path, exclude)?, "Reading") {
+                    tokens = tokens.merge(bundle::read::<Tokens>(path)?);
                 }
 
                 println!("Storing merged tokens bundle...");
 
-                std::fs::write(output, postcard::to_allocvec(&tokens)?)?;
+                bundle::write(output, &tokens, compression_level)?;
+
+                println!("Done");
+            }
+
+            Self::Export { path, format, output } => {
+                println!("Reading tokens bundle...");
+
+                let tokens = bundle::read::<Tokens>(path)?;
+
+                println!("Storing tokens table...");
+
+                match format {
+                    TokensFormat::Tsv => tokens.write_tsv(std::io::BufWriter::new(std::fs::File::create(output)?))?,
+                    TokensFormat::Json => std::fs::write(output, bundle::to_json(&tokens)?)?
+                }
+
+                println!("Done");
+            }
+
+            Self::Import { path, format, original, update, output } => {
+                println!("Reading tokens table...");
+
+                let (tokens, merged) = match format {
+                    TokensFormat::Tsv => Tokens::parse_tsv(&std::fs::read_to_string(path)?)?,
+                    TokensFormat::Json => (bundle::from_json::<Tokens>(&std::fs::read(path)?)?, TokensRemap::default())
+                };
+
+                println!("  Words: {}", tokens.len());
+                println!("  Merged words: {}", merged.len());
+
+                if let Some(original) = original {
+                    println!("Reading original tokens bundle...");
+
+                    let edit = bundle::read::<Tokens>(original)?.edit_to(&tokens, &merged);
+
+                    println!("  Translated tokens: {}", edit.remap.len());
+                    println!("  Removed words: {}", edit.removed.len());
+
+                    for path in search_files(update, exclude)? {
+                        println!("Updating {path:?}...");
+
+                        let bytes = std::fs::read(&path)?;
+
+                        match bundle::format_header(&bytes)?.0 {
+                            Some(BundleKind::TokenizedMessages) => {
+                                let messages = bundle::from_bytes::<TokenizedMessages>(&bytes)?
+                                    .apply_tokens_edit(&edit);
+
+                                bundle::write(&path, &messages, compression_level)?;
+                            }
+
+                            Some(BundleKind::Dataset) => {
+                                let dataset = bundle::from_bytes::<Dataset>(&bytes)?
+                                    .apply_tokens_edit(&tokens, &edit);
+
+                                bundle::write(&path, &dataset, compression_level)?;
+                            }
+
+                            _ => anyhow::bail!("{path:?} is not a tokenized messages or dataset bundle")
+                        }
+                    }
+                }
+
+                println!("Storing tokens bundle...");
+
+                bundle::write(output, &tokens, compression_level)?;
 
                 println!("Done");
             }
+
+            Self::Convert { path, to, output } => {
+                convert_bundle::<Tokens>(path, *to, output, compression_level)?;
+            }
         }
 
         Ok(())
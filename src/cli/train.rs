@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use globset::GlobSet;
+
+use crate::prelude::DEFAULT_PUNCTUATION;
+
+use super::model::{CliModelCommand, ModelFormat};
+
+#[derive(Args)]
+pub struct CliTrainCommand {
+    #[arg(short, long, required = true)]
+    /// Paths to the plain messages files or folders with them
+    input: Vec<PathBuf>,
+
+    #[arg(long, default_value_t = 3)]
+    /// Build transitions tables of all the orders up to this one
+    ///
+    /// Supported orders are 1 to 5.
+    order: usize,
+
+    #[arg(long)]
+    /// Count repeated messages only once
+    dedup: bool,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PUNCTUATION)]
+    /// Split these punctuation characters from the words into separate tokens
+    ///
+    /// Uses `.,!?;:"()[]…` if no characters are given.
+    split_punctuation: Option<String>,
+
+    #[arg(long)]
+    /// Read, tokenize and count messages line by line
+    ///
+    /// Uses much less memory on huge corpora.
+    streaming: bool,
+
+    #[arg(long, value_enum, default_value_t = ModelFormat::Bundle)]
+    /// Format of the stored model
+    format: ModelFormat,
+
+    #[arg(short, long)]
+    /// Path to the model output
+    output: PathBuf
+}
+
+impl CliTrainCommand {
+    /// Parse messages, generate tokens, tokenize messages,
+    /// create dataset and build the model in one go
+    ///
+    /// Same as `model from-scratch` with the given order,
+    /// intermediate bundles are not stored.
+    pub fn execute(&self, compression_level: i32, exclude: &GlobSet) -> anyhow::Result<()> {
+        CliModelCommand::FromScratch {
+            messages: self.input.clone(),
+            manifest: None,
+            bigrams: false,
+            trigrams: false,
+            order: Some(self.order),
+            streaming: self.streaming,
+            dedup: self.dedup,
+            split_punctuation: self.split_punctuation.clone(),
+            deterministic: false,
+            preserve_case: false,
+            bpe_vocab_size: None,
+            tokenizer: None,
+            header: Vec::new(),
+            format: self.format,
+            output: self.output.clone()
+        }.execute(compression_level, exclude)
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::prelude::{
+    Messages,
+    Tokens,
+    TokenizedMessages,
+    Dataset,
+    Model
+};
+
+use crate::verify::*;
+use crate::bundle;
+
+/// Maximal amount of printed violations per check
+const MAX_PRINTED_VIOLATIONS: usize = 20;
+
+#[derive(Args)]
+pub struct CliVerifyCommand {
+    #[arg(long)]
+    /// Path to the messages bundle
+    messages: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Path to the tokens bundle
+    tokens: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Path to the tokenized messages bundle
+    tokenized: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Path to the dataset bundle
+    dataset: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Path to the model bundle
+    model: Option<PathBuf>
+}
+
+fn report(check: &str, violations: &[Violation]) -> usize {
+    if violations.is_empty() {
+        println!("  [ok]   {check}");
+    }
+
+    else {
+        println!("  [fail] {check}: {} violations", violations.len());
+
+        for violation in violations.iter().take(MAX_PRINTED_VIOLATIONS) {
+            println!("           - {violation}");
+        }
+
+        if violations.len() > MAX_PRINTED_VIOLATIONS {
+            println!("           ...");
+        }
+    }
+
+    violations.len()
+}
+
+impl CliVerifyCommand {
+    pub fn execute(&self) -> anyhow::Result<()> {
+        println!("Reading bundles...");
+
+        let messages = match &self.messages {
+            Some(path) => Some(bundle::read::<Messages>(path)?),
+            None => None
+        };
+
+        let tokens = match &self.tokens {
+            Some(path) => Some(bundle::read::<Tokens>(path)?),
+            None => None
+        };
+
+        let tokenized = match &self.tokenized {
+            Some(path) => Some(bundle::read::<TokenizedMessages>(path)?),
+            None => None
+        };
+
+        let dataset = match &self.dataset {
+            Some(path) => Some(bundle::read::<Dataset>(path)?),
+            None => None
+        };
+
+        let model = match &self.model {
+            Some(path) => Some(Model::load(path)?),
+            None => None
+        };
+
+        println!("Running checks...");
+        println!();
+
+        let mut checks = 0;
+        let mut violations = 0;
+
+        if let (Some(messages), Some(tokens)) = (&messages, &tokens) {
+            checks += 1;
+            violations += report("messages words have tokens", &verify_tokens(messages, tokens));
+        }
+
+        if let (Some(messages), Some(tokens), Some(tokenized)) = (&messages, &tokens, &tokenized) {
+            checks += 1;
+            violations += report("tokenized messages reproduce messages", &verify_tokenized(messages, tokens, tokenized));
+        }
+
+        if let Some(dataset) = &dataset {
+            checks += 1;
+            violations += report("dataset tokens have words", &verify_dataset(dataset));
+
+            if let Some(messages) = &messages {
+                for (tokenized, _) in dataset.messages() {
+                    checks += 1;
+                    violations += report("dataset messages reproduce messages", &verify_tokenized(messages, dataset.tokens(), tokenized));
+                }
+            }
+        }
+
+        if let (Some(model), Some(dataset)) = (&model, &dataset) {
+            checks += 1;
+            violations += report("model transitions are backed by dataset", &verify_model(model, dataset));
+        }
+
+        if checks == 0 {
+            anyhow::bail!("Nothing to verify: provide messages with tokens, a dataset or a model with dataset");
+        }
+
+        println!();
+
+        if violations > 0 {
+            anyhow::bail!("Found {violations} violations");
+        }
+
+        println!("Done");
+
+        Ok(())
+    }
+}
@@ -1,14 +1,96 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::prelude::{
     TokenizedMessages,
     Tokens,
-    Transitions
+    TokensRemap,
+    Transitions,
+    ManifestEntry
 };
 
+use crate::Error;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Summary of the dataset contents
+///
+/// Messages weights are not applied to the counts.
+pub struct DatasetStats {
+    /// Amount of messages
+    pub messages: usize,
+
+    /// Amount of tokens in all the messages
+    pub tokens: u64,
+
+    /// Amount of words known to the dataset
+    pub vocabulary: usize,
+
+    /// (messages, weight) of every messages bundle
+    pub sources: Vec<(usize, u64)>,
+
+    /// Occurrences of every token
+    pub token_counts: HashMap<u64, u64>,
+
+    /// Sorted lengths of the messages
+    lengths: Vec<usize>
+}
+
+impl DatasetStats {
+    #[inline]
+    /// Average length of the messages in tokens
+    pub fn average_len(&self) -> f64 {
+        if self.messages == 0 {
+            return 0.0;
+        }
+
+        self.tokens as f64 / self.messages as f64
+    }
+
+    #[inline]
+    /// Length of the messages below which the given percent of them lies
+    ///
+    /// Uses the nearest-rank method, so the result is always
+    /// the length of some message, or 0 if there are none.
+    pub fn percentile_len(&self, percentile: f64) -> usize {
+        if self.lengths.is_empty() {
+            return 0;
+        }
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.lengths.len() as f64).ceil() as usize;
+
+        self.lengths[rank.saturating_sub(1)]
+    }
+
+    /// Get `n` most frequent tokens with their occurrences
+    ///
+    /// Tokens with the same occurrences are sorted by their values
+    /// so the result is stable.
+    pub fn top_tokens(&self, n: usize) -> Vec<(u64, u64)> {
+        let mut tokens = self.token_counts.iter()
+            .map(|(token, count)| (*token, *count))
+            .collect::<Vec<_>>();
+
+        tokens.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        tokens.truncate(n);
+
+        tokens
+    }
+}
+
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Dataset {
     /// (messages, weight)
     pub(crate) messages: Vec<(TokenizedMessages, u64)>,
-    pub(crate) tokens: Tokens
+    pub(crate) tokens: Tokens,
+
+    /// Files used to create the dataset
+    pub(crate) provenance: Vec<ManifestEntry>,
+
+    /// Indexes of the messages bundles which are conversations
+    pub(crate) dialogues: Vec<usize>,
+
+    /// Names of the messages bundles by their indexes,
+    /// empty for the unnamed ones
+    pub(crate) sources: Vec<String>
 }
 
 impl Dataset {
@@ -20,12 +102,204 @@ impl Dataset {
     }
 
     #[inline]
+    /// Merge tokens bundle into the dataset's tokens
+    ///
+    /// Messages of the dataset are not changed, so messages tokenized
+    /// with the merged bundle should be added by `with_tokenized_messages`.
     pub fn with_tokens(mut self, tokens: Tokens) -> Self {
         self.tokens = self.tokens.merge(tokens);
 
         self
     }
 
+    /// Merge tokens bundle into the dataset's tokens, failing
+    /// if it assigns any word or token differently
+    ///
+    /// Unlike `with_tokens`, words of the messages tokenized
+    /// with the merged bundle are never changed.
+    pub fn try_with_tokens(mut self, tokens: Tokens) -> Result<Self, Error> {
+        for (word, token) in &tokens.word_token {
+            let collides = match self.tokens.find_token(word) {
+                Some(existing) => existing != *token,
+                None => self.tokens.find_word(*token).is_some()
+            };
+
+            if collides {
+                return Err(Error::TokenCollision {
+                    word: word.clone(),
+                    token: *token
+                });
+            }
+        }
+
+        self.tokens = self.tokens.merge(tokens);
+
+        Ok(self)
+    }
+
+    /// Add messages tokenized with the tokens bundle
+    ///
+    /// The bundle is merged into the dataset's tokens,
+    /// and messages are translated to the merged tokens.
+    pub fn with_tokenized_messages(mut self, messages: TokenizedMessages, tokens: Tokens, weight: u64) -> Self {
+        let remap;
+
+        (self.tokens, remap) = self.tokens.merge_with_remap(tokens);
+
+        self.messages.push((messages.remap(&remap), weight));
+
+        self
+    }
+
+    #[inline]
+    /// Add conversation messages
+    ///
+    /// See `mark_dialogue`.
+    pub fn with_dialogue(self, messages: TokenizedMessages, weight: u64) -> Self {
+        let index = self.messages.len();
+
+        self.with_messages(messages, weight)
+            .mark_dialogue(index)
+    }
+
+    /// Treat messages bundle by the given index as a conversation
+    ///
+    /// Every message of the conversation is a reply to the previous
+    /// one, so models built from the dataset learn which words start
+    /// replies to the last words of the messages.
+    pub fn mark_dialogue(mut self, index: usize) -> Self {
+        if index < self.messages.len() && !self.dialogues.contains(&index) {
+            self.dialogues.push(index);
+            self.dialogues.sort_unstable();
+        }
+
+        self
+    }
+
+    /// Name the messages bundle by the given index, e.g. by its file name
+    ///
+    /// Several bundles can share the name to be reweighted together.
+    pub fn name_source(mut self, index: usize, name: impl ToString) -> Self {
+        if index < self.messages.len() {
+            if self.sources.len() <= index {
+                self.sources.resize(index + 1, String::new());
+            }
+
+            self.sources[index] = name.to_string();
+        }
+
+        self
+    }
+
+    /// Change weight of the messages bundles with the given name
+    ///
+    /// Bundles can also be referenced by their indexes, e.g. `#2`.
+    /// Returns amount of the changed bundles.
+    pub fn set_source_weight(&mut self, source: &str, weight: u64) -> usize {
+        let indexes = self.find_sources(source);
+
+        for index in &indexes {
+            self.messages[*index].1 = weight;
+        }
+
+        indexes.len()
+    }
+
+    /// Find indexes of the messages bundles by their name,
+    /// or by the `#index` reference
+    pub fn find_sources(&self, source: &str) -> Vec<usize> {
+        let named = (0..self.messages.len())
+            .filter(|index| self.source_name(*index) == Some(source))
+            .collect::<Vec<_>>();
+
+        if !named.is_empty() {
+            return named;
+        }
+
+        source.strip_prefix('#')
+            .and_then(|index| index.parse::<usize>().ok())
+            .filter(|index| *index < self.messages.len())
+            .into_iter()
+            .collect()
+    }
+
+    /// Replace tokens seen less than `min_count` times by the `unk` word
+    ///
+    /// Occurrences are counted without messages weights.
+    pub fn prune_vocab(mut self, min_count: u64, unk: &str) -> Self {
+        let mut counts = HashMap::new();
+
+        for (messages, _) in &self.messages {
+            for (token, count) in messages.token_counts() {
+                *counts.entry(token).or_default() += count;
+            }
+        }
+
+        let remap;
+
+        (self.tokens, remap) = std::mem::take(&mut self.tokens).prune(&counts, min_count, unk);
+
+        let indexes = 0..self.messages.len();
+
+        self.remap(indexes, &remap)
+    }
+
+    /// Remove the tokens from the vocabulary and all the messages
+    ///
+    /// Words around the removed ones become adjacent, messages
+    /// left empty are removed. Special words are kept. Returns
+    /// amounts of removed occurrences and words.
+    pub fn remove_tokens(&mut self, tokens: &HashSet<u64>) -> (usize, usize) {
+        let mut occurrences = 0;
+
+        for (messages, _) in &mut self.messages {
+            for message in &mut messages.messages {
+                let len = message.len();
+
+                message.retain(|token| !tokens.contains(token));
+
+                occurrences += len - message.len();
+            }
+
+            messages.messages.retain(|message| !message.is_empty());
+        }
+
+        let words = self.tokens.retain(|token| !tokens.contains(&token));
+
+        (occurrences, words)
+    }
+
+    /// Translate tokens of the messages by the given indexes
+    pub fn remap(mut self, indexes: impl IntoIterator<Item = usize>, remap: &TokensRemap) -> Self {
+        for index in indexes {
+            if let Some((messages, _)) = self.messages.get_mut(index) {
+                *messages = std::mem::take(messages).remap(remap);
+            }
+        }
+
+        self
+    }
+
+    #[inline]
+    /// Record files used to create the dataset
+    ///
+    /// Already recorded entries are not duplicated.
+    pub fn with_provenance(mut self, entries: impl IntoIterator<Item = ManifestEntry>) -> Self {
+        for entry in entries {
+            if !self.provenance.contains(&entry) {
+                self.provenance.push(entry);
+            }
+        }
+
+        self
+    }
+
+    #[inline]
+    /// Check if the dataset has no messages to build a model from
+    pub fn is_empty(&self) -> bool {
+        self.messages.iter().all(|(messages, _)| messages.messages().is_empty())
+    }
+
     #[inline]
     pub fn messages(&self) -> &[(TokenizedMessages, u64)] {
         &self.messages
@@ -36,8 +310,216 @@ impl Dataset {
         &self.tokens
     }
 
+    #[inline]
+    pub fn provenance(&self) -> &[ManifestEntry] {
+        &self.provenance
+    }
+
+    #[inline]
+    /// Indexes of the messages bundles which are conversations
+    pub fn dialogues(&self) -> &[usize] {
+        &self.dialogues
+    }
+
+    #[inline]
+    /// Name of the messages bundle by the given index
+    pub fn source_name(&self, index: usize) -> Option<&str> {
+        self.sources.get(index)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Iterate over (context, reply, weight) pairs of consecutive
+    /// messages of the conversations
+    ///
+    /// Pairs with empty messages are skipped.
+    pub fn dialogue_pairs(&self) -> impl Iterator<Item = (&'_ [u64], &'_ [u64], u64)> {
+        self.dialogues.iter()
+            .filter_map(|index| self.messages.get(*index))
+            .flat_map(|(messages, weight)| {
+                messages.messages()
+                    .windows(2)
+                    .filter(|pair| !pair[0].is_empty() && !pair[1].is_empty())
+                    .map(move |pair| (pair[0].as_slice(), pair[1].as_slice(), *weight))
+            })
+    }
+
+    /// Calculate summary of the dataset contents
+    pub fn stats(&self) -> DatasetStats {
+        let mut stats = DatasetStats {
+            vocabulary: self.tokens.len(),
+            ..DatasetStats::default()
+        };
+
+        for (messages, weight) in &self.messages {
+            stats.sources.push((messages.messages().len(), *weight));
+
+            for message in messages.messages() {
+                stats.lengths.push(message.len());
+
+                for token in message {
+                    *stats.token_counts.entry(*token).or_default() += 1;
+                }
+            }
+        }
+
+        stats.lengths.sort_unstable();
+
+        stats.messages = stats.lengths.len();
+        stats.tokens = stats.lengths.iter().sum::<usize>() as u64;
+
+        stats
+    }
+
     #[inline]
     pub fn build_transitions(&self, build_bigrams: bool, build_trigrams: bool) -> Transitions {
         Transitions::build_from_dataset(self, build_bigrams, build_trigrams)
     }
+
+    #[inline]
+    /// Build transitions tables of all the orders up to the given one
+    pub fn build_transitions_with_order(&self, order: usize) -> Transitions {
+        Transitions::build_from_dataset_with_order(self, order)
+    }
+}
+
+mod tests {
+    #[test]
+    fn stats() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b"),
+            String::from("a")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let dataset = Dataset::default()
+            .with_messages(tokenized.clone(), 1)
+            .with_messages(tokenized, 3)
+            .with_tokens(tokens);
+
+        let stats = dataset.stats();
+
+        assert_eq!(stats.messages, 6);
+        assert_eq!(stats.tokens, 12);
+        assert_eq!(stats.vocabulary, 3);
+        assert_eq!(stats.sources, [(3, 1), (3, 3)]);
+
+        assert_eq!(stats.average_len(), 2.0);
+        assert_eq!(stats.percentile_len(50.0), 2);
+        assert_eq!(stats.percentile_len(100.0), 3);
+        assert_eq!(stats.percentile_len(0.0), 1);
+
+        let a = dataset.tokens().find_token("a").unwrap();
+        let b = dataset.tokens().find_token("b").unwrap();
+
+        assert_eq!(stats.top_tokens(2), [(a, 6), (b, 4)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sources() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let mut dataset = Dataset::default()
+            .with_messages(tokenized.clone(), 1)
+            .with_messages(tokenized.clone(), 1)
+            .with_messages(tokenized, 1)
+            .name_source(0, "chat2023")
+            .name_source(2, "chat2023")
+            .name_source(5, "missing");
+
+        assert_eq!(dataset.source_name(0), Some("chat2023"));
+        assert_eq!(dataset.source_name(1), None);
+        assert_eq!(dataset.source_name(5), None);
+
+        assert_eq!(dataset.set_source_weight("chat2023", 5), 2);
+        assert_eq!(dataset.set_source_weight("#1", 3), 1);
+        assert_eq!(dataset.set_source_weight("#3", 3), 0);
+        assert_eq!(dataset.set_source_weight("books", 3), 0);
+
+        assert_eq!(dataset.stats().sources, [(1, 5), (1, 3), (1, 5)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_tokens() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello alice how are you"),
+            String::from("alice"),
+            String::from("bye alice")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let mut dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let alice = dataset.tokens().find_token("alice").unwrap();
+
+        assert_eq!(dataset.remove_tokens(&HashSet::from([alice])), (3, 1));
+
+        assert!(dataset.tokens().find_token("alice").is_none());
+
+        let messages = dataset.messages()[0].0.detokenize(dataset.tokens())?;
+
+        assert_eq!(messages.messages(), [
+            vec!["hello", "how", "are", "you"],
+            vec!["bye"]
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_with_tokens() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::Error;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b")
+        ]);
+
+        let tokens = Tokens::parse_from_messages_hashed(&messages);
+
+        let dataset = Dataset::default();
+
+        assert!(dataset.is_empty());
+
+        let dataset = dataset
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .try_with_tokens(tokens.clone())?;
+
+        assert!(!dataset.is_empty());
+
+        // The same tokens merge without changes
+        let dataset = dataset.try_with_tokens(tokens)?;
+
+        assert_eq!(dataset.tokens().len(), 2);
+
+        // Random tokens of the same words don't
+        let random = Tokens::parse_from_messages(&messages);
+
+        assert!(matches!(dataset.try_with_tokens(random), Err(Error::TokenCollision { .. })));
+
+        Ok(())
+    }
 }
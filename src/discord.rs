@@ -0,0 +1,311 @@
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::prelude::Messages;
+use crate::input::{open_input, strip_compression_extension};
+use crate::Error;
+
+/// Raw user, role and channel mentions and rendered user mentions
+static MENTIONS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(@[!&]?|#)\d+>|@[\w.]*\w").unwrap());
+
+/// Raw and rendered custom emoji
+static EMOJI: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<a?:\w+:\d+>|:\w+:").unwrap());
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Message of the DiscordChatExporter dump
+pub struct DiscordMessage {
+    /// Channel id and name, not available in CSV exports
+    pub channel: Option<(String, String)>,
+
+    pub author_id: String,
+    pub author: String,
+    pub author_nickname: Option<String>,
+
+    /// Always false for CSV exports
+    pub is_bot: bool,
+
+    pub content: String,
+
+    /// Names and nicknames of the mentioned users
+    pub mentions: Vec<String>
+}
+
+impl DiscordMessage {
+    #[inline]
+    /// Check if the author's id, name or nickname equals the value
+    pub fn is_author(&self, author: &str) -> bool {
+        self.author_id == author
+            || self.author.eq_ignore_ascii_case(author)
+            || self.author_nickname.as_ref().is_some_and(|nickname| nickname.eq_ignore_ascii_case(author))
+    }
+
+    #[inline]
+    /// Check if the channel's id or name equals the value
+    pub fn is_channel(&self, channel: &str) -> bool {
+        self.channel.as_ref().is_some_and(|(id, name)| {
+            id == channel || name.eq_ignore_ascii_case(channel)
+        })
+    }
+}
+
+mod json {
+    #[derive(serde::Deserialize)]
+    pub struct Export {
+        #[serde(default)]
+        pub channel: Option<Channel>,
+
+        pub messages: Vec<Message>
+    }
+
+    #[derive(serde::Deserialize)]
+    pub struct Channel {
+        pub id: String,
+        pub name: String
+    }
+
+    #[derive(serde::Deserialize)]
+    pub struct Message {
+        #[serde(default)]
+        pub content: String,
+
+        pub author: User,
+
+        #[serde(default)]
+        pub mentions: Vec<User>
+    }
+
+    #[derive(serde::Deserialize)]
+    pub struct User {
+        #[serde(default)]
+        pub id: String,
+
+        pub name: String,
+
+        #[serde(default)]
+        pub nickname: Option<String>,
+
+        #[serde(default, rename = "isBot")]
+        pub is_bot: bool
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct CsvMessage {
+    #[serde(rename = "AuthorID")]
+    author_id: String,
+
+    #[serde(rename = "Author")]
+    author: String,
+
+    #[serde(rename = "Content")]
+    content: String
+}
+
+/// Parse DiscordChatExporter JSON dump
+pub fn parse_discord_json(json: &str) -> Result<Vec<DiscordMessage>, Error> {
+    let export = serde_json::from_str::<json::Export>(json)?;
+
+    let channel = export.channel.map(|channel| (channel.id, channel.name));
+
+    let messages = export.messages.into_iter()
+        .map(|message| DiscordMessage {
+            channel: channel.clone(),
+            author_id: message.author.id,
+            author: message.author.name,
+            author_nickname: message.author.nickname,
+            is_bot: message.author.is_bot,
+            content: message.content,
+            mentions: message.mentions.into_iter()
+                .flat_map(|user| std::iter::once(user.name).chain(user.nickname))
+                .collect()
+        })
+        .collect();
+
+    Ok(messages)
+}
+
+/// Parse DiscordChatExporter CSV dump
+pub fn parse_discord_csv(csv: impl std::io::Read) -> Result<Vec<DiscordMessage>, Error> {
+    csv::Reader::from_reader(csv)
+        .deserialize::<CsvMessage>()
+        .map(|message| {
+            let message = message?;
+
+            Ok(DiscordMessage {
+                author_id: message.author_id,
+                author: message.author,
+                content: message.content,
+                ..DiscordMessage::default()
+            })
+        })
+        .collect()
+}
+
+/// Read DiscordChatExporter dump
+///
+/// Files with `.csv` extension are parsed as CSV exports,
+/// all the others as JSON exports. Compressed files are
+/// decompressed, see `open_input`.
+pub fn read_discord_export(path: impl AsRef<Path>) -> Result<Vec<DiscordMessage>, Error> {
+    let path = path.as_ref();
+
+    let is_csv = strip_compression_extension(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+
+    let input = open_input(path)?;
+
+    if is_csv {
+        parse_discord_csv(input)
+    } else {
+        parse_discord_json(&std::io::read_to_string(input)?)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// Filter of the Discord messages
+pub struct DiscordFilter {
+    /// Skip messages of the bots
+    pub drop_bots: bool,
+
+    /// Remove user, role and channel mentions from the messages
+    pub strip_mentions: bool,
+
+    /// Remove custom emoji from the messages
+    pub strip_emoji: bool,
+
+    /// Keep only messages of these channels' ids or names
+    ///
+    /// Messages without channel information are skipped
+    /// if this list is not empty.
+    pub channels: Vec<String>,
+
+    /// Keep only messages of these authors' ids, names or nicknames
+    pub authors: Vec<String>
+}
+
+impl DiscordFilter {
+    /// Check if the message passes the filter
+    pub fn accepts(&self, message: &DiscordMessage) -> bool {
+        if self.drop_bots && message.is_bot {
+            return false;
+        }
+
+        if !self.channels.is_empty() && !self.channels.iter().any(|channel| message.is_channel(channel)) {
+            return false;
+        }
+
+        if !self.authors.is_empty() && !self.authors.iter().any(|author| message.is_author(author)) {
+            return false;
+        }
+
+        true
+    }
+
+    /// Get text of the message with stripped mentions and emoji
+    pub fn clean(&self, message: &DiscordMessage) -> String {
+        let mut content = message.content.clone();
+
+        if self.strip_mentions {
+            // Longest names first so they're not stripped partially
+            let mut names = message.mentions.iter()
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>();
+
+            names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+            for name in names {
+                content = content.replace(&format!("@{name}"), " ");
+            }
+
+            content = MENTIONS.replace_all(&content, " ").into_owned();
+        }
+
+        if self.strip_emoji {
+            content = EMOJI.replace_all(&content, " ").into_owned();
+        }
+
+        content
+    }
+}
+
+impl Messages {
+    /// Parse messages from the Discord dump which pass the filter
+    pub fn parse_from_discord(messages: &[DiscordMessage], filter: &DiscordFilter) -> Self {
+        let lines = messages.iter()
+            .filter(|message| filter.accepts(message))
+            .map(|message| filter.clean(message))
+            .collect::<Vec<_>>();
+
+        Self::parse_from_lines(&lines)
+    }
+}
+
+mod tests {
+    #[test]
+    fn discord() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::discord::*;
+
+        let json = r#"{
+            "channel": { "id": "1", "name": "general" },
+            "messages": [
+                {
+                    "content": "Hello @Big Bob <:pog:123> :smile:",
+                    "author": { "id": "10", "name": "alice", "isBot": false },
+                    "mentions": [{ "id": "11", "name": "bob", "nickname": "Big Bob", "isBot": false }]
+                },
+                {
+                    "content": "Beep boop",
+                    "author": { "id": "12", "name": "robot", "isBot": true }
+                }
+            ]
+        }"#;
+
+        let messages = parse_discord_json(json)?;
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].is_channel("General"));
+        assert!(messages[1].is_bot);
+
+        let filter = DiscordFilter {
+            drop_bots: true,
+            strip_mentions: true,
+            strip_emoji: true,
+            ..DiscordFilter::default()
+        };
+
+        let parsed = Messages::parse_from_discord(&messages, &filter);
+
+        assert_eq!(parsed.messages().len(), 1);
+        assert!(parsed.messages().contains(&vec![String::from("hello")]));
+
+        let csv = "AuthorID,Author,Date,Content,Attachments,Reactions\n\
+            10,alice,2024-01-01,\"Hi, there\",,\n\
+            11,bob,2024-01-01,Hey,,\n";
+
+        let messages = parse_discord_csv(csv.as_bytes())?;
+
+        let filter = DiscordFilter {
+            authors: vec![String::from("alice")],
+            ..DiscordFilter::default()
+        };
+
+        let parsed = Messages::parse_from_discord(&messages, &filter);
+
+        assert_eq!(parsed.messages().len(), 1);
+        assert!(parsed.messages().contains(&vec![String::from("hi,"), String::from("there")]));
+
+        // CSV exports have no channels
+        let filter = DiscordFilter {
+            channels: vec![String::from("general")],
+            ..DiscordFilter::default()
+        };
+
+        assert!(Messages::parse_from_discord(&messages, &filter).messages().is_empty());
+
+        Ok(())
+    }
+}
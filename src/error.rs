@@ -0,0 +1,104 @@
+use crate::bundle::BundleKind;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+/// Error of the library operations
+///
+/// The CLI wraps it into `anyhow::Error`, library consumers can match it.
+pub enum Error {
+    #[error("Could not find token for word: {0}")]
+    UnknownWord(String),
+
+    #[error("Could not find word for token: {0}")]
+    TokenNotFound(u64),
+
+    #[error("Token {token} of word {word:?} is assigned to another word or the word has another token")]
+    TokenCollision {
+        word: String,
+        token: u64
+    },
+
+    #[error("Invalid tokens table at line {line}: {reason}")]
+    InvalidTokensTable {
+        line: usize,
+        reason: String
+    },
+
+    #[error("Dataset has no messages")]
+    EmptyDataset,
+
+    #[error("Invalid temperature schedule {0}")]
+    InvalidTemperatureSchedule(String),
+
+    #[error("Invalid interpolation lambdas: {0}")]
+    InvalidInterpolationLambdas(String),
+
+    #[error("Model was built with crate version {found}.x, but version {expected}.x is expected, please rebuild it")]
+    FormatVersionMismatch {
+        expected: String,
+        found: String
+    },
+
+    #[error("Expected {expected} bundle, but found {found} bundle")]
+    BundleKindMismatch {
+        expected: BundleKind,
+        found: BundleKind
+    },
+
+    #[error("Bundle was written with format version {found}, but only versions up to {supported} are supported, please update the crate")]
+    UnsupportedFormatVersion {
+        found: u8,
+        supported: u8
+    },
+
+    #[error("Bundle has invalid format header")]
+    InvalidBundleHeader,
+
+    #[error("Compressed bundle is truncated or corrupted: {0}")]
+    CorruptedBundle(std::io::Error),
+
+    #[error("Mapped model file is truncated or corrupted")]
+    CorruptedMappedModel,
+
+    #[error("Model checksum {found} doesn't match {expected} stored in its headers, the file is truncated or corrupted")]
+    ChecksumMismatch {
+        expected: String,
+        found: String
+    },
+
+    #[error("Failed to read {kind} bundle written without format header by an older crate version, please rebuild it: {source}")]
+    LegacyBundle {
+        kind: BundleKind,
+        source: postcard::Error
+    },
+
+    #[error("Invalid chain model: {0}")]
+    InvalidChain(String),
+
+    #[error("Model has no transitions")]
+    EmptyModel,
+
+    #[error("Ensemble has no models")]
+    EmptyEnsemble,
+
+    #[error("Invalid ensemble weight {0}, weights must be positive")]
+    InvalidEnsembleWeight(f64),
+
+    #[error("Generation exceeded its steps budget")]
+    BudgetExceeded,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Postcard(#[from] postcard::Error),
+
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error)
+}
@@ -0,0 +1,356 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::AssertUnwindSafe;
+
+use crate::prelude::{
+    Model,
+    GenerationParams,
+    GenerationOverrides,
+    GenerationBounds
+};
+
+use crate::Error;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remember the error to be returned by `mc_last_error`
+fn set_error(error: impl ToString) {
+    let error = CString::new(error.to_string().replace('\0', " "))
+        .unwrap_or_default();
+
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error));
+}
+
+/// Run the function, remembering its panic as the error
+///
+/// Unwinding into the host application is undefined behavior,
+/// so the `fallback` value is returned instead.
+fn catch_panic<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("unknown error"));
+
+            set_error(format!("Library panicked: {message}"));
+
+            fallback
+        }
+    }
+}
+
+/// Read the string argument
+///
+/// # Safety
+///
+/// `string` must be NULL or a valid nul-terminated string.
+unsafe fn read_str<'a>(string: *const c_char, name: &str) -> Option<&'a str> {
+    if string.is_null() {
+        set_error(format!("{name} is NULL"));
+
+        return None;
+    }
+
+    match CStr::from_ptr(string).to_str() {
+        Ok(string) => Some(string),
+
+        Err(err) => {
+            set_error(format!("{name} is not valid UTF-8: {err}"));
+
+            None
+        }
+    }
+}
+
+/// Language model loaded by the host application
+pub struct McModel {
+    model: Model,
+    params: GenerationParams
+}
+
+impl McModel {
+    #[inline]
+    fn new(model: Model) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            model,
+            params: GenerationParams::default()
+        }))
+    }
+
+    /// Generate text continuing the prompt with the JSON overrides
+    fn generate(&self, prompt: &str, overrides: Option<&str>) -> Result<String, Error> {
+        let params = match overrides {
+            Some(overrides) => {
+                let overrides = serde_json::from_str::<GenerationOverrides>(overrides)?;
+
+                self.params.with_overrides(&overrides, &GenerationBounds::unlimited())
+            }
+
+            None => self.params
+        };
+
+        let beginning = self.model.tokenize_text(prompt)?;
+
+        self.model.generate_text(beginning, &params)
+    }
+}
+
+#[no_mangle]
+/// Get message of the last error happened in the calling thread
+///
+/// Returns NULL if there were no errors. The string is owned by
+/// the library and valid until the next error in the thread.
+pub extern "C" fn mc_last_error() -> *const c_char {
+    catch_panic(std::ptr::null(), || {
+        LAST_ERROR.with(|last| {
+            last.borrow()
+                .as_ref()
+                .map(|error| error.as_ptr())
+                .unwrap_or(std::ptr::null())
+        })
+    })
+}
+
+#[no_mangle]
+/// Load model from the file
+///
+/// Returns NULL on failure, see `mc_last_error`.
+/// The model must be freed by `mc_model_free`.
+///
+/// # Safety
+///
+/// `path` must be a valid nul-terminated string.
+pub unsafe extern "C" fn mc_model_load(path: *const c_char) -> *mut McModel {
+    catch_panic(std::ptr::null_mut(), || {
+        let Some(path) = read_str(path, "Path") else {
+            return std::ptr::null_mut();
+        };
+
+        match Model::load(path) {
+            Ok(model) => McModel::new(model),
+
+            Err(err) => {
+                set_error(err);
+
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+/// Load model from the bytes of its bundle
+///
+/// Returns NULL on failure, see `mc_last_error`. The bytes are
+/// not used after the call. The model must be freed by `mc_model_free`.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes.
+pub unsafe extern "C" fn mc_model_from_bytes(bytes: *const u8, len: usize) -> *mut McModel {
+    catch_panic(std::ptr::null_mut(), || {
+        if bytes.is_null() {
+            set_error("Bytes are NULL");
+
+            return std::ptr::null_mut();
+        }
+
+        match Model::from_bytes(std::slice::from_raw_parts(bytes, len)) {
+            Ok(model) => McModel::new(model),
+
+            Err(err) => {
+                set_error(err);
+
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+/// Replace default generation params of the model by the JSON object
+///
+/// Missing params take their default values. Returns false
+/// on failure, see `mc_last_error`.
+///
+/// # Safety
+///
+/// `model` must be returned by the library and not freed,
+/// `params` must be a valid nul-terminated string.
+pub unsafe extern "C" fn mc_model_set_params(model: *mut McModel, params: *const c_char) -> bool {
+    catch_panic(false, || {
+        let Some(model) = model.as_mut() else {
+            set_error("Model is NULL");
+
+            return false;
+        };
+
+        let Some(params) = read_str(params, "Params") else {
+            return false;
+        };
+
+        match serde_json::from_str(params) {
+            Ok(params) => {
+                model.params = params;
+
+                true
+            }
+
+            Err(err) => {
+                set_error(err);
+
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+/// Generate text continuing the prompt into the buffer
+///
+/// Empty prompt generates a new message. `overrides` is NULL or
+/// a JSON object with `temperature`, `max_len`, `seed` and `top_k`
+/// fields changing the params of this call.
+///
+/// The text is written nul-terminated and cut at a character boundary
+/// to fit the buffer. Returns the length of the whole text in bytes
+/// without the terminator, so the text was cut if it's not less than
+/// `buffer_len`, or -1 on failure, see `mc_last_error`. Set `seed`
+/// to get the same text when retrying with a larger buffer.
+///
+/// # Safety
+///
+/// `model` must be returned by the library and not freed, `prompt`
+/// and non-NULL `overrides` must be valid nul-terminated strings,
+/// `buffer` must point to `buffer_len` writable bytes.
+pub unsafe extern "C" fn mc_model_generate(
+    model: *const McModel,
+    prompt: *const c_char,
+    overrides: *const c_char,
+    buffer: *mut c_char,
+    buffer_len: usize
+) -> isize {
+    catch_panic(-1, || {
+        let Some(model) = model.as_ref() else {
+            set_error("Model is NULL");
+
+            return -1;
+        };
+
+        let Some(prompt) = read_str(prompt, "Prompt") else {
+            return -1;
+        };
+
+        let overrides = if overrides.is_null() {
+            None
+        } else {
+            match read_str(overrides, "Overrides") {
+                Some(overrides) => Some(overrides),
+                None => return -1
+            }
+        };
+
+        let text = match model.generate(prompt, overrides) {
+            Ok(text) => text,
+
+            Err(err) => {
+                set_error(err);
+
+                return -1;
+            }
+        };
+
+        if !buffer.is_null() && buffer_len > 0 {
+            let mut len = text.len().min(buffer_len - 1);
+
+            while !text.is_char_boundary(len) {
+                len -= 1;
+            }
+
+            std::ptr::copy_nonoverlapping(text.as_ptr(), buffer as *mut u8, len);
+
+            *buffer.add(len) = 0;
+        }
+
+        text.len() as isize
+    })
+}
+
+#[no_mangle]
+/// Free the model
+///
+/// # Safety
+///
+/// `model` must be NULL or returned by the library and not freed.
+pub unsafe extern "C" fn mc_model_free(model: *mut McModel) {
+    catch_panic((), || {
+        if !model.is_null() {
+            drop(Box::from_raw(model));
+        }
+    })
+}
+
+mod tests {
+    #[test]
+    fn ffi() -> anyhow::Result<()> {
+        use std::ffi::{CStr, CString};
+
+        use crate::prelude::*;
+        use crate::bundle;
+
+        use super::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let bytes = bundle::to_bytes(&Model::build(dataset, true, true), 3)?;
+
+        unsafe {
+            let model = mc_model_from_bytes(bytes.as_ptr(), bytes.len());
+
+            assert!(!model.is_null());
+
+            let prompt = CString::new("hello")?;
+            let mut buffer = vec![0 as c_char; 64];
+
+            let len = mc_model_generate(model, prompt.as_ptr(), std::ptr::null(), buffer.as_mut_ptr(), buffer.len());
+
+            assert_eq!(len, 11);
+            assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str()?, "hello world");
+
+            // Text is cut to fit the buffer
+            let len = mc_model_generate(model, prompt.as_ptr(), std::ptr::null(), buffer.as_mut_ptr(), 4);
+
+            assert_eq!(len, 11);
+            assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str()?, "hel");
+
+            let prompt = CString::new("bye")?;
+
+            assert_eq!(mc_model_generate(model, prompt.as_ptr(), std::ptr::null(), buffer.as_mut_ptr(), buffer.len()), -1);
+            assert!(CStr::from_ptr(mc_last_error()).to_str()?.contains("bye"));
+
+            mc_model_free(model);
+
+            assert!(mc_model_from_bytes([1, 2, 3].as_ptr(), 3).is_null());
+        }
+
+        // Panics don't unwind into the host application
+        assert_eq!(catch_panic(-1, || panic!("Test panic")), -1);
+
+        assert!(unsafe { CStr::from_ptr(mc_last_error()) }.to_str()?.contains("Test panic"));
+
+        Ok(())
+    }
+}
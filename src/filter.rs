@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::prelude::{
+    Messages,
+    Dataset,
+    Tokens,
+    normalize_word
+};
+
+use crate::Error;
+
+/// Word replacing the blocked words when masking them
+pub const BLOCKED_PLACEHOLDER: &str = "<BLOCKED>";
+
+#[derive(Debug, Default, Clone)]
+/// Filter of the messages by their length and content
+///
+/// Patterns are matched against the message words joined
+/// by spaces, so the words are already lowercased unless
+/// the case was preserved when parsing.
+pub struct MessageFilter {
+    /// Skip messages with fewer words
+    pub min_words: Option<usize>,
+
+    /// Skip messages with more words
+    pub max_words: Option<usize>,
+
+    /// Keep only messages matching any of these patterns
+    ///
+    /// All the messages are kept if the list is empty.
+    pub include: Vec<Regex>,
+
+    /// Skip messages matching any of these patterns
+    pub exclude: Vec<Regex>
+}
+
+impl MessageFilter {
+    #[inline]
+    /// Keep only messages matching any of the include patterns
+    pub fn with_include(mut self, pattern: impl AsRef<str>) -> Result<Self, Error> {
+        self.include.push(Regex::new(pattern.as_ref())?);
+
+        Ok(self)
+    }
+
+    #[inline]
+    /// Skip messages matching the pattern
+    pub fn with_exclude(mut self, pattern: impl AsRef<str>) -> Result<Self, Error> {
+        self.exclude.push(Regex::new(pattern.as_ref())?);
+
+        Ok(self)
+    }
+
+    /// Check if the message passes the filter
+    pub fn accepts<T: AsRef<str>>(&self, words: &[T]) -> bool {
+        if self.min_words.is_some_and(|min| words.len() < min) {
+            return false;
+        }
+
+        if self.max_words.is_some_and(|max| words.len() > max) {
+            return false;
+        }
+
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+
+        let text = words.iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if !self.include.is_empty() && !self.include.iter().any(|pattern| pattern.is_match(&text)) {
+            return false;
+        }
+
+        !self.exclude.iter().any(|pattern| pattern.is_match(&text))
+    }
+}
+
+impl Messages {
+    /// Keep only messages which pass the filter
+    pub fn filter(mut self, filter: &MessageFilter) -> Self {
+        self.messages.retain(|message| filter.accepts(message));
+
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// List of the words models must not learn
+///
+/// Words are compared in their normalized form
+/// with the punctuation around them trimmed,
+/// so "Word" and "word!" are both blocked by "word".
+pub struct Blocklist {
+    words: HashSet<String>
+}
+
+impl Blocklist {
+    pub fn new<T: AsRef<str>>(words: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            words: words.into_iter()
+                .map(|word| Self::key(word.as_ref()))
+                .filter(|word| !word.is_empty())
+                .collect()
+        }
+    }
+
+    /// Parse the list from the text with a word per line
+    ///
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Self {
+        Self::new(text.lines().filter(|line| !line.trim_start().starts_with('#')))
+    }
+
+    #[inline]
+    fn key(word: &str) -> String {
+        let word = normalize_word(word.trim());
+
+        let trimmed = word.trim_matches(|char: char| !char.is_alphanumeric());
+
+        // Keep words made only of punctuation as is
+        if trimmed.is_empty() {
+            word
+        } else {
+            trimmed.to_string()
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    #[inline]
+    /// Check if the word is blocked
+    pub fn is_blocked(&self, word: &str) -> bool {
+        self.words.contains(&Self::key(word))
+    }
+
+    /// Find tokens of the blocked words
+    pub fn find_tokens(&self, tokens: &Tokens) -> HashSet<u64> {
+        tokens.word_token.iter()
+            .filter(|(word, _)| self.is_blocked(word))
+            .map(|(_, token)| *token)
+            .collect()
+    }
+
+    #[inline]
+    /// Check if the message contains blocked words
+    pub fn is_match<T: AsRef<str>>(&self, words: &[T]) -> bool {
+        words.iter().any(|word| self.is_blocked(word.as_ref()))
+    }
+}
+
+impl Messages {
+    /// Remove messages containing blocked words
+    pub fn drop_blocked(mut self, blocklist: &Blocklist) -> Self {
+        self.messages.retain(|message| !blocklist.is_match(message));
+
+        self
+    }
+
+    /// Replace blocked words by the `<BLOCKED>` placeholder
+    pub fn mask_blocked(mut self, blocklist: &Blocklist) -> Self {
+        for word in self.messages.iter_mut().flatten() {
+            if blocklist.is_blocked(word) {
+                *word = String::from(BLOCKED_PLACEHOLDER);
+            }
+        }
+
+        self
+    }
+}
+
+impl Dataset {
+    /// Remove messages containing blocked words and the words themselves
+    ///
+    /// Messages of the dialogues are answered by the next kept
+    /// message, so masking is preferred for the dialogues.
+    pub fn drop_blocked(mut self, blocklist: &Blocklist) -> Self {
+        let blocked = blocklist.find_tokens(&self.tokens);
+
+        for (messages, _) in &mut self.messages {
+            messages.messages.retain(|message| !message.iter().any(|token| blocked.contains(token)));
+        }
+
+        self.tokens.retain(|token| !blocked.contains(&token));
+
+        self
+    }
+
+    /// Replace blocked words by the `<BLOCKED>` placeholder
+    ///
+    /// Blocked words are removed from the dataset tokens.
+    pub fn mask_blocked(mut self, blocklist: &Blocklist) -> Self {
+        let blocked = blocklist.find_tokens(&self.tokens);
+
+        if blocked.is_empty() {
+            return self;
+        }
+
+        let placeholder = if self.tokens.is_hashed() {
+            self.tokens.insert_word_hashed(BLOCKED_PLACEHOLDER)
+        } else {
+            self.tokens.insert_word(BLOCKED_PLACEHOLDER)
+        };
+
+        for (messages, _) in &mut self.messages {
+            for token in messages.messages.iter_mut().flatten() {
+                if blocked.contains(token) {
+                    *token = placeholder;
+                }
+            }
+        }
+
+        self.tokens.retain(|token| !blocked.contains(&token));
+
+        self
+    }
+}
+
+mod tests {
+    #[test]
+    fn filter() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("lol"),
+            String::from("!play some song"),
+            String::from("https://example.com"),
+            String::from("this is a normal message"),
+            String::from("and this one is way too long to be a normal message")
+        ]);
+
+        let filter = MessageFilter {
+            min_words: Some(2),
+            max_words: Some(8),
+            ..MessageFilter::default()
+        };
+
+        let filter = filter.with_exclude("^!")?
+            .with_exclude(r"^https?://\S+$")?;
+
+        assert_eq!(messages.clone().filter(&filter).messages(), [
+            ["this", "is", "a", "normal", "message"]
+        ]);
+
+        let filter = MessageFilter::default()
+            .with_include("normal|song")?
+            .with_exclude("long")?;
+
+        assert_eq!(messages.filter(&filter).messages().len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocklist() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let blocklist = Blocklist::parse("# Blocked words\n\nbadword\n  Secret \n");
+
+        assert_eq!(blocklist.len(), 2);
+        assert!(blocklist.is_blocked("BadWord!"));
+        assert!(blocklist.is_blocked("secret"));
+        assert!(!blocklist.is_blocked("badwords"));
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world"),
+            String::from("this is a badword, sorry"),
+            String::from("tell me the secret")
+        ]);
+
+        assert_eq!(messages.clone().drop_blocked(&blocklist).messages(), [
+            ["hello", "world"]
+        ]);
+
+        assert_eq!(messages.clone().mask_blocked(&blocklist).messages()[1], [
+            "this", "is", "a", BLOCKED_PLACEHOLDER, "sorry"
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let dropped = dataset.clone().drop_blocked(&blocklist);
+
+        assert_eq!(dropped.stats().messages, 1);
+        assert!(dropped.tokens().find_token("secret").is_none());
+
+        let masked = dataset.mask_blocked(&blocklist);
+        let placeholder = masked.tokens().find_token(BLOCKED_PLACEHOLDER).unwrap();
+
+        assert_eq!(masked.stats().messages, 3);
+        assert_eq!(masked.messages()[0].0.messages()[2][3], placeholder);
+        assert!(masked.tokens().find_token("badword,").is_none());
+
+        Ok(())
+    }
+}
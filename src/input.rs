@@ -0,0 +1,169 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+/// Compression of the input file
+pub enum InputCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+    Xz
+}
+
+impl InputCompression {
+    /// Detect compression by the magic bytes of the file
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1F, 0x8B]) {
+            Some(Self::Gzip)
+        } else if header.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Self::Zstd)
+        } else if header.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Detect compression by the file extension
+    pub fn from_extension(path: impl AsRef<Path>) -> Self {
+        let extension = path.as_ref()
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("gz")  => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            Some("xz")  => Self::Xz,
+
+            _ => Self::None
+        }
+    }
+}
+
+/// Path of the file with the compression extension removed,
+/// so `messages.jsonl.gz` becomes `messages.jsonl`
+pub fn strip_compression_extension(path: &Path) -> PathBuf {
+    if InputCompression::from_extension(path) == InputCompression::None {
+        return path.to_path_buf();
+    }
+
+    path.with_extension("")
+}
+
+/// Open the file decompressing its gzip, zstd or xz contents
+///
+/// Compression is detected by the magic bytes and by the
+/// `.gz`, `.zst` and `.xz` extensions for the files without
+/// them, other files are read as is. Gzip and xz files
+/// need the `compression` feature.
+pub fn open_input(path: impl AsRef<Path>) -> Result<Box<dyn BufRead>, Error> {
+    let path = path.as_ref();
+
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+    let compression = InputCompression::from_magic(reader.fill_buf()?)
+        .unwrap_or_else(|| InputCompression::from_extension(path));
+
+    decode_input(reader, compression)
+}
+
+#[cfg(not(feature = "compression"))]
+#[inline]
+fn unsupported(format: &str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, format!("{format} inputs need the `compression` feature")).into()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_input(reader: BufReader<std::fs::File>, compression: InputCompression) -> Result<Box<dyn BufRead>, Error> {
+    Ok(match compression {
+        InputCompression::None => Box::new(reader),
+        InputCompression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+
+        #[cfg(feature = "compression")]
+        InputCompression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+
+        #[cfg(feature = "compression")]
+        InputCompression::Xz => Box::new(BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))),
+
+        #[cfg(not(feature = "compression"))]
+        InputCompression::Gzip => return Err(unsupported("gzip")),
+
+        #[cfg(not(feature = "compression"))]
+        InputCompression::Xz => return Err(unsupported("xz"))
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+/// liblzma can't be built for wasm, so xz inputs are not supported
+fn decode_input(reader: BufReader<std::fs::File>, compression: InputCompression) -> Result<Box<dyn BufRead>, Error> {
+    Ok(match compression {
+        InputCompression::None => Box::new(reader),
+
+        #[cfg(feature = "compression")]
+        InputCompression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+
+        #[cfg(not(feature = "compression"))]
+        InputCompression::Gzip => return Err(unsupported("gzip")),
+
+        InputCompression::Zstd => {
+            let decoder = ruzstd::StreamingDecoder::new(reader)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+            Box::new(BufReader::new(decoder))
+        }
+
+        InputCompression::Xz => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "xz inputs are not supported on wasm").into())
+    })
+}
+
+mod tests {
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_inputs() -> anyhow::Result<()> {
+        use std::io::{Read, Write};
+        use std::path::Path;
+
+        use super::*;
+
+        let text = "hello world\nsecond line\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+        gzip.write_all(text.as_bytes())?;
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+
+        xz.write_all(text.as_bytes())?;
+
+        let files = [
+            ("txt", text.as_bytes().to_vec()),
+            ("gz", gzip.finish()?),
+            ("zst", zstd::encode_all(text.as_bytes(), 3)?),
+            ("xz", xz.finish()?)
+        ];
+
+        for (extension, bytes) in files {
+            // Misleading extension to check the magic bytes detection
+            let path = std::env::temp_dir().join(format!("markov-chains-input-{}.{extension}.bin", std::process::id()));
+
+            std::fs::write(&path, bytes)?;
+
+            let mut decoded = String::new();
+
+            open_input(&path)?.read_to_string(&mut decoded)?;
+
+            std::fs::remove_file(&path)?;
+
+            assert_eq!(decoded, text);
+        }
+
+        assert_eq!(InputCompression::from_extension("dump.jsonl.XZ"), InputCompression::Xz);
+        assert_eq!(strip_compression_extension(Path::new("dump/messages.csv.gz")), Path::new("dump/messages.csv"));
+        assert_eq!(strip_compression_extension(Path::new("messages.csv")), Path::new("messages.csv"));
+
+        Ok(())
+    }
+}
@@ -1,33 +1,177 @@
+pub mod error;
 pub mod messages;
 pub mod tokens;
+pub mod tokens_table;
 pub mod tokenized_messages;
 pub mod ngram;
 pub mod dataset;
+pub mod manifest;
 pub mod model;
+pub mod prompt;
+pub mod anonymizer;
+pub mod discord;
+pub mod punctuation;
+pub mod bpe;
+pub mod tokenizer;
+pub mod similarity;
+pub mod filter;
+pub mod verify;
+pub mod input;
+pub mod sentences;
+pub mod bundle;
 
+#[cfg(feature = "cli")]
 pub mod cli;
 
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use error::Error;
+
 pub mod prelude {
-    pub use super::messages::Messages;
+    pub use super::messages::{
+        Messages,
+        MessagesStats,
+        NormalizationGroup,
+        normalize_word
+    };
 
     pub use super::tokens::{
         Tokens,
+        TokensRemap,
         START_TOKEN,
-        END_TOKEN
+        END_TOKEN,
+        REPLY_TOKEN,
+        START_TOKEN_NAME,
+        END_TOKEN_NAME,
+        REPLY_TOKEN_NAME,
+        UNK_TOKEN_NAME,
+        DEFAULT_TYPO_DISTANCE,
+        edit_distance
     };
 
+    pub use super::tokens_table::TokensEdit;
     pub use super::tokenized_messages::TokenizedMessages;
 
     pub use super::ngram::{
         Ngram,
         Unigram,
         Bigram,
-        Trigram
+        Trigram,
+        Quadgram,
+        Pentagram
+    };
+
+    pub use super::dataset::{Dataset, DatasetStats};
+    pub use super::manifest::ManifestEntry;
+    pub use super::model::params::{GenerationParams, GenerationOverrides, GenerationBounds, LoopAction};
+    pub use super::model::table::{
+        TransitionsTable,
+        TransitionsTableBuilder,
+        TransitionsRow,
+        DEFAULT_SHARDS
+    };
+
+    pub use super::model::transitions::{Transitions, TransitionsBuilder, MAX_ORDER};
+
+    pub use super::model::smoothing::{
+        SmoothingAlgorithm,
+        InterpolationLambdas,
+        Smoothing,
+        SmoothingStats
+    };
+
+    pub(crate) use super::model::smoothing::ContextSmoother;
+    pub use super::model::evaluation::Evaluation;
+    pub use super::model::cache::CandidateCache;
+    pub use super::model::schedule::{TemperatureSchedule, ScheduleCurve, MAX_SCHEDULE_STEPS};
+    pub use super::model::diagnostics::{Diagnostic, TableContinuations};
+    pub use super::model::model::{Model, CHECKSUM_HEADER};
+    pub use super::model::streaming::StreamingBuilder;
+    pub use super::model::beam::BeamCompletion;
+    pub use super::model::mapped::{MappedModel, MappedGenerator};
+    pub use super::model::ensemble::{ModelEnsemble, EnsembleGenerator};
+    pub use super::model::import::ChainFormat;
+    pub use super::model::graph::{GraphNode, GraphEdge, TransitionsGraph};
+    pub use super::model::classifier::{Classifier, Classification};
+
+    pub use super::model::sampler::{
+        Sampler,
+        DefaultSampler,
+        GreedySampler,
+        TemperatureSampler,
+        TopKSampler,
+        TopPSampler
+    };
+
+    pub use super::model::generator::{
+        Generator,
+        GeneratorState,
+        Words,
+        TokenProbability,
+        Probabilities,
+        find_cycle
+    };
+
+    pub use super::prompt::{
+        PromptTemplate,
+        PROMPT_PLACEHOLDER
     };
 
-    pub use super::dataset::Dataset;
-    pub use super::model::params::GenerationParams;
-    pub use super::model::transitions::Transitions;
-    pub use super::model::generator::Generator;
-    pub use super::model::model::Model;
+    pub use super::anonymizer::{
+        Anonymizer,
+        EMAIL_PLACEHOLDER,
+        PHONE_PLACEHOLDER,
+        HANDLE_PLACEHOLDER,
+        NAME_PLACEHOLDER,
+        URL_PLACEHOLDER,
+        USER_PLACEHOLDER,
+        CARD_PLACEHOLDER
+    };
+
+    pub use super::discord::{
+        DiscordMessage,
+        DiscordFilter
+    };
+
+    pub use super::punctuation::{
+        Punctuation,
+        DEFAULT_PUNCTUATION,
+        PUNCTUATION_HEADER
+    };
+
+    pub use super::bpe::{
+        Bpe,
+        SUBWORD_MARKER,
+        SUBWORDS_HEADER
+    };
+
+    pub use super::tokenizer::{
+        Tokenizer,
+        TokenizerKind,
+        WhitespaceTokenizer,
+        CharTokenizer,
+        ModelTokenizer,
+        TOKENIZER_HEADER
+    };
+
+    pub use super::similarity::{
+        NearDuplicates,
+        MINHASH_SIZE,
+        DEFAULT_SIMILARITY
+    };
+
+    pub use super::sentences::{
+        SplitMode,
+        split_sentences
+    };
+
+    pub use super::filter::{
+        MessageFilter,
+        Blocklist,
+        BLOCKED_PLACEHOLDER
+    };
 }
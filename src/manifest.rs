@@ -0,0 +1,31 @@
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+/// Description of the input file used to build a bundle
+pub struct ManifestEntry {
+    pub path: PathBuf,
+
+    /// Size of the file in bytes
+    pub size: u64,
+
+    /// Hex-encoded blake3 hash of the file content
+    pub hash: String
+}
+
+impl ManifestEntry {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let mut hasher = blake3::Hasher::new();
+
+        hasher.update_reader(std::fs::File::open(path)?)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: path.metadata()?.len(),
+            hash: hasher.finalize().to_hex().to_string()
+        })
+    }
+}
@@ -1,49 +1,209 @@
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 use std::path::Path;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+use unicode_normalization::UnicodeNormalization;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::input::open_input;
+use crate::Error;
+
+/// Normalize the word to compare it with its spelling variants
+///
+/// Applies unicode compatibility normalization, lowercasing
+/// and replaces typographic quotes and dashes by the ASCII ones.
+pub fn normalize_word(word: &str) -> String {
+    word.nfkc()
+        .flat_map(char::to_lowercase)
+        .map(|char| match char {
+            '\u{2018}' | '\u{2019}' | '\u{201B}' | '\u{02BC}' | '`' | '\u{00B4}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201F}' | '\u{00AB}' | '\u{00BB}' => '"',
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => '-',
+            _ => char
+        })
+        .collect()
+}
+
+/// Get string value by the dot-separated path
+///
+/// Numeric path segments index arrays.
+pub fn json_field<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a str> {
+    let mut value = value;
+
+    for key in path.split('.').filter(|key| !key.is_empty()) {
+        value = match value {
+            serde_json::Value::Object(object) => object.get(key)?,
+            serde_json::Value::Array(array) => array.get(key.parse::<usize>().ok()?)?,
+
+            _ => return None
+        };
+    }
+
+    value.as_str()
+}
+
+/// Split the plain text or json string line into filtered words
+pub(crate) fn parse_line(line: &str, filter: impl Fn(&str) -> String) -> Vec<String> {
+    let line = line.trim().to_string();
+
+    let line = serde_json::from_str::<String>(&line)
+        .unwrap_or(line);
+
+    line.split_whitespace()
+        .filter(|word| !word.is_empty())
+        .map(filter)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Words which normalize to the same form
+pub struct NormalizationGroup {
+    pub normalized: String,
+
+    /// (word, occurrences), most frequent first
+    pub variants: Vec<(String, u64)>
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Summary of the messages contents
+pub struct MessagesStats {
+    /// Amount of messages
+    pub messages: usize,
+
+    /// Amount of unique messages
+    pub unique_messages: usize,
+
+    /// Amount of words in all the messages
+    pub words: u64,
+
+    /// Amount of unique words
+    pub vocabulary: usize,
+
+    /// Amount of words seen only once
+    pub singletons: usize,
+
+    /// Sorted lengths of the messages
+    lengths: Vec<usize>
+}
+
+impl MessagesStats {
+    #[inline]
+    /// Average length of the messages in words
+    pub fn average_len(&self) -> f64 {
+        if self.messages == 0 {
+            return 0.0;
+        }
+
+        self.words as f64 / self.messages as f64
+    }
+
+    #[inline]
+    /// Length of the messages below which the given percent of them lies
+    ///
+    /// Uses the nearest-rank method, so the result is always
+    /// the length of some message, or 0 if there are none.
+    pub fn percentile_len(&self, percentile: f64) -> usize {
+        if self.lengths.is_empty() {
+            return 0;
+        }
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.lengths.len() as f64).ceil() as usize;
+
+        self.lengths[rank.saturating_sub(1)]
+    }
+
+    /// Amount of messages by their lengths
+    ///
+    /// Returns (min length, max length, messages) buckets
+    /// doubling in size: 1, 2-3, 4-7, 8-15 and so on up
+    /// to the longest message, empty buckets are kept.
+    pub fn length_histogram(&self) -> Vec<(usize, usize, usize)> {
+        let mut histogram = Vec::new();
+
+        for length in &self.lengths {
+            let bucket = length.max(&1).ilog2() as usize;
+
+            while histogram.len() <= bucket {
+                let min = 1 << histogram.len();
+
+                histogram.push((min, min * 2 - 1, 0));
+            }
+
+            histogram[bucket].2 += 1;
+        }
+
+        histogram
+    }
+}
 
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// List of the parsed messages
+///
+/// Messages are stored in the parsing order, and repeated messages
+/// are kept so they weigh proportionally to their frequency.
+/// Use `dedup` to keep only unique messages.
 pub struct Messages {
-    pub(crate) messages: HashSet<Vec<String>>
+    pub(crate) messages: Vec<Vec<String>>
 }
 
 impl Messages {
     #[inline]
-    pub fn parse_from_messages(file: impl AsRef<Path>) -> anyhow::Result<Self> {
+    pub fn parse_from_messages(file: impl AsRef<Path>) -> Result<Self, Error> {
         Self::parse_from_messages_with_filter(file, |word| word.to_lowercase())
     }
 
-    pub fn parse_from_messages_with_filter(file: impl AsRef<Path>, filter: impl Fn(&str) -> String) -> anyhow::Result<Self> {
-        let file = std::fs::File::open(file)?;
-
-        let lines = std::io::BufReader::new(file)
+    pub fn parse_from_messages_with_filter(file: impl AsRef<Path>, filter: impl Fn(&str) -> String) -> Result<Self, Error> {
+        let lines = open_input(file)?
             .lines()
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Self::parse_from_lines_with_filter(&lines, filter))
     }
 
+    #[inline]
+    /// Parse messages from the newline-delimited JSON file
+    ///
+    /// `field` is a dot-separated path to the message text in every
+    /// JSON object, e.g. `text.body` or `replies.0.text`. Lines without
+    /// the string field are skipped.
+    pub fn parse_from_jsonl(file: impl AsRef<Path>, field: &str) -> Result<Self, Error> {
+        Self::parse_from_jsonl_with_filter(file, field, |word| word.to_lowercase())
+    }
+
+    pub fn parse_from_jsonl_with_filter(file: impl AsRef<Path>, field: &str, filter: impl Fn(&str) -> String) -> Result<Self, Error> {
+        let mut lines = Vec::new();
+
+        for line in open_input(file)?.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let value = serde_json::from_str::<serde_json::Value>(&line)?;
+
+            if let Some(text) = json_field(&value, field) {
+                lines.push(text.to_string());
+            }
+        }
+
+        Ok(Self::parse_from_lines_with_filter(&lines, filter))
+    }
+
     #[inline]
     pub fn parse_from_lines(lines: &[String]) -> Self {
         Self::parse_from_lines_with_filter(lines, |word| word.to_lowercase())
     }
 
     pub fn parse_from_lines_with_filter(lines: &[String], filter: impl Fn(&str) -> String) -> Self {
-        let mut messages = HashSet::new();
+        let mut messages = Vec::with_capacity(lines.len());
 
         for line in lines {
-            let line = line.trim().to_string();
-
-            let line = serde_json::from_str::<String>(&line)
-                .unwrap_or(line);
-
-            let words = line.split_whitespace()
-                .filter(|word| !word.is_empty())
-                .map(&filter)
-                .collect::<Vec<_>>();
+            let words = parse_line(line, &filter);
 
             if !words.is_empty() {
-                messages.insert(words);
+                messages.push(words);
             }
         }
 
@@ -53,16 +213,192 @@ impl Messages {
     }
 
     #[inline]
-    pub fn messages(&self) -> &HashSet<Vec<String>> {
+    pub fn messages(&self) -> &[Vec<String>] {
         &self.messages
     }
 
+    /// Get amount of occurrences of every word
+    pub fn word_counts(&self) -> HashMap<&str, u64> {
+        let mut counts = HashMap::new();
+
+        for word in self.messages.iter().flatten() {
+            *counts.entry(word.as_str()).or_default() += 1;
+        }
+
+        counts
+    }
+
+    /// Write messages to the writer one per line
+    ///
+    /// Lines which would be read as JSON strings by the parser
+    /// are written as JSON strings, so parsing the output
+    /// returns the same messages.
+    pub fn write_lines(&self, mut writer: impl Write) -> Result<(), Error> {
+        for message in &self.messages {
+            let line = message.join(" ");
+
+            if serde_json::from_str::<String>(&line).is_ok() {
+                writeln!(writer, "{}", serde_json::to_string(&line)?)?;
+            } else {
+                writeln!(writer, "{line}")?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Calculate statistics of the messages
+    pub fn stats(&self) -> MessagesStats {
+        let counts = self.word_counts();
+
+        let mut lengths = self.messages.iter()
+            .map(Vec::len)
+            .collect::<Vec<_>>();
+
+        lengths.sort_unstable();
+
+        MessagesStats {
+            messages: self.messages.len(),
+            unique_messages: self.messages.iter().collect::<HashSet<_>>().len(),
+            words: lengths.iter().sum::<usize>() as u64,
+            vocabulary: counts.len(),
+            singletons: counts.values().filter(|count| **count == 1).count(),
+            lengths
+        }
+    }
+
+    /// Get up to `count` random messages
+    ///
+    /// Messages are returned in their order in the list.
+    /// The same seed returns the same messages.
+    pub fn sample(&self, count: usize, seed: Option<u64>) -> Vec<&[String]> {
+        let mut rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        };
+
+        let mut indexes = rand::seq::index::sample(&mut rng, self.messages.len(), count.min(self.messages.len()))
+            .into_vec();
+
+        indexes.sort_unstable();
+
+        indexes.into_iter()
+            .map(|index| self.messages[index].as_slice())
+            .collect()
+    }
+
+    /// Remove repeated messages keeping their first occurrences
+    pub fn dedup(mut self) -> Self {
+        let mut seen = HashSet::with_capacity(self.messages.len());
+
+        self.messages.retain(|message| seen.insert(message.clone()));
+
+        self
+    }
+
+    /// Split messages longer than `window` words into windows
+    /// of `window` words overlapping by `overlap` words
+    ///
+    /// The last window of every message ends with its last word,
+    /// so it can overlap the previous one by more words. Overlap
+    /// is reduced to `window - 1` words if it's not less than the window.
+    pub fn chunk(self, window: usize, overlap: usize) -> Self {
+        let window = window.max(1);
+        let step = window - overlap.min(window - 1);
+
+        let mut messages = Vec::with_capacity(self.messages.len());
+
+        for message in self.messages {
+            if message.len() <= window {
+                messages.push(message);
+
+                continue;
+            }
+
+            let mut start = 0;
+
+            loop {
+                let end = (start + window).min(message.len());
+
+                messages.push(message[end - window..end].to_vec());
+
+                if end == message.len() {
+                    break;
+                }
+
+                start += step;
+            }
+        }
+
+        Self {
+            messages
+        }
+    }
+
     #[inline]
     pub fn merge(mut self, messages: Messages) -> Self {
         self.messages.extend(messages.messages);
 
         self
     }
+
+    /// Find words which have several spelling variants
+    /// according to the `normalize_word` function
+    pub fn normalization_report(&self) -> Vec<NormalizationGroup> {
+        let counts = self.word_counts();
+
+        let mut groups = HashMap::<String, Vec<(String, u64)>>::new();
+
+        for (word, count) in counts {
+            groups.entry(normalize_word(word))
+                .or_default()
+                .push((word.to_string(), count));
+        }
+
+        let mut groups = groups.into_iter()
+            .filter(|(_, variants)| variants.len() > 1)
+            .map(|(normalized, mut variants)| {
+                variants.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+                NormalizationGroup {
+                    normalized,
+                    variants
+                }
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by(|a, b| a.normalized.cmp(&b.normalized));
+
+        groups
+    }
+
+    /// Replace spelling variants of the words by their most frequent variant
+    pub fn merge_normalized(self) -> Self {
+        let replacements = self.normalization_report()
+            .into_iter()
+            .flat_map(|group| {
+                let target = group.variants[0].0.clone();
+
+                group.variants.into_iter()
+                    .skip(1)
+                    .map(move |(word, _)| (word, target.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let messages = self.messages.into_iter()
+            .map(|message| {
+                message.into_iter()
+                    .map(|word| replacements.get(&word).cloned().unwrap_or(word))
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            messages
+        }
+    }
 }
 
 mod tests {
@@ -86,6 +422,130 @@ mod tests {
         ]));
     }
 
+    #[test]
+    fn json_field() {
+        use super::json_field;
+
+        let value = serde_json::json!({
+            "text": { "body": "Hello" },
+            "replies": [{ "text": "World" }],
+            "score": 10
+        });
+
+        assert_eq!(json_field(&value, "text.body"), Some("Hello"));
+        assert_eq!(json_field(&value, "replies.0.text"), Some("World"));
+        assert_eq!(json_field(&value, "replies.1.text"), None);
+        assert_eq!(json_field(&value, "score"), None);
+        assert_eq!(json_field(&value, "text"), None);
+    }
+
+    #[test]
+    fn multiplicity() {
+        use super::Messages;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Example text"),
+            String::from("Hello, World!")
+        ]);
+
+        assert_eq!(messages.messages().len(), 3);
+        assert_eq!(messages.messages()[1], [String::from("example"), String::from("text")]);
+
+        let messages = messages.dedup();
+
+        assert_eq!(messages.messages(), [
+            vec![String::from("hello,"), String::from("world!")],
+            vec![String::from("example"), String::from("text")]
+        ]);
+    }
+
+    #[test]
+    fn chunking() {
+        use super::Messages;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c d e f g"),
+            String::from("short one")
+        ]);
+
+        let chunks = messages.clone().chunk(3, 1);
+
+        assert_eq!(chunks.messages()[..3], [
+            ["a", "b", "c"],
+            ["c", "d", "e"],
+            ["e", "f", "g"]
+        ]);
+
+        assert_eq!(chunks.messages()[3], ["short", "one"]);
+
+        // The last window ends with the last word
+        assert_eq!(messages.clone().chunk(4, 1).messages()[..2], [
+            ["a", "b", "c", "d"],
+            ["d", "e", "f", "g"]
+        ]);
+
+        assert_eq!(messages.clone().chunk(5, 0).messages()[1], ["c", "d", "e", "f", "g"]);
+
+        // Overlap is reduced to keep the windows moving
+        assert_eq!(messages.chunk(2, 5).messages().len(), 7);
+    }
+
+    #[test]
+    fn stats() {
+        use super::Messages;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello"),
+            String::from("hello world"),
+            String::from("hello world"),
+            String::from("this is a bit longer message")
+        ]);
+
+        let stats = messages.stats();
+
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.unique_messages, 3);
+        assert_eq!(stats.words, 11);
+        assert_eq!(stats.vocabulary, 8);
+        assert_eq!(stats.singletons, 6);
+        assert_eq!(stats.percentile_len(50.0), 2);
+        assert_eq!(stats.length_histogram(), [(1, 1, 1), (2, 3, 2), (4, 7, 1)]);
+
+        let sample = messages.sample(2, Some(1));
+
+        assert_eq!(sample.len(), 2);
+        assert_eq!(sample, messages.sample(2, Some(1)));
+        assert_eq!(messages.sample(10, None).len(), 4);
+    }
+
+    #[test]
+    fn write_lines() -> anyhow::Result<()> {
+        use super::Messages;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("\"\\\"quoted\\\"\""),
+            String::from("\"half quoted")
+        ]);
+
+        assert_eq!(messages.messages()[1], ["\"quoted\""]);
+
+        let mut output = Vec::new();
+
+        messages.write_lines(&mut output)?;
+
+        let lines = String::from_utf8(output)?
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, ["hello, world!", "\"\\\"quoted\\\"\"", "\"half quoted"]);
+        assert_eq!(Messages::parse_from_lines(&lines).messages(), messages.messages());
+
+        Ok(())
+    }
+
     #[test]
     fn merging() {
         use super::Messages;
@@ -108,4 +568,37 @@ mod tests {
             String::from("text")
         ]));
     }
+
+    #[test]
+    fn normalization() {
+        use super::{Messages, normalize_word};
+
+        assert_eq!(normalize_word("Don\u{2019}t"), "don't");
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("don't stop"),
+            String::from("don't go"),
+            String::from("don\u{2019}t stop me")
+        ]);
+
+        let report = messages.normalization_report();
+
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].normalized, "don't");
+
+        assert_eq!(report[0].variants, [
+            (String::from("don't"), 2),
+            (String::from("don\u{2019}t"), 1)
+        ]);
+
+        let messages = messages.merge_normalized();
+
+        assert!(messages.messages().contains(&vec![
+            String::from("don't"),
+            String::from("stop"),
+            String::from("me")
+        ]));
+
+        assert!(messages.normalization_report().is_empty());
+    }
 }
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::prelude::{
+    TransitionsTable,
+    Model,
+    START_TOKEN,
+    END_TOKEN
+};
+
+use crate::Error;
+
+/// Word of the beginning of the text in ARPA files
+pub const ARPA_START: &str = "<s>";
+
+/// Word of the end of the text in ARPA files
+pub const ARPA_END: &str = "</s>";
+
+/// Log10 probability of the words which can't be predicted
+const ARPA_ZERO: f64 = -99.0;
+
+/// Counts of the words following the history
+#[derive(Debug, Default)]
+struct ArpaRow {
+    words: HashMap<u64, u64>,
+    total: u64
+}
+
+impl ArpaRow {
+    #[inline]
+    fn observe(&mut self, word: u64, count: u64) {
+        *self.words.entry(word).or_default() += count;
+
+        self.total += count;
+    }
+
+    #[inline]
+    /// Witten-Bell discounted probability of the seen word
+    fn discounted(&self, word: u64) -> Option<f64> {
+        let count = self.words.get(&word)?;
+
+        Some(*count as f64 / (self.total + self.words.len() as u64) as f64)
+    }
+
+    #[inline]
+    /// Probability mass reserved for the unseen words
+    fn reserved(&self) -> f64 {
+        self.words.len() as f64 / (self.total + self.words.len() as u64) as f64
+    }
+}
+
+/// Backoff language model built from the transitions tables
+///
+/// Histories of the length `n` are stored at the `n`th level.
+struct ArpaModel {
+    levels: Vec<HashMap<Vec<u64>, ArpaRow>>,
+    backoffs: Vec<HashMap<Vec<u64>, f64>>
+}
+
+impl ArpaModel {
+    fn new(model: &Model) -> Self {
+        let transitions = model.transitions();
+
+        let mut levels = (0..=transitions.order())
+            .map(|_| HashMap::new())
+            .collect::<Vec<_>>();
+
+        // Words are counted by their appearances as continuations
+        let mut unigrams = ArpaRow::default();
+
+        for (_, row) in transitions.unigrams.iter() {
+            for (next, count) in row {
+                unigrams.observe(Self::word(next.is_end(), next.token()), *count);
+            }
+        }
+
+        levels[0].insert(vec![], unigrams);
+
+        Self::add_table(&mut levels[1], &transitions.unigrams);
+
+        if let Some(table) = &transitions.bigrams {
+            Self::add_table(&mut levels[2], table);
+        }
+
+        if let Some(table) = &transitions.trigrams {
+            Self::add_table(&mut levels[3], table);
+        }
+
+        if let Some(table) = &transitions.quadgrams {
+            Self::add_table(&mut levels[4], table);
+        }
+
+        if let Some(table) = &transitions.pentagrams {
+            Self::add_table(&mut levels[5], table);
+        }
+
+        let mut model = Self {
+            levels,
+            backoffs: Vec::new()
+        };
+
+        // Backoff weights of each level use only the lower ones
+        for level in 0..model.levels.len() {
+            let backoffs = model.levels[level].iter()
+                .map(|(history, row)| (history.clone(), model.compute_backoff(history, row)))
+                .collect();
+
+            model.backoffs.push(backoffs);
+        }
+
+        model
+    }
+
+    #[inline]
+    fn word(is_end: bool, token: u64) -> u64 {
+        if is_end { END_TOKEN } else { token }
+    }
+
+    fn add_table<const SIZE: usize>(level: &mut HashMap<Vec<u64>, ArpaRow>, table: &TransitionsTable<SIZE>) {
+        for (context, row) in table.iter() {
+            let history = context.tokens();
+
+            // ARPA histories have a single beginning of the text, and contexts
+            // padded with more of them repeat the lower order tables
+            if SIZE > 1 && history[1] == START_TOKEN {
+                continue;
+            }
+
+            let entry = level.entry(history.to_vec()).or_default();
+
+            for (next, count) in row {
+                entry.observe(Self::word(next.is_end(), next.token()), *count);
+            }
+        }
+    }
+
+    /// Weight of the lower order probabilities of the words unseen after the history
+    fn compute_backoff(&self, history: &[u64], row: &ArpaRow) -> f64 {
+        if history.is_empty() {
+            return 1.0;
+        }
+
+        let seen = row.words.keys()
+            .map(|word| self.probability(&history[1..], *word))
+            .sum::<f64>();
+
+        row.reserved() / (1.0 - seen).max(f64::EPSILON)
+    }
+
+    #[inline]
+    fn backoff(&self, history: &[u64]) -> f64 {
+        self.backoffs.get(history.len())
+            .and_then(|backoffs| backoffs.get(history))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Probability of the word following the history
+    fn probability(&self, history: &[u64], word: u64) -> f64 {
+        let row = self.levels[history.len()].get(history);
+
+        if history.is_empty() {
+            return row.and_then(|row| Some(*row.words.get(&word)? as f64 / row.total as f64))
+                .unwrap_or(0.0);
+        }
+
+        match row.and_then(|row| row.discounted(word)) {
+            Some(probability) => probability,
+            None => self.backoff(history) * self.probability(&history[1..], word)
+        }
+    }
+
+    /// Sorted ngrams of the given order with their probabilities
+    fn ngrams(&self, order: usize) -> Vec<(Vec<u64>, f64)> {
+        let mut ngrams = self.levels[order - 1].iter()
+            .flat_map(|(history, row)| {
+                row.words.keys().map(move |word| {
+                    let mut ngram = history.clone();
+
+                    ngram.push(*word);
+
+                    let probability = if history.is_empty() {
+                        self.probability(history, *word)
+                    } else {
+                        row.discounted(*word).unwrap_or_default()
+                    };
+
+                    (ngram, probability)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Beginning of the text is never predicted but has a backoff weight
+        if order == 1 {
+            ngrams.push((vec![START_TOKEN], 0.0));
+        }
+
+        ngrams.sort_by(|a, b| a.0.cmp(&b.0));
+
+        ngrams
+    }
+}
+
+#[inline]
+fn log10(value: f64) -> f64 {
+    if value > 0.0 { value.log10() } else { ARPA_ZERO }
+}
+
+impl Model {
+    /// Write the model as an ARPA backoff language model
+    ///
+    /// Transitions tables of the order `n` become `n + 1`-grams, and
+    /// words are counted by their appearances as continuations. Seen
+    /// ngrams use Witten-Bell discounted probabilities, and backoff weights
+    /// spread the reserved mass over the lower order probabilities of the
+    /// unseen words.
+    ///
+    /// Beginning and end of the text are written as `<s>` and `</s>`.
+    pub fn write_arpa(&self, mut writer: impl Write) -> Result<(), Error> {
+        let arpa = ArpaModel::new(self);
+
+        let orders = (1..=arpa.levels.len())
+            .map(|order| arpa.ngrams(order))
+            .collect::<Vec<_>>();
+
+        let word = |token: u64| match token {
+            START_TOKEN => ARPA_START.to_string(),
+            END_TOKEN => ARPA_END.to_string(),
+
+            _ => self.tokens().find_word(token)
+                .map(String::from)
+                .unwrap_or_else(|| token.to_string())
+        };
+
+        writeln!(writer)?;
+        writeln!(writer, "\\data\\")?;
+
+        for (i, ngrams) in orders.iter().enumerate() {
+            writeln!(writer, "ngram {}={}", i + 1, ngrams.len())?;
+        }
+
+        for (i, ngrams) in orders.iter().enumerate() {
+            writeln!(writer)?;
+            writeln!(writer, "\\{}-grams:", i + 1)?;
+
+            for (ngram, probability) in ngrams {
+                let words = ngram.iter()
+                    .map(|token| word(*token))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                write!(writer, "{:.6}\t{words}", log10(*probability))?;
+
+                // Only ngrams continued by the higher orders have backoff weights
+                match arpa.backoffs.get(ngram.len()).and_then(|backoffs| backoffs.get(ngram)) {
+                    Some(backoff) => writeln!(writer, "\t{:.6}", log10(*backoff))?,
+                    None => writeln!(writer)?
+                }
+            }
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "\\end\\")?;
+
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn write_arpa() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("a b"),
+            String::from("a c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 2);
+
+        let mut arpa = Vec::new();
+
+        model.write_arpa(&mut arpa)?;
+
+        let arpa = String::from_utf8(arpa)?;
+        let lines = arpa.lines().collect::<Vec<_>>();
+
+        // <s>, </s>, a, b, c
+        assert!(lines.contains(&"ngram 1=5"));
+
+        // <s> a, a b, a c, b </s>, c </s>
+        assert!(lines.contains(&"ngram 2=5"));
+
+        // <s> a b, <s> a c, a b </s>, a c </s>
+        assert!(lines.contains(&"ngram 3=4"));
+
+        assert!(lines.contains(&"\\3-grams:"));
+        assert!(lines.contains(&"\\end\\"));
+
+        // Unigrams are maximum likelihood estimates: 3 of 9 continuations are </s>
+        assert!(lines.iter().any(|line| line.starts_with(&format!("{:.6}\t</s>", (1.0f64 / 3.0).log10()))));
+
+        // Beginning of the text is never predicted
+        assert!(lines.iter().any(|line| line.starts_with("-99.000000\t<s>\t")));
+
+        // Witten-Bell discounting: 2 of 3 continuations with 2 distinct words
+        assert!(lines.iter().any(|line| line.starts_with(&format!("{:.6}\ta b\t", 0.4f64.log10()))));
+
+        // Highest order ngrams have no backoff weights
+        assert!(lines.contains(&format!("{:.6}\ta b </s>", (2.0f64 / 3.0).log10()).as_str()));
+
+        Ok(())
+    }
+}
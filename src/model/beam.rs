@@ -0,0 +1,184 @@
+use crate::prelude::{
+    GenerationParams,
+    ContextSmoother,
+    Model,
+    END_TOKEN
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Completion found by the beam search
+pub struct BeamCompletion {
+    /// Generated tokens, including the beginning
+    pub chain: Vec<u64>,
+
+    /// Sum of the natural logarithms of the generated tokens' probabilities,
+    /// including the end of the text if it was reached
+    pub score: f64
+}
+
+impl Model {
+    /// Find the most probable continuations of the beginning
+    ///
+    /// Keeps `width` best partial chains by their cumulative log-probability
+    /// on every step and expands them by continuations of the highest order
+    /// table which has them. Chains end with the end of the text or when they
+    /// reach `max_len` tokens. Like in the generator, the end of the text and
+    /// dead-end tokens are not used before `min_len` tokens if there are
+    /// other variants.
+    ///
+    /// Returns at most `width` completions, the most probable first.
+    pub fn beam_search(&self, beginning: impl Into<Vec<u64>>, width: usize, params: &GenerationParams) -> Vec<BeamCompletion> {
+        let width = width.max(1);
+
+        let mut beams = vec![BeamCompletion {
+            chain: beginning.into(),
+            score: 0.0
+        }];
+
+        let mut finished = Vec::new();
+
+        while !beams.is_empty() {
+            let mut candidates = Vec::with_capacity(beams.len() * width);
+
+            for beam in beams {
+                if beam.chain.len() >= params.max_len {
+                    finished.push(beam);
+
+                    continue;
+                }
+
+                let rows = self.transitions.context_rows(&beam.chain, |order| params.is_order_enabled(order));
+
+                let mut continuations = rows.iter()
+                    .rev()
+                    .map(|row| row.continuations())
+                    .find(|continuations| !continuations.is_empty())
+                    .unwrap_or_default();
+
+                // Same as the generator, avoid the end of the text and dead-ends
+                // before the minimal length if there are other variants
+                if beam.chain.len() < params.min_len {
+                    if continuations.iter().any(|(token, _)| *token != END_TOKEN) {
+                        continuations.retain(|(token, _)| *token != END_TOKEN);
+                    }
+
+                    if continuations.iter().any(|(token, _)| !self.is_dead_end(*token)) {
+                        continuations.retain(|(token, _)| !self.is_dead_end(*token));
+                    }
+                }
+
+                // Chain has no continuations at all
+                if continuations.is_empty() {
+                    finished.push(beam);
+
+                    continue;
+                }
+
+                let smoother = ContextSmoother::new(rows, self.smoothing_stats(), params.smoothing);
+
+                let mut scored = continuations.into_iter()
+                    .filter_map(|(token, _)| {
+                        let probability = smoother.probability(token)?;
+
+                        (probability > 0.0).then(|| (token, beam.score + probability.ln()))
+                    })
+                    .collect::<Vec<_>>();
+
+                // Only the best continuations of each beam can get into the next step
+                scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+                scored.truncate(width);
+
+                for (token, score) in scored {
+                    if token == END_TOKEN {
+                        finished.push(BeamCompletion {
+                            chain: beam.chain.clone(),
+                            score
+                        });
+                    } else {
+                        let mut chain = beam.chain.clone();
+
+                        chain.push(token);
+
+                        candidates.push(BeamCompletion {
+                            chain,
+                            score
+                        });
+                    }
+                }
+            }
+
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+            candidates.truncate(width);
+
+            // Stop if no partial chain can beat the finished ones,
+            // since scores only decrease with new tokens
+            if finished.len() >= width {
+                finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+                finished.truncate(width);
+
+                let worst = finished[width - 1].score;
+
+                candidates.retain(|candidate| candidate.score > worst);
+            }
+
+            beams = candidates;
+        }
+
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(width);
+
+        finished
+    }
+}
+
+mod tests {
+    #[test]
+    fn beam_search() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("a b d e"),
+            String::from("a x")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 2);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let params = GenerationParams::default();
+
+        let completions = model.beam_search([token("a")], 3, &params);
+
+        assert_eq!(completions.len(), 3);
+        assert_eq!(completions[0].chain, [token("a"), token("b"), token("c")]);
+        assert!((completions[0].score - (0.75f64 * 2.0 / 3.0).ln()).abs() < 1e-9);
+
+        assert!(completions.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // Greedy search is the beam of width 1
+        let completions = model.beam_search([token("a")], 1, &params);
+
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].chain, [token("a"), token("b"), token("c")]);
+
+        // End of the text is not used before the minimal length
+        let params = GenerationParams {
+            min_len: 4,
+            ..GenerationParams::default()
+        };
+
+        let completions = model.beam_search([token("a")], 1, &params);
+
+        assert_eq!(completions[0].chain, [token("a"), token("b"), token("d"), token("e")]);
+
+        Ok(())
+    }
+}
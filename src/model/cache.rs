@@ -0,0 +1,83 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use lru::LruCache;
+
+pub const DEFAULT_CANDIDATE_CACHE_CAPACITY: usize = 4096;
+
+/// LRU cache of the sorted continuations of the context ngrams
+///
+/// Can be shared between generators of the same model to avoid
+/// collecting and sorting continuations of the same contexts
+/// multiple times. Must be cleared if the model was changed.
+pub struct CandidateCache {
+    /// context tokens -> (token, count) sorted by count
+    pub(crate) entries: LruCache<Vec<u64>, Arc<[(u64, u64)]>>,
+
+    pub(crate) hits: u64,
+    pub(crate) misses: u64
+}
+
+impl Default for CandidateCache {
+    #[inline]
+    fn default() -> Self {
+        Self::new(DEFAULT_CANDIDATE_CACHE_CAPACITY)
+    }
+}
+
+impl CandidateCache {
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    #[inline]
+    /// Get cached continuations of the context
+    pub fn get(&mut self, context: &[u64]) -> Option<Arc<[(u64, u64)]>> {
+        let continuations = self.entries.get(context).cloned();
+
+        if continuations.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+
+        continuations
+    }
+
+    #[inline]
+    pub fn insert(&mut self, context: &[u64], continuations: Arc<[(u64, u64)]>) {
+        self.entries.put(context.to_vec(), continuations);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    /// Amount of successful lookups
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    #[inline]
+    /// Amount of failed lookups
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::Path;
+
+use crate::prelude::{
+    Model,
+    Smoothing
+};
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Result of the text classification
+pub struct Classification {
+    /// Name of the model giving the text the highest likelihood
+    pub label: String,
+
+    /// Natural log-odds of the winner against the runner-up
+    pub log_odds: f64,
+
+    /// Posterior probability of the winner assuming equal priors
+    pub confidence: f64,
+
+    /// Log-likelihoods of the text under every model, in the models order
+    pub scores: Vec<(String, f64)>
+}
+
+#[derive(Debug, Clone)]
+/// Set of named models scoring texts against each other
+pub struct Classifier {
+    models: Vec<(String, Model)>,
+    smoothing: Smoothing,
+    unseen_log_probability: f64
+}
+
+impl Classifier {
+    #[inline]
+    /// Create classifier from the named models
+    ///
+    /// See `Model::log_likelihood` for `unseen_log_probability` meaning.
+    pub fn new(models: Vec<(String, Model)>, smoothing: Smoothing, unseen_log_probability: f64) -> Self {
+        Self {
+            models,
+            smoothing,
+            unseen_log_probability
+        }
+    }
+
+    /// Load models from the files named by their labels
+    pub fn load(paths: &[impl AsRef<Path>], smoothing: Smoothing, unseen_log_probability: f64) -> Result<Self, Error> {
+        let models = paths.iter()
+            .map(|path| {
+                let path = path.as_ref();
+
+                let name = path.file_stem()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.to_string_lossy().to_string());
+
+                Ok((name, Model::load(path)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self::new(models, smoothing, unseen_log_probability))
+    }
+
+    /// Load all the models of the directory
+    ///
+    /// Every file is a model named by its file name, so a directory
+    /// of `en.bin`, `de.bin` and `fr.bin` models can identify languages.
+    pub fn load_dir(path: impl AsRef<Path>, smoothing: Smoothing, unseen_log_probability: f64) -> Result<Self, Error> {
+        let mut paths = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        paths.retain(|path| path.is_file());
+        paths.sort();
+
+        Self::load(&paths, smoothing, unseen_log_probability)
+    }
+
+    #[inline]
+    pub fn models(&self) -> &[(String, Model)] {
+        &self.models
+    }
+
+    /// Classify text by the model which gives it the highest likelihood
+    ///
+    /// Returns `None` if there are no models.
+    pub fn classify(&self, words: &[String]) -> Option<Classification> {
+        let scores = self.models.iter()
+            .map(|(name, model)| (name.clone(), model.log_likelihood(words, &self.smoothing, self.unseen_log_probability)))
+            .collect::<Vec<_>>();
+
+        // First model wins the ties
+        let (best, (label, best_score)) = scores.iter()
+            .enumerate()
+            .fold(None, |best: Option<(usize, &(String, f64))>, (i, score)| match best {
+                Some((_, best_score)) if best_score.1 >= score.1 => best,
+                _ => Some((i, score))
+            })?;
+
+        let runner_up = scores.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != best)
+            .map(|(_, (_, score))| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        // Softmax of the log-likelihoods, shifted by the best one to not underflow
+        let total = scores.iter()
+            .map(|(_, score)| (score - best_score).exp())
+            .sum::<f64>();
+
+        Some(Classification {
+            label: label.clone(),
+            log_odds: best_score - runner_up,
+            confidence: 1.0 / total,
+            scores
+        })
+    }
+}
+
+mod tests {
+    #[test]
+    fn classify() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let model = |lines: &[&str]| -> anyhow::Result<Model> {
+            let messages = Messages::parse_from_lines(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>());
+            let tokens = Tokens::parse_from_messages(&messages);
+
+            let dataset = Dataset::default()
+                .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+                .with_tokens(tokens);
+
+            Ok(Model::build(dataset, true, true))
+        };
+
+        let classifier = Classifier::new(vec![
+            (String::from("en"), model(&["the cat is here", "the dog is there"])?),
+            (String::from("de"), model(&["die katze ist hier", "der hund ist da"])?)
+        ], Smoothing::default(), -10.0);
+
+        let words = |text: &str| text.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+        let result = classifier.classify(&words("the dog is here")).unwrap();
+
+        assert_eq!(result.label, "en");
+        assert!(result.log_odds > 0.0);
+        assert!(result.confidence > 0.5 && result.confidence <= 1.0);
+        assert_eq!(result.scores.len(), 2);
+
+        assert_eq!(classifier.classify(&words("der hund ist hier")).unwrap().label, "de");
+
+        // Nothing is known so the models are tied
+        let result = classifier.classify(&words("zzz")).unwrap();
+
+        assert_eq!(result.label, "en");
+        assert_eq!(result.log_odds, 0.0);
+        assert!((result.confidence - 0.5).abs() < 1e-9);
+
+        assert!(Classifier::new(vec![], Smoothing::default(), -10.0).classify(&words("the")).is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use crate::prelude::{
+    Model,
+    START_TOKEN,
+    END_TOKEN
+};
+
+/// Share of the vocabulary seen only once to report it
+pub const HAPAX_THRESHOLD: f64 = 0.5;
+
+/// Average amount of paths per unigram to report low branching
+pub const BRANCHING_THRESHOLD: f64 = 1.5;
+
+/// Share of the words which can only end the text to report it
+pub const DEAD_ENDS_THRESHOLD: f64 = 0.3;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Quality problem of the model
+pub enum Diagnostic {
+    /// Too many words were seen only once
+    Hapaxes {
+        hapaxes: usize,
+        vocabulary: usize
+    },
+
+    MissingBigrams,
+    MissingTrigrams,
+
+    /// Most of the words have a single continuation
+    LowBranching {
+        avg_paths: f64
+    },
+
+    /// Many words can only be followed by the end of the text
+    DeadEnds {
+        dead_ends: usize,
+        words: usize
+    },
+
+    /// Transitions use tokens which are not stored in the model
+    UnknownTokens {
+        unknown: usize
+    },
+
+    /// Tokens which are not used by any transition
+    UnusedTokens {
+        unused: usize
+    }
+}
+
+impl Diagnostic {
+    #[inline]
+    /// Check if the problem makes the model work incorrectly
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::UnknownTokens { .. })
+    }
+
+    /// Get suggested fix of the problem
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            Self::Hapaxes { .. } => "prune rare words from the vocabulary or add more messages",
+            Self::MissingBigrams => "rebuild the model with `--bigrams`",
+            Self::MissingTrigrams => "rebuild the model with `--trigrams`",
+            Self::LowBranching { .. } => "add more messages, the model mostly repeats them as is",
+            Self::DeadEnds { .. } => "add more messages or increase `--min-len` during generation",
+            Self::UnknownTokens { .. } => "rebuild the model from a dataset with the same tokens bundle",
+            Self::UnusedTokens { .. } => "rebuild the model to drop tokens of the removed messages"
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hapaxes { hapaxes, vocabulary } => {
+                write!(f, "{hapaxes} of {vocabulary} words were seen only once ({:.2}%)", *hapaxes as f64 / *vocabulary as f64 * 100.0)
+            }
+
+            Self::MissingBigrams => write!(f, "model has no bigrams table"),
+            Self::MissingTrigrams => write!(f, "model has no trigrams table"),
+
+            Self::LowBranching { avg_paths } => {
+                write!(f, "words have only {avg_paths:.4} continuations on average")
+            }
+
+            Self::DeadEnds { dead_ends, words } => {
+                write!(f, "{dead_ends} of {words} words can only end the text ({:.2}%)", *dead_ends as f64 / *words as f64 * 100.0)
+            }
+
+            Self::UnknownTokens { unknown } => write!(f, "{unknown} tokens of the transitions have no words"),
+            Self::UnusedTokens { unused } => write!(f, "{unused} tokens are not used by any transition")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Continuations of the chain context in a single transitions table
+pub struct TableContinuations {
+    /// Order of the table
+    pub order: usize,
+
+    /// Sum of the counts of all the continuations
+    pub total: u64,
+
+    /// (token, count) continuations sorted by count, the most
+    /// frequent first, the end of the text is `END_TOKEN`
+    pub continuations: Vec<(u64, u64)>
+}
+
+impl TableContinuations {
+    #[inline]
+    /// Probability of the continuation in the table
+    pub fn probability(&self, count: u64) -> f64 {
+        count as f64 / self.total as f64
+    }
+}
+
+impl Model {
+    /// Get continuations of the chain in every table
+    /// which has its context, lowest order first
+    ///
+    /// Tables of the orders higher than the chain length are not used,
+    /// so the context is never padded by the beginning of the text.
+    pub fn inspect(&self, chain: &[u64]) -> Vec<TableContinuations> {
+        self.transitions.context_rows(chain, |order| order <= chain.len())
+            .into_iter()
+            .map(|row| {
+                let mut continuations = row.continuations();
+
+                continuations.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+                TableContinuations {
+                    order: row.order(),
+                    total: row.total(),
+                    continuations
+                }
+            })
+            .collect()
+    }
+
+    /// Inspect the model for common quality problems
+    pub fn diagnose(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        // token -> occurrences
+        let mut occurrences = HashMap::<u64, u64>::new();
+
+        let mut words = 0;
+        let mut dead_ends = 0;
+
+        for (current, transitions) in self.transitions.unigrams.iter() {
+            if !current.is_start() {
+                words += 1;
+
+                if transitions.keys().all(|next| next.is_end()) {
+                    dead_ends += 1;
+                }
+            }
+
+            for (next, count) in transitions {
+                if !next.is_end() {
+                    *occurrences.entry(next.token()).or_default() += count;
+                }
+            }
+        }
+
+        let hapaxes = occurrences.values()
+            .filter(|count| **count == 1)
+            .count();
+
+        if !occurrences.is_empty() && hapaxes as f64 / occurrences.len() as f64 > HAPAX_THRESHOLD {
+            diagnostics.push(Diagnostic::Hapaxes {
+                hapaxes,
+                vocabulary: occurrences.len()
+            });
+        }
+
+        if self.transitions.bigrams.is_none() {
+            diagnostics.push(Diagnostic::MissingBigrams);
+        }
+
+        if self.transitions.trigrams.is_none() {
+            diagnostics.push(Diagnostic::MissingTrigrams);
+        }
+
+        let avg_paths = self.transitions.calc_avg_unigram_paths();
+
+        if avg_paths < BRANCHING_THRESHOLD {
+            diagnostics.push(Diagnostic::LowBranching {
+                avg_paths
+            });
+        }
+
+        if words > 0 && dead_ends as f64 / words as f64 > DEAD_ENDS_THRESHOLD {
+            diagnostics.push(Diagnostic::DeadEnds {
+                dead_ends,
+                words
+            });
+        }
+
+        let unknown = occurrences.keys()
+            .filter(|token| **token != START_TOKEN && **token != END_TOKEN)
+            .filter(|token| self.tokens.find_word(**token).is_none())
+            .count();
+
+        if unknown > 0 {
+            diagnostics.push(Diagnostic::UnknownTokens {
+                unknown
+            });
+        }
+
+        let unused = self.tokens.token_word.keys()
+            .filter(|token| !occurrences.contains_key(token))
+            .count();
+
+        if unused > 0 {
+            diagnostics.push(Diagnostic::UnusedTokens {
+                unused
+            });
+        }
+
+        diagnostics
+    }
+}
+
+mod tests {
+    #[test]
+    fn diagnose() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Example text")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let diagnostics = Model::build(dataset, false, true).diagnose();
+
+        assert!(diagnostics.contains(&Diagnostic::MissingBigrams));
+        assert!(!diagnostics.contains(&Diagnostic::MissingTrigrams));
+
+        assert!(diagnostics.contains(&Diagnostic::Hapaxes {
+            hapaxes: 4,
+            vocabulary: 4
+        }));
+
+        assert!(diagnostics.contains(&Diagnostic::DeadEnds {
+            dead_ends: 2,
+            words: 4
+        }));
+
+        assert!(!diagnostics.iter().any(Diagnostic::is_error));
+
+        Ok(())
+    }
+
+    #[test]
+    fn inspect() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("x b d")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 3);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let tables = model.inspect(&[token("b")]);
+
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].order, 1);
+        assert_eq!(tables[0].total, 3);
+        assert_eq!(tables[0].continuations, [(token("c"), 2), (token("d"), 1)]);
+        assert!((tables[0].probability(2) - 2.0 / 3.0).abs() < 1e-9);
+
+        let tables = model.inspect(&[token("x"), token("b")]);
+
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[1].order, 2);
+        assert_eq!(tables[1].continuations, [(token("d"), 1)]);
+
+        // Only the tables which have the context are used
+        let tables = model.inspect(&[token("c"), token("x")]);
+
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].continuations, [(token("b"), 1)]);
+
+        Ok(())
+    }
+}
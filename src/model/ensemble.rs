@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::iter::FusedIterator;
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::prelude::{
+    Tokens,
+    TokensRemap,
+    GenerationParams,
+    LoopAction,
+    Model,
+    END_TOKEN
+};
+
+use crate::model::generator::{choose_continuation, break_cycle, find_cycle};
+use crate::Error;
+
+/// Scale of the mixed probabilities converted to the counts
+const PROBABILITY_SCALE: f64 = u32::MAX as f64;
+
+/// Model of the ensemble with its vocabulary translations
+struct EnsembleMember {
+    model: Model,
+    weight: f64,
+
+    /// Translation of the model's tokens to the ensemble ones
+    to_ensemble: TokensRemap,
+
+    /// Tokens of the model's words in the ensemble
+    to_model: HashMap<u64, u64>
+}
+
+/// Models whose continuations are mixed at generation time
+///
+/// Vocabularies of the models are merged, so every model predicts
+/// the next token from its own transitions and their probabilities
+/// are summed with the models' weights. Models which don't know the
+/// last word of the chain don't take part, so the weights are
+/// normalized by the weights of the participating models.
+pub struct ModelEnsemble {
+    /// Headers of the first model and the merged tokens,
+    /// transitions are empty
+    pub(crate) meta: Model,
+
+    members: Vec<EnsembleMember>
+}
+
+impl ModelEnsemble {
+    /// Mix the models with their weights
+    ///
+    /// Weights must be positive, they don't have to sum to 1.
+    pub fn new(models: impl IntoIterator<Item = (Model, f64)>) -> Result<Self, Error> {
+        let mut meta = Model::default();
+        let mut members = Vec::new();
+
+        for (model, weight) in models {
+            if !weight.is_finite() || weight <= 0.0 {
+                return Err(Error::InvalidEnsembleWeight(weight));
+            }
+
+            let to_ensemble = if members.is_empty() {
+                meta.headers = model.headers.clone();
+                meta.tokens = model.tokens.clone();
+
+                TokensRemap::default()
+            } else {
+                let (tokens, remap) = std::mem::take(&mut meta.tokens).merge_with_remap(model.tokens.clone());
+
+                meta.tokens = tokens;
+
+                remap
+            };
+
+            let to_model = model.tokens.token_word.keys()
+                .map(|token| (to_ensemble.get(*token), *token))
+                .collect();
+
+            members.push(EnsembleMember {
+                model,
+                weight,
+                to_ensemble,
+                to_model
+            });
+        }
+
+        if members.is_empty() {
+            return Err(Error::EmptyEnsemble);
+        }
+
+        Ok(Self {
+            meta,
+            members
+        })
+    }
+
+    #[inline]
+    /// Headers of the first model
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.meta.headers
+    }
+
+    #[inline]
+    /// Merged tokens of the models
+    pub fn tokens(&self) -> &Tokens {
+        &self.meta.tokens
+    }
+
+    #[inline]
+    /// Models of the ensemble with their weights
+    pub fn models(&self) -> impl Iterator<Item = (&Model, f64)> {
+        self.members.iter().map(|member| (&member.model, member.weight))
+    }
+
+    /// Get (token, probability) continuations of the chain
+    /// mixed by the weights of the models, sorted by probability
+    ///
+    /// Every model uses the highest order table which knows the context.
+    /// Empty if no model can continue the chain.
+    pub fn continuations(&self, chain: &[u64], params: &GenerationParams) -> Vec<(u64, f64)> {
+        self.mix(chain, |order| params.is_order_enabled(order))
+    }
+
+    fn mix(&self, chain: &[u64], enabled: impl Fn(usize) -> bool) -> Vec<(u64, f64)> {
+        let mut probabilities = HashMap::<u64, f64>::new();
+        let mut total_weight = 0.0;
+
+        for member in &self.members {
+            // Context of the model ends at its last unknown token
+            let known = chain.iter()
+                .rev()
+                .map_while(|token| member.to_model.get(token).copied())
+                .collect::<Vec<_>>();
+
+            let complete = known.len() == chain.len();
+
+            if !complete && known.is_empty() {
+                continue;
+            }
+
+            let context = known.into_iter().rev().collect::<Vec<_>>();
+
+            // Contexts padded by the start tokens are used
+            // only if the whole chain is known to the model
+            let rows = member.model.transitions.context_rows(&context, |order| {
+                enabled(order) && (complete || order <= context.len())
+            });
+
+            let Some(row) = rows.last() else {
+                continue;
+            };
+
+            let total = row.total() as f64;
+
+            for (token, count) in row.continuations() {
+                *probabilities.entry(member.to_ensemble.get(token)).or_default() += member.weight * count as f64 / total;
+            }
+
+            total_weight += member.weight;
+        }
+
+        let mut continuations = probabilities.into_iter()
+            .map(|(token, probability)| (token, probability / total_weight))
+            .collect::<Vec<_>>();
+
+        continuations.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        continuations
+    }
+
+    #[inline]
+    /// Generate tokens continuing the beginning
+    ///
+    /// Random numbers generator is seeded from `params.seed`
+    /// or from the system entropy. See `EnsembleGenerator`
+    /// for the supported parameters.
+    pub fn generate<'a>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams) -> EnsembleGenerator<'a> {
+        let rng = match params.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        };
+
+        self.generate_with_rng(beginning, params, rng)
+    }
+
+    #[inline]
+    /// Generate tokens continuing the beginning using
+    /// the provided random numbers generator
+    pub fn generate_with_rng<'a, R: RngCore>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams, rng: R) -> EnsembleGenerator<'a, R> {
+        EnsembleGenerator {
+            chain: beginning.into(),
+            rng,
+            params,
+            ensemble: self
+        }
+    }
+}
+
+/// Tokens generator of the models ensemble
+///
+/// Works like the model's generator, including the cycles detection
+/// and temperature schedules, but doesn't support smoothing, dialogue,
+/// `no_repeat_ngram` and `context_window`, which are ignored.
+pub struct EnsembleGenerator<'a, R = ChaCha8Rng> {
+    chain: Vec<u64>,
+    rng: R,
+    params: &'a GenerationParams,
+    ensemble: &'a ModelEnsemble
+}
+
+impl<'a, R: RngCore> EnsembleGenerator<'a, R> {
+    #[inline]
+    /// Tokens generated so far, including the beginning
+    pub fn chain(&self) -> &[u64] {
+        &self.chain
+    }
+
+    /// Get mixed continuations as (token, count) pairs
+    /// sorted by count, without the end of the text
+    /// before the minimum length
+    fn continuations(&self, enabled: impl Fn(usize) -> bool) -> Vec<(u64, u64)> {
+        let allow_end = self.chain.len() >= self.params.min_len;
+
+        self.ensemble.mix(&self.chain, enabled)
+            .into_iter()
+            .filter(|(token, _)| allow_end || *token != END_TOKEN)
+            .map(|(token, probability)| (token, (probability * PROBABILITY_SCALE).ceil() as u64))
+            .collect()
+    }
+}
+
+impl<'a, R: RngCore> Iterator for EnsembleGenerator<'a, R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.chain.len() >= self.params.max_len {
+            return None;
+        }
+
+        let next = match find_cycle(&self.chain, self.params.max_loop_period, self.params.loop_repeats) {
+            Some(_) if self.params.loop_action == LoopAction::Stop => return None,
+
+            // Break the cycle by a random continuation of the lowest order
+            Some(period) => {
+                let continuations = self.continuations(|order| order == 1);
+
+                break_cycle(continuations, &self.chain, period, &mut self.rng)?
+            }
+
+            None => {
+                let continuations = self.continuations(|order| self.params.is_order_enabled(order));
+
+                if continuations.is_empty() {
+                    return None;
+                }
+
+                let params = self.params.scheduled(&self.chain, self.ensemble.tokens());
+
+                choose_continuation(continuations, &self.chain, &params, &mut self.rng)
+            }
+        };
+
+        if next == END_TOKEN {
+            return None;
+        }
+
+        self.chain.push(next);
+
+        Some(next)
+    }
+}
+
+impl<'a, R: RngCore> FusedIterator for EnsembleGenerator<'a, R> {}
+
+mod tests {
+    #[test]
+    fn model_ensemble() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let build = |lines: &[&str]| -> anyhow::Result<Model> {
+            let messages = Messages::parse_from_lines(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>());
+
+            let tokens = Tokens::parse_from_messages(&messages);
+
+            let dataset = Dataset::default()
+                .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+                .with_tokens(tokens);
+
+            Ok(Model::build(dataset, true, false))
+        };
+
+        let generic = build(&["hello world", "hello world again"])?;
+        let persona = build(&["hello there", "there it is"])?;
+
+        assert!(ModelEnsemble::new([(generic.clone(), 0.0)]).is_err());
+        assert!(ModelEnsemble::new(Vec::<(Model, f64)>::new()).is_err());
+
+        let ensemble = ModelEnsemble::new([(generic, 1.0), (persona, 3.0)])?;
+
+        let token = |word| ensemble.tokens().find_token(word).unwrap();
+
+        assert_eq!(ensemble.tokens().len(), 6);
+
+        let params = GenerationParams::default();
+
+        assert_eq!(ensemble.continuations(&[token("hello")], &params), [
+            (token("world"), 0.25),
+            (token("there"), 0.75)
+        ]);
+
+        // Only the persona model knows the word
+        assert_eq!(ensemble.continuations(&[token("hello"), token("there")], &params), [
+            (END_TOKEN, 1.0)
+        ]);
+
+        let params = GenerationParams {
+            seed: Some(1),
+            ..GenerationParams::default()
+        };
+
+        let generated = ensemble.generate([token("hello")], &params).collect::<Vec<_>>();
+
+        assert!(!generated.is_empty());
+        assert!(generated.iter().all(|token| ensemble.tokens().find_word(*token).is_some()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use rayon::prelude::*;
+
+use crate::prelude::{
+    Messages,
+    Model,
+    Smoothing,
+    END_TOKEN
+};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Evaluation {
+    /// Amount of scored messages
+    pub messages: u64,
+
+    /// Amount of messages skipped because of unknown words
+    pub skipped: u64,
+
+    /// Amount of scored tokens (including the end of each message)
+    pub tokens: u64,
+
+    /// Amount of tokens which have no transition in any table
+    ///
+    /// These tokens are not included in the `tokens` number
+    /// and the log-probability.
+    pub unseen: u64,
+
+    /// Sum of natural log-probabilities of all scored tokens
+    pub log_probability: f64
+}
+
+impl Evaluation {
+    #[inline]
+    pub fn merge(self, other: Evaluation) -> Self {
+        Self {
+            messages: self.messages + other.messages,
+            skipped: self.skipped + other.skipped,
+            tokens: self.tokens + other.tokens,
+            unseen: self.unseen + other.unseen,
+            log_probability: self.log_probability + other.log_probability
+        }
+    }
+
+    #[inline]
+    /// Average negative log-probability per token
+    pub fn cross_entropy(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+
+        -self.log_probability / self.tokens as f64
+    }
+
+    #[inline]
+    /// Perplexity of the evaluated messages
+    ///
+    /// `exp(cross_entropy)`, lower is better.
+    pub fn perplexity(&self) -> f64 {
+        self.cross_entropy().exp()
+    }
+}
+
+impl Model {
+    #[inline]
+    /// Evaluate tokenized message
+    ///
+    /// Every token (and the end of the message) is scored by the
+    /// highest order table which has seen the transition.
+    pub fn evaluate_tokens(&self, tokens: &[u64]) -> Evaluation {
+        self.evaluate_tokens_with_smoothing(tokens, &Smoothing::default())
+    }
+
+    /// Evaluate tokenized message using the smoothed probabilities
+    ///
+    /// With smoothing there are no unseen tokens.
+    pub fn evaluate_tokens_with_smoothing(&self, tokens: &[u64], smoothing: &Smoothing) -> Evaluation {
+        let mut evaluation = Evaluation {
+            messages: 1,
+            ..Evaluation::default()
+        };
+
+        for i in 0..=tokens.len() {
+            let next = tokens.get(i).copied().unwrap_or(END_TOKEN);
+
+            match self.probability(&tokens[..i], next, smoothing) {
+                Some(probability) => {
+                    evaluation.tokens += 1;
+                    evaluation.log_probability += probability.ln();
+                }
+
+                None => evaluation.unseen += 1
+            }
+        }
+
+        evaluation
+    }
+
+    #[inline]
+    /// Get natural log-probability of the tokenized message
+    ///
+    /// Returns negative infinity if any transition of the message
+    /// is unseen by the model.
+    pub fn score(&self, tokens: &[u64]) -> f64 {
+        self.score_with_smoothing(tokens, &Smoothing::default())
+    }
+
+    #[inline]
+    /// Get natural log-probability of the tokenized message
+    /// using the smoothed probabilities
+    pub fn score_with_smoothing(&self, tokens: &[u64], smoothing: &Smoothing) -> f64 {
+        let evaluation = self.evaluate_tokens_with_smoothing(tokens, smoothing);
+
+        if evaluation.unseen > 0 {
+            return f64::NEG_INFINITY;
+        }
+
+        evaluation.log_probability
+    }
+
+    #[inline]
+    /// Get perplexity of the tokenized message
+    ///
+    /// Returns infinity if any transition of the message
+    /// is unseen by the model.
+    pub fn perplexity(&self, tokens: &[u64]) -> f64 {
+        let log_probability = self.score(tokens);
+
+        // Every token and the end of the message are scored
+        (-log_probability / (tokens.len() + 1) as f64).exp()
+    }
+
+    /// Get natural log-probability of the words penalizing the unknown ones
+    ///
+    /// Words are matched ignoring case. Unknown words are skipped, and
+    /// every one of them, as well as every unseen transition, is scored
+    /// by `unseen_log_probability`, so models with different vocabularies
+    /// can be compared on the same text.
+    pub fn log_likelihood(&self, words: &[String], smoothing: &Smoothing, unseen_log_probability: f64) -> f64 {
+        let tokens = words.iter()
+            .filter_map(|word| self.tokens.find_token_ignore_case(word))
+            .collect::<Vec<_>>();
+
+        let evaluation = self.evaluate_tokens_with_smoothing(&tokens, smoothing);
+
+        let unseen = (words.len() - tokens.len()) as u64 + evaluation.unseen;
+
+        evaluation.log_probability + unseen as f64 * unseen_log_probability
+    }
+
+    #[inline]
+    /// Evaluate messages in parallel
+    ///
+    /// Messages with words unknown to the model are skipped.
+    pub fn evaluate(&self, messages: &Messages) -> Evaluation {
+        self.evaluate_with_smoothing(messages, &Smoothing::default())
+    }
+
+    /// Evaluate messages in parallel using the smoothed probabilities
+    ///
+    /// Words are matched ignoring case, and messages with
+    /// words unknown to the model are skipped.
+    pub fn evaluate_with_smoothing(&self, messages: &Messages, smoothing: &Smoothing) -> Evaluation {
+        messages.messages()
+            .par_iter()
+            .map(|message| {
+                let tokens = message.iter()
+                    .map(|word| self.tokens.find_token_ignore_case(word))
+                    .collect::<Option<Vec<_>>>();
+
+                match tokens {
+                    Some(tokens) => self.evaluate_tokens_with_smoothing(&tokens, smoothing),
+
+                    None => Evaluation {
+                        skipped: 1,
+                        ..Evaluation::default()
+                    }
+                }
+            })
+            .reduce(Evaluation::default, Evaluation::merge)
+    }
+}
+
+mod tests {
+    #[test]
+    fn evaluate() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Hello, there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let evaluation = model.evaluate(&Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Unknown words")
+        ]));
+
+        assert_eq!(evaluation.messages, 1);
+        assert_eq!(evaluation.skipped, 1);
+        assert_eq!(evaluation.tokens, 3);
+        assert_eq!(evaluation.unseen, 0);
+
+        // <START> -> hello, -> world! (1/2) -> <END>
+        assert!((evaluation.log_probability - 0.5_f64.ln()).abs() < 1e-9);
+        assert!((evaluation.perplexity() - 2.0_f64.powf(1.0 / 3.0)).abs() < 1e-9);
+
+        let hello = model.tokens().find_token("hello,").unwrap();
+        let world = model.tokens().find_token("world!").unwrap();
+
+        assert!((model.score(&[hello, world]) - 0.5_f64.ln()).abs() < 1e-9);
+        assert!((model.perplexity(&[hello, world]) - 2.0_f64.powf(1.0 / 3.0)).abs() < 1e-9);
+
+        let words = |text: &str| text.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+        assert!((model.log_likelihood(&words("HELLO, world!"), &Smoothing::default(), -10.0) - 0.5_f64.ln()).abs() < 1e-9);
+
+        // Unknown word and the unseen transition to the end of the text
+        assert!((model.log_likelihood(&words("hello, unknown"), &Smoothing::default(), -10.0) + 20.0).abs() < 1e-9);
+
+        // world! -> hello, is never seen
+        assert_eq!(model.score(&[world, hello]), f64::NEG_INFINITY);
+        assert_eq!(model.perplexity(&[world, hello]), f64::INFINITY);
+
+        let smoothing = Smoothing {
+            algorithm: SmoothingAlgorithm::Laplace,
+            ..Smoothing::default()
+        };
+
+        assert!(model.score_with_smoothing(&[world, hello], &smoothing).is_finite());
+
+        Ok(())
+    }
+}
@@ -1,180 +1,530 @@
 use std::iter::FusedIterator;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use rand::{Rng, RngCore};
+use rand_chacha::ChaCha8Rng;
 
 use crate::prelude::{
+    Ngram,
     Unigram,
-    Bigram,
-    Trigram,
+    TransitionsTable,
     GenerationParams,
+    LoopAction,
+    SmoothingAlgorithm,
+    ContextSmoother,
+    CandidateCache,
     Model,
+    Sampler,
+    DefaultSampler,
     END_TOKEN
 };
 
-pub struct Generator<'a> {
+use crate::Error;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// Serializable state of the text generation
+///
+/// Can be stored and later resumed by the same model
+/// using `Model::resume`.
+pub struct GeneratorState<R = ChaCha8Rng> {
+    pub chain: Vec<u64>,
+    pub rng: R,
+    pub params: GenerationParams
+}
+
+pub struct Generator<'a, R = ChaCha8Rng, S = DefaultSampler> {
     pub(crate) chain: Vec<u64>,
-    pub(crate) params: &'a GenerationParams,
-    pub(crate) model: &'a Model
+    pub(crate) rng: R,
+    pub(crate) sampler: S,
+    pub(crate) params: Cow<'a, GenerationParams>,
+    pub(crate) model: &'a Model,
+    pub(crate) cache: Option<&'a mut CandidateCache>,
+    pub(crate) banned: Option<&'a HashSet<u64>>
 }
 
-impl<'a> Iterator for Generator<'a> {
-    type Item = anyhow::Result<u64>;
+impl<'a, R: RngCore, S: Sampler> Generator<'a, R, S> {
+    #[inline]
+    /// Tokens generated so far, including the beginning
+    pub fn chain(&self) -> &[u64] {
+        &self.chain
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut continuations = None;
+    #[inline]
+    /// Use the cache of the sorted continuations
+    ///
+    /// Cache must be created for the same model.
+    pub fn with_cache(mut self, cache: &'a mut CandidateCache) -> Self {
+        self.cache = Some(cache);
+
+        self
+    }
+
+    #[inline]
+    /// Choose the next tokens by the sampler instead of the params
+    ///
+    /// Continuations are still filtered by the params, e.g. by
+    /// the minimal length and repeated n-grams.
+    pub fn with_sampler<T: Sampler>(self, sampler: T) -> Generator<'a, R, T> {
+        Generator {
+            chain: self.chain,
+            rng: self.rng,
+            sampler,
+            params: self.params,
+            model: self.model,
+            cache: self.cache,
+            banned: self.banned
+        }
+    }
 
-        // Get initial predictions from the trigram
-        if !self.params.no_trigrams {
-            let trigram = Trigram::construct_tailless(&self.chain);
+    #[inline]
+    /// Never generate these tokens
+    ///
+    /// Lower order tables are used if all the continuations are banned.
+    pub fn with_banned(mut self, banned: &'a HashSet<u64>) -> Self {
+        self.banned = Some(banned);
 
-            if let Some(trigram) = trigram.last() {
-                if let Some(trigram_continuations) = self.model.transitions.for_trigram(trigram) {
-                    let trigram_continuations = trigram_continuations
-                        .filter(|(token, _)| !token.is_end())
-                        .map(|(token, number)| (token.token(), *number))
-                        .collect::<Vec<_>>();
+        self
+    }
+
+    /// Get (token, count) continuations of the context sorted by count
+    ///
+    /// Transitions must be iterated from the least frequent one,
+    /// tables store them in this order so nothing is sorted here.
+    fn sorted_continuations<'b, const SIZE: usize>(
+        &mut self,
+        context: &Ngram<SIZE>,
+        transitions: Option<impl Iterator<Item = (&'b Ngram<SIZE>, &'b u64)>>
+    ) -> Option<Arc<[(u64, u64)]>> {
+        if let Some(continuations) = self.cache.as_mut().and_then(|cache| cache.get(context.tokens())) {
+            return Some(continuations);
+        }
 
-                    if !trigram_continuations.is_empty() {
-                        continuations = Some(trigram_continuations);
-                    }
+        let continuations = transitions?
+            .map(|(ngram, count)| {
+                if ngram.is_end() {
+                    (END_TOKEN, *count)
+                } else {
+                    (ngram.token(), *count)
                 }
-            }
+            })
+            .collect::<Vec<_>>();
+
+        let continuations = Arc::<[(u64, u64)]>::from(continuations);
+
+        if let Some(cache) = &mut self.cache {
+            cache.insert(context.tokens(), continuations.clone());
         }
 
-        // If there are no continuations from the trigram - try to get them from the bigram
-        if !self.params.no_bigrams && continuations.is_none() {
-            let bigram = Bigram::construct_tailless(&self.chain);
+        Some(continuations)
+    }
 
-            if let Some(bigram) = bigram.last() {
-                if let Some(bigram_continuations) = self.model.transitions.for_bigram(bigram) {
-                    let bigram_continuations = bigram_continuations
-                        .filter(|(token, _)| !token.is_end())
-                        .map(|(token, number)| (token.token(), *number))
-                        .collect::<Vec<_>>();
+    /// Get tokens which would repeat an n-gram of the `no_repeat_ngram` size
+    fn repeating_tokens(&self) -> HashSet<u64> {
+        let size = self.params.no_repeat_ngram;
 
-                    if !bigram_continuations.is_empty() {
-                        continuations = Some(bigram_continuations);
-                    }
-                }
-            }
+        if size == 0 || self.chain.len() < size {
+            return HashSet::new();
         }
 
-        // If there are no continuations from the bigram - try to get them from the unigram
-        if continuations.is_none() {
-            let unigram = Unigram::construct_tailless(&self.chain);
+        let tail = &self.chain[self.chain.len() - (size - 1)..];
 
-            if let Some(unigram) = unigram.last() {
-                if let Some(unigram_continuations) = self.model.transitions.for_unigram(unigram) {
-                    let unigram_continuations = unigram_continuations
-                        .filter(|(token, _)| !token.is_end())
-                        .map(|(token, number)| (token.token(), *number))
-                        .collect::<Vec<_>>();
+        self.chain.windows(size)
+            .filter(|ngram| &ngram[..size - 1] == tail)
+            .map(|ngram| ngram[size - 1])
+            .collect()
+    }
 
-                    if !unigram_continuations.is_empty() {
-                        continuations = Some(unigram_continuations);
-                    }
-                }
+    /// Filter sorted (token, count) continuations
+    ///
+    /// Banned tokens and tokens repeating n-grams are always excluded.
+    /// Until the chain reaches minimum length the end of the text
+    /// is excluded and dead-end tokens are removed if there are
+    /// other variants. Returned flag is false when only dead-ends left.
+    fn continuations(&self, continuations: Option<Arc<[(u64, u64)]>>) -> Option<(Vec<(u64, u64)>, bool)> {
+        let allow_end = self.chain.len() >= self.params.min_len;
+
+        let repeating = self.repeating_tokens();
+
+        let continuations = continuations?
+            .iter()
+            .filter(|(token, _)| allow_end || *token != END_TOKEN)
+            .filter(|(token, _)| !self.banned.is_some_and(|banned| banned.contains(token)))
+            .filter(|(token, _)| !repeating.contains(token))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if continuations.is_empty() {
+            return None;
+        }
+
+        if allow_end {
+            return Some((continuations, true));
+        }
+
+        let alive = continuations.iter()
+            .filter(|(token, _)| !self.model.is_dead_end(*token))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if alive.is_empty() {
+            return Some((continuations, false));
+        }
+
+        Some((alive, true))
+    }
+
+    /// Get continuations of the chain from the table of the given order
+    ///
+    /// Dead-end continuations are stored to the fallback
+    /// if it has no continuations from a higher order table yet.
+    fn ngram_continuations<const SIZE: usize>(
+        &mut self,
+        table: Option<&'a TransitionsTable<SIZE>>,
+        fallback: &mut Option<Vec<(u64, u64)>>
+    ) -> Option<Vec<(u64, u64)>> {
+        let table = table?;
+        let context = *Ngram::<SIZE>::construct_tailless(&self.chain).last()?;
+
+        let sorted = self.sorted_continuations(&context, table.get(&context).map(|transitions| transitions.iter_by_count()));
+
+        match self.continuations(sorted)? {
+            (continuations, true) => Some(continuations),
+
+            (continuations, false) => {
+                fallback.get_or_insert(continuations);
+
+                None
             }
         }
+    }
+
+    #[inline]
+    /// Capture current generation state
+    pub fn snapshot(&self) -> GeneratorState<R> where R: Clone {
+        GeneratorState {
+            chain: self.chain.clone(),
+            rng: self.rng.clone(),
+            params: *self.params
+        }
+    }
+}
 
-        // Stop generation if there are no continuations
-        let mut continuations = continuations?;
+/// Add continuations of the lower order which are not listed yet
+///
+/// Continuations are re-sorted by the smoothed probabilities afterwards,
+/// so counts of the added ones don't matter.
+fn merge_continuations(continuations: &mut Option<Vec<(u64, u64)>>, lower: Option<Vec<(u64, u64)>>) {
+    let Some(lower) = lower else {
+        return;
+    };
 
-        // Find offset according to the normal distribution
-        let offset = ((1.0 - self.params.k_normal) * continuations.len() as f64).floor() as usize / 2;
+    let Some(continuations) = continuations else {
+        *continuations = Some(lower);
 
-        // If there's less possible variants than expected
-        if continuations.len() <= offset * 2 {
-            // Stop tokens generation
-            return None;
+        return;
+    };
+
+    let listed = continuations.iter()
+        .map(|(token, _)| *token)
+        .collect::<HashSet<_>>();
+
+    continuations.extend(lower.into_iter().filter(|(token, _)| !listed.contains(token)));
+}
+
+/// Remove `least` and `most` percents of the sorted continuations
+/// from the beginning and end respectively
+///
+/// At least one continuation is always kept.
+fn trim_continuations<T>(continuations: &mut Vec<(u64, T)>, least: f64, most: f64) {
+    if continuations.is_empty() {
+        return;
+    }
+
+    // Find amount of the least and most probable variants to remove
+    let least = (least * continuations.len() as f64).floor() as usize;
+    let most = (most * continuations.len() as f64).floor() as usize;
+
+    // Always keep at least one variant
+    let least = least.min(continuations.len() - 1);
+    let most = most.min(continuations.len() - least - 1);
+
+    continuations.truncate(continuations.len() - most);
+    continuations.drain(..least);
+}
+
+/// Sort continuations by their compatibility with the last
+/// `context_window` tokens of the chain
+///
+/// Continuations are compared by the product of their add-one smoothed
+/// probabilities in the enabled tables of the orders up to the window
+/// size, which replaces their weights. Equally compatible continuations
+/// keep their order.
+fn rerank_by_context(continuations: Vec<(u64, f64)>, model: &Model, chain: &[u64], params: &GenerationParams) -> Vec<(u64, f64)> {
+    let rows = model.transitions.context_rows(chain, |order| {
+        order <= params.context_window && params.is_order_enabled(order)
+    });
+
+    let mut ranked = continuations.into_iter()
+        .map(|(token, _)| {
+            let score = rows.iter()
+                .map(|row| (row.count(token) + 1) as f64 / (row.total() + row.len() as u64 + 1) as f64)
+                .product::<f64>();
+
+            (token, score)
+        })
+        .collect::<Vec<_>>();
+
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    ranked
+}
+
+/// Scale weights of the continuations so they sum to 1
+///
+/// Weights which are all zero are kept as is.
+fn normalize_continuations(continuations: &mut [(u64, f64)]) {
+    let total = continuations.iter()
+        .map(|(_, weight)| *weight)
+        .sum::<f64>();
+
+    if total > 0.0 {
+        for (_, weight) in continuations {
+            *weight /= total;
         }
+    }
+}
 
-        // Remove most and least probable variants
-        continuations = continuations[offset..continuations.len() - offset].to_vec();
+/// Find period of the cycle the chain ends with
+///
+/// Returns the shortest period up to `max_period` such that the last
+/// `repeats` sequences of this length are the same.
+pub fn find_cycle(chain: &[u64], max_period: usize, repeats: usize) -> Option<usize> {
+    if repeats < 2 {
+        return None;
+    }
 
-        // If there are no continuations
-        if continuations.is_empty() {
-            // Stop tokens generation
+    (1..=max_period)
+        .take_while(|period| period * repeats <= chain.len())
+        .find(|period| {
+            let tail = &chain[chain.len() - period * repeats..];
+
+            tail[*period..].iter()
+                .zip(tail)
+                .all(|(token, previous)| token == previous)
+        })
+}
+
+/// Choose a random continuation which doesn't repeat the cycle
+/// of the period the chain ends with
+pub(crate) fn break_cycle(continuations: Vec<(u64, u64)>, chain: &[u64], period: usize, rng: &mut impl RngCore) -> Option<u64> {
+    let repeated = chain[chain.len() - period];
+
+    let continuations = continuations.into_iter()
+        .filter(|(token, _)| *token != repeated)
+        .collect::<Vec<_>>();
+
+    if continuations.is_empty() {
+        return None;
+    }
+
+    Some(continuations[rng.gen_range(0..continuations.len())].0)
+}
+
+/// Choose the next token from the continuations sorted by probability
+///
+/// Continuations are trimmed and limited by `top_k`, then the most
+/// probable ones are skipped according to the temperature and the
+/// repeat penalty. Only the order of the continuations is used, not
+/// their weights. Continuations must not be empty.
+pub(crate) fn choose_continuation<T>(mut continuations: Vec<(u64, T)>, chain: &[u64], params: &GenerationParams, rng: &mut impl RngCore) -> u64 {
+    // Remove least and most probable variants
+    trim_continuations(&mut continuations, params.trim_least, params.trim_most);
+
+    // Keep only top-k most probable variants
+    if params.top_k > 0 && continuations.len() > params.top_k {
+        continuations.drain(..continuations.len() - params.top_k);
+    }
+
+    // While there are continuations
+    while continuations.len() > 1 {
+        // Get random seed from 0.0 to 1.0
+        let random_seed = rng.gen::<u32>() as f64 / u32::MAX as f64;
+
+        // Get the next most probable token
+        let next = continuations.last().unwrap().0;
+
+        // Find last repeats of the next token
+        let repeats = chain.iter()
+            .rev()
+            .take(params.repeat_penalty_window)
+            .filter(|token| **token == next)
+            .count();
+
+        // If the next token is repeated
+        if repeats > 0 {
+            // If the random seed is lower than the repeat penalty
+            //
+            // repeat_penalty: 0.5 -> 0.25 -> 0.125 -> 0.0625 -> ...
+            //
+            // lower repeat_penalty => lower chance that the if statement works
+            // => higher chance that the next token is skipped
+            if random_seed < params.repeat_penalty.powi(repeats as i32) {
+                // Keep current token as the next one
+                break;
+            }
+        }
+
+        // Otherwise
+        else {
+            // Calculate the temperature
+            let temperature = params.temperature * params.temperature_alpha.powi(chain.len() as i32);
+
+            // If the random seed is lower than the temperature
+            //
+            // temperature: 0.5 -> 0.25 -> 0.125 -> 0.0625 -> ...
+            //
+            // lower temperature => lower chance that the if statement works
+            // => higher chance that the next token is skipped
+            if random_seed < temperature {
+                // Keep current token as the next one
+                break;
+            }
+        }
+
+        // Remove current most probable token
+        continuations.pop();
+    }
+
+    // Get the most probable token
+    continuations.last().unwrap().0
+}
+
+impl<'a, R: RngCore, S: Sampler> Iterator for Generator<'a, R, S> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Stop generation if the chain is long enough
+        if self.chain.len() >= self.params.max_len {
             return None;
         }
 
-        // // Get the context window from the chain history
-        // let chain_window = &self.chain[self.chain.len().saturating_sub(self.params.context_window)..];
-
-        // // Update probabilities for each continuation
-        // for continuation in &mut continuations {
-        //     // Iterate over the context window
-        //     for i in 1..chain_window.len() {
-        //         // Multiply the probability by the continuation's probability
-        //         continuation.1 *= self.model.chains.get_probability(chain_window[i - 1], chain_window[i])?;
-        //     }
-        // }
-
-        // Sort the continuations by probability
-        continuations.sort_by(|a, b| a.1.cmp(&b.1));
-
-        // dbg!(&continuations);
-
-        // While there are continuations
-        while continuations.len() > 1 {
-            // Get random seed from 0.0 to 1.0
-            let random_seed = rand::random::<u32>() as f64 / u32::MAX as f64;
-
-            // Get the next most probable token
-            let next = continuations.last().unwrap().0;
-
-            // Find last repeats of the next token
-            let repeats = self.chain.iter()
-                .rev()
-                .take(self.params.repeat_penalty_window)
-                .filter(|token| **token == next)
-                .count();
-
-            // If the next token is repeated
-            if repeats > 0 {
-                // If the random seed is lower than the repeat penalty
-                //
-                // repeat_penalty: 0.5 -> 0.25 -> 0.125 -> 0.0625 -> ...
-                //
-                // lower repeat_penalty => lower chance that the if statement works
-                // => higher chance that the next token is skipped
-                if random_seed < self.params.repeat_penalty.powi(repeats as i32) {
-                    // Keep current token as the next one
-                    break;
-                }
+        let transitions = &self.model.transitions;
+
+        let mut continuations = None;
+
+        // Dead-end continuations to use if nothing better is found
+        let mut fallback = None;
+
+        // Break the cycle by a random continuation of the lowest order
+        if let Some(period) = find_cycle(&self.chain, self.params.max_loop_period, self.params.loop_repeats) {
+            if self.params.loop_action == LoopAction::Stop {
+                return None;
             }
 
-            // Otherwise
-            else {
-                // Calculate the temperature
-                let temperature = self.params.temperature * self.params.temperature_alpha.powi(self.chain.len() as i32);
-
-                // If the random seed is lower than the temperature
-                //
-                // temperature: 0.5 -> 0.25 -> 0.125 -> 0.0625 -> ...
-                //
-                // lower temperature => lower chance that the if statement works
-                // => higher chance that the next token is skipped
-                if random_seed < temperature {
-                    // Keep current token as the next one
-                    break;
-                }
+            let continuations = self.ngram_continuations(Some(&transitions.unigrams), &mut fallback)
+                .or(fallback)?;
+
+            let next = break_cycle(continuations, &self.chain, period, &mut self.rng)?;
+
+            if next == END_TOKEN {
+                return None;
             }
 
-            // Remove current most probable token
-            continuations.pop();
+            self.chain.push(next);
+
+            return Some(Ok(next));
         }
 
-        // Get the most probable token
-        let next = continuations.last().unwrap().0;
+        // Get initial predictions from the highest order table
+        // and back off to the lower orders if there are no continuations
+        let smoothing = self.params.smoothing;
 
-        // If the chain's length is greater than the minimum length
-        if self.chain.len() > self.params.min_len {
-            // If the chain's length is greater than the maximum length
-            if self.chain.len() > self.params.max_len {
-                // Stop tokens generation
-                return None;
+        // Interpolation mixes all the orders, so take continuations of all of them
+        let interpolate = smoothing.algorithm == SmoothingAlgorithm::Interpolation;
+
+        if !self.params.no_pentagrams {
+            continuations = self.ngram_continuations(transitions.pentagrams.as_ref(), &mut fallback);
+        }
+
+        if !self.params.no_quadgrams && (continuations.is_none() || interpolate) {
+            let lower = self.ngram_continuations(transitions.quadgrams.as_ref(), &mut fallback);
+
+            merge_continuations(&mut continuations, lower);
+        }
+
+        if !self.params.no_trigrams && (continuations.is_none() || interpolate) {
+            let lower = self.ngram_continuations(transitions.trigrams.as_ref(), &mut fallback);
+
+            merge_continuations(&mut continuations, lower);
+        }
+
+        if !self.params.no_bigrams && (continuations.is_none() || interpolate) {
+            let lower = self.ngram_continuations(transitions.bigrams.as_ref(), &mut fallback);
+
+            merge_continuations(&mut continuations, lower);
+        }
+
+        if continuations.is_none() || interpolate {
+            let lower = self.ngram_continuations(Some(&transitions.unigrams), &mut fallback);
+
+            merge_continuations(&mut continuations, lower);
+        }
+
+        let continuations = match continuations.or(fallback) {
+            Some(continuations) => continuations,
+
+            // Smoothing gives non-zero probability to the unseen transitions,
+            // so continue with the tokens which continue most of the contexts
+            None if !smoothing.is_none() => {
+                let ranking = self.model.smoothing_stats().ranking.clone();
+
+                self.continuations(Some(ranking))?.0
+            }
+
+            // Only the end of the text continues the chain before
+            // the minimum length, so continue with a message opener
+            None if self.chain.len() < self.params.min_len => {
+                let start = Unigram::start();
+
+                let openers = self.sorted_continuations(&start, transitions.unigrams.get(&start).map(|transitions| transitions.iter_by_count()));
+
+                self.continuations(openers)?.0
             }
+
+            // Stop generation if there are no continuations
+            None => return None
+        };
+
+        // Sort continuations by their smoothed probabilities
+        let mut continuations = if !smoothing.is_none() {
+            let rows = self.model.transitions.context_rows(&self.chain, |order| self.params.is_order_enabled(order));
+            let smoother = ContextSmoother::new(rows, self.model.smoothing_stats(), smoothing);
+
+            let mut ranked = continuations.into_iter()
+                .map(|(token, _)| (token, smoother.probability(token).unwrap_or(0.0)))
+                .collect::<Vec<_>>();
+
+            ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            ranked
+        } else {
+            continuations.into_iter()
+                .map(|(token, count)| (token, count as f64))
+                .collect()
+        };
+
+        // Prefer continuations fitting the last tokens of the chain
+        if self.params.context_window > 0 {
+            continuations = rerank_by_context(continuations, self.model, &self.chain, &self.params);
         }
 
+        normalize_continuations(&mut continuations);
+
+        let params = self.params.scheduled(&self.chain, &self.model.tokens);
+        let next = self.sampler.sample(continuations, &self.chain, &params, &mut self.rng);
+
         // If the next token is an end of the text
         if next == END_TOKEN {
             // Stop tokens generation
@@ -189,4 +539,719 @@ impl<'a> Iterator for Generator<'a> {
     }
 }
 
-impl<'a> FusedIterator for Generator<'a> {}
+impl<'a, R: RngCore, S: Sampler> FusedIterator for Generator<'a, R, S> {}
+
+/// Generator yielding words instead of tokens
+///
+/// Words are yielded as stored in the model, so subwords
+/// keep their `@@` markers and punctuation is not attached.
+/// Use `Model::join_words` to get the text.
+pub struct Words<'a, R = ChaCha8Rng, S = DefaultSampler> {
+    generator: Generator<'a, R, S>
+}
+
+impl<'a, R: RngCore, S: Sampler> Generator<'a, R, S> {
+    #[inline]
+    /// Yield words of the generated tokens
+    pub fn words(self) -> Words<'a, R, S> {
+        Words {
+            generator: self
+        }
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> Words<'a, R, S> {
+    #[inline]
+    /// Underlying tokens generator
+    pub fn generator(&self) -> &Generator<'a, R, S> {
+        &self.generator
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> Iterator for Words<'a, R, S> {
+    type Item = Result<&'a str, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let model = self.generator.model;
+
+        let word = self.generator.next()?.and_then(|token| {
+            model.tokens.find_word(token)
+                .ok_or(Error::TokenNotFound(token))
+        });
+
+        Some(word)
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> FusedIterator for Words<'a, R, S> {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Generated token with its probability
+pub struct TokenProbability {
+    pub token: u64,
+
+    /// Probability of the token to continue the chain,
+    /// smoothed if the generation uses smoothing
+    pub probability: f64,
+
+    /// Order of the highest enabled table which has the transition,
+    /// 0 if the token was chosen without any seen transition
+    pub order: usize
+}
+
+/// Generator yielding tokens with their probabilities
+pub struct Probabilities<'a, R = ChaCha8Rng, S = DefaultSampler> {
+    generator: Generator<'a, R, S>
+}
+
+impl<'a, R: RngCore, S: Sampler> Generator<'a, R, S> {
+    #[inline]
+    /// Yield probabilities of the generated tokens
+    pub fn with_probabilities(self) -> Probabilities<'a, R, S> {
+        Probabilities {
+            generator: self
+        }
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> Probabilities<'a, R, S> {
+    #[inline]
+    /// Underlying tokens generator
+    pub fn generator(&self) -> &Generator<'a, R, S> {
+        &self.generator
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> Iterator for Probabilities<'a, R, S> {
+    type Item = Result<TokenProbability, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = match self.generator.next()? {
+            Ok(token) => token,
+            Err(err) => return Some(Err(err))
+        };
+
+        let model = self.generator.model;
+        let params = &self.generator.params;
+
+        // Chain before the generated token
+        let chain = &self.generator.chain[..self.generator.chain.len() - 1];
+
+        let rows = model.transitions.context_rows(chain, |order| params.is_order_enabled(order));
+
+        let order = rows.iter()
+            .rev()
+            .find(|row| row.count(token) > 0)
+            .map(|row| row.order())
+            .unwrap_or(0);
+
+        let probability = ContextSmoother::new(rows, model.smoothing_stats(), params.smoothing)
+            .probability(token)
+            .unwrap_or(0.0);
+
+        Some(Ok(TokenProbability {
+            token,
+            probability,
+            order
+        }))
+    }
+}
+
+impl<'a, R: RngCore, S: Sampler> FusedIterator for Probabilities<'a, R, S> {}
+
+mod tests {
+    #[test]
+    fn trimming() {
+        use super::trim_continuations;
+
+        let continuations = (1..=10).map(|i| (i, i)).collect::<Vec<_>>();
+
+        let mut trimmed = continuations.clone();
+
+        trim_continuations(&mut trimmed, 0.2, 0.0);
+
+        assert_eq!(trimmed, continuations[2..]);
+
+        let mut trimmed = continuations.clone();
+
+        trim_continuations(&mut trimmed, 0.1, 0.3);
+
+        assert_eq!(trimmed, continuations[1..7]);
+
+        let mut trimmed = continuations.clone();
+
+        trim_continuations(&mut trimmed, 1.0, 1.0);
+
+        assert_eq!(trimmed, [(10, 10)]);
+    }
+
+    #[test]
+    fn higher_orders() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c d e f"),
+            String::from("x b c d e g")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        // Make "e g" more probable for all the orders below 5
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&Messages::parse_from_lines(&[String::from("x b c d e g")]), &tokens)?, 2)
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 5);
+
+        assert_eq!(model.transitions().order(), 5);
+
+        let beginning = ["a", "b", "c", "d", "e"].map(|word| model.tokens().find_token(word).unwrap());
+
+        let params = GenerationParams {
+            temperature: 1.0,
+            ..GenerationParams::default()
+        };
+
+        let generated = model.generate(beginning, &params)
+            .map(|token| token.map(|token| model.tokens().find_word(token).unwrap().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, ["f"]);
+
+        let params = GenerationParams {
+            temperature: 1.0,
+            no_pentagrams: true,
+            ..GenerationParams::default()
+        };
+
+        let generated = model.generate(beginning, &params)
+            .map(|token| token.map(|token| model.tokens().find_word(token).unwrap().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, ["g"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn smoothing() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let tokens = Tokens::parse_from_messages(&Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("z")
+        ]));
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b")
+        ]);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let unseen = model.tokens().find_token("z").unwrap();
+
+        // "z" was never followed by anything
+        assert_eq!(model.generate([unseen], &GenerationParams::default()).count(), 0);
+
+        for algorithm in [SmoothingAlgorithm::Laplace, SmoothingAlgorithm::GoodTuring, SmoothingAlgorithm::KneserNey] {
+            let params = GenerationParams {
+                smoothing: Smoothing {
+                    algorithm,
+                    ..Smoothing::default()
+                },
+                min_len: 3,
+                ..GenerationParams::default()
+            };
+
+            let generated = model.generate([unseen], &params)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            assert!(!generated.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn min_length() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("a b c d e")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        // Make "a b" 3 times more probable than "a b c d e"
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&Messages::parse_from_lines(&[String::from("a b")]), &tokens)?, 2)
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let a = model.tokens().find_token("a").unwrap();
+
+        let params = GenerationParams {
+            temperature: 1.0,
+            ..GenerationParams::default()
+        };
+
+        let generated = model.generate([a], &params)
+            .map(|token| token.map(|token| model.tokens().find_word(token).unwrap().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, ["b"]);
+
+        let params = GenerationParams {
+            temperature: 1.0,
+            min_len: 5,
+            ..GenerationParams::default()
+        };
+
+        let generated = model.generate([a], &params)
+            .map(|token| token.map(|token| model.tokens().find_word(token).unwrap().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, ["b", "c", "d", "e"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_rng() -> anyhow::Result<()> {
+        use rand::SeedableRng;
+        use rand::rngs::mock::StepRng;
+        use rand_chacha::ChaCha20Rng;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c d e f"),
+            String::from("a c e b d f"),
+            String::from("b a d c f e")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let params = GenerationParams {
+            temperature: 0.5,
+            ..GenerationParams::default()
+        };
+
+        let a = model.tokens().find_token("a").unwrap();
+
+        let first = model.generate_with_rng([a], &params, ChaCha20Rng::seed_from_u64(42))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let second = model.generate_with_rng([a], &params, ChaCha20Rng::seed_from_u64(42))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(first, second);
+
+        // Random seed is always 0 so the most probable token is always kept
+        let generated = model.generate_with_rng([a], &params, StepRng::new(0, 0))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert!(!generated.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn candidate_cache() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b a b a b c"),
+            String::from("a c a b c a b")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let params = GenerationParams {
+            seed: Some(42),
+            ..GenerationParams::default()
+        };
+
+        let a = model.tokens().find_token("a").unwrap();
+
+        let mut cache = CandidateCache::default();
+
+        let cached = model.generate([a], &params)
+            .with_cache(&mut cache)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let uncached = model.generate([a], &params)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(cached, uncached);
+        assert!(!cache.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn banned() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("a b d"),
+            String::from("c b d")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let banned = HashSet::from([token("c")]);
+
+        for seed in 0..20 {
+            let params = GenerationParams {
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            let generated = model.generate([token("a")], &params)
+                .with_banned(&banned)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            assert_eq!(generated, [token("b"), token("d")]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn min_length_backoff() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let params = GenerationParams {
+            min_len: 4,
+            ..GenerationParams::default()
+        };
+
+        // Only the end follows "b", so a new message is started
+        let generated = model.generate([token("a")], &params)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, [token("b"), token("a"), token("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_beginning() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("x y z")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        for seed in 0..10 {
+            let params = GenerationParams {
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            let generated = model.generate([], &params)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            assert!(generated == [token("a"), token("b"), token("c")] || generated == [token("x"), token("y"), token("z")]);
+        }
+
+        // Banned words are never used as the first token
+        let banned = HashSet::from([token("a")]);
+
+        let params = GenerationParams::default();
+
+        for _ in 0..10 {
+            let generated = model.generate([], &params)
+                .with_banned(&banned)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            assert_eq!(generated[0], token("x"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn probabilities() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("x b d")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, false);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let params = GenerationParams::default();
+
+        let steps = model.generate([token("a")], &params)
+            .with_probabilities()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(steps, [
+            TokenProbability { token: token("b"), probability: 1.0, order: 2 },
+            TokenProbability { token: token("c"), probability: 1.0, order: 2 }
+        ]);
+
+        // Bigram "b b" is unseen, so the unigram table is used
+        let steps = model.generate([token("b"), token("b")], &params)
+            .with_probabilities()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(steps[0].order, 1);
+        assert_eq!(steps[0].probability, 0.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_repeat_ngram() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b a b a b a b c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, false);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        for seed in 0..20 {
+            let params = GenerationParams {
+                no_repeat_ngram: 2,
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            let mut chain = vec![token("a")];
+
+            for next in model.generate(chain.clone(), &params) {
+                chain.push(next?);
+            }
+
+            // Either "a b c" or "a b a" which can't be continued
+            assert_eq!(chain.len(), 3);
+
+            let bigrams = chain.windows(2).collect::<HashSet<_>>();
+
+            assert_eq!(bigrams.len(), chain.len() - 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c d e f"),
+            String::from("a c e b d f"),
+            String::from("b a d c f e"),
+            String::from("f e d c b a")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let params = GenerationParams {
+            temperature: 0.5,
+            max_len: 30,
+            ..GenerationParams::default()
+        };
+
+        let beginning = model.tokens().find_token("a").unwrap();
+
+        let mut generator = model.generate([beginning], &params);
+
+        for _ in 0..5 {
+            generator.next().transpose()?;
+        }
+
+        let state = postcard::to_allocvec(&generator.snapshot())?;
+        let state = postcard::from_bytes::<GeneratorState>(&state)?;
+
+        let resumed = model.resume(state)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let continued = generator.collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(resumed, continued);
+
+        Ok(())
+    }
+
+    #[test]
+    fn context_window() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("the big cat"),
+            String::from("a big dog"),
+            String::from("my big dog")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, false);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let chain = [token("the"), token("big")];
+        let continuations = vec![(token("cat"), 1.0), (token("dog"), 2.0)];
+
+        let rerank = |context_window| {
+            let params = GenerationParams {
+                context_window,
+                ..GenerationParams::default()
+            };
+
+            super::rerank_by_context(continuations.clone(), &model, &chain, &params)
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect::<Vec<_>>()
+        };
+
+        // Only the last token is known to precede both words
+        assert_eq!(rerank(1), [token("cat"), token("dog")]);
+
+        // "dog" never follows "the big"
+        assert_eq!(rerank(2), [token("dog"), token("cat")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cycles() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        assert_eq!(find_cycle(&[1, 2, 2, 2], 4, 3), Some(1));
+        assert_eq!(find_cycle(&[1, 2, 3, 2, 3], 4, 2), Some(2));
+        assert_eq!(find_cycle(&[1, 2, 3, 2, 3], 4, 3), None);
+        assert_eq!(find_cycle(&[2, 2, 2], 0, 3), None);
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("no no no no no no no no"),
+            String::from("no way")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let no = model.tokens().find_token("no").unwrap();
+
+        for loop_action in [LoopAction::Perturb, LoopAction::Stop] {
+            for seed in 0..20 {
+                let params = GenerationParams {
+                    temperature: 1.0,
+                    repeat_penalty: 1.0,
+                    max_loop_period: 2,
+                    loop_action,
+                    seed: Some(seed),
+                    ..GenerationParams::default()
+                };
+
+                let generated = model.generate([no], &params)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                assert!(!generated.windows(4).any(|window| window.iter().all(|token| *token == no)));
+
+                if loop_action == LoopAction::Stop {
+                    assert_eq!(generated, [no, no]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
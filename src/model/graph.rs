@@ -0,0 +1,169 @@
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+
+use crate::prelude::{
+    Unigram,
+    Model,
+    END_TOKEN,
+    UNK_TOKEN_NAME
+};
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Word of the transitions graph
+pub struct GraphNode {
+    pub token: u64,
+    pub word: String,
+
+    /// Amount of transitions from the start word to this one
+    pub depth: usize
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Transition between the words of the graph
+pub struct GraphEdge {
+    pub from: u64,
+    pub to: u64,
+    pub count: u64,
+    pub probability: f64
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Subgraph of the unigram transitions around a word
+pub struct TransitionsGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>
+}
+
+impl TransitionsGraph {
+    /// Format the graph in the GraphViz DOT language
+    ///
+    /// Edges are labeled by the transitions probabilities.
+    pub fn to_dot(&self) -> String {
+        fn escape(word: &str) -> String {
+            word.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+
+        let mut dot = String::from("digraph transitions {\n");
+
+        for node in &self.nodes {
+            dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", node.token, escape(&node.word)));
+        }
+
+        for edge in &self.edges {
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{:.4}\", weight={}];\n", edge.from, edge.to, edge.probability, edge.count));
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+impl Model {
+    /// Get subgraph of the unigram transitions reachable from the token
+    ///
+    /// Words are visited breadth-first up to `depth` transitions away from
+    /// the start one, following only `top` most frequent continuations of
+    /// every word. 0 means no limit. The end of the text is a node without
+    /// continuations.
+    pub fn transitions_graph(&self, token: u64, depth: usize, top: usize) -> TransitionsGraph {
+        let mut graph = TransitionsGraph::default();
+
+        let mut depths = HashMap::from([(token, 0)]);
+        let mut queue = VecDeque::from([token]);
+
+        while let Some(current) = queue.pop_front() {
+            let current_depth = depths[&current];
+
+            graph.nodes.push(GraphNode {
+                token: current,
+                word: self.tokens.find_word(current)
+                    .unwrap_or(UNK_TOKEN_NAME)
+                    .to_string(),
+                depth: current_depth
+            });
+
+            if current_depth >= depth || current == END_TOKEN {
+                continue;
+            }
+
+            let Some(row) = self.transitions.unigrams.get(&Unigram::new([current])) else {
+                continue;
+            };
+
+            let mut continuations = row.iter()
+                .map(|(next, count)| (if next.is_end() { END_TOKEN } else { next.token() }, *count))
+                .collect::<Vec<_>>();
+
+            continuations.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+            if top > 0 {
+                continuations.truncate(top);
+            }
+
+            for (next, count) in continuations {
+                graph.edges.push(GraphEdge {
+                    from: current,
+                    to: next,
+                    count,
+                    probability: count as f64 / row.total() as f64
+                });
+
+                if let Entry::Vacant(entry) = depths.entry(next) {
+                    entry.insert(current_depth + 1);
+
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        graph
+    }
+}
+
+mod tests {
+    #[test]
+    fn transitions_graph() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("a d")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 1);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let graph = model.transitions_graph(token("a"), 1, 0);
+
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.edges.len(), 2);
+
+        assert_eq!(graph.edges[0].to, token("b"));
+        assert!((graph.edges[0].probability - 2.0 / 3.0).abs() < 1e-9);
+
+        // Only the most frequent continuations are followed
+        let graph = model.transitions_graph(token("a"), 3, 1);
+
+        let words = graph.nodes.iter()
+            .map(|node| node.word.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(words, ["a", "b", "c", "<END>"]);
+
+        let dot = graph.to_dot();
+
+        assert!(dot.starts_with("digraph transitions {"));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"0.6667\", weight=2];", token("a"), token("b"))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+use crate::prelude::{
+    TransitionsBuilder,
+    Tokens,
+    Model,
+    MAX_ORDER,
+    START_TOKEN,
+    END_TOKEN,
+    START_TOKEN_NAME,
+    END_TOKEN_NAME
+};
+
+use crate::Error;
+
+/// Word of the beginning of the text in markovify chains
+pub const MARKOVIFY_BEGIN: &str = "___BEGIN__";
+
+/// Word of the end of the text in markovify chains
+pub const MARKOVIFY_END: &str = "___END__";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+/// Format of the JSON chain models made by other tools
+pub enum ChainFormat {
+    #[default]
+    /// Chain exported by `Chain.to_json` or text model exported
+    /// by `Text.to_json` of the Python markovify library
+    Markovify,
+
+    /// Object of space-separated states with counts of their next words,
+    /// using `<START>` and `<END>` for the beginning and end of the text
+    Words
+}
+
+/// State of the chain with counts of its next words
+type ChainState = (Vec<String>, HashMap<String, u64>);
+
+fn parse_markovify(value: Value) -> Result<Vec<ChainState>, Error> {
+    // Text models store the chain as a JSON string
+    let chain = match value {
+        Value::Object(mut text) => match text.remove("chain") {
+            Some(Value::String(chain)) => serde_json::from_str(&chain)?,
+            Some(chain) => chain,
+
+            None => return Err(Error::InvalidChain(String::from("text model has no chain")))
+        },
+
+        chain => chain
+    };
+
+    Ok(serde_json::from_value(chain)?)
+}
+
+fn parse_words(value: Value) -> Result<Vec<ChainState>, Error> {
+    let states = serde_json::from_value::<HashMap<String, HashMap<String, u64>>>(value)?;
+
+    let states = states.into_iter()
+        .map(|(state, next)| {
+            let state = state.split_whitespace()
+                .map(String::from)
+                .collect();
+
+            (state, next)
+        })
+        .collect();
+
+    Ok(states)
+}
+
+impl Model {
+    /// Import model from the JSON chain made by another tool
+    ///
+    /// Order of the model is the state size of the chain. Tables of
+    /// the lower orders are counted from the last words of the states,
+    /// so the imported model backs off like the trained ones.
+    pub fn import_chain(json: &[u8], format: ChainFormat) -> Result<Self, Error> {
+        let value = serde_json::from_slice::<Value>(json)?;
+
+        let (states, begin, end) = match format {
+            ChainFormat::Markovify => (parse_markovify(value)?, MARKOVIFY_BEGIN, MARKOVIFY_END),
+            ChainFormat::Words => (parse_words(value)?, START_TOKEN_NAME, END_TOKEN_NAME)
+        };
+
+        let Some(order) = states.first().map(|(state, _)| state.len()) else {
+            return Err(Error::EmptyModel);
+        };
+
+        if !(1..=MAX_ORDER).contains(&order) {
+            return Err(Error::InvalidChain(format!("state size {order} is not in the [1, {MAX_ORDER}] range")));
+        }
+
+        let mut tokens = Tokens::default();
+        let mut builder = TransitionsBuilder::with_order(order);
+
+        let mut token = |word: &str| {
+            if word == begin {
+                START_TOKEN
+            } else if word == end {
+                END_TOKEN
+            } else {
+                tokens.insert_word(word)
+            }
+        };
+
+        for (state, next) in states {
+            if state.len() != order {
+                return Err(Error::InvalidChain(String::from("states have different sizes")));
+            }
+
+            let state = state.iter()
+                .map(|word| token(word))
+                .collect::<Vec<_>>();
+
+            for (word, count) in next {
+                builder.observe_transition(&state, token(&word), count);
+            }
+        }
+
+        let model = Self {
+            headers: HashMap::new(),
+            transitions: builder.build(),
+            tokens,
+            smoothing_stats: OnceLock::new()
+        };
+
+        Ok(model.with_header("version", env!("CARGO_PKG_VERSION")))
+    }
+}
+
+mod tests {
+    #[test]
+    fn import_markovify() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        // markovify.Chain([["a", "b"], ["a", "b"], ["a", "c"]], state_size=2).to_json()
+        let chain = r#"[
+            [["___BEGIN__", "___BEGIN__"], {"a": 3}],
+            [["___BEGIN__", "a"], {"b": 2, "c": 1}],
+            [["a", "b"], {"___END__": 2}],
+            [["a", "c"], {"___END__": 1}]
+        ]"#;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("a b"),
+            String::from("a c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let trained = Model::build_with_order(dataset, 2);
+
+        // Text models wrap the chain to a string
+        let text = serde_json::json!({
+            "state_size": 2,
+            "chain": chain,
+            "parsed_sentences": null
+        });
+
+        for model in [Model::import_chain(chain.as_bytes(), ChainFormat::Markovify)?, Model::import_chain(text.to_string().as_bytes(), ChainFormat::Markovify)?] {
+            assert_eq!(model.transitions().order(), 2);
+
+            assert_eq!(model.transitions().unigrams.transitions_len(), trained.transitions().unigrams.transitions_len());
+            assert_eq!(model.transitions().bigrams.as_ref().unwrap().transitions_len(), trained.transitions().bigrams.as_ref().unwrap().transitions_len());
+
+            let a = model.tokens().find_token("a").unwrap();
+            let b = model.tokens().find_token("b").unwrap();
+
+            let trained_a = trained.tokens().find_token("a").unwrap();
+            let trained_b = trained.tokens().find_token("b").unwrap();
+
+            assert_eq!(model.probability(&[a], b, &Smoothing::default()), trained.probability(&[trained_a], trained_b, &Smoothing::default()));
+        }
+
+        // Simple word chains
+        let model = Model::import_chain(br#"{"<START>": {"a": 2}, "a": {"b": 1, "<END>": 1}, "b": {"<END>": 1}}"#, ChainFormat::Words)?;
+
+        assert_eq!(model.transitions().order(), 1);
+        assert_eq!(model.transitions().unigrams.transitions_len(), 4);
+
+        // Too large states
+        assert!(Model::import_chain(br#"{"a b c d e f": {"g": 1}}"#, ChainFormat::Words).is_err());
+
+        Ok(())
+    }
+}
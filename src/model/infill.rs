@@ -0,0 +1,183 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::{Rng, RngCore};
+
+use crate::prelude::{
+    GenerationParams,
+    Model,
+    START_TOKEN,
+    END_TOKEN
+};
+
+use crate::Error;
+
+impl Model {
+    /// Get the smallest amount of steps from every token to the target one
+    ///
+    /// Walks unigram transitions backwards from the target.
+    fn distances_to(&self, target: u64) -> HashMap<u64, usize> {
+        let mut previous = HashMap::<u64, Vec<u64>>::new();
+
+        for (current, row) in self.transitions.unigrams.iter() {
+            for next in row.keys() {
+                let next = if next.is_end() { END_TOKEN } else { next.token() };
+
+                previous.entry(next).or_default().push(current.token());
+            }
+        }
+
+        let mut distances = HashMap::from([(target, 0)]);
+        let mut queue = VecDeque::from([target]);
+
+        while let Some(token) = queue.pop_front() {
+            let distance = distances[&token];
+
+            for prev in previous.get(&token).into_iter().flatten() {
+                if !distances.contains_key(prev) {
+                    distances.insert(*prev, distance + 1);
+                    queue.push_back(*prev);
+                }
+            }
+        }
+
+        distances
+    }
+
+    /// Generate tokens connecting the prefix with the suffix
+    ///
+    /// Tokens are sampled forward proportionally to their counts in the
+    /// highest order table, but only those from which the first suffix
+    /// token is still reachable within `max_len` tokens of the chain are
+    /// kept. Empty suffix is connected with the end of the text.
+    ///
+    /// Returns `None` if the suffix can't be reached from the prefix at all,
+    /// and `Error::BudgetExceeded` if it can't be reached within `max_len` tokens.
+    pub fn infill_with_rng(&self, prefix: &[u64], suffix: &[u64], params: &GenerationParams, rng: &mut impl RngCore) -> Result<Option<Vec<u64>>, Error> {
+        let target = suffix.first().copied().unwrap_or(END_TOKEN);
+
+        let distances = self.distances_to(target);
+
+        let mut chain = prefix.to_vec();
+        let mut middle = Vec::new();
+
+        // Check that the suffix is reachable at all
+        let last = chain.last().copied().unwrap_or(START_TOKEN);
+
+        let Some(distance) = distances.get(&last) else {
+            return Ok(None);
+        };
+
+        if chain.len() + distance > params.max_len + 1 {
+            return Err(Error::BudgetExceeded);
+        }
+
+        loop {
+            let rows = self.transitions.context_rows(&chain, |order| params.is_order_enabled(order));
+
+            // Highest order continuations which can still reach the target
+            let mut continuations = rows.iter()
+                .rev()
+                .map(|row| {
+                    row.continuations()
+                        .into_iter()
+                        .filter(|(token, _)| {
+                            *token == target || distances.get(token)
+                                .is_some_and(|distance| *token != END_TOKEN && chain.len() + distance <= params.max_len)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .find(|continuations| !continuations.is_empty())
+                .ok_or(Error::BudgetExceeded)?;
+
+            // Don't reach the target too early if there are other variants
+            if middle.len() + prefix.len() < params.min_len && continuations.iter().any(|(token, _)| *token != target) {
+                continuations.retain(|(token, _)| *token != target);
+            }
+
+            if params.top_k > 0 && continuations.len() > params.top_k {
+                continuations.sort_by_key(|(_, count)| *count);
+                continuations.drain(..continuations.len() - params.top_k);
+            }
+
+            let total = continuations.iter()
+                .map(|(_, count)| *count)
+                .sum::<u64>();
+
+            let mut random = rng.gen_range(0..total.max(1));
+
+            let next = continuations.iter()
+                .find(|(_, count)| {
+                    if random < *count {
+                        return true;
+                    }
+
+                    random -= count;
+
+                    false
+                })
+                .map(|(token, _)| *token)
+                .unwrap_or(continuations[0].0);
+
+            if next == target {
+                return Ok(Some(middle));
+            }
+
+            chain.push(next);
+            middle.push(next);
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn infill() -> anyhow::Result<()> {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::prelude::*;
+        use crate::Error;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c d e"),
+            String::from("a x y z e"),
+            String::from("q r s")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 3);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let params = GenerationParams::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let middle = model.infill_with_rng(&[token("a")], &[token("e")], &params, &mut rng)?.unwrap();
+
+            assert!(middle == [token("b"), token("c"), token("d")] || middle == [token("x"), token("y"), token("z")]);
+        }
+
+        // Empty suffix is the end of the text
+        let middle = model.infill_with_rng(&[token("q")], &[], &params, &mut rng)?.unwrap();
+
+        assert_eq!(middle, [token("r"), token("s")]);
+
+        // Unreachable suffix
+        assert!(model.infill_with_rng(&[token("e")], &[token("a")], &params, &mut rng)?.is_none());
+
+        // Suffix is too far
+        let params = GenerationParams {
+            max_len: 2,
+            ..GenerationParams::default()
+        };
+
+        assert!(matches!(model.infill_with_rng(&[token("a")], &[token("e")], &params, &mut rng), Err(Error::BudgetExceeded)));
+
+        Ok(())
+    }
+}
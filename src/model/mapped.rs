@@ -0,0 +1,667 @@
+use std::collections::{HashMap, BTreeMap};
+use std::path::Path;
+use std::io::Read;
+use std::iter::FusedIterator;
+use std::borrow::Cow;
+
+use memmap2::Mmap;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::prelude::{
+    Tokens,
+    TransitionsTable,
+    GenerationParams,
+    LoopAction,
+    Model,
+    START_TOKEN,
+    END_TOKEN,
+    MAX_ORDER
+};
+
+use crate::bundle::{BundleKind, BUNDLE_MAGIC, FORMAT_VERSION, format_header};
+use crate::model::generator::{choose_continuation, break_cycle, find_cycle};
+use crate::tokens::LegacyTokens;
+use crate::Error;
+
+/// Size of the bundle format header
+const HEADER_LEN: usize = BUNDLE_MAGIC.len() + 2;
+
+#[inline]
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut number = [0; 8];
+
+    number.copy_from_slice(&bytes[offset..offset + 8]);
+
+    u64::from_le_bytes(number)
+}
+
+/// Store the table as sorted contexts, their offsets and continuations
+///
+/// Continuations of every context are sorted by count.
+fn write_table<const SIZE: usize>(bytes: &mut Vec<u8>, table: &TransitionsTable<SIZE>) {
+    let mut rows = table.iter()
+        .map(|(context, row)| {
+            let mut continuations = row.iter()
+                .map(|(next, count)| {
+                    if next.is_end() {
+                        (END_TOKEN, *count)
+                    } else {
+                        (next.token(), *count)
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            continuations.sort_by_key(|(token, count)| (*count, *token));
+
+            (context.tokens().to_vec(), continuations)
+        })
+        .collect::<Vec<_>>();
+
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let transitions = rows.iter()
+        .map(|(_, continuations)| continuations.len())
+        .sum::<usize>();
+
+    bytes.extend((rows.len() as u64).to_le_bytes());
+    bytes.extend((transitions as u64).to_le_bytes());
+
+    for (context, _) in &rows {
+        for token in context {
+            bytes.extend(token.to_le_bytes());
+        }
+    }
+
+    let mut offset = 0;
+
+    for (_, continuations) in &rows {
+        bytes.extend((offset as u64).to_le_bytes());
+
+        offset += continuations.len();
+    }
+
+    bytes.extend((offset as u64).to_le_bytes());
+
+    for (_, continuations) in &rows {
+        for (token, count) in continuations {
+            bytes.extend(token.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+        }
+    }
+}
+
+/// Store the table missing below the highest order as one without rows
+fn write_empty_table(bytes: &mut Vec<u8>) {
+    bytes.extend(0_u64.to_le_bytes());
+    bytes.extend(0_u64.to_le_bytes());
+    bytes.extend(0_u64.to_le_bytes());
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Location of the table in the mapped file
+struct MappedTable {
+    order: usize,
+    rows: usize,
+    contexts: usize,
+    offsets: usize,
+    transitions: usize,
+    transitions_len: usize
+}
+
+/// Model queried directly from the memory-mapped file
+///
+/// Only headers and tokens are decoded when the file is opened,
+/// transitions are found by binary search in the sorted tables.
+/// Suitable for large models which take long to load.
+pub struct MappedModel {
+    /// Headers and tokens of the model, its transitions are empty
+    pub(crate) meta: Model,
+
+    mmap: Mmap,
+    tables: Vec<MappedTable>
+}
+
+impl MappedModel {
+    /// Convert the model to the mapped format bytes
+    pub fn to_bytes(model: &Model) -> Result<Vec<u8>, Error> {
+        // Headers are sorted so equal models have equal bytes
+        let headers = model.headers.iter().collect::<BTreeMap<_, _>>();
+
+        let meta = postcard::to_allocvec(&(headers, &model.tokens))?;
+
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&BUNDLE_MAGIC);
+        bytes.push(BundleKind::MappedModel.to_byte());
+        bytes.push(FORMAT_VERSION);
+
+        bytes.extend((meta.len() as u64).to_le_bytes());
+        bytes.extend(meta);
+
+        let mut transitions = Cow::Borrowed(&model.transitions);
+
+        if transitions.has_pending() {
+            transitions.to_mut().flush();
+        }
+
+        let order = transitions.order();
+
+        bytes.extend((order as u64).to_le_bytes());
+
+        write_table(&mut bytes, &transitions.unigrams);
+
+        // Every table up to the highest order is stored,
+        // e.g. models can be built without bigrams
+        if order >= 2 {
+            match &transitions.bigrams {
+                Some(bigrams) => write_table(&mut bytes, bigrams),
+                None => write_empty_table(&mut bytes)
+            }
+        }
+
+        if order >= 3 {
+            match &transitions.trigrams {
+                Some(trigrams) => write_table(&mut bytes, trigrams),
+                None => write_empty_table(&mut bytes)
+            }
+        }
+
+        if order >= 4 {
+            match &transitions.quadgrams {
+                Some(quadgrams) => write_table(&mut bytes, quadgrams),
+                None => write_empty_table(&mut bytes)
+            }
+        }
+
+        if let Some(pentagrams) = &transitions.pentagrams {
+            write_table(&mut bytes, pentagrams);
+        }
+
+        Ok(bytes)
+    }
+
+    #[inline]
+    /// Write the model to the file in the mapped format
+    pub fn write(model: &Model, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, Self::to_bytes(model)?)?;
+
+        Ok(())
+    }
+
+    /// Check if the file stores a mapped model
+    pub fn is_mapped(path: impl AsRef<Path>) -> Result<bool, Error> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+
+        std::fs::File::open(path)?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+
+        Ok(format_header(&header)?.0 == Some(BundleKind::MappedModel))
+    }
+
+    /// Map the model file to memory
+    ///
+    /// The file must not be changed while the model is used.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+
+        // SAFETY: the mapped bytes are only read and bounds-checked below,
+        // concurrent modification of the file is documented as forbidden.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let (kind, version) = format_header(&mmap)?;
+
+        match kind {
+            Some(BundleKind::MappedModel) => (),
+
+            Some(found) => return Err(Error::BundleKindMismatch {
+                expected: BundleKind::MappedModel,
+                found
+            }),
+
+            None => return Err(Error::InvalidBundleHeader)
+        }
+
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormatVersion {
+                found: version,
+                supported: FORMAT_VERSION
+            });
+        }
+
+        let len = mmap.len();
+
+        // Check that the section of the given size fits into the file
+        let section = |offset: usize, size: u64| -> Result<usize, Error> {
+            usize::try_from(size).ok()
+                .and_then(|size| offset.checked_add(size))
+                .filter(|end| *end <= len)
+                .ok_or(Error::CorruptedMappedModel)
+        };
+
+        let mut offset = section(HEADER_LEN, 8)?;
+
+        let meta_end = section(offset, read_u64(&mmap, HEADER_LEN))?;
+
+        let meta = &mmap[offset..meta_end];
+
+        // Tokens counts are stored since the format version 4
+        let (headers, tokens) = if version < 4 {
+            let (headers, tokens) = postcard::from_bytes::<(HashMap<String, String>, LegacyTokens)>(meta)?;
+
+            (headers, Tokens::from(tokens))
+        } else {
+            postcard::from_bytes::<(HashMap<String, String>, Tokens)>(meta)?
+        };
+
+        offset = section(meta_end, 8)?;
+
+        let order = read_u64(&mmap, meta_end);
+
+        if order == 0 || order > MAX_ORDER as u64 {
+            return Err(Error::CorruptedMappedModel);
+        }
+
+        let order = order as usize;
+
+        let mut tables = Vec::with_capacity(order);
+
+        for order in 1..=order {
+            let header_end = section(offset, 16)?;
+
+            let rows = read_u64(&mmap, offset);
+            let transitions = read_u64(&mmap, offset + 8);
+
+            let contexts = header_end;
+            let offsets = section(contexts, rows.saturating_mul(order as u64 * 8))?;
+            let continuations = section(offsets, rows.saturating_add(1).saturating_mul(8))?;
+
+            offset = section(continuations, transitions.saturating_mul(16))?;
+
+            let table = MappedTable {
+                order,
+                rows: rows as usize,
+                contexts,
+                offsets,
+                transitions: continuations,
+                transitions_len: transitions as usize
+            };
+
+            // Rows must point inside the table's continuations
+            if read_u64(&mmap, offsets + table.rows * 8) != transitions {
+                return Err(Error::CorruptedMappedModel);
+            }
+
+            tables.push(table);
+        }
+
+        if tables.is_empty() || tables[0].rows == 0 {
+            return Err(Error::EmptyModel);
+        }
+
+        Ok(Self {
+            meta: Model {
+                headers,
+                tokens,
+                ..Model::default()
+            },
+            mmap,
+            tables
+        })
+    }
+
+    #[inline]
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.meta.headers
+    }
+
+    #[inline]
+    pub fn tokens(&self) -> &Tokens {
+        &self.meta.tokens
+    }
+
+    #[inline]
+    /// Highest order of the transitions tables
+    pub fn order(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Get (token, count) continuations of the context sorted by count
+    ///
+    /// Context length is the order of the used table.
+    /// The end of the text is `END_TOKEN`.
+    pub fn continuations(&self, context: &[u64]) -> Option<impl Iterator<Item = (u64, u64)> + '_> {
+        let table = *self.tables.get(context.len().checked_sub(1)?)?;
+
+        let context_at = |row: usize| {
+            (0..table.order).map(move |i| read_u64(&self.mmap, table.contexts + (row * table.order + i) * 8))
+        };
+
+        // Binary search of the context in the sorted rows
+        let mut low = 0;
+        let mut high = table.rows;
+
+        while low < high {
+            let middle = (low + high) / 2;
+
+            match context_at(middle).cmp(context.iter().copied()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+
+                std::cmp::Ordering::Equal => {
+                    let start = read_u64(&self.mmap, table.offsets + middle * 8) as usize;
+                    let end = read_u64(&self.mmap, table.offsets + (middle + 1) * 8) as usize;
+
+                    // Corrupted offsets would point outside of the table
+                    let end = end.min(table.transitions_len);
+
+                    return Some((start..end).map(move |i| {
+                        let offset = table.transitions + i * 16;
+
+                        (read_u64(&self.mmap, offset), read_u64(&self.mmap, offset + 8))
+                    }));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Get continuations of the chain from the table of the given order
+    fn chain_continuations(&self, chain: &[u64], order: usize) -> Option<impl Iterator<Item = (u64, u64)> + '_> {
+        let padding = order.saturating_sub(chain.len());
+
+        let context = std::iter::repeat_n(START_TOKEN, padding)
+            .chain(chain[chain.len() + padding - order..].iter().copied())
+            .collect::<Vec<_>>();
+
+        self.continuations(&context)
+    }
+
+    /// Check if the token has no continuations except the end of the text
+    pub(crate) fn is_dead_end(&self, token: u64) -> bool {
+        self.continuations(&[token])
+            .map(|mut continuations| !continuations.any(|(next, _)| next != END_TOKEN))
+            .unwrap_or(true)
+    }
+
+    #[inline]
+    /// Generate tokens continuing the beginning
+    ///
+    /// Random numbers generator is seeded from `params.seed`
+    /// or from the system entropy. See `MappedGenerator`
+    /// for the supported parameters.
+    pub fn generate<'a>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams) -> MappedGenerator<'a> {
+        let rng = match params.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        };
+
+        self.generate_with_rng(beginning, params, rng)
+    }
+
+    #[inline]
+    /// Generate tokens continuing the beginning using
+    /// the provided random numbers generator
+    pub fn generate_with_rng<'a, R: RngCore>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams, rng: R) -> MappedGenerator<'a, R> {
+        MappedGenerator {
+            chain: beginning.into(),
+            rng,
+            params,
+            model: self
+        }
+    }
+}
+
+/// Tokens generator of the mapped model
+///
+/// Works like the model's generator, but doesn't support smoothing,
+/// `no_repeat_ngram` and `context_window`, which are ignored.
+pub struct MappedGenerator<'a, R = ChaCha8Rng> {
+    chain: Vec<u64>,
+    rng: R,
+    params: &'a GenerationParams,
+    model: &'a MappedModel
+}
+
+impl<'a, R: RngCore> MappedGenerator<'a, R> {
+    #[inline]
+    /// Tokens generated so far, including the beginning
+    pub fn chain(&self) -> &[u64] {
+        &self.chain
+    }
+
+    /// Filter sorted (token, count) continuations the same way as the model's generator
+    ///
+    /// Returned flag is false when only dead-ends left.
+    fn filter_continuations(&self, continuations: impl Iterator<Item = (u64, u64)>) -> Option<(Vec<(u64, u64)>, bool)> {
+        let allow_end = self.chain.len() >= self.params.min_len;
+
+        let continuations = continuations
+            .filter(|(token, _)| allow_end || *token != END_TOKEN)
+            .collect::<Vec<_>>();
+
+        if continuations.is_empty() {
+            return None;
+        }
+
+        if allow_end {
+            return Some((continuations, true));
+        }
+
+        let alive = continuations.iter()
+            .filter(|(token, _)| !self.model.is_dead_end(*token))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if alive.is_empty() {
+            return Some((continuations, false));
+        }
+
+        Some((alive, true))
+    }
+}
+
+impl<'a, R: RngCore> Iterator for MappedGenerator<'a, R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.chain.len() >= self.params.max_len {
+            return None;
+        }
+
+        // Break the cycle by a random continuation of the lowest order
+        if let Some(period) = find_cycle(&self.chain, self.params.max_loop_period, self.params.loop_repeats) {
+            if self.params.loop_action == LoopAction::Stop {
+                return None;
+            }
+
+            let (continuations, _) = self.filter_continuations(self.model.chain_continuations(&self.chain, 1)?)?;
+
+            let next = break_cycle(continuations, &self.chain, period, &mut self.rng)?;
+
+            if next == END_TOKEN {
+                return None;
+            }
+
+            self.chain.push(next);
+
+            return Some(next);
+        }
+
+        let mut continuations = None;
+
+        // Dead-end continuations to use if nothing better is found
+        let mut fallback = None;
+
+        // Back off from the highest order table to the lower ones
+        for order in (1..=self.model.order()).rev() {
+            if !self.params.is_order_enabled(order) {
+                continue;
+            }
+
+            let Some(row) = self.model.chain_continuations(&self.chain, order) else {
+                continue;
+            };
+
+            match self.filter_continuations(row) {
+                Some((row, true)) => {
+                    continuations = Some(row);
+
+                    break;
+                }
+
+                Some((row, false)) => {
+                    fallback.get_or_insert(row);
+                }
+
+                None => ()
+            }
+        }
+
+        let continuations = match continuations.or(fallback) {
+            Some(continuations) => continuations,
+
+            // Only the end of the text continues the chain before
+            // the minimum length, so continue with a message opener
+            None if self.chain.len() < self.params.min_len => {
+                self.filter_continuations(self.model.continuations(&[START_TOKEN])?)?.0
+            }
+
+            None => return None
+        };
+
+        let params = self.params.scheduled(&self.chain, self.model.tokens());
+        let next = choose_continuation(continuations, &self.chain, &params, &mut self.rng);
+
+        if next == END_TOKEN {
+            return None;
+        }
+
+        self.chain.push(next);
+
+        Some(next)
+    }
+}
+
+impl<'a, R: RngCore> FusedIterator for MappedGenerator<'a, R> {}
+
+mod tests {
+    #[test]
+    fn mapped_model() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("a b d e"),
+            String::from("x y z")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 3);
+
+        let path = std::env::temp_dir().join(format!("markov-chains-mapped-{}.bin", std::process::id()));
+
+        MappedModel::write(&model, &path)?;
+
+        assert!(MappedModel::is_mapped(&path)?);
+
+        let mapped = MappedModel::open(&path)?;
+
+        let token = |word| mapped.tokens().find_token(word).unwrap();
+
+        assert_eq!(mapped.order(), 3);
+        assert_eq!(mapped.tokens().len(), model.tokens().len());
+
+        // Continuations are sorted by count
+        let continuations = mapped.continuations(&[token("b")]).unwrap().collect::<Vec<_>>();
+
+        assert_eq!(continuations, [(token("d"), 1), (token("c"), 2)]);
+
+        let continuations = mapped.continuations(&[token("b"), token("c")]).unwrap().collect::<Vec<_>>();
+
+        assert_eq!(continuations, [(END_TOKEN, 2)]);
+
+        assert!(mapped.continuations(&[token("c"), token("b")]).is_none());
+
+        // Corrupted order is rejected
+        let mut bytes = std::fs::read(&path)?;
+
+        let order_offset = super::HEADER_LEN + 8 + super::read_u64(&bytes, super::HEADER_LEN) as usize;
+
+        bytes[order_offset..order_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let corrupted = std::env::temp_dir().join(format!("markov-chains-corrupted-{}.bin", std::process::id()));
+
+        std::fs::write(&corrupted, bytes)?;
+
+        assert!(matches!(MappedModel::open(&corrupted), Err(crate::Error::CorruptedMappedModel)));
+
+        std::fs::remove_file(corrupted)?;
+
+        for seed in 0..10 {
+            let params = GenerationParams {
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            let generated = mapped.generate([], &params).collect::<Vec<_>>();
+
+            assert!([
+                vec![token("a"), token("b"), token("c")],
+                vec![token("a"), token("b"), token("d"), token("e")],
+                vec![token("x"), token("y"), token("z")]
+            ].contains(&generated));
+        }
+
+        // Regular bundles are not mapped models
+        let bundle = std::env::temp_dir().join(format!("markov-chains-bundle-{}.bin", std::process::id()));
+
+        crate::bundle::write(&bundle, &model, 0)?;
+
+        assert!(!MappedModel::is_mapped(&bundle)?);
+        assert!(MappedModel::open(&bundle).is_err());
+
+        std::fs::remove_file(bundle)?;
+
+        // Missing lower order tables are stored empty
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, true);
+
+        MappedModel::write(&model, &path)?;
+
+        let mapped = MappedModel::open(&path)?;
+
+        let token = |word| mapped.tokens().find_token(word).unwrap();
+
+        assert_eq!(mapped.order(), 3);
+        assert!(mapped.continuations(&[token("a")]).is_some());
+        assert!(mapped.continuations(&[token("x"), token("y")]).is_none());
+        assert!(mapped.continuations(&[token("x"), token("y"), token("z")]).is_some());
+
+        for seed in 0..10 {
+            let params = GenerationParams {
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            assert!(!mapped.generate([], &params).collect::<Vec<_>>().is_empty());
+        }
+
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+}
@@ -1,6 +1,23 @@
 pub mod params;
+pub mod table;
 pub mod transitions;
+pub mod smoothing;
 pub mod generator;
+pub mod sampler;
+pub mod schedule;
+pub mod evaluation;
+pub mod cache;
+pub mod diagnostics;
+pub mod streaming;
+pub mod infill;
+pub mod beam;
+pub mod mapped;
+pub mod ensemble;
+pub mod arpa;
+pub mod import;
+pub mod graph;
+pub mod classifier;
+pub mod suggest;
 
 #[allow(clippy::module_inception)]
 pub mod model;
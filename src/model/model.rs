@@ -1,18 +1,69 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use rand::{RngCore, SeedableRng};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand_chacha::ChaCha8Rng;
 
 use crate::prelude::{
+    Unigram,
+    Messages,
     Dataset,
     Tokens,
     GenerationParams,
+    TransitionsTable,
     Transitions,
-    Generator
+    Smoothing,
+    SmoothingStats,
+    ContextSmoother,
+    Generator,
+    GeneratorState,
+    DefaultSampler,
+    Tokenizer,
+    MAX_ORDER,
+    REPLY_TOKEN
 };
 
+use crate::tokens::{serialize_sorted, LegacyTokens};
+use crate::bundle::{BundleKind, format_header, payload};
+use crate::Error;
+
+/// First crate version storing quadgrams and pentagrams tables
+const HIGHER_ORDERS_VERSION: (u64, u64) = (1, 5);
+
+#[derive(serde::Serialize, serde::Deserialize)]
+/// Transitions of the models built before `HIGHER_ORDERS_VERSION`
+struct LegacyTransitions {
+    unigrams: TransitionsTable<1>,
+    bigrams: Option<TransitionsTable<2>>,
+    trigrams: Option<TransitionsTable<3>>
+}
+
+/// Parse (major, minor) numbers of the version
+fn parse_version(version: &str) -> Option<(u64, u64)> {
+    let mut numbers = version.split('.');
+
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+
+    Some((major, minor))
+}
+
+/// Header storing the checksum of the model's transitions and tokens
+pub const CHECKSUM_HEADER: &str = "checksum";
+
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Model {
+    #[serde(serialize_with = "serialize_sorted")]
     pub(crate) headers: HashMap<String, String>,
     pub(crate) transitions: Transitions,
-    pub(crate) tokens: Tokens
+    pub(crate) tokens: Tokens,
+
+    #[serde(skip)]
+    /// Lazily calculated statistics for the smoothing algorithms
+    pub(crate) smoothing_stats: OnceLock<SmoothingStats>
 }
 
 impl Model {
@@ -21,12 +72,130 @@ impl Model {
         let model = Self {
             headers: HashMap::new(),
             transitions: dataset.build_transitions(build_bigrams, build_trigrams),
-            tokens: dataset.tokens
+            tokens: dataset.tokens,
+            smoothing_stats: OnceLock::new()
         };
 
         model.with_header("version", env!("CARGO_PKG_VERSION"))
     }
 
+    #[inline]
+    /// Build model with transitions tables of all the orders up to the given one
+    ///
+    /// Order is clamped to the `[1, MAX_ORDER]` range.
+    pub fn build_with_order(dataset: Dataset, order: usize) -> Self {
+        let model = Self {
+            headers: HashMap::new(),
+            transitions: dataset.build_transitions_with_order(order),
+            tokens: dataset.tokens,
+            smoothing_stats: OnceLock::new()
+        };
+
+        model.with_header("version", env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Decode model from the bytes
+    ///
+    /// Fails if the model was built by an incompatible
+    /// crate version or has no transitions.
+    ///
+    /// Models built before higher orders support are converted
+    /// to the current format. Models written without format header
+    /// and compressed models are detected automatically.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (_, format_version) = format_header(bytes)?;
+
+        let bytes = &*payload(bytes, BundleKind::Model)?;
+
+        // Headerless models which can't be decoded were written by an older crate
+        let legacy_error = |err: postcard::Error| {
+            if format_version == 0 {
+                Error::LegacyBundle {
+                    kind: BundleKind::Model,
+                    source: err
+                }
+            } else {
+                Error::Postcard(err)
+            }
+        };
+
+        // Headers are stored first so they can be checked before the rest
+        let (headers, sections) = postcard::take_from_bytes::<HashMap<String, String>>(bytes)
+            .map_err(legacy_error)?;
+
+        // Corrupted files are reported before they fail to decode
+        if let Some(expected) = headers.get(CHECKSUM_HEADER) {
+            let found = blake3::hash(sections).to_hex().to_string();
+
+            if &found != expected {
+                return Err(Error::ChecksumMismatch {
+                    expected: expected.clone(),
+                    found
+                });
+            }
+        }
+
+        let version = headers.get("version");
+
+        if let Some(version) = version {
+            let expected = env!("CARGO_PKG_VERSION_MAJOR");
+            let found = version.split('.').next().unwrap_or_default();
+
+            if found != expected {
+                return Err(Error::FormatVersionMismatch {
+                    expected: expected.to_string(),
+                    found: found.to_string()
+                });
+            }
+        }
+
+        let legacy = version.and_then(|version| parse_version(version))
+            .is_some_and(|version| version < HIGHER_ORDERS_VERSION);
+
+        let model = if legacy {
+            let (headers, transitions, tokens) = postcard::from_bytes::<(HashMap<String, String>, LegacyTransitions, LegacyTokens)>(bytes)
+                .map_err(legacy_error)?;
+
+            Self {
+                headers,
+                transitions: Transitions {
+                    unigrams: transitions.unigrams,
+                    bigrams: transitions.bigrams,
+                    trigrams: transitions.trigrams,
+                    quadgrams: None,
+                    pentagrams: None
+                },
+                tokens: tokens.into(),
+                smoothing_stats: OnceLock::new()
+            }.with_header("version", env!("CARGO_PKG_VERSION"))
+        } else if format_version < 4 {
+            // Tokens counts are stored since the format version 4
+            let (headers, transitions, tokens) = postcard::from_bytes::<(HashMap<String, String>, Transitions, LegacyTokens)>(bytes)
+                .map_err(legacy_error)?;
+
+            Self {
+                headers,
+                transitions,
+                tokens: tokens.into(),
+                smoothing_stats: OnceLock::new()
+            }
+        } else {
+            postcard::from_bytes::<Self>(bytes).map_err(legacy_error)?
+        };
+
+        if model.transitions.unigrams_len() == 0 {
+            return Err(Error::EmptyModel);
+        }
+
+        Ok(model)
+    }
+
+    #[inline]
+    /// Read model from the file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
     #[inline]
     pub fn with_header(mut self, tag: impl ToString, value: impl ToString) -> Self {
         self.headers.insert(tag.to_string(), value.to_string());
@@ -39,6 +208,25 @@ impl Model {
         &self.headers
     }
 
+    /// Calculate blake3 checksum of the transitions and tokens
+    ///
+    /// Headers are not included, so equal models built from the same
+    /// messages have equal checksums regardless of their headers.
+    /// Stored checksum is checked when the model is decoded.
+    pub fn checksum(&self) -> Result<String, Error> {
+        let bytes = postcard::to_allocvec(&(&self.transitions, &self.tokens))?;
+
+        Ok(blake3::hash(&bytes).to_hex().to_string())
+    }
+
+    #[inline]
+    /// Store checksum of the model in its headers
+    pub fn with_checksum(self) -> Result<Self, Error> {
+        let checksum = self.checksum()?;
+
+        Ok(self.with_header(CHECKSUM_HEADER, checksum))
+    }
+
     #[inline]
     pub fn transitions(&self) -> &Transitions {
         &self.transitions
@@ -50,11 +238,627 @@ impl Model {
     }
 
     #[inline]
+    /// Get statistics of the transitions used by the smoothing algorithms
+    ///
+    /// Calculated on the first call.
+    pub fn smoothing_stats(&self) -> &SmoothingStats {
+        self.smoothing_stats.get_or_init(|| {
+            SmoothingStats::from_transitions(&self.transitions, self.tokens.len() as u64 + 1)
+        })
+    }
+
+    #[inline]
+    /// Get probability of the token to continue the chain
+    ///
+    /// Without smoothing the highest order table which has
+    /// the transition is used and unseen transitions have no probability.
+    pub fn probability(&self, chain: &[u64], token: u64, smoothing: &Smoothing) -> Option<f64> {
+        let rows = self.transitions.context_rows(chain, |_| true);
+
+        ContextSmoother::new(rows, self.smoothing_stats(), *smoothing)
+            .probability(token)
+    }
+
+    /// Check if the token has no continuations except the end of the text
+    pub(crate) fn is_dead_end(&self, token: u64) -> bool {
+        self.transitions.for_unigram(&Unigram::new([token]))
+            .map(|mut transitions| !transitions.any(|(next, _)| !next.is_end()))
+            .unwrap_or(true)
+    }
+
+    #[inline]
+    /// Sample a message opener from the start tokens distribution
+    ///
+    /// Tokens are chosen with probability proportional to how often
+    /// they start messages in the dataset.
+    pub fn sample_start_token(&self) -> Option<u64> {
+        self.sample_start_token_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Sample a message opener using the provided random numbers generator
+    ///
+    /// See `sample_start_token`.
+    pub fn sample_start_token_with_rng(&self, rng: &mut impl RngCore) -> Option<u64> {
+        let openers = self.transitions.for_unigram(&Unigram::start())?
+            .filter(|(unigram, _)| !unigram.is_end())
+            .map(|(unigram, count)| (unigram.token(), *count))
+            .collect::<Vec<_>>();
+
+        let distribution = WeightedIndex::new(openers.iter().map(|(_, count)| *count)).ok()?;
+
+        Some(openers[distribution.sample(rng)].0)
+    }
+
+    #[inline]
+    /// Generate tokens continuing the beginning
+    ///
+    /// Empty beginning generates a new message: the chain starts with
+    /// the START n-gram, so the first token is sampled from the message
+    /// openers using the same parameters as the rest of the tokens.
+    ///
+    /// Random numbers generator is seeded from `params.seed`
+    /// or from the system entropy.
     pub fn generate<'a>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams) -> Generator<'a> {
+        let rng = match params.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        };
+
+        self.generate_with_rng(beginning, params, rng)
+    }
+
+    /// Generate tokens continuing the beginning using
+    /// the provided random numbers generator
+    ///
+    /// With `params.dialogue` the chain starts with the last words
+    /// of the beginning followed by the `REPLY_TOKEN`, so the
+    /// generated tokens are the reply to the beginning.
+    pub fn generate_with_rng<'a, R: RngCore>(&'a self, beginning: impl Into<Vec<u64>>, params: &'a GenerationParams, rng: R) -> Generator<'a, R> {
+        let mut chain = beginning.into();
+        let mut params = Cow::Borrowed(params);
+
+        if params.dialogue {
+            chain.drain(..chain.len().saturating_sub(MAX_ORDER - 1));
+            chain.push(REPLY_TOKEN);
+
+            // Length limits count the reply tokens only
+            let params = params.to_mut();
+
+            params.min_len = params.min_len.saturating_add(chain.len());
+            params.max_len = params.max_len.saturating_add(chain.len());
+        }
+
         Generator {
-            chain: beginning.into(),
+            chain,
+            rng,
+            sampler: DefaultSampler,
             params,
-            model: self
+            model: self,
+            cache: None,
+            banned: None
+        }
+    }
+
+    /// Generate the text continuing the beginning
+    ///
+    /// Returned text includes the beginning and is joined
+    /// the same way as by `join_words`. Replies generated
+    /// with `params.dialogue` don't include the beginning.
+    pub fn generate_text(&self, beginning: impl Into<Vec<u64>>, params: &GenerationParams) -> Result<String, Error> {
+        let mut generator = self.generate(beginning, params);
+
+        for token in generator.by_ref() {
+            token?;
+        }
+
+        let chain = Generator::chain(&generator);
+
+        match chain.iter().position(|token| *token == REPLY_TOKEN) {
+            Some(reply) if params.dialogue => self.detokenize(&chain[reply + 1..]),
+            _ => self.detokenize(chain)
+        }
+    }
+
+    /// Join words of the model into the text
+    ///
+    /// Subwords are joined into words and punctuation is attached
+    /// to its words if the model was built with them.
+    pub fn join_words<T: AsRef<str>>(&self, words: &[T]) -> String {
+        let words = words.iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>();
+
+        self.tokenizer().detokenize(&words)
+    }
+
+    /// Convert tokens of the model into the text, see `join_words`
+    pub fn detokenize(&self, tokens: &[u64]) -> Result<String, Error> {
+        let words = tokens.iter()
+            .map(|token| self.tokens.find_word(*token).ok_or(Error::TokenNotFound(*token)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.join_words(&words))
+    }
+
+    #[inline]
+    /// Continue generation from the captured state
+    pub fn resume<R: RngCore>(&self, state: GeneratorState<R>) -> Generator<'_, R> {
+        Generator {
+            chain: state.chain,
+            rng: state.rng,
+            sampler: DefaultSampler,
+            params: Cow::Owned(state.params),
+            model: self,
+            cache: None,
+            banned: None
+        }
+    }
+
+    #[inline]
+    /// Forget everything calculated from the changed transitions or tokens
+    fn invalidate(&mut self) {
+        self.smoothing_stats = OnceLock::new();
+
+        self.headers.remove(CHECKSUM_HEADER);
+    }
+
+    #[inline]
+    /// Learn transitions from the tokenized message
+    ///
+    /// All the tokens must already be known to the model. New transitions
+    /// are used for generation right away, but packed into the tables in
+    /// batches, see `Model::flush`. Smoothing statistics are recalculated
+    /// after the tables are packed.
+    pub fn observe(&mut self, message: &[u64]) {
+        self.headers.remove(CHECKSUM_HEADER);
+
+        if self.transitions.observe(message, 1) {
+            self.invalidate();
         }
     }
+
+    #[inline]
+    /// Pack the new transitions of the observed messages
+    ///
+    /// They're packed automatically when enough of them is observed
+    /// and when the model is saved, but iterating the tables,
+    /// e.g. by the smoothing statistics, only returns packed transitions.
+    pub fn flush(&mut self) {
+        if self.transitions.has_pending() {
+            self.invalidate();
+
+            self.transitions.flush();
+        }
+    }
+
+    #[inline]
+    /// Learn transitions from the tokenized message, decaying
+    /// old continuations of its contexts by the factor
+    ///
+    /// See `Transitions::observe_with_decay` and `Model::observe`.
+    pub fn observe_with_decay(&mut self, message: &[u64], factor: f64) {
+        self.headers.remove(CHECKSUM_HEADER);
+
+        if self.transitions.observe_with_decay(message, 1, factor) {
+            self.invalidate();
+        }
+    }
+
+    /// Learn transitions from the messages, adding their words
+    /// to the model's vocabulary
+    ///
+    /// Messages must be prepared the same way as the ones the model was
+    /// built from. New words get hashed tokens if the model's tokens are
+    /// hashed and random ones otherwise. Returns amount of new words.
+    pub fn update(&mut self, messages: &Messages, weight: u64) -> usize {
+        self.invalidate();
+
+        let hashed = self.tokens.is_hashed();
+        let known = self.tokens.len();
+
+        let mut transitions = self.transitions.builder();
+
+        for message in messages.messages() {
+            let message = message.iter()
+                .map(|word| {
+                    if hashed {
+                        self.tokens.insert_word_hashed(word)
+                    } else {
+                        self.tokens.insert_word(word)
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            transitions.observe(&message, weight);
+        }
+
+        self.transitions = std::mem::take(&mut self.transitions).merge(transitions.build());
+
+        self.tokens.len() - known
+    }
+
+    /// Remove transitions seen less than `min_count` times
+    ///
+    /// Words left without transitions are removed from the vocabulary,
+    /// special words are kept. Returns amounts of removed transitions
+    /// and words.
+    pub fn prune(&mut self, min_count: u64) -> (usize, usize) {
+        self.invalidate();
+
+        let transitions = self.transitions.prune(min_count);
+
+        let used = self.transitions.tokens();
+
+        let words = self.tokens.retain(|token| used.contains(&token));
+
+        (transitions, words)
+    }
+
+    /// Remove the tokens from the vocabulary and all the transitions
+    ///
+    /// Special words are kept. Returns amounts of removed
+    /// transitions and words, see `Transitions::remove_tokens`.
+    pub fn remove_tokens(&mut self, tokens: &HashSet<u64>) -> (usize, usize) {
+        self.invalidate();
+
+        let transitions = self.transitions.remove_tokens(tokens);
+
+        let words = self.tokens.retain(|token| !tokens.contains(&token));
+
+        (transitions, words)
+    }
+
+    #[inline]
+    /// Multiply counts of all the transitions by the factor
+    ///
+    /// See `Transitions::decay`.
+    pub fn decay(&mut self, factor: f64) {
+        self.invalidate();
+
+        self.transitions.decay(factor);
+    }
+}
+
+mod tests {
+    #[test]
+    fn prune() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b c"),
+            String::from("a b rare")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let mut model = Model::build(dataset, true, true);
+
+        let (transitions, words) = model.prune(2);
+
+        // (b -> rare), (rare -> END) in every table
+        assert_eq!(transitions, 6);
+        assert_eq!(words, 1);
+
+        assert!(model.tokens().find_token("rare").is_none());
+
+        let a = model.tokens().find_token("a").unwrap();
+        let c = model.tokens().find_token("c").unwrap();
+
+        let params = GenerationParams::default();
+
+        for _ in 0..10 {
+            assert_eq!(model.generate([a], &params).collect::<Result<Vec<_>, _>>()?.last(), Some(&c));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_tokens() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello alice how are you"),
+            String::from("hello bob how are you")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let mut model = Model::build_with_order(dataset, 3);
+
+        let alice = model.tokens().find_token("alice").unwrap();
+        let hello = model.tokens().find_token("hello").unwrap();
+
+        let (transitions, words) = model.remove_tokens(&HashSet::from([alice]));
+
+        assert!(transitions > 0);
+        assert_eq!(words, 1);
+
+        assert!(model.tokens().find_word(alice).is_none());
+        assert!(!model.transitions().tokens().contains(&alice));
+
+        let params = GenerationParams::default();
+
+        for _ in 0..10 {
+            let text = model.generate([hello], &params).collect::<Result<Vec<_>, _>>()?;
+
+            assert!(!text.contains(&alice));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn update() -> anyhow::Result<()> {
+        use std::collections::HashMap;
+
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages_hashed(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let mut model = Model::build(dataset, true, true);
+
+        let b = model.tokens().find_token("b").unwrap();
+
+        let added = model.update(&Messages::parse_from_lines(&[
+            String::from("a b d"),
+            String::from("a b d")
+        ]), 1);
+
+        assert_eq!(added, 1);
+        assert!(model.tokens().is_hashed());
+
+        let d = model.tokens().find_token("d").unwrap();
+
+        let counts = model.transitions()
+            .for_unigram(&Unigram::new([b]))
+            .unwrap()
+            .map(|(next, count)| (next.token(), *count))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(counts.get(&d), Some(&2));
+        assert_eq!(counts.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn observe() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let mut model = Model::build(dataset, true, true);
+
+        let b = model.tokens().find_token("b").unwrap();
+        let c = model.tokens().find_token("c").unwrap();
+
+        assert_eq!(model.probability(&[b], c, &Smoothing::default()), None);
+
+        // New transitions are used right after they're observed
+        model.observe(&[b, c]);
+
+        assert!(model.probability(&[b], c, &Smoothing::default()).is_some_and(|probability| probability > 0.0));
+
+        let params = GenerationParams::default();
+
+        let generated = (0..20)
+            .map(|_| model.generate([b], &params).collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert!(generated.iter().any(|tokens| tokens.first() == Some(&c)));
+
+        // Tables are not repacked for every observed message
+        let packed = model.transitions().unigrams.transitions_len();
+
+        let a = model.tokens().find_token("a").unwrap();
+
+        for _ in 0..100 {
+            model.observe(&[c, a, b, c]);
+        }
+
+        assert_eq!(model.transitions().unigrams.transitions_len(), packed);
+        assert!(model.transitions().has_pending());
+
+        assert!(model.probability(&[c, a], b, &Smoothing::default()).is_some());
+
+        model.flush();
+
+        assert!(!model.transitions().has_pending());
+        assert!(model.transitions().unigrams.transitions_len() > packed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_text() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let punctuation = Punctuation::default();
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello, world!")
+        ]).split_punctuation(&punctuation);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true)
+            .with_header(PUNCTUATION_HEADER, punctuation.chars());
+
+        let hello = model.tokens().find_token("hello").unwrap();
+
+        let params = GenerationParams::default();
+
+        let words = model.generate([hello], &params)
+            .words()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(words, [",", "world", "!"]);
+
+        assert_eq!(model.generate_text([hello], &params)?, "hello, world!");
+        assert_eq!(model.join_words(&["hello", ",", "world"]), "hello, world");
+
+        assert!(model.detokenize(&[hello, 1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_build() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::bundle;
+
+        let build = |headers: &[(&str, &str)]| -> anyhow::Result<Model> {
+            let messages = Messages::parse_from_lines(&[
+                String::from("a b c"),
+                String::from("c b a d")
+            ]);
+
+            let tokens = Tokens::parse_from_messages_hashed(&messages);
+
+            let dataset = Dataset::default()
+                .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+                .with_tokens(tokens);
+
+            let mut model = Model::build_with_order(dataset, 3);
+
+            for (key, value) in headers {
+                model = model.with_header(key, value);
+            }
+
+            Ok(model.with_checksum()?)
+        };
+
+        let headers = (0..16)
+            .map(|i| (i.to_string(), i.to_string()))
+            .collect::<Vec<_>>();
+
+        let headers = headers.iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+
+        let model = build(&headers)?;
+
+        // Headers are written sorted whatever their hashing order is
+        assert_eq!(bundle::to_bytes(&model, 0)?, bundle::to_bytes(&build(&headers)?, 0)?);
+        assert_eq!(MappedModel::to_bytes(&model)?, MappedModel::to_bytes(&build(&headers)?)?);
+
+        // Checksum doesn't depend on the headers
+        let checksum = build(&[])?.checksum()?;
+
+        assert_eq!(model.headers().get(CHECKSUM_HEADER), Some(&checksum));
+        assert_eq!(checksum.len(), 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::{bundle, Error};
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let mut model = Model::build(dataset, true, true).with_checksum()?;
+
+        let mut bytes = bundle::to_bytes(&model, 0)?;
+
+        assert!(Model::from_bytes(&bytes).is_ok());
+
+        // Flip a bit of the last token's word
+        let last = bytes.len() - 1;
+
+        bytes[last] ^= 1;
+
+        assert!(matches!(Model::from_bytes(&bytes), Err(Error::ChecksumMismatch { .. })));
+        assert!(matches!(Model::from_bytes(&bytes[..last]), Err(Error::ChecksumMismatch { .. })));
+
+        // Changed models have no valid checksum
+        let a = model.tokens().find_token("a").unwrap();
+
+        model.observe(&[a, a]);
+
+        assert!(!model.headers().contains_key(CHECKSUM_HEADER));
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_format() -> anyhow::Result<()> {
+        use std::collections::HashMap;
+
+        use crate::prelude::*;
+
+        use super::LegacyTransitions;
+
+        let mut transitions = Transitions::default();
+
+        transitions.observe(&[1, 2, 3], 1);
+        transitions.flush();
+
+        let headers = HashMap::from([
+            (String::from("version"), String::from("1.4.4"))
+        ]);
+
+        let legacy = LegacyTransitions {
+            unigrams: transitions.unigrams.clone(),
+            bigrams: None,
+            trigrams: None
+        };
+
+        let bytes = postcard::to_allocvec(&(headers, legacy, Tokens::default()))?;
+
+        let model = Model::from_bytes(&bytes)?;
+
+        assert_eq!(model.transitions().order(), 1);
+        assert_eq!(model.transitions().unigrams_len(), transitions.unigrams_len());
+        assert_eq!(model.headers().get("version").map(String::as_str), Some(env!("CARGO_PKG_VERSION")));
+
+        // Re-encoded model uses the current format
+        let model = Model::from_bytes(&postcard::to_allocvec(&model)?)?;
+
+        assert_eq!(model.transitions().unigrams_len(), transitions.unigrams_len());
+
+        Ok(())
+    }
 }
@@ -1,8 +1,26 @@
-use clap::Args;
+use crate::prelude::{
+    Smoothing,
+    TemperatureSchedule
+};
 
-#[derive(Debug, Clone, Copy, Args)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+/// What to do when the generated text enters a cycle
+pub enum LoopAction {
+    #[default]
+    /// Continue with a random token of the lowest order table
+    /// which doesn't continue the cycle
+    Perturb,
+
+    /// Stop the generation
+    Stop
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+#[serde(default)]
 pub struct GenerationParams {
-    #[arg(long, default_value_t = 0.85)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.85))]
     /// Probability to keep the most probable token
     ///
     /// If `random_seed > temperature * temperature_alpha^[token number]`,
@@ -13,13 +31,23 @@ pub struct GenerationParams {
     /// `random_seed` is a random number from 0.0 to 1.0.
     pub temperature: f64,
 
-    #[arg(long, default_value_t = 1.0)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 1.0))]
     /// Probability multiplier to skip the most probable token
     ///
     /// See `temperature` for the formula.
     pub temperature_alpha: f64,
 
-    #[arg(long, default_value_t = 0.7)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = TemperatureSchedule::default()))]
+    /// How the temperature changes over the generated tokens
+    ///
+    /// `geometric` multiplies it by `temperature_alpha` every token,
+    /// `linear:<end>:<steps>` moves it to `end` over `steps` tokens,
+    /// `steps:<position>=<temperature>,...` sets it from the positions.
+    /// `+punct` suffix restarts the schedule after every sentence end.
+    #[serde(default)]
+    pub temperature_schedule: TemperatureSchedule,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.7))]
     /// Reverse probability to skip repeated token
     ///
     /// If `random_seed > repeat_penalty^[repeats number]`,
@@ -30,39 +58,125 @@ pub struct GenerationParams {
     /// `random_seed` is a random number from 0.0 to 1.0.
     pub repeat_penalty: f64,
 
-    #[arg(long, default_value_t = 10)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 10))]
     /// Size of window which calculates repeats number
     ///
     /// See `repeat_penalty` for the formula.
     pub repeat_penalty_window: usize,
 
-    #[arg(long, default_value_t = 0.95)]
-    /// Percent of tokens to keep from the normal distribution
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.05))]
+    /// Percent of the least probable tokens to remove
+    ///
+    /// Continuations are sorted by probability before trimming.
+    /// The most probable remaining token is always kept.
+    ///
+    /// Higher value will generate more predictable text.
+    pub trim_least: f64,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.0))]
+    /// Percent of the most probable tokens to remove
+    ///
+    /// Continuations are sorted by probability before trimming.
+    /// The least probable remaining token is always kept.
+    ///
+    /// Higher value will generate more "bot-looking" (weird) text.
+    pub trim_most: f64,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    /// Amount of the most probable tokens to keep
     ///
-    /// Other tokens will be removed equally from the beginning
-    /// (least probable) and end (most probable).
+    /// Applied after trimming. 0 means no limit.
+    #[serde(default)]
+    pub top_k: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    /// Forbid generating n-grams of this size which already occur in the text
     ///
-    /// Lower value will generate more "bot-looking" (weird) text.
-    pub k_normal: f64,
+    /// Lower order tables are used if all the continuations repeat
+    /// some n-gram. 0 means no limit.
+    #[serde(default)]
+    pub no_repeat_ngram: usize,
 
-    #[arg(long, default_value_t = 1)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    /// Amount of the last tokens to rerank continuations by
+    ///
+    /// Continuations are sorted by the product of their probabilities
+    /// in the enabled tables of the orders up to this one, so tokens
+    /// fitting the longer context are preferred. 0 disables reranking.
+    #[serde(default)]
+    pub context_window: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    /// Maximal period of the cycles to break
+    ///
+    /// Text ending with `loop_repeats` repetitions of the same
+    /// tokens sequence of this or shorter length, e.g. "no no no",
+    /// is handled by the `loop_action`. 0 disables the detection.
+    #[serde(default)]
+    pub max_loop_period: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 3))]
+    /// Amount of the sequence repetitions considered a cycle
+    #[serde(default = "default_loop_repeats")]
+    pub loop_repeats: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = LoopAction::Perturb))]
+    /// What to do when the text enters a cycle
+    #[serde(default)]
+    pub loop_action: LoopAction,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 1))]
     /// Minimum length of the generated text
+    ///
+    /// The end of the text and tokens which can't be continued
+    /// are not generated until the text reaches this length.
+    /// If only the end of the text can continue it, a new message
+    /// is started with one of the messages openers.
     pub min_len: usize,
 
-    #[arg(long, default_value_t = 150)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 150))]
     /// Maximum length of the generated text
     ///
     /// Breaks new tokens generation if we have generated
     /// `max_len` tokens.
     pub max_len: usize,
 
-    #[arg(long, default_value_t = false)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
     /// Do not use bigrams for text generation
     pub no_bigrams: bool,
 
-    #[arg(long, default_value_t = false)]
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
     /// Do not use trigrams for text generation
-    pub no_trigrams: bool
+    pub no_trigrams: bool,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    /// Do not use quadgrams for text generation
+    #[serde(default)]
+    pub no_quadgrams: bool,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    /// Do not use pentagrams for text generation
+    #[serde(default)]
+    pub no_pentagrams: bool,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    /// Generate reply to the beginning instead of continuing it
+    ///
+    /// Replies are started from the transitions learned from the
+    /// dataset conversations. Length limits apply to the reply only.
+    #[serde(default)]
+    pub dialogue: bool,
+
+    #[cfg_attr(feature = "cli", command(flatten))]
+    #[serde(default)]
+    pub smoothing: Smoothing,
+
+    #[cfg_attr(feature = "cli", arg(long))]
+    /// Seed of the random numbers generator
+    ///
+    /// Same seed with the same model and params
+    /// generates the same text.
+    pub seed: Option<u64>
 }
 
 impl Default for GenerationParams {
@@ -71,13 +185,222 @@ impl Default for GenerationParams {
         Self {
             temperature: 0.85,
             temperature_alpha: 1.0,
+            temperature_schedule: TemperatureSchedule::default(),
             repeat_penalty: 0.7,
             repeat_penalty_window: 10,
-            k_normal: 0.95,
+            trim_least: 0.05,
+            trim_most: 0.0,
+            top_k: 0,
+            no_repeat_ngram: 0,
+            context_window: 0,
+            max_loop_period: 0,
+            loop_repeats: default_loop_repeats(),
+            loop_action: LoopAction::default(),
             min_len: 1,
             max_len: 150,
             no_bigrams: false,
-            no_trigrams: false
+            no_trigrams: false,
+            no_quadgrams: false,
+            no_pentagrams: false,
+            dialogue: false,
+            smoothing: Smoothing::default(),
+            seed: None
         }
     }
 }
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+/// Generation params which can be changed by a single request
+///
+/// Used by long-running modes (server, bots) so one loaded model
+/// can serve consumers with different settings. Unset fields keep
+/// the configured values.
+pub struct GenerationOverrides {
+    pub temperature: Option<f64>,
+    pub max_len: Option<usize>,
+    pub seed: Option<u64>,
+    pub top_k: Option<usize>
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+/// Limits of the values requests can override
+pub struct GenerationBounds {
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.0))]
+    /// Minimal temperature a request can set
+    pub min_temperature: f64,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 1.0))]
+    /// Maximal temperature a request can set
+    pub max_temperature: f64,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 500))]
+    /// Maximal length of the text a request can set
+    pub max_len_limit: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    /// Maximal top-k value a request can set
+    ///
+    /// 0 means no limit.
+    pub max_top_k: usize,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    /// Ignore seeds provided by requests
+    pub deny_seed: bool
+}
+
+impl Default for GenerationBounds {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            min_temperature: 0.0,
+            max_temperature: 1.0,
+            max_len_limit: 500,
+            max_top_k: 0,
+            deny_seed: false
+        }
+    }
+}
+
+impl GenerationBounds {
+    #[inline]
+    /// Bounds which don't limit the overrides
+    ///
+    /// Used when requests come from the host application
+    /// itself rather than from untrusted clients.
+    pub fn unlimited() -> Self {
+        Self {
+            min_temperature: f64::MIN,
+            max_temperature: f64::MAX,
+            max_len_limit: usize::MAX,
+            max_top_k: 0,
+            deny_seed: false
+        }
+    }
+}
+
+#[inline]
+fn default_loop_repeats() -> usize {
+    3
+}
+
+impl GenerationParams {
+    #[inline]
+    /// Check if the transitions table of the order can be used
+    pub fn is_order_enabled(&self, order: usize) -> bool {
+        match order {
+            2 => !self.no_bigrams,
+            3 => !self.no_trigrams,
+            4 => !self.no_quadgrams,
+            5 => !self.no_pentagrams,
+            _ => true
+        }
+    }
+
+    /// Apply request overrides, clamping them by the bounds
+    pub fn with_overrides(&self, overrides: &GenerationOverrides, bounds: &GenerationBounds) -> Self {
+        let mut params = *self;
+
+        if let Some(temperature) = overrides.temperature {
+            params.temperature = temperature.clamp(bounds.min_temperature, bounds.max_temperature.max(bounds.min_temperature));
+        }
+
+        if let Some(max_len) = overrides.max_len {
+            params.max_len = max_len.min(bounds.max_len_limit);
+        }
+
+        if let Some(top_k) = overrides.top_k {
+            params.top_k = match bounds.max_top_k {
+                0 => top_k,
+                max_top_k if top_k == 0 => max_top_k,
+                max_top_k => top_k.min(max_top_k)
+            };
+        }
+
+        if !bounds.deny_seed && overrides.seed.is_some() {
+            params.seed = overrides.seed;
+        }
+
+        params
+    }
+}
+
+mod tests {
+    #[test]
+    fn overrides() {
+        use super::*;
+
+        let params = GenerationParams::default();
+
+        let bounds = GenerationBounds {
+            max_len_limit: 50,
+            max_top_k: 10,
+            ..GenerationBounds::default()
+        };
+
+        let overridden = params.with_overrides(&GenerationOverrides {
+            temperature: Some(2.0),
+            max_len: Some(1000),
+            seed: Some(1),
+            top_k: Some(0)
+        }, &bounds);
+
+        assert_eq!(overridden.temperature, 1.0);
+        assert_eq!(overridden.max_len, 50);
+        assert_eq!(overridden.top_k, 10);
+        assert_eq!(overridden.seed, Some(1));
+        assert_eq!(overridden.repeat_penalty, params.repeat_penalty);
+
+        let overridden = params.with_overrides(&GenerationOverrides::default(), &GenerationBounds {
+            deny_seed: true,
+            ..bounds
+        });
+
+        assert_eq!(overridden.max_len, params.max_len);
+
+        let overridden = params.with_overrides(&GenerationOverrides {
+            seed: Some(1),
+            ..GenerationOverrides::default()
+        }, &GenerationBounds {
+            deny_seed: true,
+            ..bounds
+        });
+
+        assert_eq!(overridden.seed, None);
+    }
+
+    #[test]
+    fn legacy_params() -> anyhow::Result<()> {
+        use super::*;
+
+        let mut params = serde_json::to_value(GenerationParams::default())?;
+
+        // Params saved before top-k, the higher orders and smoothing were added
+        for field in ["top_k", "no_quadgrams", "no_pentagrams", "smoothing"] {
+            params.as_object_mut().unwrap().remove(field);
+        }
+
+        let params = serde_json::from_value::<GenerationParams>(params)?;
+
+        assert_eq!(params.top_k, 0);
+        assert!(!params.no_quadgrams && !params.no_pentagrams);
+        assert_eq!(params.smoothing, Smoothing::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn partial_params() -> anyhow::Result<()> {
+        use super::*;
+
+        let params = serde_json::from_str::<GenerationParams>(r#"{ "temperature": 0.5, "max_len": 20 }"#)?;
+
+        assert_eq!(params.temperature, 0.5);
+        assert_eq!(params.max_len, 20);
+        assert_eq!(params.min_len, GenerationParams::default().min_len);
+        assert_eq!(params.repeat_penalty, GenerationParams::default().repeat_penalty);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use rand::RngCore;
+use rand::distributions::{Distribution, WeightedIndex};
+
+use crate::prelude::GenerationParams;
+
+use super::generator::choose_continuation;
+
+/// Strategy choosing the next token of the generated text
+///
+/// Continuations are (token, probability) pairs sorted from the least
+/// probable to the most probable one. Probabilities are the ones the
+/// generator ranked the continuations by: smoothed if the params use
+/// smoothing and replaced by the context scores with `context_window`,
+/// normalized to sum to 1. Banned, repeating and too early ending
+/// tokens are already removed.
+///
+/// Samplers choose one token at a time, while beam search
+/// compares whole texts and is done by `Model::beam_search`.
+pub trait Sampler {
+    /// Choose the next token from the non-empty continuations of the chain
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, chain: &[u64], params: &GenerationParams, rng: &mut dyn RngCore) -> u64;
+}
+
+impl<S: Sampler + ?Sized> Sampler for Box<S> {
+    #[inline]
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, chain: &[u64], params: &GenerationParams, rng: &mut dyn RngCore) -> u64 {
+        (**self).sample(continuations, chain, params, rng)
+    }
+}
+
+impl<S: Sampler + ?Sized> Sampler for &mut S {
+    #[inline]
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, chain: &[u64], params: &GenerationParams, rng: &mut dyn RngCore) -> u64 {
+        (**self).sample(continuations, chain, params, rng)
+    }
+}
+
+/// Choose the token with probability proportional to its weight
+fn weighted_choice(continuations: &[(u64, f64)], rng: &mut dyn RngCore) -> u64 {
+    match WeightedIndex::new(continuations.iter().map(|(_, weight)| *weight)) {
+        Ok(index) => continuations[index.sample(rng)].0,
+
+        // All the weights are zero, keep the most probable token
+        Err(_) => continuations[continuations.len() - 1].0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Sampler configured by the generation params
+///
+/// Continuations are trimmed and limited by `top_k`, then the most
+/// probable ones are skipped according to the temperature and the
+/// repeat penalty.
+pub struct DefaultSampler;
+
+impl Sampler for DefaultSampler {
+    #[inline]
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, chain: &[u64], params: &GenerationParams, mut rng: &mut dyn RngCore) -> u64 {
+        choose_continuation(continuations, chain, params, &mut rng)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Always choose the most probable continuation
+pub struct GreedySampler;
+
+impl Sampler for GreedySampler {
+    #[inline]
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, _rng: &mut dyn RngCore) -> u64 {
+        continuations[continuations.len() - 1].0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Choose continuations with probabilities proportional
+/// to their probabilities raised to the `1 / softmax_temperature` power
+///
+/// Unlike `GenerationParams::temperature`, lower temperature generates
+/// less random text: 1 follows the probabilities, lower ones prefer the
+/// most probable continuations and higher ones flatten the distribution.
+pub struct TemperatureSampler {
+    pub softmax_temperature: f64
+}
+
+impl Sampler for TemperatureSampler {
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, rng: &mut dyn RngCore) -> u64 {
+        if self.softmax_temperature <= 0.0 {
+            return continuations[continuations.len() - 1].0;
+        }
+
+        // Probabilities are scaled by the largest one so the powers don't underflow
+        let max = continuations.iter()
+            .map(|(_, probability)| *probability)
+            .fold(0.0, f64::max);
+
+        if max <= 0.0 {
+            return continuations[continuations.len() - 1].0;
+        }
+
+        let weighted = continuations.iter()
+            .map(|(token, probability)| (*token, (*probability / max).powf(1.0 / self.softmax_temperature)))
+            .collect::<Vec<_>>();
+
+        weighted_choice(&weighted, rng)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Choose one of `k` most probable continuations
+/// proportionally to their probabilities
+pub struct TopKSampler {
+    pub k: usize
+}
+
+impl Sampler for TopKSampler {
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, rng: &mut dyn RngCore) -> u64 {
+        let k = self.k.clamp(1, continuations.len());
+
+        weighted_choice(&continuations[continuations.len() - k..], rng)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Nucleus sampling: choose among the fewest most probable continuations
+/// which cover `p` share of the probability, proportionally to their probabilities
+pub struct TopPSampler {
+    pub p: f64
+}
+
+impl Sampler for TopPSampler {
+    fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, rng: &mut dyn RngCore) -> u64 {
+        let total = continuations.iter()
+            .map(|(_, probability)| *probability)
+            .sum::<f64>();
+
+        let mut covered = 0.0;
+        let mut nucleus = Vec::new();
+
+        for (token, probability) in continuations.iter().rev() {
+            nucleus.push((*token, *probability));
+
+            covered += *probability;
+
+            if covered >= self.p * total {
+                break;
+            }
+        }
+
+        weighted_choice(&nucleus, rng)
+    }
+}
+
+mod tests {
+    #[test]
+    fn samplers() -> anyhow::Result<()> {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::prelude::*;
+
+        let params = GenerationParams::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let continuations = vec![(1, 0.01), (2, 0.02), (3, 0.97)];
+
+        let mut sample = |sampler: &mut dyn Sampler| (0..200)
+            .map(|_| sampler.sample(continuations.clone(), &[], &params, &mut rng))
+            .collect::<std::collections::HashSet<_>>();
+
+        assert_eq!(sample(&mut GreedySampler), [3].into());
+        assert_eq!(sample(&mut TopKSampler { k: 1 }), [3].into());
+        assert_eq!(sample(&mut TopPSampler { p: 0.9 }), [3].into());
+        assert_eq!(sample(&mut TemperatureSampler { softmax_temperature: 0.0 }), [3].into());
+
+        assert_eq!(sample(&mut TopKSampler { k: 2 }), [2, 3].into());
+        assert_eq!(sample(&mut TopPSampler { p: 0.99 }), [2, 3].into());
+        assert_eq!(sample(&mut TemperatureSampler { softmax_temperature: 100.0 }), [1, 2, 3].into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_sampler() -> anyhow::Result<()> {
+        use rand::RngCore;
+
+        use crate::prelude::*;
+
+        /// Choose the least probable continuation
+        struct Contrarian;
+
+        impl Sampler for Contrarian {
+            fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, _rng: &mut dyn RngCore) -> u64 {
+                continuations[0].0
+            }
+        }
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("a b"),
+            String::from("a c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let a = model.tokens().find_token("a").unwrap();
+        let b = model.tokens().find_token("b").unwrap();
+        let c = model.tokens().find_token("c").unwrap();
+
+        let params = GenerationParams::default();
+
+        let generated = model.generate([a], &params)
+            .with_sampler(GreedySampler)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, [b]);
+
+        let generated = model.generate([a], &params)
+            .with_sampler(Box::new(Contrarian) as Box<dyn Sampler>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(generated, [c]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sampled_probabilities() -> anyhow::Result<()> {
+        use rand::RngCore;
+
+        use crate::prelude::*;
+
+        /// Remember the continuations and choose the most probable one
+        struct Recorder(Vec<(u64, f64)>);
+
+        impl Sampler for Recorder {
+            fn sample(&mut self, continuations: Vec<(u64, f64)>, _chain: &[u64], _params: &GenerationParams, _rng: &mut dyn RngCore) -> u64 {
+                self.0 = continuations.clone();
+
+                continuations[continuations.len() - 1].0
+            }
+        }
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b"),
+            String::from("a b"),
+            String::from("a c")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, false, false);
+
+        let a = model.tokens().find_token("a").unwrap();
+        let b = model.tokens().find_token("b").unwrap();
+        let c = model.tokens().find_token("c").unwrap();
+
+        let mut params = GenerationParams {
+            max_len: 2,
+            ..GenerationParams::default()
+        };
+
+        let mut recorder = Recorder(Vec::new());
+
+        model.generate([a], &params)
+            .with_sampler(&mut recorder)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(recorder.0, [(c, 1.0 / 3.0), (b, 2.0 / 3.0)]);
+
+        // Smoothed probabilities are passed to the sampler
+        params.smoothing.algorithm = SmoothingAlgorithm::Laplace;
+
+        model.generate([a], &params)
+            .with_sampler(&mut recorder)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Laplace counts 1 + 1 and 2 + 1 normalized over c and b
+        assert_eq!(recorder.0.iter().map(|(token, _)| *token).collect::<Vec<_>>(), [c, b]);
+
+        assert!((recorder.0[0].1 - 0.4).abs() < 1e-9);
+        assert!((recorder.0[1].1 - 0.6).abs() < 1e-9);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use std::str::FromStr;
+
+use crate::prelude::{Tokens, GenerationParams};
+use crate::sentences::ends_sentence;
+use crate::Error;
+
+/// Maximal amount of the piecewise schedule steps
+pub const MAX_SCHEDULE_STEPS: usize = 8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+/// How the temperature changes with the position of the generated token
+pub enum ScheduleCurve {
+    #[default]
+    /// `temperature * temperature_alpha^position`
+    Geometric,
+
+    /// Linear change from the params temperature to `end`
+    /// over `steps` tokens, then `end`
+    Linear {
+        end: f64,
+        steps: usize
+    },
+
+    /// (position, temperature) steps sorted by position
+    ///
+    /// Temperature of the last step with not greater position is used,
+    /// the params temperature is used before the first step.
+    Piecewise {
+        steps: [(usize, f64); MAX_SCHEDULE_STEPS],
+        len: usize
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Schedule of the temperature during the generation
+///
+/// Parsed from the spec like `linear:0.5:40`, `steps:0=0.9,20=0.6`
+/// or `geometric`, with the `+punct` suffix to restart the schedule
+/// after the sentence ending punctuation, e.g. `linear:0.5:20+punct`.
+pub struct TemperatureSchedule {
+    pub curve: ScheduleCurve,
+
+    /// Count positions from the last sentence ending word
+    pub reset_at_punctuation: bool
+}
+
+impl TemperatureSchedule {
+    /// Get position of the next token in the schedule
+    ///
+    /// Position is the chain length, or the amount of tokens after
+    /// the last sentence ending word if the schedule is reset.
+    pub fn position(&self, chain: &[u64], tokens: &Tokens) -> usize {
+        if !self.reset_at_punctuation {
+            return chain.len();
+        }
+
+        chain.iter()
+            .rev()
+            .take_while(|token| !tokens.find_word(**token).is_some_and(ends_sentence))
+            .count()
+    }
+
+    /// Get temperature of the token at the position
+    pub fn temperature(&self, params: &GenerationParams, position: usize) -> f64 {
+        match self.curve {
+            ScheduleCurve::Geometric => params.temperature * params.temperature_alpha.powi(position as i32),
+
+            ScheduleCurve::Linear { end, steps } => {
+                if position >= steps {
+                    return end;
+                }
+
+                params.temperature + (end - params.temperature) * position as f64 / steps as f64
+            }
+
+            ScheduleCurve::Piecewise { steps, len } => steps[..len].iter()
+                .rev()
+                .find(|(start, _)| *start <= position)
+                .map(|(_, temperature)| *temperature)
+                .unwrap_or(params.temperature)
+        }
+    }
+}
+
+impl GenerationParams {
+    /// Get params of the next token with the scheduled temperature
+    ///
+    /// `temperature_alpha` of the returned params is 1.0 since
+    /// the geometric decay is already applied by the schedule.
+    pub fn scheduled(&self, chain: &[u64], tokens: &Tokens) -> Self {
+        let schedule = self.temperature_schedule;
+        let position = schedule.position(chain, tokens);
+
+        Self {
+            temperature: schedule.temperature(self, position),
+            temperature_alpha: 1.0,
+            ..*self
+        }
+    }
+}
+
+impl FromStr for TemperatureSchedule {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::InvalidTemperatureSchedule(format!("{spec:?}, {reason}"));
+
+        let (curve, reset_at_punctuation) = match spec.trim().strip_suffix("+punct") {
+            Some(curve) => (curve, true),
+            None => (spec.trim(), false)
+        };
+
+        let (kind, args) = curve.split_once(':').unwrap_or((curve, ""));
+
+        let number = |value: &str| value.trim().parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite() && *value >= 0.0)
+            .ok_or_else(|| invalid("expected non-negative temperature"));
+
+        let position = |value: &str| value.trim().parse::<usize>()
+            .map_err(|_| invalid("expected position"));
+
+        let curve = match kind {
+            "geometric" if args.is_empty() => ScheduleCurve::Geometric,
+
+            "linear" => {
+                let Some((end, steps)) = args.split_once(':') else {
+                    return Err(invalid("expected linear:<end>:<steps>"));
+                };
+
+                ScheduleCurve::Linear {
+                    end: number(end)?,
+                    steps: position(steps)?
+                }
+            }
+
+            "steps" => {
+                let mut steps = [(0, 0.0); MAX_SCHEDULE_STEPS];
+                let mut len = 0;
+
+                for step in args.split(',') {
+                    let Some((start, temperature)) = step.split_once('=') else {
+                        return Err(invalid("expected steps:<position>=<temperature>,..."));
+                    };
+
+                    if len == MAX_SCHEDULE_STEPS {
+                        return Err(invalid("too many steps"));
+                    }
+
+                    steps[len] = (position(start)?, number(temperature)?);
+                    len += 1;
+                }
+
+                steps[..len].sort_by_key(|(start, _)| *start);
+
+                ScheduleCurve::Piecewise {
+                    steps,
+                    len
+                }
+            }
+
+            _ => return Err(invalid("expected geometric, linear or steps schedule"))
+        };
+
+        Ok(Self {
+            curve,
+            reset_at_punctuation
+        })
+    }
+}
+
+impl std::fmt::Display for TemperatureSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.curve {
+            ScheduleCurve::Geometric => write!(f, "geometric")?,
+
+            ScheduleCurve::Linear { end, steps } => write!(f, "linear:{end}:{steps}")?,
+
+            ScheduleCurve::Piecewise { steps, len } => {
+                let steps = steps[..len].iter()
+                    .map(|(start, temperature)| format!("{start}={temperature}"))
+                    .collect::<Vec<_>>();
+
+                write!(f, "steps:{}", steps.join(","))?;
+            }
+        }
+
+        if self.reset_at_punctuation {
+            write!(f, "+punct")?;
+        }
+
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn temperature_schedule() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let params = GenerationParams {
+            temperature: 0.9,
+            temperature_alpha: 0.5,
+            ..GenerationParams::default()
+        };
+
+        let geometric = "geometric".parse::<TemperatureSchedule>()?;
+
+        assert_eq!(geometric, TemperatureSchedule::default());
+        assert_eq!(geometric.temperature(&params, 2), 0.225);
+
+        let linear = "linear:0.5:4".parse::<TemperatureSchedule>()?;
+
+        assert_eq!(linear.temperature(&params, 0), 0.9);
+        assert!((linear.temperature(&params, 2) - 0.7).abs() < 1e-9);
+        assert_eq!(linear.temperature(&params, 10), 0.5);
+
+        let steps = "steps:20=0.5,5=0.7+punct".parse::<TemperatureSchedule>()?;
+
+        assert_eq!(steps.to_string(), "steps:5=0.7,20=0.5+punct");
+        assert_eq!(steps.temperature(&params, 0), 0.9);
+        assert_eq!(steps.temperature(&params, 5), 0.7);
+        assert_eq!(steps.temperature(&params, 30), 0.5);
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello there. how are you")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let chain = ["hello", "there.", "how", "are"].map(|word| tokens.find_token(word).unwrap());
+
+        assert_eq!(steps.position(&chain, &tokens), 2);
+        assert_eq!(linear.position(&chain, &tokens), 4);
+
+        assert!("linear:0.5".parse::<TemperatureSchedule>().is_err());
+        assert!("steps:a=1".parse::<TemperatureSchedule>().is_err());
+        assert!("cosine".parse::<TemperatureSchedule>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,614 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::prelude::{
+    Ngram,
+    TransitionsRow,
+    TransitionsTable,
+    Transitions,
+    MAX_ORDER,
+    START_TOKEN,
+    END_TOKEN
+};
+
+use crate::Error;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SmoothingAlgorithm {
+    #[default]
+    /// Use raw counts of the highest order table which has the transition
+    None,
+
+    /// Add `k` to the counts of all the vocabulary tokens
+    Laplace,
+
+    /// Re-estimate counts by the frequencies of counts and give
+    /// the share of singletons to the unseen tokens
+    GoodTuring,
+
+    /// Interpolated absolute discounting with the continuation
+    /// probability as the lowest order distribution
+    KneserNey,
+
+    /// Weighted mix of all the tables' distributions and the continuation
+    /// probability, with weights learned by deleted interpolation
+    /// unless `interpolation_lambdas` are given
+    Interpolation
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Weights of the interpolated distributions
+///
+/// The first weight is for the continuation probability,
+/// the next ones are for the tables from the lowest order.
+pub struct InterpolationLambdas(pub [f64; MAX_ORDER + 1]);
+
+impl std::str::FromStr for InterpolationLambdas {
+    type Err = Error;
+
+    /// Parse comma-separated weights, missing ones are zero
+    fn from_str(lambdas: &str) -> Result<Self, Self::Err> {
+        let mut weights = [0.0; MAX_ORDER + 1];
+
+        let values = lambdas.split(',')
+            .map(|lambda| lambda.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::InvalidInterpolationLambdas(err.to_string()))?;
+
+        if values.len() > weights.len() {
+            return Err(Error::InvalidInterpolationLambdas(format!("at most {} weights expected", weights.len())));
+        }
+
+        if values.iter().any(|lambda| !lambda.is_finite() || *lambda < 0.0) {
+            return Err(Error::InvalidInterpolationLambdas(String::from("weights must be non-negative numbers")));
+        }
+
+        weights[..values.len()].copy_from_slice(&values);
+
+        Ok(Self(weights))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct Smoothing {
+    #[cfg_attr(feature = "cli", arg(long = "smoothing", value_enum, default_value_t = SmoothingAlgorithm::None))]
+    /// Smoothing algorithm of the transitions probabilities
+    ///
+    /// Smoothed probabilities give non-zero chance to the unseen
+    /// transitions and are used to rank continuations.
+    pub algorithm: SmoothingAlgorithm,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 1.0))]
+    /// Count added to every transition by the Laplace smoothing
+    pub smoothing_k: f64,
+
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0.75))]
+    /// Count subtracted from every transition by the Kneser-Ney smoothing
+    pub smoothing_discount: f64,
+
+    #[cfg_attr(feature = "cli", arg(long))]
+    /// Comma-separated weights of the interpolation smoothing
+    ///
+    /// The first weight is for the continuation probability, the next
+    /// ones are for the tables from the lowest order, e.g. `0.1,0.3,0.6`
+    /// for the model with bigrams. Weights are normalized over the tables
+    /// which have the context. Learned from the model if not given.
+    #[serde(default)]
+    pub interpolation_lambdas: Option<InterpolationLambdas>
+}
+
+impl Default for Smoothing {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            algorithm: SmoothingAlgorithm::None,
+            smoothing_k: 1.0,
+            smoothing_discount: 0.75,
+            interpolation_lambdas: None
+        }
+    }
+}
+
+impl Smoothing {
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        self.algorithm == SmoothingAlgorithm::None
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// Statistics of the transitions tables used by the smoothing algorithms
+pub struct SmoothingStats {
+    /// Amount of tokens which can be predicted, including the end of the text
+    pub vocabulary: u64,
+
+    /// token -> amount of distinct tokens preceding it
+    pub continuations: HashMap<u64, u64>,
+
+    /// Amount of distinct (token -> next_token) transitions
+    pub continuations_total: u64,
+
+    /// (token, continuations) pairs sorted by continuations
+    pub ranking: Arc<[(u64, u64)]>,
+
+    /// count -> amount of transitions with this count, for each table order
+    pub count_of_counts: [HashMap<u64, u64>; MAX_ORDER],
+
+    /// Sum of all the counts, for each table order
+    pub totals: [u64; MAX_ORDER],
+
+    /// Interpolation weights learned by deleted interpolation
+    pub interpolation_lambdas: InterpolationLambdas
+}
+
+/// Get (count_of_counts, total) statistics of the table
+fn table_counts<const SIZE: usize>(table: Option<&TransitionsTable<SIZE>>) -> (HashMap<u64, u64>, u64) {
+    let mut count_of_counts = HashMap::new();
+    let mut total = 0;
+
+    for (_, transitions) in table.into_iter().flat_map(TransitionsTable::iter) {
+        for (_, count) in transitions {
+            *count_of_counts.entry(*count).or_default() += 1;
+
+            total += count;
+        }
+    }
+
+    (count_of_counts, total)
+}
+
+impl SmoothingStats {
+    pub fn from_transitions(transitions: &Transitions, vocabulary: u64) -> Self {
+        let mut continuations = HashMap::<u64, u64>::new();
+        let mut continuations_total = 0;
+
+        for (_, transitions) in transitions.unigrams.iter() {
+            for next in transitions.keys() {
+                *continuations.entry(next.tokens()[0]).or_default() += 1;
+
+                continuations_total += 1;
+            }
+        }
+
+        let mut ranking = continuations.iter()
+            .map(|(token, count)| (*token, *count))
+            .collect::<Vec<_>>();
+
+        ranking.sort_by_key(|(token, count)| (*count, *token));
+
+        let counts = [
+            table_counts(Some(&transitions.unigrams)),
+            table_counts(transitions.bigrams.as_ref()),
+            table_counts(transitions.trigrams.as_ref()),
+            table_counts(transitions.quadgrams.as_ref()),
+            table_counts(transitions.pentagrams.as_ref())
+        ];
+
+        let totals = std::array::from_fn(|i| counts[i].1);
+        let count_of_counts = counts.map(|(count_of_counts, _)| count_of_counts);
+
+        let mut stats = Self {
+            vocabulary: vocabulary.max(1),
+            continuations,
+            continuations_total,
+            ranking: Arc::from(ranking),
+            count_of_counts,
+            totals,
+            interpolation_lambdas: InterpolationLambdas::default()
+        };
+
+        stats.interpolation_lambdas = match transitions.order() {
+            5 => stats.deleted_interpolation(transitions, transitions.pentagrams.as_ref()),
+            4 => stats.deleted_interpolation(transitions, transitions.quadgrams.as_ref()),
+            3 => stats.deleted_interpolation(transitions, transitions.trigrams.as_ref()),
+            2 => stats.deleted_interpolation(transitions, transitions.bigrams.as_ref()),
+            _ => stats.deleted_interpolation(transitions, Some(&transitions.unigrams))
+        };
+
+        stats
+    }
+
+    /// Learn interpolation weights from the highest order table
+    ///
+    /// Every transition votes with its count for the distribution which
+    /// predicts it best when the transition itself is removed from the counts.
+    fn deleted_interpolation<const SIZE: usize>(&self, transitions: &Transitions, table: Option<&TransitionsTable<SIZE>>) -> InterpolationLambdas {
+        let mut lambdas = [0.0; MAX_ORDER + 1];
+
+        let ratio = |count: u64, total: u64| match total {
+            0 | 1 => 0.0,
+            _ => count.saturating_sub(1) as f64 / (total - 1) as f64
+        };
+
+        for (context, row) in table.into_iter().flat_map(TransitionsTable::iter) {
+            let rows = transitions.context_rows(context.tokens(), |_| true);
+
+            for (next, count) in row.iter() {
+                let token = if next.is_end() { END_TOKEN } else { next.token() };
+
+                let mut best = (ratio(self.continuations.get(&token).copied().unwrap_or(0), self.continuations_total), 0);
+
+                for row in &rows {
+                    let estimate = ratio(row.count(token), row.total());
+
+                    // Prefer higher orders on ties
+                    if estimate >= best.0 {
+                        best = (estimate, row.order());
+                    }
+                }
+
+                lambdas[best.1] += *count as f64;
+            }
+        }
+
+        let total = lambdas.iter().sum::<f64>();
+
+        if total > 0.0 {
+            for lambda in &mut lambdas {
+                *lambda /= total;
+            }
+        }
+
+        InterpolationLambdas(lambdas)
+    }
+}
+
+/// Transitions row of the chain context
+pub(crate) trait ContextRow {
+    /// Order of the table
+    fn order(&self) -> usize;
+
+    /// Count of the transition to the token
+    fn count(&self, token: u64) -> u64;
+
+    /// Sum of the counts of all the transitions
+    fn total(&self) -> u64;
+
+    /// Amount of distinct continuations
+    fn len(&self) -> usize;
+
+    /// Counts of all the continuations
+    fn counts(&self) -> Vec<u64>;
+
+    /// (token, count) continuations, the end of the text is `END_TOKEN`
+    fn continuations(&self) -> Vec<(u64, u64)>;
+}
+
+struct TableRow<'a, const SIZE: usize> {
+    context: Ngram<SIZE>,
+    row: TransitionsRow<'a, SIZE>
+}
+
+impl<const SIZE: usize> ContextRow for TableRow<'_, SIZE> {
+    #[inline]
+    fn order(&self) -> usize {
+        SIZE
+    }
+
+    fn count(&self, token: u64) -> u64 {
+        let mut next = [token; SIZE];
+
+        next[..SIZE - 1].copy_from_slice(self.context.tail());
+
+        self.row.get(&Ngram::new(next)).unwrap_or(0)
+    }
+
+    #[inline]
+    fn total(&self) -> u64 {
+        self.row.total()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.row.len()
+    }
+
+    #[inline]
+    fn counts(&self) -> Vec<u64> {
+        self.row.iter().map(|(_, count)| *count).collect()
+    }
+
+    fn continuations(&self) -> Vec<(u64, u64)> {
+        self.row.iter()
+            .map(|(next, count)| {
+                if next.is_end() {
+                    (END_TOKEN, *count)
+                } else {
+                    (next.token(), *count)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Get the last ngram of the chain, padded by the start tokens
+fn chain_context<const SIZE: usize>(chain: &[u64]) -> Ngram<SIZE> {
+    let mut context = [START_TOKEN; SIZE];
+
+    let len = chain.len().min(SIZE);
+
+    context[SIZE - len..].copy_from_slice(&chain[chain.len() - len..]);
+
+    Ngram::new(context)
+}
+
+fn push_row<'a, const SIZE: usize>(rows: &mut Vec<Box<dyn ContextRow + 'a>>, table: Option<&'a TransitionsTable<SIZE>>, chain: &[u64]) {
+    let context = chain_context::<SIZE>(chain);
+
+    if let Some(row) = table.and_then(|table| table.get(&context)) {
+        rows.push(Box::new(TableRow {
+            context,
+            row
+        }));
+    }
+}
+
+impl Transitions {
+    /// Get transitions rows of the chain context
+    /// from the enabled orders tables, lowest order first
+    pub(crate) fn context_rows(&self, chain: &[u64], enabled: impl Fn(usize) -> bool) -> Vec<Box<dyn ContextRow + '_>> {
+        let mut rows = Vec::with_capacity(MAX_ORDER);
+
+        push_row(&mut rows, Some(&self.unigrams), chain);
+
+        if enabled(2) {
+            push_row(&mut rows, self.bigrams.as_ref(), chain);
+        }
+
+        if enabled(3) {
+            push_row(&mut rows, self.trigrams.as_ref(), chain);
+        }
+
+        if enabled(4) {
+            push_row(&mut rows, self.quadgrams.as_ref(), chain);
+        }
+
+        if enabled(5) {
+            push_row(&mut rows, self.pentagrams.as_ref(), chain);
+        }
+
+        rows
+    }
+}
+
+/// Probabilities of the next token of a single chain context
+pub(crate) struct ContextSmoother<'a> {
+    rows: Vec<Box<dyn ContextRow + 'a>>,
+    stats: &'a SmoothingStats,
+    smoothing: Smoothing,
+
+    /// Sum of Good-Turing adjusted counts of the highest order row
+    adjusted_total: f64
+}
+
+impl<'a> ContextSmoother<'a> {
+    pub fn new(rows: Vec<Box<dyn ContextRow + 'a>>, stats: &'a SmoothingStats, smoothing: Smoothing) -> Self {
+        let mut smoother = Self {
+            rows,
+            stats,
+            smoothing,
+            adjusted_total: 0.0
+        };
+
+        if smoothing.algorithm == SmoothingAlgorithm::GoodTuring {
+            if let Some(row) = smoother.rows.last() {
+                smoother.adjusted_total = row.counts()
+                    .into_iter()
+                    .map(|count| smoother.adjusted_count(row.order(), count))
+                    .sum();
+            }
+        }
+
+        smoother
+    }
+
+    /// Good-Turing adjusted count: `(c + 1) * N[c + 1] / N[c]`
+    fn adjusted_count(&self, order: usize, count: u64) -> f64 {
+        let count_of_counts = &self.stats.count_of_counts[order - 1];
+
+        match (count_of_counts.get(&count), count_of_counts.get(&(count + 1))) {
+            (Some(current), Some(next)) => (count + 1) as f64 * *next as f64 / *current as f64,
+            _ => count as f64
+        }
+    }
+
+    /// Kneser-Ney continuation probability of the token
+    fn continuation_probability(&self, token: u64) -> f64 {
+        let uniform = 1.0 / self.stats.vocabulary as f64;
+
+        if self.stats.continuations_total == 0 {
+            return uniform;
+        }
+
+        let discount = self.smoothing.smoothing_discount;
+        let total = self.stats.continuations_total as f64;
+
+        let continuations = self.stats.continuations.get(&token).copied().unwrap_or(0) as f64;
+        let distinct = self.stats.continuations.len() as f64;
+
+        (continuations - discount).max(0.0) / total + discount * distinct / total * uniform
+    }
+
+    /// Get probability of the token
+    ///
+    /// Returns `None` only without smoothing for the unseen tokens.
+    pub fn probability(&self, token: u64) -> Option<f64> {
+        let uniform = 1.0 / self.stats.vocabulary as f64;
+
+        match self.smoothing.algorithm {
+            SmoothingAlgorithm::None => {
+                let row = self.rows.iter()
+                    .rev()
+                    .find(|row| row.count(token) > 0)?;
+
+                Some(row.count(token) as f64 / row.total() as f64)
+            }
+
+            SmoothingAlgorithm::Laplace => {
+                let Some(row) = self.rows.last() else {
+                    return Some(uniform);
+                };
+
+                let k = self.smoothing.smoothing_k;
+
+                Some((row.count(token) as f64 + k) / (row.total() as f64 + k * self.stats.vocabulary as f64))
+            }
+
+            SmoothingAlgorithm::GoodTuring => {
+                let Some(row) = self.rows.last() else {
+                    return Some(uniform);
+                };
+
+                let order = row.order();
+                let total = self.stats.totals[order - 1];
+
+                // Share of the singletons is given to the unseen tokens
+                let unseen = match total {
+                    0 => 0.0,
+                    _ => self.stats.count_of_counts[order - 1].get(&1).copied().unwrap_or(0) as f64 / total as f64
+                };
+
+                let unseen = unseen.min(0.5);
+
+                match row.count(token) {
+                    0 => {
+                        let unseen_tokens = self.stats.vocabulary.saturating_sub(row.len() as u64).max(1);
+
+                        Some(unseen / unseen_tokens as f64)
+                    }
+
+                    count => Some((1.0 - unseen) * self.adjusted_count(order, count) / self.adjusted_total)
+                }
+            }
+
+            SmoothingAlgorithm::KneserNey => {
+                let discount = self.smoothing.smoothing_discount;
+
+                let mut probability = self.continuation_probability(token);
+
+                for row in &self.rows {
+                    let total = row.total() as f64;
+
+                    probability = (row.count(token) as f64 - discount).max(0.0) / total
+                        + discount * row.len() as f64 / total * probability;
+                }
+
+                Some(probability)
+            }
+
+            SmoothingAlgorithm::Interpolation => {
+                let lambdas = self.smoothing.interpolation_lambdas
+                    .unwrap_or(self.stats.interpolation_lambdas)
+                    .0;
+
+                let continuation = match self.stats.continuations_total {
+                    0 => uniform,
+                    total => self.stats.continuations.get(&token).copied().unwrap_or(0) as f64 / total as f64
+                };
+
+                let mut weights = lambdas[0];
+                let mut probability = lambdas[0] * continuation;
+
+                for row in &self.rows {
+                    let lambda = lambdas[row.order()];
+
+                    weights += lambda;
+                    probability += lambda * row.count(token) as f64 / row.total() as f64;
+                }
+
+                if weights > 0.0 {
+                    Some(probability / weights)
+                } else {
+                    Some(uniform)
+                }
+            }
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn smoothing() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("a b c"),
+            String::from("a b d"),
+            String::from("b c a")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let chain = [token("a"), token("b")];
+
+        // Probabilities of all the vocabulary tokens (and the end) sum to 1
+        let mut vocabulary = ["a", "b", "c", "d"].map(token).to_vec();
+
+        vocabulary.push(END_TOKEN);
+
+        for algorithm in [SmoothingAlgorithm::Laplace, SmoothingAlgorithm::GoodTuring, SmoothingAlgorithm::KneserNey] {
+            let smoothing = Smoothing {
+                algorithm,
+                ..Smoothing::default()
+            };
+
+            let probabilities = vocabulary.iter()
+                .map(|token| model.probability(&chain, *token, &smoothing))
+                .collect::<Option<Vec<_>>>()
+                .unwrap();
+
+            assert!(probabilities.iter().all(|probability| *probability > 0.0));
+            assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9, "{algorithm:?}");
+        }
+
+        // Interpolation with the given weights
+        let smoothing = Smoothing {
+            algorithm: SmoothingAlgorithm::Interpolation,
+            interpolation_lambdas: Some("0.1,0.2,0.3,0.4".parse().unwrap()),
+            ..Smoothing::default()
+        };
+
+        let probabilities = vocabulary.iter()
+            .map(|token| model.probability(&chain, *token, &smoothing).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(probabilities.iter().all(|probability| *probability > 0.0));
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // Learned weights
+        let lambdas = model.smoothing_stats().interpolation_lambdas.0;
+
+        assert!((lambdas.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(lambdas[4..].iter().all(|lambda| *lambda == 0.0));
+
+        let smoothing = Smoothing {
+            algorithm: SmoothingAlgorithm::Interpolation,
+            ..Smoothing::default()
+        };
+
+        let probabilities = vocabulary.iter()
+            .map(|token| model.probability(&chain, *token, &smoothing).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        assert!("0.5,-1".parse::<InterpolationLambdas>().is_err());
+        assert!("1,1,1,1,1,1,1".parse::<InterpolationLambdas>().is_err());
+
+        // Unseen transition has no probability without smoothing
+        assert_eq!(model.probability(&chain, token("a"), &Smoothing::default()), None);
+        assert_eq!(model.probability(&chain, token("c"), &Smoothing::default()), Some(0.5));
+
+        Ok(())
+    }
+}
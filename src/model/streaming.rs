@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher, DefaultHasher};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::prelude::{
+    Tokens,
+    TransitionsBuilder,
+    Model,
+    Punctuation
+};
+
+use crate::messages::parse_line;
+use crate::Error;
+
+#[derive(Debug, Clone)]
+/// Builds the model line by line without storing the whole corpus
+///
+/// Lines are parsed the same way as by `Messages`, and repeated
+/// messages are counted every time they appear unless `with_dedup`
+/// is used. Deduplication keeps a hash of every seen message.
+pub struct StreamingBuilder {
+    tokens: Tokens,
+    transitions: TransitionsBuilder,
+    messages: usize,
+    seen: Option<HashSet<u64>>,
+    punctuation: Option<Punctuation>,
+    hashed_tokens: bool,
+    preserve_case: bool
+}
+
+impl StreamingBuilder {
+    #[inline]
+    pub fn new(build_bigrams: bool, build_trigrams: bool) -> Self {
+        Self::from_transitions(TransitionsBuilder::new(build_bigrams, build_trigrams))
+    }
+
+    #[inline]
+    /// Build transitions tables of all the orders up to the given one
+    ///
+    /// Order is clamped to the `[1, MAX_ORDER]` range.
+    pub fn with_order(order: usize) -> Self {
+        Self::from_transitions(TransitionsBuilder::with_order(order))
+    }
+
+    #[inline]
+    fn from_transitions(transitions: TransitionsBuilder) -> Self {
+        Self {
+            tokens: Tokens::default(),
+            transitions,
+            messages: 0,
+            seen: None,
+            punctuation: None,
+            hashed_tokens: false,
+            preserve_case: false
+        }
+    }
+
+    #[inline]
+    /// Count repeated messages only once
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.seen = dedup.then(HashSet::new);
+
+        self
+    }
+
+    #[inline]
+    /// Split punctuation from the words into separate tokens
+    pub fn with_punctuation(mut self, punctuation: Option<Punctuation>) -> Self {
+        self.punctuation = punctuation;
+
+        self
+    }
+
+    #[inline]
+    /// Derive tokens from the words' hashes, see `Tokens::parse_from_messages_hashed`
+    pub fn with_hashed_tokens(mut self, hashed_tokens: bool) -> Self {
+        self.hashed_tokens = hashed_tokens;
+
+        self
+    }
+
+    #[inline]
+    /// Keep original case of the words instead of lowercasing them
+    pub fn with_preserve_case(mut self, preserve_case: bool) -> Self {
+        self.preserve_case = preserve_case;
+
+        self
+    }
+
+    #[inline]
+    /// Amount of observed messages
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    /// Tokenize the line and count its words and transitions
+    pub fn push_line(&mut self, line: &str) {
+        let mut words = parse_line(line, |word| if self.preserve_case {
+            word.to_string()
+        } else {
+            word.to_lowercase()
+        });
+
+        if let Some(punctuation) = &self.punctuation {
+            words = punctuation.split_words(words);
+        }
+
+        if words.is_empty() {
+            return;
+        }
+
+        if let Some(seen) = &mut self.seen {
+            let mut hasher = DefaultHasher::new();
+
+            words.hash(&mut hasher);
+
+            if !seen.insert(hasher.finish()) {
+                return;
+            }
+        }
+
+        self.messages += 1;
+
+        let message = words.iter()
+            .map(|word| {
+                let token = if self.hashed_tokens {
+                    self.tokens.insert_word_hashed(word)
+                } else {
+                    self.tokens.insert_word(word)
+                };
+
+                *self.tokens.counts.entry(token).or_default() += 1;
+
+                token
+            })
+            .collect::<Vec<_>>();
+
+        self.transitions.observe(&message, 1);
+    }
+
+    /// Push all the lines of the reader
+    pub fn push_reader(&mut self, reader: impl BufRead) -> Result<(), Error> {
+        for line in reader.lines() {
+            self.push_line(&line?);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Push all the lines of the file
+    pub fn push_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = std::fs::File::open(path)?;
+
+        self.push_reader(std::io::BufReader::new(file))
+    }
+
+    #[inline]
+    pub fn build(self) -> Model {
+        let model = Model {
+            headers: HashMap::new(),
+            transitions: self.transitions.build(),
+            tokens: self.tokens,
+            smoothing_stats: OnceLock::new()
+        };
+
+        model.with_header("version", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+mod tests {
+    #[test]
+    fn streaming() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let lines = [
+            String::from("Hello, World!"),
+            String::from("\"Hello, there\""),
+            String::from("Hello, World!"),
+            String::new()
+        ];
+
+        let mut builder = StreamingBuilder::with_order(3);
+
+        builder.push_reader(lines.join("\n").as_bytes())?;
+
+        assert_eq!(builder.messages(), 3);
+
+        let mut builder = StreamingBuilder::with_order(3)
+            .with_dedup(true);
+
+        builder.push_reader(lines.join("\n").as_bytes())?;
+
+        assert_eq!(builder.messages(), 2);
+
+        let streamed = builder.build();
+
+        let messages = Messages::parse_from_lines(&lines).dedup();
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build_with_order(dataset, 3);
+
+        assert_eq!(streamed.transitions().order(), 3);
+        assert_eq!(streamed.tokens().len(), model.tokens().len());
+        assert_eq!(streamed.transitions().unigrams_len(), model.transitions().unigrams_len());
+        assert_eq!(streamed.transitions().trigrams_len(), model.transitions().trigrams_len());
+
+        let hello = streamed.tokens().find_token("hello,").unwrap();
+        let world = streamed.tokens().find_token("world!").unwrap();
+
+        assert_eq!(streamed.probability(&[hello], world, &Smoothing::default()), Some(0.5));
+
+        // Words are counted as by the messages parser
+        assert_eq!(streamed.tokens().count(hello), 2);
+        assert_eq!(streamed.tokens().count(world), 1);
+        assert_eq!(streamed.tokens().count(hello), model.tokens().count(model.tokens().find_token("hello,").unwrap()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::prelude::{
+    Model,
+    Smoothing,
+    SmoothingAlgorithm,
+    TokenProbability,
+    ContextSmoother
+};
+
+use crate::tokens::is_reserved;
+
+impl Model {
+    /// Get the most probable next words of the chain
+    ///
+    /// Returns up to `top` tokens known to the model, the most probable
+    /// first, 0 means no limit. The end of the text is never suggested.
+    ///
+    /// Without smoothing continuations of the highest order table are
+    /// suggested first, and the lower order tables only fill the rest.
+    /// With smoothing all of them are ranked by the smoothed probability.
+    pub fn suggest(&self, chain: &[u64], top: usize, smoothing: &Smoothing) -> Vec<TokenProbability> {
+        let rows = self.transitions.context_rows(chain, |_| true);
+
+        // Highest order of the table with every continuation
+        let mut orders = HashMap::new();
+
+        for row in &rows {
+            for (token, _) in row.continuations() {
+                orders.insert(token, row.order());
+            }
+        }
+
+        let smoother = ContextSmoother::new(rows, self.smoothing_stats(), *smoothing);
+
+        let mut suggestions = orders.into_iter()
+            .filter(|(token, _)| !is_reserved(*token) && self.tokens.find_word(*token).is_some())
+            .filter_map(|(token, order)| {
+                Some(TokenProbability {
+                    token,
+                    probability: smoother.probability(token)?,
+                    order
+                })
+            })
+            .collect::<Vec<_>>();
+
+        suggestions.sort_by(|a, b| {
+            let order = match smoothing.algorithm {
+                SmoothingAlgorithm::None => b.order.cmp(&a.order),
+                _ => std::cmp::Ordering::Equal
+            };
+
+            order.then(b.probability.total_cmp(&a.probability))
+                .then(a.token.cmp(&b.token))
+        });
+
+        if top > 0 {
+            suggestions.truncate(top);
+        }
+
+        suggestions
+    }
+}
+
+mod tests {
+    #[test]
+    fn suggest() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("i want to go"),
+            String::from("i want to go"),
+            String::from("i want to sleep"),
+            String::from("you need to eat"),
+            String::from("you need to eat"),
+            String::from("you need to eat"),
+            String::from("you need sleep")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        let words = |suggestions: &[TokenProbability]| suggestions.iter()
+            .map(|suggestion| model.tokens().find_word(suggestion.token).unwrap())
+            .collect::<Vec<_>>();
+
+        // "eat" is the most frequent after "to", but not after "want to"
+        let suggestions = model.suggest(&[token("i"), token("want"), token("to")], 0, &Smoothing::default());
+
+        assert_eq!(words(&suggestions), ["go", "sleep", "eat"]);
+        assert!((suggestions[0].probability - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(suggestions[0].order, 3);
+        assert_eq!(suggestions[2].order, 1);
+
+        let suggestions = model.suggest(&[token("to")], 1, &Smoothing::default());
+
+        assert_eq!(words(&suggestions), ["eat"]);
+
+        // The end of the text is not a word
+        assert!(model.suggest(&[token("go")], 0, &Smoothing::default()).is_empty());
+
+        // Message openers
+        assert_eq!(words(&model.suggest(&[], 0, &Smoothing::default())), ["you", "i"]);
+
+        Ok(())
+    }
+}
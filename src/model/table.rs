@@ -0,0 +1,1125 @@
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+use rand::{Rng, RngCore};
+
+use serde::ser::SerializeMap;
+use serde::de::{Visitor, MapAccess};
+
+use crate::prelude::Ngram;
+
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Minimal amount of the pending transitions packed into the table at once
+const MIN_PENDING: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Continuations of a single ngram
+///
+/// Borrows the row's (next_ngram, count) pairs from the table, sorted
+/// by the next ngram, and keeps the sum of all the counts so probabilities
+/// can be calculated without iterating the row.
+pub struct TransitionsRow<'a, const SIZE: usize> {
+    transitions: &'a [(Ngram<SIZE>, u64)],
+
+    /// Indices of the transitions sorted by their counts
+    by_count: &'a [u32],
+
+    /// Sum of all the counts
+    total: u64
+}
+
+impl<'a, const SIZE: usize> TransitionsRow<'a, SIZE> {
+    #[inline]
+    /// Get count of the (current_ngram -> next_ngram) transition
+    pub fn get(&self, next: &Ngram<SIZE>) -> Option<u64> {
+        self.transitions.binary_search_by_key(next, |(next, _)| *next)
+            .ok()
+            .map(|i| self.transitions[i].1)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, next: &Ngram<SIZE>) -> bool {
+        self.get(next).is_some()
+    }
+
+    #[inline]
+    /// Sum of the counts of all the transitions
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    #[inline]
+    /// Get probability of the (current_ngram -> next_ngram) transition
+    ///
+    /// Probabilities of all the transitions of the row sum to 1.
+    pub fn probability(&self, next: &Ngram<SIZE>) -> Option<f64> {
+        Some(self.get(next)? as f64 / self.total as f64)
+    }
+
+    #[inline]
+    /// Amount of distinct continuations
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&'a Ngram<SIZE>, &'a u64)> {
+        self.transitions.iter().map(|(next, count)| (next, count))
+    }
+
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = &'a Ngram<SIZE>> {
+        self.transitions.iter().map(|(next, _)| next)
+    }
+
+    #[inline]
+    /// Iterate over the transitions from the least to the most frequent one
+    ///
+    /// Order is precomputed when the table is built, transitions
+    /// with equal counts are ordered by the next ngram.
+    pub fn iter_by_count(&self) -> impl Iterator<Item = (&'a Ngram<SIZE>, &'a u64)> {
+        let transitions = self.transitions;
+
+        self.by_count.iter().map(move |index| {
+            let (next, count) = &transitions[*index as usize];
+
+            (next, count)
+        })
+    }
+}
+
+impl<'a, const SIZE: usize> IntoIterator for TransitionsRow<'a, SIZE> {
+    type Item = (&'a Ngram<SIZE>, &'a u64);
+    type IntoIter = std::iter::Map<std::slice::Iter<'a, (Ngram<SIZE>, u64)>, fn(&'a (Ngram<SIZE>, u64)) -> (&'a Ngram<SIZE>, &'a u64)>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.transitions.iter().map(|(next, count)| (next, count))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Row of the ngram with observed new transitions
+///
+/// Holds all the transitions of the ngram and shadows its packed row
+/// until the table is flushed. Rows left without transitions
+/// hide the packed ones as well.
+struct PendingRow<const SIZE: usize> {
+    /// (next_ngram, count) pairs sorted by the next ngram
+    transitions: Vec<(Ngram<SIZE>, u64)>,
+
+    /// Indices of the transitions sorted by (count, next_ngram)
+    by_count: Vec<u32>,
+
+    total: u64
+}
+
+impl<const SIZE: usize> PendingRow<SIZE> {
+    #[inline]
+    fn row(&self) -> TransitionsRow<'_, SIZE> {
+        TransitionsRow {
+            transitions: &self.transitions,
+            by_count: &self.by_count,
+            total: self.total
+        }
+    }
+
+    /// Add count to the transition, inserting it if it's new
+    fn add(&mut self, next: Ngram<SIZE>, count: u64) {
+        match self.transitions.binary_search_by_key(&next, |(next, _)| *next) {
+            Ok(index) => self.transitions[index].1 += count,
+            Err(index) => self.transitions.insert(index, (next, count))
+        }
+
+        self.total += count;
+    }
+
+    /// Update totals and order of the transitions after their counts were changed
+    fn sort(&mut self) {
+        let transitions = &self.transitions;
+
+        self.total = transitions.iter().map(|(_, count)| *count).sum();
+
+        self.by_count = (0..transitions.len() as u32).collect();
+
+        self.by_count.sort_unstable_by_key(|index| {
+            let (next, count) = transitions[*index as usize];
+
+            (count, next)
+        });
+    }
+}
+
+impl<const SIZE: usize> serde::Serialize for TransitionsRow<'_, SIZE> {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer
+    {
+        // Total is not stored and calculated when the row is loaded
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Transitions of the ngrams of the same size
+///
+/// Stored in the compressed sparse row layout: sorted ngrams with offsets
+/// of their rows in a single array of (next_ngram, count) pairs. Rows are
+/// found by binary search and iterated without jumping over the memory.
+///
+/// Rows with observed new transitions are copied aside and packed into
+/// the table in batches, but many messages should still be counted
+/// by the `TransitionsTableBuilder` and packed at once.
+pub struct TransitionsTable<const SIZE: usize> {
+    /// Sorted ngrams which have transitions
+    ngrams: Vec<Ngram<SIZE>>,
+
+    /// transitions\[offsets\[i\]..offsets\[i + 1\]\] are the transitions of ngrams\[i\]
+    offsets: Vec<usize>,
+
+    /// Sums of the counts of every row
+    totals: Vec<u64>,
+
+    /// (next_ngram, count) pairs sorted by the next ngram within every row
+    transitions: Vec<(Ngram<SIZE>, u64)>,
+
+    /// Row-local indices of the transitions sorted by (count, next_ngram) within every row
+    by_count: Vec<u32>,
+
+    /// Rows with observed transitions not packed into the table yet
+    pending: HashMap<Ngram<SIZE>, PendingRow<SIZE>>,
+
+    /// Amount of the transitions of the pending rows
+    pending_len: usize
+}
+
+impl<const SIZE: usize> Default for TransitionsTable<SIZE> {
+    #[inline]
+    fn default() -> Self {
+        Self::with_capacity(0, 0)
+    }
+}
+
+impl<const SIZE: usize> TransitionsTable<SIZE> {
+    #[inline]
+    pub fn with_capacity(rows: usize, transitions: usize) -> Self {
+        let mut offsets = Vec::with_capacity(rows + 1);
+
+        offsets.push(0);
+
+        Self {
+            ngrams: Vec::with_capacity(rows),
+            offsets,
+            totals: Vec::with_capacity(rows),
+            transitions: Vec::with_capacity(transitions),
+            by_count: Vec::with_capacity(transitions),
+            pending: HashMap::new(),
+            pending_len: 0
+        }
+    }
+
+    /// Build table from the (message, weight) pairs in parallel
+    pub fn build<'a>(messages: impl ParallelIterator<Item = (&'a Vec<u64>, u64)>) -> Self {
+        messages
+            .fold(TransitionsTableBuilder::default, |mut builder, (message, weight)| {
+                builder.observe(&Ngram::construct(message), weight);
+
+                builder
+            })
+            .reduce(TransitionsTableBuilder::default, TransitionsTableBuilder::merge)
+            .build()
+    }
+
+    /// Pack (ngram, transitions) rows to the table
+    ///
+    /// Rows and their transitions are sorted in parallel.
+    fn from_rows(mut rows: Vec<(Ngram<SIZE>, Vec<(Ngram<SIZE>, u64)>)>) -> Self {
+        rows.par_iter_mut()
+            .for_each(|(_, transitions)| transitions.sort_unstable_by_key(|(next, _)| *next));
+
+        rows.par_sort_unstable_by_key(|(ngram, _)| *ngram);
+
+        let transitions = rows.iter()
+            .map(|(_, transitions)| transitions.len())
+            .sum();
+
+        let mut table = Self::with_capacity(rows.len(), transitions);
+
+        for (ngram, transitions) in rows {
+            for (next, count) in transitions {
+                table.push(ngram, next, count);
+            }
+        }
+
+        table.sort_rows();
+
+        table
+    }
+
+    /// Append the (current_ngram -> next_ngram) transition to the end of the table
+    ///
+    /// Transitions must be pushed in the sorted order, counts
+    /// of the repeated ones are summed. Rows must be sorted
+    /// by counts after all the transitions are pushed.
+    fn push(&mut self, current: Ngram<SIZE>, next: Ngram<SIZE>, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        if self.ngrams.last() != Some(&current) {
+            self.ngrams.push(current);
+            self.totals.push(0);
+            self.offsets.push(self.transitions.len());
+        }
+
+        let row = self.ngrams.len() - 1;
+
+        match self.transitions.last_mut() {
+            Some((last, last_count)) if self.offsets[row] < self.offsets[row + 1] && *last == next => {
+                *last_count += count;
+            }
+
+            _ => self.transitions.push((next, count))
+        }
+
+        self.totals[row] += count;
+        self.offsets[row + 1] = self.transitions.len();
+    }
+
+    /// Sort transitions of the row by their counts
+    fn sort_row(&mut self, row: usize) {
+        let (start, end) = (self.offsets[row], self.offsets[row + 1]);
+
+        let transitions = &self.transitions[start..end];
+
+        self.by_count[start..end].sort_unstable_by_key(|index| {
+            let (next, count) = transitions[*index as usize];
+
+            (count, next)
+        });
+    }
+
+    /// Sort transitions of all the rows by their counts
+    fn sort_rows(&mut self) {
+        self.by_count.clear();
+
+        for row in 0..self.ngrams.len() {
+            let len = self.offsets[row + 1] - self.offsets[row];
+
+            self.by_count.extend(0..len as u32);
+
+            self.sort_row(row);
+        }
+    }
+
+    #[inline]
+    fn row(&self, index: usize) -> TransitionsRow<'_, SIZE> {
+        let range = self.offsets[index]..self.offsets[index + 1];
+
+        TransitionsRow {
+            transitions: &self.transitions[range.clone()],
+            by_count: &self.by_count[range],
+            total: self.totals[index]
+        }
+    }
+
+    /// Get (row, transition) indices of the (current_ngram -> next_ngram) transition
+    fn find(&self, current: &Ngram<SIZE>, next: &Ngram<SIZE>) -> Option<(usize, usize)> {
+        let row = self.ngrams.binary_search(current).ok()?;
+
+        let start = self.offsets[row];
+
+        let index = self.transitions[start..self.offsets[row + 1]]
+            .binary_search_by_key(next, |(next, _)| *next)
+            .ok()?;
+
+        Some((row, start + index))
+    }
+
+    #[inline]
+    /// Get transitions of the ngram
+    ///
+    /// Pending transitions are returned as well.
+    pub fn get(&self, ngram: &Ngram<SIZE>) -> Option<TransitionsRow<'_, SIZE>> {
+        if let Some(row) = self.pending.get(ngram) {
+            return (!row.transitions.is_empty()).then(|| row.row());
+        }
+
+        self.ngrams.binary_search(ngram)
+            .ok()
+            .map(|index| self.row(index))
+    }
+
+    #[inline]
+    /// Amount of ngrams with packed transitions
+    pub fn len(&self) -> usize {
+        self.ngrams.len()
+    }
+
+    #[inline]
+    /// Amount of packed (current_ngram -> next_ngram) transitions
+    pub fn transitions_len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ngrams.is_empty()
+    }
+
+    #[inline]
+    /// Iterate over ngrams and their packed transitions in the sorted order
+    ///
+    /// Pending transitions are iterated after the table is flushed.
+    pub fn iter(&self) -> impl Iterator<Item = (&'_ Ngram<SIZE>, TransitionsRow<'_, SIZE>)> {
+        self.ngrams.iter()
+            .enumerate()
+            .map(|(index, ngram)| (ngram, self.row(index)))
+    }
+
+    #[inline]
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (&'_ Ngram<SIZE>, TransitionsRow<'_, SIZE>)> {
+        self.ngrams.par_iter()
+            .enumerate()
+            .map(|(index, ngram)| (ngram, self.row(index)))
+    }
+
+    #[inline]
+    /// Iterate over (current_ngram, next_ngram, count) transitions in the sorted order
+    fn entries(&self) -> impl Iterator<Item = (Ngram<SIZE>, Ngram<SIZE>, u64)> + '_ {
+        self.iter().flat_map(|(ngram, row)| {
+            row.iter().map(move |(next, count)| (*ngram, *next, *count))
+        })
+    }
+
+    #[inline]
+    /// Get probability of the (current_ngram -> next_ngram) transition
+    pub fn probability(&self, current: &Ngram<SIZE>, next: &Ngram<SIZE>) -> Option<f64> {
+        self.get(current)?.probability(next)
+    }
+
+    /// Add (ngram -> next_ngram) transitions to the table
+    ///
+    /// Counts of the known transitions are updated in place. Rows with new
+    /// transitions are copied aside and returned by `get` right away, but
+    /// packed into the table only when enough of them is pending, so
+    /// observing a message costs the size of its rows, not of the table.
+    ///
+    /// Returns true if the table was repacked.
+    pub fn observe(&mut self, ngrams: &[Ngram<SIZE>], weight: u64) -> bool {
+        let mut updated = Vec::new();
+        let mut updated_pending = Vec::new();
+
+        for pair in ngrams.windows(2) {
+            if let Some(row) = self.pending.get_mut(&pair[0]) {
+                let len = row.transitions.len();
+
+                row.add(pair[1], weight);
+
+                self.pending_len += row.transitions.len() - len;
+
+                updated_pending.push(pair[0]);
+
+                continue;
+            }
+
+            match self.find(&pair[0], &pair[1]) {
+                Some((row, index)) => {
+                    self.transitions[index].1 += weight;
+                    self.totals[row] += weight;
+
+                    updated.push(row);
+                }
+
+                None => {
+                    // Packed row is copied so lookups return all its transitions
+                    let mut row = PendingRow {
+                        transitions: self.get(&pair[0])
+                            .map(|row| row.iter().map(|(next, count)| (*next, *count)).collect())
+                            .unwrap_or_default(),
+
+                        ..PendingRow::default()
+                    };
+
+                    row.add(pair[1], weight);
+
+                    self.pending_len += row.transitions.len();
+                    self.pending.insert(pair[0], row);
+
+                    updated_pending.push(pair[0]);
+                }
+            }
+        }
+
+        updated.sort_unstable();
+        updated.dedup();
+
+        for row in updated {
+            self.sort_row(row);
+        }
+
+        updated_pending.sort_unstable();
+        updated_pending.dedup();
+
+        for ngram in updated_pending {
+            if let Some(row) = self.pending.get_mut(&ngram) {
+                row.sort();
+            }
+        }
+
+        // Repacking cost is shared by many new transitions
+        if self.pending_len >= MIN_PENDING.max(self.transitions.len() / 16) {
+            self.flush();
+
+            return true;
+        }
+
+        false
+    }
+
+    #[inline]
+    /// Check if the table has observed transitions which are not packed yet
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Pack the pending rows into the table, replacing their packed rows
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+
+        self.pending_len = 0;
+
+        self.retain(|current, _, _| !pending.contains_key(current));
+
+        let rows = pending.into_iter()
+            .map(|(ngram, row)| (ngram, row.transitions))
+            .collect();
+
+        *self = std::mem::take(self).merge(Self::from_rows(rows));
+    }
+
+    /// Merge transitions of two tables
+    ///
+    /// Both tables are walked once in the sorted order.
+    pub fn merge(mut self, mut other: Self) -> Self {
+        self.flush();
+        other.flush();
+
+        if other.is_empty() {
+            return self;
+        }
+
+        if self.is_empty() {
+            return other;
+        }
+
+        let mut table = Self::with_capacity(self.len().max(other.len()), self.transitions_len().max(other.transitions_len()));
+
+        let mut a = self.entries().peekable();
+        let mut b = other.entries().peekable();
+
+        loop {
+            let entry = match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if (x.0, x.1) <= (y.0, y.1) => a.next(),
+                (Some(_), Some(_)) => b.next(),
+                (Some(_), None) => a.next(),
+                (None, Some(_)) => b.next(),
+                (None, None) => break
+            };
+
+            if let Some((current, next, count)) = entry {
+                table.push(current, next, count);
+            }
+        }
+
+        table.sort_rows();
+
+        table
+    }
+
+    /// Update counts of the transitions, removing ones
+    /// for which the function returns false
+    ///
+    /// Rows left without transitions are removed.
+    /// The table is compacted in place.
+    pub fn retain(&mut self, mut f: impl FnMut(&Ngram<SIZE>, &Ngram<SIZE>, &mut u64) -> bool) {
+        // Empty pending rows are kept to hide their packed rows
+        for (current, row) in &mut self.pending {
+            row.transitions.retain_mut(|(next, count)| f(current, next, count));
+            row.sort();
+        }
+
+        self.pending_len = self.pending.values()
+            .map(|row| row.transitions.len())
+            .sum();
+
+        let mut rows = 0;
+        let mut len = 0;
+
+        for row in 0..self.ngrams.len() {
+            let start = len;
+            let mut total = 0;
+
+            for index in self.offsets[row]..self.offsets[row + 1] {
+                let (next, mut count) = self.transitions[index];
+
+                if f(&self.ngrams[row], &next, &mut count) {
+                    self.transitions[len] = (next, count);
+
+                    len += 1;
+                    total += count;
+                }
+            }
+
+            // Offsets of the next rows are not overwritten
+            // since the rows are only moved backwards
+            if len > start {
+                self.ngrams[rows] = self.ngrams[row];
+                self.totals[rows] = total;
+                self.offsets[rows] = start;
+
+                rows += 1;
+            }
+        }
+
+        self.ngrams.truncate(rows);
+        self.totals.truncate(rows);
+        self.offsets.truncate(rows);
+        self.offsets.push(len);
+        self.transitions.truncate(len);
+
+        self.sort_rows();
+    }
+
+    /// Decay transitions rows of the given ngrams
+    ///
+    /// Counts are updated in place, the table is compacted
+    /// only if some transitions were removed.
+    pub fn decay_rows(&mut self, ngrams: &[Ngram<SIZE>], factor: f64, rng: &mut impl RngCore) {
+        // Rows are decayed in the sorted order so the same rng
+        // state always gives the same counts
+        let mut ngrams = ngrams[..ngrams.len().saturating_sub(1)].to_vec();
+
+        ngrams.sort_unstable();
+        ngrams.dedup();
+
+        let mut removed = false;
+        let mut updated = Vec::new();
+
+        for ngram in &ngrams {
+            // Packed rows of the pending ones are outdated
+            if let Some(row) = self.pending.get_mut(ngram) {
+                let len = row.transitions.len();
+
+                row.transitions.retain_mut(|(_, count)| {
+                    *count = decay_count(*count, factor, rng);
+
+                    *count > 0
+                });
+
+                row.sort();
+
+                self.pending_len -= len - row.transitions.len();
+            }
+
+            else if let Ok(row) = self.ngrams.binary_search(ngram) {
+                let mut total = 0;
+
+                for (_, count) in &mut self.transitions[self.offsets[row]..self.offsets[row + 1]] {
+                    *count = decay_count(*count, factor, rng);
+
+                    total += *count;
+                    removed |= *count == 0;
+                }
+
+                self.totals[row] = total;
+
+                updated.push(row);
+            }
+        }
+
+        if removed {
+            self.retain(|_, _, count| *count > 0);
+        }
+
+        else {
+            for row in updated {
+                self.sort_row(row);
+            }
+        }
+    }
+
+    /// Remove transitions seen less than `min_count` times
+    ///
+    /// Rows left without transitions are removed.
+    /// Returns amount of removed transitions.
+    pub fn prune(&mut self, min_count: u64) -> usize {
+        let len = self.transitions.len();
+
+        self.retain(|_, _, count| *count >= min_count);
+
+        len - self.transitions.len()
+    }
+
+    /// Remove transitions from and to ngrams containing any of the tokens
+    ///
+    /// Rows left without transitions are removed.
+    /// Returns amount of removed transitions.
+    pub fn remove_tokens(&mut self, tokens: &HashSet<u64>) -> usize {
+        let len = self.transitions.len();
+
+        self.retain(|current, next, _| {
+            !current.tokens().iter()
+                .chain(next.tokens())
+                .any(|token| tokens.contains(token))
+        });
+
+        len - self.transitions.len()
+    }
+
+    /// Decay all the transitions of the table
+    ///
+    /// Transitions with counts falling below 1 are removed,
+    /// others are rounded stochastically.
+    pub fn decay(&mut self, factor: f64, rng: &mut impl RngCore) {
+        self.flush();
+
+        self.transitions.iter_mut()
+            .for_each(|(_, count)| *count = decay_count(*count, factor, rng));
+
+        self.retain(|_, _, count| *count > 0);
+    }
+}
+
+#[inline]
+/// Multiply the count by the factor, rounding it stochastically
+///
+/// Counts falling below 1 are always zeroed. Others are rounded up
+/// with probability of their fractional part, so the expected count
+/// is kept and counts don't vanish after a few small decays
+/// as they would with rounding down.
+fn decay_count(count: u64, factor: f64, rng: &mut impl RngCore) -> u64 {
+    let count = count as f64 * factor;
+
+    if count < 1.0 {
+        return 0;
+    }
+
+    let floor = count.floor();
+
+    floor as u64 + u64::from(rng.gen::<f64>() < count - floor)
+}
+
+impl<const SIZE: usize> serde::Serialize for TransitionsTable<SIZE> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer
+    {
+        if self.has_pending() {
+            let mut table = self.clone();
+
+            table.flush();
+
+            return table.serialize(serializer);
+        }
+
+        // Human-readable formats like JSON don't support ngrams as map keys
+        if serializer.is_human_readable() {
+            return serializer.collect_seq(self.iter().map(|(ngram, transitions)| ReadableRow {
+                ngram: *ngram,
+                transitions: transitions.iter()
+                    .map(|(next, count)| (*next, *count))
+                    .collect()
+            }));
+        }
+
+        // Stored as a map of maps so the format doesn't depend on the table layout
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+
+        for (ngram, transitions) in self.iter() {
+            map.serialize_entry(ngram, &transitions)?;
+        }
+
+        map.end()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+/// Transitions row stored in the human-readable formats
+struct ReadableRow<const SIZE: usize> {
+    ngram: Ngram<SIZE>,
+    transitions: Vec<(Ngram<SIZE>, u64)>
+}
+
+/// (next_ngram, count) transitions of the serialized row
+struct SerializedRow<const SIZE: usize>(Vec<(Ngram<SIZE>, u64)>);
+
+impl<'de, const SIZE: usize> serde::Deserialize<'de> for SerializedRow<SIZE> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>
+    {
+        struct RowVisitor<const SIZE: usize>;
+
+        impl<'de, const SIZE: usize> Visitor<'de> for RowVisitor<SIZE> {
+            type Value = SerializedRow<SIZE>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("map of ngram transitions")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>
+            {
+                let mut transitions = Vec::with_capacity(map.size_hint().unwrap_or(0));
+
+                while let Some(transition) = map.next_entry::<Ngram<SIZE>, u64>()? {
+                    transitions.push(transition);
+                }
+
+                Ok(SerializedRow(transitions))
+            }
+        }
+
+        deserializer.deserialize_map(RowVisitor::<SIZE>)
+    }
+}
+
+impl<'de, const SIZE: usize> serde::Deserialize<'de> for TransitionsTable<SIZE> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>
+    {
+        if deserializer.is_human_readable() {
+            let rows = Vec::<ReadableRow<SIZE>>::deserialize(deserializer)?
+                .into_iter()
+                .map(|row| (row.ngram, row.transitions))
+                .collect();
+
+            return Ok(Self::from_rows(rows));
+        }
+
+        struct TableVisitor<const SIZE: usize>;
+
+        impl<'de, const SIZE: usize> Visitor<'de> for TableVisitor<SIZE> {
+            type Value = TransitionsTable<SIZE>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("map of ngrams transitions")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>
+            {
+                let mut rows = Vec::with_capacity(map.size_hint().unwrap_or(0));
+
+                while let Some((ngram, SerializedRow(transitions))) = map.next_entry::<Ngram<SIZE>, SerializedRow<SIZE>>()? {
+                    rows.push((ngram, transitions));
+                }
+
+                Ok(TransitionsTable::from_rows(rows))
+            }
+        }
+
+        deserializer.deserialize_map(TableVisitor::<SIZE>)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Counts transitions of the ngrams before packing them into the table
+///
+/// Rows are split into shards by the hash of their ngram,
+/// so the transitions can be counted by multiple threads
+/// without locks, each working with its own shards.
+pub struct TransitionsTableBuilder<const SIZE: usize> {
+    /// count = shards\[shard_index(current_ngram)\]\[current_ngram\]\[next_ngram\]
+    shards: Vec<HashMap<Ngram<SIZE>, HashMap<Ngram<SIZE>, u64>>>
+}
+
+impl<const SIZE: usize> Default for TransitionsTableBuilder<SIZE> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
+
+impl<const SIZE: usize> TransitionsTableBuilder<SIZE> {
+    #[inline]
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: vec![HashMap::new(); shards.max(1)]
+        }
+    }
+
+    #[inline]
+    /// Get index of the shard storing transitions of the ngram
+    ///
+    /// Uses stable hash function so the index doesn't depend
+    /// on the crate or compiler version.
+    pub fn shard_index(&self, ngram: &Ngram<SIZE>) -> usize {
+        let mut hash = 0xcbf29ce484222325_u64;
+
+        for token in ngram.tokens() {
+            hash = (hash ^ token).wrapping_mul(0x100000001b3);
+        }
+
+        hash ^= hash >> 32;
+
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    #[inline]
+    pub fn shards_len(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline]
+    /// Amount of ngrams with packed transitions
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(HashMap::is_empty)
+    }
+
+    /// Add (ngram -> next_ngram) transitions
+    pub fn observe(&mut self, ngrams: &[Ngram<SIZE>], weight: u64) {
+        for pair in ngrams.windows(2) {
+            let shard = self.shard_index(&pair[0]);
+
+            *self.shards[shard].entry(pair[0])
+                .or_default()
+                .entry(pair[1])
+                .or_default() += weight;
+        }
+    }
+
+    /// Merge counted transitions of two builders
+    ///
+    /// Shards are merged in parallel.
+    pub fn merge(mut self, mut other: Self) -> Self {
+        fn merge_rows<const SIZE: usize>(shard: &mut HashMap<Ngram<SIZE>, HashMap<Ngram<SIZE>, u64>>, ngram: Ngram<SIZE>, transitions: HashMap<Ngram<SIZE>, u64>) {
+            let row = shard.entry(ngram).or_default();
+
+            for (next, count) in transitions {
+                *row.entry(next).or_default() += count;
+            }
+        }
+
+        if self.shards.len() != other.shards.len() {
+            for (ngram, transitions) in other.shards.into_iter().flatten() {
+                let shard = self.shard_index(&ngram);
+
+                merge_rows(&mut self.shards[shard], ngram, transitions);
+            }
+
+            return self;
+        }
+
+        self.shards.par_iter_mut()
+            .zip(other.shards.par_drain(..))
+            .for_each(|(shard, mut other)| {
+                if shard.len() < other.len() {
+                    std::mem::swap(shard, &mut other);
+                }
+
+                for (ngram, transitions) in other {
+                    merge_rows(shard, ngram, transitions);
+                }
+            });
+
+        self
+    }
+
+    /// Pack counted transitions to the table
+    pub fn build(self) -> TransitionsTable<SIZE> {
+        let rows = self.shards.into_par_iter()
+            .flat_map_iter(|shard| {
+                shard.into_iter()
+                    .map(|(ngram, transitions)| (ngram, transitions.into_iter().collect()))
+            })
+            .collect();
+
+        TransitionsTable::from_rows(rows)
+    }
+}
+
+mod tests {
+    #[test]
+    fn sharding() -> anyhow::Result<()> {
+        use rayon::prelude::*;
+
+        use crate::prelude::*;
+
+        let messages = (0..100_u64)
+            .map(|i| vec![i % 7, i % 11, i % 13])
+            .collect::<Vec<_>>();
+
+        let parallel = TransitionsTable::<2>::build(messages.par_iter().map(|message| (message, 1)));
+
+        let mut sequential = TransitionsTableBuilder::<2>::new(3);
+
+        for message in &messages {
+            sequential.observe(&Bigram::construct(message), 1);
+        }
+
+        assert_eq!(sequential.shards_len(), 3);
+
+        let sequential = sequential.build();
+
+        assert_eq!(parallel.len(), sequential.len());
+
+        for (ngram, transitions) in sequential.iter() {
+            assert_eq!(parallel.get(ngram), Some(transitions));
+        }
+
+        // Serialized format doesn't depend on the table layout
+        let restored = postcard::from_bytes::<TransitionsTable<2>>(&postcard::to_allocvec(&sequential)?)?;
+
+        assert_eq!(restored, sequential);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_layout() {
+        use crate::prelude::*;
+
+        let mut table = TransitionsTable::<1>::default();
+
+        table.observe(&Unigram::construct(&[3, 1, 2]), 1);
+        table.observe(&Unigram::construct(&[1, 2]), 2);
+        table.observe(&Unigram::construct(&[2, 3]), 1);
+
+        // New transitions are pending until the table is flushed
+        assert!(table.has_pending() && table.is_empty());
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.total()), Some(3));
+
+        table.flush();
+
+        assert!(!table.has_pending());
+
+        // Rows are stored in the sorted order
+        let ngrams = table.iter()
+            .map(|(ngram, _)| *ngram)
+            .collect::<Vec<_>>();
+
+        let mut sorted = ngrams.clone();
+
+        sorted.sort();
+
+        assert_eq!(ngrams, sorted);
+
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.total()), Some(3));
+        assert_eq!(table.get(&Unigram::new([1])).and_then(|row| row.get(&Unigram::new([2]))), Some(3));
+        assert_eq!(table.get(&Unigram::new([2])).map(|row| row.len()), Some(2));
+
+        // Transitions are pre-sorted by counts
+        let counts = table.get(&Unigram::start())
+            .map(|row| row.iter_by_count().map(|(_, count)| *count).collect::<Vec<_>>());
+
+        assert_eq!(counts, Some(vec![1, 1, 2]));
+
+        table.observe(&Unigram::construct(&[3]), 2);
+
+        let most_frequent = table.get(&Unigram::start())
+            .and_then(|row| row.iter_by_count().last())
+            .map(|(next, count)| (*next, *count));
+
+        assert_eq!(most_frequent, Some((Unigram::new([3]), 3)));
+
+        // Merging is the same as observing
+        let mut other = TransitionsTable::<1>::default();
+
+        other.observe(&Unigram::construct(&[2, 3]), 1);
+
+        let merged = table.clone().merge(other);
+
+        assert_eq!(merged.get(&Unigram::new([2])).and_then(|row| row.get(&Unigram::new([3]))), Some(2));
+
+        // Removed rows are compacted
+        assert_eq!(table.prune(2), 3);
+        assert_eq!(table.transitions_len(), 5);
+        assert_eq!(table.get(&Unigram::new([3])).map(|row| row.len()), Some(1));
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.total()), Some(3));
+
+        assert_eq!(table.prune(4), 5);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn pending_transitions() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let mut table = TransitionsTable::<1>::default();
+
+        // Every message has two new transitions
+        for token in 1..super::MIN_PENDING as u64 / 2 {
+            assert!(!table.observe(&Unigram::construct(&[token]), 1));
+
+            // Pending transitions are returned right away
+            assert_eq!(table.get(&Unigram::new([token])).map(|row| row.total()), Some(1));
+        }
+
+        // Table is not repacked for every message
+        assert!(table.has_pending() && table.is_empty());
+        assert_eq!(table.get(&Unigram::start()).map(|row| row.len()), Some(super::MIN_PENDING / 2 - 1));
+
+        // Table is repacked once enough transitions are pending
+        assert!(table.observe(&Unigram::construct(&[u64::MAX - 1]), 1));
+
+        assert!(!table.has_pending());
+        assert_eq!(table.transitions_len(), super::MIN_PENDING);
+
+        // Known transitions are updated in place
+        table.observe(&Unigram::construct(&[1]), 1);
+
+        assert!(!table.has_pending());
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.total()), Some(2));
+
+        // Rows with new transitions are copied aside
+        for _ in 0..10 {
+            table.observe(&Unigram::construct(&[1, 2]), 1);
+        }
+
+        assert_eq!(table.transitions_len(), super::MIN_PENDING);
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.total()), Some(12));
+        assert_eq!(table.get(&Unigram::new([1])).and_then(|row| row.iter_by_count().last()).map(|(next, _)| *next), Some(Unigram::new([2])));
+
+        // Pending transitions are decayed and pruned with the packed ones
+        table.decay_rows(&Unigram::construct(&[1]), 0.5, &mut rand::thread_rng());
+
+        assert_eq!(table.get(&Unigram::new([1])).and_then(|row| row.get(&Unigram::new([2]))), Some(5));
+
+        table.prune(2);
+
+        assert_eq!(table.get(&Unigram::new([1])).map(|row| row.len()), Some(1));
+
+        // Pending transitions are stored
+        let restored = postcard::from_bytes::<TransitionsTable<1>>(&postcard::to_allocvec(&table)?)?;
+
+        assert_eq!(restored.get(&Unigram::new([1])).and_then(|row| row.get(&Unigram::new([2]))), Some(5));
+        assert_eq!(restored.get(&Unigram::new([1])).map(|row| row.len()), Some(1));
+
+        table.flush();
+
+        assert_eq!(table, restored);
+
+        Ok(())
+    }
+}
@@ -1,82 +1,359 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
 use rayon::prelude::*;
+use rand::RngCore;
 
 use crate::prelude::{
     Dataset,
+    START_TOKEN,
+    END_TOKEN,
+    REPLY_TOKEN,
+    TransitionsTable,
+    TransitionsTableBuilder,
+    Ngram,
     Unigram,
     Bigram,
-    Trigram
+    Trigram,
+    Quadgram,
+    Pentagram
 };
 
+/// Maximal supported ngrams order
+pub const MAX_ORDER: usize = 5;
+
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Transitions {
     /// count = forward_transitions\[current_ngram\]\[next_ngram\]
-    pub(crate) unigrams: HashMap<Unigram, HashMap<Unigram, u64>>,
+    pub(crate) unigrams: TransitionsTable<1>,
+
+    /// count = forward_transitions\[current_ngram\]\[next_ngram\]
+    pub(crate) bigrams: Option<TransitionsTable<2>>,
+
+    /// count = forward_transitions\[current_ngram\]\[next_ngram\]
+    pub(crate) trigrams: Option<TransitionsTable<3>>,
 
     /// count = forward_transitions\[current_ngram\]\[next_ngram\]
-    pub(crate) bigrams: Option<HashMap<Bigram, HashMap<Bigram, u64>>>,
+    pub(crate) quadgrams: Option<TransitionsTable<4>>,
 
     /// count = forward_transitions\[current_ngram\]\[next_ngram\]
-    pub(crate) trigrams: Option<HashMap<Trigram, HashMap<Trigram, u64>>>
+    pub(crate) pentagrams: Option<TransitionsTable<5>>
+}
+
+/// Iterate over (message, weight) pairs of the dataset in parallel
+fn dataset_messages(dataset: &Dataset) -> impl ParallelIterator<Item = (&'_ Vec<u64>, u64)> {
+    dataset.messages()
+        .par_iter()
+        .flat_map(|(messages, weight)| {
+            messages.messages()
+                .par_iter()
+                .map(move |message| (message, *weight))
+        })
 }
 
 impl Transitions {
+    /// Build transitions from the dataset in parallel
+    ///
+    /// Tables are built one after another to keep the memory usage low.
+    /// Every thread of the rayon pool counts transitions of its own
+    /// messages and then the counts are merged shard by shard.
     pub fn build_from_dataset(dataset: &Dataset, build_bigrams: bool, build_trigrams: bool) -> Self {
-        let mut unigrams = HashMap::<Unigram, HashMap<Unigram, u64>>::new();
-
-        let mut bigrams = if build_bigrams {
-            Some(HashMap::<Bigram, HashMap<Bigram, u64>>::new())
-        } else {
-            None
+        let transitions = Self {
+            unigrams: TransitionsTable::build(dataset_messages(dataset)),
+            bigrams: build_bigrams.then(|| TransitionsTable::build(dataset_messages(dataset))),
+            trigrams: build_trigrams.then(|| TransitionsTable::build(dataset_messages(dataset))),
+            quadgrams: None,
+            pentagrams: None
         };
 
-        let mut trigrams = if build_trigrams {
-            Some(HashMap::<Trigram, HashMap<Trigram, u64>>::new())
-        } else {
-            None
+        transitions.with_replies(dataset)
+    }
+
+    /// Build transitions tables of all the orders up to the given one
+    ///
+    /// Order is clamped to the `[1, MAX_ORDER]` range.
+    pub fn build_from_dataset_with_order(dataset: &Dataset, order: usize) -> Self {
+        let transitions = Self {
+            unigrams: TransitionsTable::build(dataset_messages(dataset)),
+            bigrams: (order >= 2).then(|| TransitionsTable::build(dataset_messages(dataset))),
+            trigrams: (order >= 3).then(|| TransitionsTable::build(dataset_messages(dataset))),
+            quadgrams: (order >= 4).then(|| TransitionsTable::build(dataset_messages(dataset))),
+            pentagrams: (order >= 5).then(|| TransitionsTable::build(dataset_messages(dataset)))
         };
 
-        for (messages, weight) in dataset.messages() {
-            for message in messages.messages() {
-                let unigram = Unigram::construct(message);
+        transitions.with_replies(dataset)
+    }
 
-                for i in 0..unigram.len() - 1 {
-                    *unigrams.entry(unigram[i])
-                        .or_default()
-                        .entry(unigram[i + 1])
-                        .or_default() += *weight;
-                }
+    /// Add transitions from the contexts into the replies
+    /// of the dataset's conversations
+    fn with_replies(self, dataset: &Dataset) -> Self {
+        if dataset.dialogues().is_empty() {
+            return self;
+        }
 
-                if let Some(bigrams) = &mut bigrams {
-                    let bigram = Bigram::construct(message);
+        let mut builder = self.builder();
 
-                    for i in 0..bigram.len() - 1 {
-                        *bigrams.entry(bigram[i])
-                            .or_default()
-                            .entry(bigram[i + 1])
-                            .or_default() += *weight;
-                    }
-                }
+        for (context, reply, weight) in dataset.dialogue_pairs() {
+            builder.observe_reply(context, reply, weight);
+        }
 
-                if let Some(trigrams) = &mut trigrams {
-                    let trigram = Trigram::construct(message);
+        self.merge(builder.build())
+    }
 
-                    for i in 0..trigram.len() - 1 {
-                        *trigrams.entry(trigram[i])
-                            .or_default()
-                            .entry(trigram[i + 1])
-                            .or_default() += *weight;
-                    }
-                }
+    /// Empty transitions tables of all the orders up to the given one
+    ///
+    /// Order is clamped to the `[1, MAX_ORDER]` range.
+    pub fn with_order(order: usize) -> Self {
+        Self {
+            unigrams: TransitionsTable::default(),
+            bigrams: (order >= 2).then(TransitionsTable::default),
+            trigrams: (order >= 3).then(TransitionsTable::default),
+            quadgrams: (order >= 4).then(TransitionsTable::default),
+            pentagrams: (order >= 5).then(TransitionsTable::default)
+        }
+    }
+
+    #[inline]
+    /// Highest order of the built transitions tables
+    pub fn order(&self) -> usize {
+        if self.pentagrams.is_some() {
+            5
+        } else if self.quadgrams.is_some() {
+            4
+        } else if self.trigrams.is_some() {
+            3
+        } else if self.bigrams.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Empty builder of the same tables as these transitions
+    pub fn builder(&self) -> TransitionsBuilder {
+        TransitionsBuilder {
+            unigrams: TransitionsTableBuilder::default(),
+            bigrams: self.bigrams.is_some().then(TransitionsTableBuilder::default),
+            trigrams: self.trigrams.is_some().then(TransitionsTableBuilder::default),
+            quadgrams: self.quadgrams.is_some().then(TransitionsTableBuilder::default),
+            pentagrams: self.pentagrams.is_some().then(TransitionsTableBuilder::default)
+        }
+    }
+
+    /// Merge transitions of the tables of the same order
+    ///
+    /// Tables which these transitions don't have are not added.
+    pub fn merge(self, other: Self) -> Self {
+        fn merge_tables<const SIZE: usize>(table: Option<TransitionsTable<SIZE>>, other: Option<TransitionsTable<SIZE>>) -> Option<TransitionsTable<SIZE>> {
+            match (table, other) {
+                (Some(table), Some(other)) => Some(table.merge(other)),
+                (table, _) => table
             }
         }
 
         Self {
-            unigrams,
-            bigrams,
-            trigrams
+            unigrams: self.unigrams.merge(other.unigrams),
+            bigrams: merge_tables(self.bigrams, other.bigrams),
+            trigrams: merge_tables(self.trigrams, other.trigrams),
+            quadgrams: merge_tables(self.quadgrams, other.quadgrams),
+            pentagrams: merge_tables(self.pentagrams, other.pentagrams)
+        }
+    }
+
+    /// Add transitions from the tokenized message
+    ///
+    /// New transitions are returned by the tables right away, but packed
+    /// only when enough of them is observed or the tables are flushed,
+    /// see `TransitionsTable::observe`. Many messages should still
+    /// be added using the `TransitionsBuilder`.
+    ///
+    /// Returns true if some table was repacked.
+    pub fn observe(&mut self, message: &[u64], weight: u64) -> bool {
+        let mut repacked = self.unigrams.observe(&Unigram::construct(message), weight);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            repacked |= bigrams.observe(&Bigram::construct(message), weight);
         }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            repacked |= trigrams.observe(&Trigram::construct(message), weight);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            repacked |= quadgrams.observe(&Quadgram::construct(message), weight);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            repacked |= pentagrams.observe(&Pentagram::construct(message), weight);
+        }
+
+        repacked
+    }
+
+    /// Check if the tables have observed transitions which are not packed yet
+    pub fn has_pending(&self) -> bool {
+        self.unigrams.has_pending()
+            || self.bigrams.as_ref().is_some_and(TransitionsTable::has_pending)
+            || self.trigrams.as_ref().is_some_and(TransitionsTable::has_pending)
+            || self.quadgrams.as_ref().is_some_and(TransitionsTable::has_pending)
+            || self.pentagrams.as_ref().is_some_and(TransitionsTable::has_pending)
+    }
+
+    /// Pack the observed transitions pending in all the tables
+    ///
+    /// See `TransitionsTable::observe`.
+    pub fn flush(&mut self) {
+        self.unigrams.flush();
+
+        if let Some(bigrams) = &mut self.bigrams {
+            bigrams.flush();
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            trigrams.flush();
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            quadgrams.flush();
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            pentagrams.flush();
+        }
+    }
+
+    #[inline]
+    /// Add transitions from the tokenized message, decaying
+    /// old transitions of its ngrams by the factor first
+    ///
+    /// Transitions falling below 1 are removed and other counts are rounded
+    /// stochastically, so frequently seen contexts gradually forget old continuations.
+    /// Returns true if some table was repacked.
+    pub fn observe_with_decay(&mut self, message: &[u64], weight: u64, factor: f64) -> bool {
+        self.observe_with_decay_with_rng(message, weight, factor, &mut rand::thread_rng())
+    }
+
+    /// Same as `observe_with_decay`, rounding counts with the given random numbers generator
+    pub fn observe_with_decay_with_rng(&mut self, message: &[u64], weight: u64, factor: f64, rng: &mut impl RngCore) -> bool {
+        self.unigrams.decay_rows(&Unigram::construct(message), factor, rng);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            bigrams.decay_rows(&Bigram::construct(message), factor, rng);
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            trigrams.decay_rows(&Trigram::construct(message), factor, rng);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            quadgrams.decay_rows(&Quadgram::construct(message), factor, rng);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            pentagrams.decay_rows(&Pentagram::construct(message), factor, rng);
+        }
+
+        self.observe(message, weight)
+    }
+
+    #[inline]
+    /// Multiply counts of all the transitions by the factor
+    ///
+    /// Transitions falling below 1 are removed and other counts are rounded stochastically.
+    pub fn decay(&mut self, factor: f64) {
+        self.decay_with_rng(factor, &mut rand::thread_rng());
+    }
+
+    /// Same as `decay`, rounding counts with the given random numbers generator
+    pub fn decay_with_rng(&mut self, factor: f64, rng: &mut impl RngCore) {
+        self.unigrams.decay(factor, rng);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            bigrams.decay(factor, rng);
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            trigrams.decay(factor, rng);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            quadgrams.decay(factor, rng);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            pentagrams.decay(factor, rng);
+        }
+    }
+
+    /// Remove transitions seen less than `min_count` times from all the tables
+    ///
+    /// Returns amount of removed transitions.
+    pub fn prune(&mut self, min_count: u64) -> usize {
+        let mut removed = self.unigrams.prune(min_count);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            removed += bigrams.prune(min_count);
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            removed += trigrams.prune(min_count);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            removed += quadgrams.prune(min_count);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            removed += pentagrams.prune(min_count);
+        }
+
+        removed
+    }
+
+    /// Remove transitions with any of the tokens from all the tables
+    ///
+    /// Words around the removed ones are not linked together,
+    /// so their contexts may end the text. Returns amount of
+    /// removed transitions.
+    pub fn remove_tokens(&mut self, tokens: &HashSet<u64>) -> usize {
+        let mut removed = self.unigrams.remove_tokens(tokens);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            removed += bigrams.remove_tokens(tokens);
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            removed += trigrams.remove_tokens(tokens);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            removed += quadgrams.remove_tokens(tokens);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            removed += pentagrams.remove_tokens(tokens);
+        }
+
+        removed
+    }
+
+    /// Get all the tokens used by the transitions
+    ///
+    /// Every transition of the higher order tables has the unigram
+    /// one, so only the unigrams table is checked.
+    pub fn tokens(&self) -> HashSet<u64> {
+        let mut tokens = HashSet::new();
+
+        for (current, row) in self.unigrams.iter() {
+            tokens.insert(current.token());
+
+            tokens.extend(row.keys().map(|next| next.token()));
+        }
+
+        tokens.remove(&START_TOKEN);
+        tokens.remove(&END_TOKEN);
+        tokens.remove(&REPLY_TOKEN);
+
+        tokens
     }
 
     #[inline]
@@ -94,6 +371,16 @@ impl Transitions {
         Some(self.trigrams.as_ref()?.len())
     }
 
+    #[inline]
+    pub fn quadgrams_len(&self) -> Option<usize> {
+        Some(self.quadgrams.as_ref()?.len())
+    }
+
+    #[inline]
+    pub fn pentagrams_len(&self) -> Option<usize> {
+        Some(self.pentagrams.as_ref()?.len())
+    }
+
     #[inline]
     pub fn for_unigram(&self, unigram: &Unigram) -> Option<impl Iterator<Item = (&'_ Unigram, &'_ u64)>> {
         self.unigrams.get(unigram).map(|transitions| transitions.iter())
@@ -109,36 +396,59 @@ impl Transitions {
         self.trigrams.as_ref()?.get(trigram).map(|transitions| transitions.iter())
     }
 
+    #[inline]
+    pub fn for_quadgram(&self, quadgram: &Quadgram) -> Option<impl Iterator<Item = (&'_ Quadgram, &'_ u64)>> {
+        self.quadgrams.as_ref()?.get(quadgram).map(|transitions| transitions.iter())
+    }
+
+    #[inline]
+    pub fn for_pentagram(&self, pentagram: &Pentagram) -> Option<impl Iterator<Item = (&'_ Pentagram, &'_ u64)>> {
+        self.pentagrams.as_ref()?.get(pentagram).map(|transitions| transitions.iter())
+    }
+
     #[inline]
     /// Get probability of the (current_ngram -> next_ngram)
+    ///
+    /// Equals to the transition count divided by the sum
+    /// of counts of all the current ngram transitions.
     pub fn calc_unigram_probability(&self, current_ngram: &Unigram, next_ngram: &Unigram) -> Option<f64> {
-        self.unigrams.get(current_ngram)
-            .and_then(|transitions| {
-                transitions.get(next_ngram).map(|count| (count, transitions.len()))
-            })
-            .map(|(count, total)| *count as f64 / total as f64)
+        self.unigrams.probability(current_ngram, next_ngram)
     }
 
     #[inline]
     /// Get probability of the (current_ngram -> next_ngram)
+    ///
+    /// Equals to the transition count divided by the sum
+    /// of counts of all the current ngram transitions.
     pub fn calc_bigram_probability(&self, current_ngram: &Bigram, next_ngram: &Bigram) -> Option<f64> {
-        self.bigrams.as_ref()?
-            .get(current_ngram)
-            .and_then(|transitions| {
-                transitions.get(next_ngram).map(|count| (count, transitions.len()))
-            })
-            .map(|(count, total)| *count as f64 / total as f64)
+        self.bigrams.as_ref()?.probability(current_ngram, next_ngram)
     }
 
     #[inline]
     /// Get probability of the (current_ngram -> next_ngram)
+    ///
+    /// Equals to the transition count divided by the sum
+    /// of counts of all the current ngram transitions.
     pub fn calc_trigram_probability(&self, current_ngram: &Trigram, next_ngram: &Trigram) -> Option<f64> {
-        self.trigrams.as_ref()?
-            .get(current_ngram)
-            .and_then(|transitions| {
-                transitions.get(next_ngram).map(|count| (count, transitions.len()))
-            })
-            .map(|(count, total)| *count as f64 / total as f64)
+        self.trigrams.as_ref()?.probability(current_ngram, next_ngram)
+    }
+
+    #[inline]
+    /// Get probability of the (current_ngram -> next_ngram)
+    ///
+    /// Equals to the transition count divided by the sum
+    /// of counts of all the current ngram transitions.
+    pub fn calc_quadgram_probability(&self, current_ngram: &Quadgram, next_ngram: &Quadgram) -> Option<f64> {
+        self.quadgrams.as_ref()?.probability(current_ngram, next_ngram)
+    }
+
+    #[inline]
+    /// Get probability of the (current_ngram -> next_ngram)
+    ///
+    /// Equals to the transition count divided by the sum
+    /// of counts of all the current ngram transitions.
+    pub fn calc_pentagram_probability(&self, current_ngram: &Pentagram, next_ngram: &Pentagram) -> Option<f64> {
+        self.pentagrams.as_ref()?.probability(current_ngram, next_ngram)
     }
 
     #[inline]
@@ -146,7 +456,7 @@ impl Transitions {
     pub fn calc_avg_unigram_paths(&self) -> f64 {
         let paths = self.unigrams.par_iter()
             .filter(|(k, _)| !k.is_start() && !k.is_end())
-            .map(|(_, transitions)| transitions.par_iter())
+            .map(|(_, transitions)| transitions.iter())
             .map(|transitions| transitions.filter(|(k, _)| !k.is_start() && !k.is_end()))
             .map(|transitions| transitions.count() as u64)
             .sum::<u64>();
@@ -160,7 +470,7 @@ impl Transitions {
         let paths = self.bigrams.as_ref()?
             .par_iter()
             .filter(|(k, _)| !k.is_start() && !k.is_end())
-            .map(|(_, transitions)| transitions.par_iter())
+            .map(|(_, transitions)| transitions.iter())
             .map(|transitions| transitions.filter(|(k, _)| !k.is_start() && !k.is_end()))
             .map(|transitions| transitions.count() as u64)
             .sum::<u64>();
@@ -174,7 +484,7 @@ impl Transitions {
         let paths = self.trigrams.as_ref()?
             .par_iter()
             .filter(|(k, _)| !k.is_start() && !k.is_end())
-            .map(|(_, transitions)| transitions.par_iter())
+            .map(|(_, transitions)| transitions.iter())
             .map(|transitions| transitions.filter(|(k, _)| !k.is_start() && !k.is_end()))
             .map(|transitions| transitions.count() as u64)
             .sum::<u64>();
@@ -233,6 +543,143 @@ impl Transitions {
     }
 }
 
+#[derive(Default, Debug, Clone)]
+/// Counts transitions of the messages before packing them into the tables
+pub struct TransitionsBuilder {
+    unigrams: TransitionsTableBuilder<1>,
+    bigrams: Option<TransitionsTableBuilder<2>>,
+    trigrams: Option<TransitionsTableBuilder<3>>,
+    quadgrams: Option<TransitionsTableBuilder<4>>,
+    pentagrams: Option<TransitionsTableBuilder<5>>
+}
+
+impl TransitionsBuilder {
+    #[inline]
+    pub fn new(build_bigrams: bool, build_trigrams: bool) -> Self {
+        Self {
+            bigrams: build_bigrams.then(TransitionsTableBuilder::default),
+            trigrams: build_trigrams.then(TransitionsTableBuilder::default),
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    /// Count transitions of all the orders up to the given one
+    ///
+    /// Order is clamped to the `[1, MAX_ORDER]` range.
+    pub fn with_order(order: usize) -> Self {
+        Self {
+            unigrams: TransitionsTableBuilder::default(),
+            bigrams: (order >= 2).then(TransitionsTableBuilder::default),
+            trigrams: (order >= 3).then(TransitionsTableBuilder::default),
+            quadgrams: (order >= 4).then(TransitionsTableBuilder::default),
+            pentagrams: (order >= 5).then(TransitionsTableBuilder::default)
+        }
+    }
+
+    /// Count transitions of the tokenized message
+    pub fn observe(&mut self, message: &[u64], weight: u64) {
+        self.unigrams.observe(&Unigram::construct(message), weight);
+
+        if let Some(bigrams) = &mut self.bigrams {
+            bigrams.observe(&Bigram::construct(message), weight);
+        }
+
+        if let Some(trigrams) = &mut self.trigrams {
+            trigrams.observe(&Trigram::construct(message), weight);
+        }
+
+        if let Some(quadgrams) = &mut self.quadgrams {
+            quadgrams.observe(&Quadgram::construct(message), weight);
+        }
+
+        if let Some(pentagrams) = &mut self.pentagrams {
+            pentagrams.observe(&Pentagram::construct(message), weight);
+        }
+    }
+
+    /// Count transition from the state to the next token
+    ///
+    /// State is the list of the previous tokens, padded with `START_TOKEN`
+    /// at the beginning of the text. Tables of the orders up to the state's
+    /// length count the transitions of the state's last tokens.
+    pub fn observe_transition(&mut self, state: &[u64], next: u64, count: u64) {
+        self.observe_transition_from_order(state, next, count, 1);
+    }
+
+    /// Count transition from the state to the next token
+    /// in the tables of the orders starting from the given one
+    fn observe_transition_from_order(&mut self, state: &[u64], next: u64, count: u64, min_order: usize) {
+        fn pair<const SIZE: usize>(state: &[u64], next: u64) -> [Ngram<SIZE>; 2] {
+            let mut current = [START_TOKEN; SIZE];
+            let mut following = [START_TOKEN; SIZE];
+
+            current.copy_from_slice(&state[state.len() - SIZE..]);
+            following[..SIZE - 1].copy_from_slice(&current[1..]);
+            following[SIZE - 1] = next;
+
+            [Ngram::new(current), Ngram::new(following)]
+        }
+
+        let observed = |order: usize| state.len() >= order && order >= min_order;
+
+        if observed(1) {
+            self.unigrams.observe(&pair(state, next), count);
+        }
+
+        if let Some(bigrams) = self.bigrams.as_mut().filter(|_| observed(2)) {
+            bigrams.observe(&pair(state, next), count);
+        }
+
+        if let Some(trigrams) = self.trigrams.as_mut().filter(|_| observed(3)) {
+            trigrams.observe(&pair(state, next), count);
+        }
+
+        if let Some(quadgrams) = self.quadgrams.as_mut().filter(|_| observed(4)) {
+            quadgrams.observe(&pair(state, next), count);
+        }
+
+        if let Some(pentagrams) = self.pentagrams.as_mut().filter(|_| observed(5)) {
+            pentagrams.observe(&pair(state, next), count);
+        }
+    }
+
+    /// Count transitions from the end of the context into the reply
+    ///
+    /// Context and reply are separated by the `REPLY_TOKEN`, and only
+    /// the transitions with this token in their states are counted,
+    /// so transitions inside of the reply are left to `observe`.
+    pub fn observe_reply(&mut self, context: &[u64], reply: &[u64], weight: u64) {
+        let context = &context[context.len().saturating_sub(MAX_ORDER - 1)..];
+
+        // Short contexts are padded like the beginnings of the messages
+        let mut sequence = vec![START_TOKEN; MAX_ORDER - 1 - context.len()];
+
+        sequence.extend_from_slice(context);
+        sequence.push(REPLY_TOKEN);
+
+        let offset = sequence.len();
+
+        sequence.extend_from_slice(reply);
+        sequence.push(END_TOKEN);
+
+        for i in offset..sequence.len().min(offset + MAX_ORDER) {
+            self.observe_transition_from_order(&sequence[..i], sequence[i], weight, i - offset + 1);
+        }
+    }
+
+    /// Pack counted transitions to the tables
+    pub fn build(self) -> Transitions {
+        Transitions {
+            unigrams: self.unigrams.build(),
+            bigrams: self.bigrams.map(TransitionsTableBuilder::build),
+            trigrams: self.trigrams.map(TransitionsTableBuilder::build),
+            quadgrams: self.quadgrams.map(TransitionsTableBuilder::build),
+            pentagrams: self.pentagrams.map(TransitionsTableBuilder::build)
+        }
+    }
+}
+
 mod tests {
     #[test]
     fn build_transitions() -> anyhow::Result<()> {
@@ -273,4 +720,192 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn observe() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use rand::SeedableRng;
+
+        let mut transitions = Transitions::default();
+
+        transitions.observe(&[1, 2], 1);
+        transitions.observe(&[1, 2], 1);
+        transitions.observe(&[1, 3], 1);
+        transitions.flush();
+
+        let one = Unigram::new([1]);
+
+        let mut continuations = transitions.for_unigram(&one)
+            .map(|t| t.map(|(ngram, count)| (ngram.token(), *count)).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        continuations.sort();
+
+        assert_eq!(continuations, [(2, 2), (3, 1)]);
+
+        // Probabilities are counts divided by the row total
+        let two = transitions.calc_unigram_probability(&one, &Unigram::new([2])).unwrap();
+        let three = transitions.calc_unigram_probability(&one, &Unigram::new([3])).unwrap();
+
+        assert!((two - 2.0 / 3.0).abs() < 1e-9);
+        assert!((two + three - 1.0).abs() < 1e-9);
+
+        // Mocked random numbers never round counts up
+        let mut rng = rand::rngs::mock::StepRng::new(u64::MAX, 0);
+
+        // 2 * 0.5 = 1, 1 * 0.5 = 0 (removed), then +1 for 2
+        transitions.observe_with_decay_with_rng(&[1, 2], 1, 0.5, &mut rng);
+
+        assert_eq!(transitions.for_unigram(&one).map(|t| t.collect::<Vec<_>>()), Some(vec![(&Unigram::new([2]), &2)]));
+
+        // 2 * 0.4 = 0 (removed), so the row is removed as well
+        transitions.decay_with_rng(0.4, &mut rng);
+
+        assert!(transitions.for_unigram(&one).is_none());
+
+        // Mocked random numbers always round counts up, but counts below 1 are still removed
+        let mut rng = rand::rngs::mock::StepRng::new(0, 0);
+
+        transitions.observe(&[1, 2], 1);
+        transitions.observe(&[1, 3], 2);
+        transitions.decay_with_rng(0.99, &mut rng);
+
+        assert_eq!(transitions.for_unigram(&one).map(|t| t.collect::<Vec<_>>()), Some(vec![(&Unigram::new([3]), &2)]));
+
+        // Counts aren't wiped out by small decays as if they were rounded down every time
+        let mut transitions = Transitions::default();
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+
+        transitions.observe(&[1, 3], 10);
+
+        for _ in 0..10 {
+            transitions.observe_with_decay_with_rng(&[1, 2], 1, 0.99, &mut rng);
+        }
+
+        transitions.flush();
+
+        let count = transitions.for_unigram(&one)
+            .and_then(|mut t| t.find(|(ngram, _)| ngram.token() == 3))
+            .map(|(_, count)| *count);
+
+        assert!(count.is_some_and(|count| count >= 5), "{count:?}");
+
+        // Same seed gives the same counts
+        let decayed = (0..2)
+            .map(|_| {
+                let mut transitions = transitions.clone();
+                let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+
+                for _ in 0..10 {
+                    transitions.observe_with_decay_with_rng(&[1, 2, 3, 1, 2], 1, 0.7, &mut rng);
+                }
+
+                // Empty rows list is ignored
+                transitions.unigrams.decay_rows(&[], 0.7, &mut rng);
+
+                postcard::to_allocvec(&transitions)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(decayed[0], decayed[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_build() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let lines = (0..500)
+            .map(|i| format!("word{} word{} word{} word{}", i % 7, i % 11, i % 13, i % 17))
+            .collect::<Vec<_>>();
+
+        let messages = Messages::parse_from_lines(&lines);
+        let tokens = Tokens::parse_from_messages(&messages);
+        let messages = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let mut builder = TransitionsBuilder::with_order(MAX_ORDER);
+
+        for message in messages.messages() {
+            builder.observe(message, 2);
+        }
+
+        let dataset = Dataset::default()
+            .with_messages(messages, 2)
+            .with_tokens(tokens);
+
+        let parallel = Transitions::build_from_dataset_with_order(&dataset, MAX_ORDER);
+        let sequential = builder.build();
+
+        assert_eq!(parallel.unigrams, sequential.unigrams);
+        assert_eq!(parallel.bigrams, sequential.bigrams);
+        assert_eq!(parallel.trigrams, sequential.trigrams);
+        assert_eq!(parallel.quadgrams, sequential.quadgrams);
+        assert_eq!(parallel.pentagrams, sequential.pentagrams);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dialogue() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("ping a"),
+            String::from("pong b"),
+            String::from("ping a"),
+            String::from("pong b")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let plain = Dataset::default()
+            .with_messages(tokenized.clone(), 1)
+            .with_tokens(tokens.clone());
+
+        let dataset = Dataset::default()
+            .with_dialogue(tokenized, 1)
+            .with_tokens(tokens);
+
+        assert_eq!(dataset.dialogue_pairs().count(), 3);
+
+        let plain = Model::build_with_order(plain, 3);
+        let model = Model::build_with_order(dataset, 3);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        // Transitions inside of the messages are not counted twice
+        let b = Unigram::new([token("b")]);
+
+        assert_eq!(model.transitions().unigrams.get(&b).map(|row| row.total()), plain.transitions().unigrams.get(&b).map(|row| row.total()));
+
+        let reply = Unigram::new([REPLY_TOKEN]);
+
+        assert_eq!(model.transitions().unigrams.get(&reply).map(|row| row.total()), Some(3));
+        assert!(!model.transitions().tokens().contains(&REPLY_TOKEN));
+
+        let params = GenerationParams {
+            dialogue: true,
+            max_len: 2,
+            seed: Some(1),
+            ..GenerationParams::default()
+        };
+
+        // Every "ping a" is answered by "pong b" and the other way around
+        assert_eq!(model.generate_text([token("ping"), token("a")], &params)?, "pong b");
+        assert_eq!(model.generate_text([token("pong"), token("b")], &params)?, "ping a");
+
+        // Continuations never start replies
+        for seed in 0..10 {
+            let params = GenerationParams {
+                seed: Some(seed),
+                ..GenerationParams::default()
+            };
+
+            assert!(!model.generate([token("b")], &params).any(|token| token.ok() == Some(REPLY_TOKEN)));
+        }
+
+        Ok(())
+    }
 }
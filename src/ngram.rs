@@ -3,7 +3,7 @@ use crate::prelude::{
     END_TOKEN
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Ngram<const SIZE: usize>([u64; SIZE]);
 
 impl<const SIZE: usize> Ngram<SIZE> {
@@ -38,6 +38,11 @@ impl<const SIZE: usize> Ngram<SIZE> {
         }
     }
 
+    #[inline]
+    pub fn tokens(&self) -> &[u64] {
+        &self.0
+    }
+
     #[inline]
     pub fn head(&self) -> &[u64] {
         &self.0[..SIZE - 1]
@@ -129,9 +134,11 @@ impl<'de, const SIZE: usize> serde::Deserialize<'de> for Ngram<SIZE> {
     }
 }
 
-pub type Unigram = Ngram<1>;
-pub type Bigram  = Ngram<2>;
-pub type Trigram = Ngram<3>;
+pub type Unigram   = Ngram<1>;
+pub type Bigram    = Ngram<2>;
+pub type Trigram   = Ngram<3>;
+pub type Quadgram = Ngram<4>;
+pub type Pentagram = Ngram<5>;
 
 mod tests {
     #[test]
@@ -0,0 +1,85 @@
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    pub(crate) prefix: String,
+    pub(crate) suffix: String
+}
+
+impl Default for PromptTemplate {
+    #[inline]
+    fn default() -> Self {
+        Self::new(PROMPT_PLACEHOLDER)
+    }
+}
+
+impl PromptTemplate {
+    /// Parse template from the string
+    ///
+    /// `{prompt}` will be replaced by the user's prompt.
+    /// If there's no placeholder in the template - the prompt
+    /// is appended to the end of it.
+    pub fn new(template: impl AsRef<str>) -> Self {
+        let template = template.as_ref();
+
+        match template.split_once(PROMPT_PLACEHOLDER) {
+            Some((prefix, suffix)) => Self {
+                prefix: prefix.to_string(),
+                suffix: suffix.to_string()
+            },
+
+            None => Self {
+                prefix: template.to_string(),
+                suffix: String::new()
+            }
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, prompt: impl AsRef<str>) -> String {
+        format!("{}{}{}", self.prefix, prompt.as_ref(), self.suffix)
+    }
+
+    #[inline]
+    /// Split the template into prefix and suffix texts around the prompt,
+    /// with the prompt included into the prefix
+    pub fn parts(&self, prompt: impl AsRef<str>) -> (String, String) {
+        (format!("{}{}", self.prefix, prompt.as_ref()), self.suffix.clone())
+    }
+}
+
+mod tests {
+    #[test]
+    fn template() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let template = PromptTemplate::new("{prompt}, and then");
+
+        assert_eq!(template.apply("hello"), "hello, and then");
+
+        // Rendered template is tokenized as a whole by the model tokenizer
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello, and then world")
+        ]).tokenize(&Punctuation::default());
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let dataset = Dataset::default()
+            .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+            .with_tokens(tokens);
+
+        let model = Model::build(dataset, true, true)
+            .with_header(PUNCTUATION_HEADER, DEFAULT_PUNCTUATION);
+
+        let token = |word| model.tokens().find_token(word).unwrap();
+
+        assert_eq!(model.tokenize_text(&template.apply("hello"))?, [token("hello"), token(","), token("and"), token("then")]);
+        assert_eq!(model.tokenize_text(&template.apply(""))?, [token(","), token("and"), token("then")]);
+
+        let template = PromptTemplate::new("once upon a time");
+
+        assert_eq!(template.apply(" there"), "once upon a time there");
+
+        Ok(())
+    }
+}
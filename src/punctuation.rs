@@ -0,0 +1,197 @@
+use crate::prelude::{
+    Messages,
+    Model
+};
+
+/// Characters split into separate tokens by default
+pub const DEFAULT_PUNCTUATION: &str = ".,!?;:\"()[]…";
+
+/// Model header storing punctuation characters of its tokens
+pub const PUNCTUATION_HEADER: &str = "punctuation";
+
+/// Punctuation attached to the following word
+const OPENING: &[char] = &['(', '[', '{', '«', '¿', '¡'];
+
+/// Punctuation which alternately opens and closes the quote
+const QUOTES: &[char] = &['"'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Splits punctuation from the words and re-attaches it back
+pub struct Punctuation {
+    chars: String
+}
+
+impl Default for Punctuation {
+    #[inline]
+    fn default() -> Self {
+        Self::new(DEFAULT_PUNCTUATION)
+    }
+}
+
+impl Punctuation {
+    #[inline]
+    pub fn new(chars: impl ToString) -> Self {
+        Self {
+            chars: chars.to_string()
+        }
+    }
+
+    #[inline]
+    /// Characters treated as punctuation
+    pub fn chars(&self) -> &str {
+        &self.chars
+    }
+
+    #[inline]
+    pub fn is_punctuation(&self, char: char) -> bool {
+        self.chars.contains(char)
+    }
+
+    #[inline]
+    /// Check if the word consists of punctuation only
+    pub fn is_punctuation_word(&self, word: &str) -> bool {
+        !word.is_empty() && word.chars().all(|char| self.is_punctuation(char))
+    }
+
+    /// Split punctuation at the start and the end of the word
+    ///
+    /// Runs of the same character (e.g. `...`) are kept as one token,
+    /// punctuation inside the word (e.g. `3.14`) is not split.
+    pub fn split_word(&self, word: &str) -> Vec<String> {
+        let start = word.find(|char| !self.is_punctuation(char))
+            .unwrap_or(word.len());
+
+        let end = word.rfind(|char| !self.is_punctuation(char))
+            .map(|i| i + word[i..].chars().next().map(char::len_utf8).unwrap_or(0))
+            .unwrap_or(start);
+
+        let mut tokens = group_runs(&word[..start]);
+
+        if start < end {
+            tokens.push(word[start..end].to_string());
+        }
+
+        tokens.extend(group_runs(&word[end..]));
+
+        tokens
+    }
+
+    /// Split punctuation of all the words
+    pub fn split_words<T: AsRef<str>>(&self, words: impl IntoIterator<Item = T>) -> Vec<String> {
+        words.into_iter()
+            .flat_map(|word| self.split_word(word.as_ref()))
+            .collect()
+    }
+
+    /// Join words with spaces re-attaching punctuation to its words
+    pub fn join<T: AsRef<str>>(&self, words: &[T]) -> String {
+        let mut text = String::new();
+
+        // Don't put a space before the next word
+        let mut attach = true;
+
+        let mut quote_open = false;
+
+        for word in words {
+            let word = word.as_ref();
+
+            let first = word.chars().next();
+
+            let is_punctuation = self.is_punctuation_word(word);
+
+            let opening = match first {
+                Some(char) if is_punctuation && QUOTES.contains(&char) => {
+                    quote_open = !quote_open;
+
+                    quote_open
+                }
+
+                Some(char) if is_punctuation => OPENING.contains(&char),
+
+                _ => false
+            };
+
+            if !attach && (!is_punctuation || opening) {
+                text.push(' ');
+            }
+
+            text.push_str(word);
+
+            attach = opening;
+        }
+
+        text
+    }
+}
+
+/// Split the string into runs of the same character
+fn group_runs(text: &str) -> Vec<String> {
+    let mut runs = Vec::<String>::new();
+
+    for char in text.chars() {
+        match runs.last_mut() {
+            Some(run) if run.ends_with(char) => run.push(char),
+            _ => runs.push(char.to_string())
+        }
+    }
+
+    runs
+}
+
+impl Messages {
+    /// Split punctuation of the messages' words into separate words
+    pub fn split_punctuation(self, punctuation: &Punctuation) -> Self {
+        let messages = self.messages.into_iter()
+            .map(|message| punctuation.split_words(message))
+            .collect();
+
+        Self {
+            messages
+        }
+    }
+}
+
+impl Model {
+    #[inline]
+    /// Punctuation split from the words of the model,
+    /// stored in the `punctuation` header
+    pub fn punctuation(&self) -> Option<Punctuation> {
+        self.headers.get(PUNCTUATION_HEADER)
+            .map(Punctuation::new)
+    }
+}
+
+mod tests {
+    #[test]
+    fn punctuation() {
+        use crate::prelude::*;
+
+        let punctuation = Punctuation::default();
+
+        assert_eq!(punctuation.split_word("world!"), ["world", "!"]);
+        assert_eq!(punctuation.split_word("(hello..."), ["(", "hello", "..."]);
+        assert_eq!(punctuation.split_word("3.14"), ["3.14"]);
+        assert_eq!(punctuation.split_word("?!"), ["?", "!"]);
+        assert_eq!(punctuation.split_word("«привет»"), ["«привет»"]);
+
+        let words = punctuation.split_words("he said : \"hello, world!\" (twice)".split_whitespace());
+
+        assert_eq!(words, [
+            "he", "said", ":", "\"", "hello", ",", "world", "!", "\"", "(", "twice", ")"
+        ]);
+
+        assert_eq!(punctuation.join(&words), "he said: \"hello, world!\" (twice)");
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("World")
+        ]);
+
+        let messages = messages.split_punctuation(&punctuation);
+
+        assert_eq!(messages.messages(), [
+            vec![String::from("hello"), String::from(","), String::from("world"), String::from("!")],
+            vec![String::from("world")]
+        ]);
+    }
+}
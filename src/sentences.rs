@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use crate::prelude::Messages;
+use crate::input::open_input;
+use crate::Error;
+
+/// Lowercase abbreviations which end with a period
+/// but don't end the sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt",
+    "vs", "etc", "cf", "al", "approx", "fig", "no", "vol",
+    "ch", "p", "pp", "ed", "gen", "col", "lt", "capt", "sgt",
+    "inc", "ltd", "co", "corp", "dept", "est", "jan", "feb",
+    "mar", "apr", "jun", "jul", "aug", "sep", "sept", "oct",
+    "nov", "dec"
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+/// How the text files are split into messages
+pub enum SplitMode {
+    #[default]
+    /// Every line is a message
+    Lines,
+
+    /// Every sentence is a message, lines of
+    /// paragraphs are joined
+    Sentences
+}
+
+/// Check if the word ends the sentence
+pub(crate) fn ends_sentence(word: &str) -> bool {
+    let word = word.trim_end_matches(['"', '\'', ')', ']', '»', '\u{201D}', '\u{2019}']);
+
+    if word.ends_with(['!', '?', '\u{2026}']) || word.ends_with("..") {
+        return true;
+    }
+
+    let Some(word) = word.strip_suffix('.') else {
+        return false;
+    };
+
+    let word = word.trim_start_matches(|char: char| !char.is_alphanumeric())
+        .to_lowercase();
+
+    // Initials, e.g. J. R. R. Tolkien
+    if word.chars().count() == 1 && word.chars().all(char::is_alphabetic) {
+        return false;
+    }
+
+    // Dotted abbreviations, e.g. i.e. or U.S.
+    if word.contains('.') {
+        return false;
+    }
+
+    !ABBREVIATIONS.contains(&word.as_str())
+}
+
+/// Check if the word can start a new sentence
+fn starts_sentence(word: &str) -> bool {
+    word.chars()
+        .find(|char| char.is_alphanumeric())
+        .is_some_and(|char| !char.is_lowercase())
+}
+
+/// Split the text into sentences
+///
+/// Sentences end with `.`, `!`, `?` or `…` followed by
+/// a word which doesn't start with a lowercase letter.
+/// Common abbreviations and initials don't end sentences.
+/// Empty lines end paragraphs and their last sentences.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::new();
+
+    let mut lines = text.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace().peekable();
+
+        while let Some(word) = words.next() {
+            sentence.push(word);
+
+            let next = words.peek()
+                .copied()
+                .or_else(|| {
+                    lines.peek().and_then(|line| line.split_whitespace().next())
+                });
+
+            if ends_sentence(word) && next.is_none_or(starts_sentence) {
+                sentences.push(sentence.join(" "));
+                sentence.clear();
+            }
+        }
+
+        if line.trim().is_empty() && !sentence.is_empty() {
+            sentences.push(sentence.join(" "));
+            sentence.clear();
+        }
+    }
+
+    if !sentence.is_empty() {
+        sentences.push(sentence.join(" "));
+    }
+
+    sentences
+}
+
+impl Messages {
+    #[inline]
+    /// Parse every sentence of the text file as a message
+    pub fn parse_from_sentences(file: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse_from_sentences_with_filter(file, |word| word.to_lowercase())
+    }
+
+    pub fn parse_from_sentences_with_filter(file: impl AsRef<Path>, filter: impl Fn(&str) -> String) -> Result<Self, Error> {
+        let text = std::io::read_to_string(open_input(file)?)?;
+
+        Ok(Self::parse_from_lines_with_filter(&split_sentences(&text), filter))
+    }
+}
+
+mod tests {
+    #[test]
+    fn split_sentences() {
+        use super::split_sentences;
+
+        let text = "Mr. Smith met Dr. J. R. Watson at 3.5 p.m. on Monday. They talked,\n\
+            e.g. about the weather! Did it rain? \"Yes,\" he said. \"It did.\" Then\n\
+            they left...\n\n\
+            Chapter 2\n\n\
+            It was over etc. and nobody cared.";
+
+        assert_eq!(split_sentences(text), [
+            "Mr. Smith met Dr. J. R. Watson at 3.5 p.m. on Monday.",
+            "They talked, e.g. about the weather!",
+            "Did it rain?",
+            "\"Yes,\" he said.",
+            "\"It did.\"",
+            "Then they left...",
+            "Chapter 2",
+            "It was over etc. and nobody cared."
+        ]);
+
+        assert!(split_sentences(" \n\n ").is_empty());
+    }
+}
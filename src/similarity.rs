@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher, DefaultHasher};
+
+use crate::prelude::Messages;
+
+/// Amount of hash functions in the MinHash signatures
+pub const MINHASH_SIZE: usize = 64;
+
+/// Similarity threshold used by default
+pub const DEFAULT_SIMILARITY: f64 = 0.8;
+
+#[inline]
+/// Mix the bits of the value, see splitmix64
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+    value ^ (value >> 31)
+}
+
+/// Hash adjacent pairs of the words
+///
+/// Texts of a single word are represented by the word itself.
+pub fn shingles<T: Hash>(words: &[T]) -> HashSet<u64> {
+    let hash = |words: &[T]| {
+        let mut hasher = DefaultHasher::new();
+
+        words.hash(&mut hasher);
+
+        hasher.finish()
+    };
+
+    if words.len() < 2 {
+        return words.iter()
+            .map(|word| hash(std::slice::from_ref(word)))
+            .collect();
+    }
+
+    words.windows(2)
+        .map(hash)
+        .collect()
+}
+
+/// Share of the shingles the sets have in common
+pub fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let common = a.intersection(b).count();
+
+    common as f64 / (a.len() + b.len() - common) as f64
+}
+
+/// Get MinHash signature of the shingles
+pub fn minhash(shingles: &HashSet<u64>) -> [u64; MINHASH_SIZE] {
+    let mut signature = [u64::MAX; MINHASH_SIZE];
+
+    for shingle in shingles {
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(mix(shingle ^ mix(i as u64 + 1)));
+        }
+    }
+
+    signature
+}
+
+#[derive(Debug, Clone)]
+/// Detects texts similar to the previously inserted ones
+///
+/// Candidates sharing a band of their MinHash signatures
+/// are compared by the Jaccard similarity of word pairs,
+/// so texts are near-duplicates if their similarity is
+/// not less than the threshold.
+pub struct NearDuplicates {
+    threshold: f64,
+    rows: usize,
+    bands: Vec<HashMap<u64, Vec<usize>>>,
+    shingles: Vec<HashSet<u64>>
+}
+
+impl NearDuplicates {
+    pub fn new(threshold: f64) -> Self {
+        // Longest band which finds 99% of the pairs at the threshold
+        let rows = [8, 4, 2, 1].into_iter()
+            .find(|rows| {
+                let bands = (MINHASH_SIZE / rows) as i32;
+
+                1.0 - (1.0 - threshold.powi(*rows as i32)).powi(bands) >= 0.99
+            })
+            .unwrap_or(1);
+
+        Self {
+            threshold,
+            rows,
+            bands: vec![HashMap::new(); MINHASH_SIZE / rows],
+            shingles: Vec::new()
+        }
+    }
+
+    #[inline]
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Find inserted text similar to the given one
+    ///
+    /// Returns index of the similar text in the insertion order.
+    pub fn find<T: Hash>(&self, words: &[T]) -> Option<usize> {
+        let shingles = shingles(words);
+        let signature = minhash(&shingles);
+
+        self.find_similar(&shingles, &signature)
+    }
+
+    /// Insert the text unless it's similar to the inserted ones
+    ///
+    /// Returns `true` if the text was inserted.
+    pub fn insert<T: Hash>(&mut self, words: &[T]) -> bool {
+        let shingles = shingles(words);
+        let signature = minhash(&shingles);
+
+        if self.find_similar(&shingles, &signature).is_some() {
+            return false;
+        }
+
+        let index = self.shingles.len();
+
+        for (band, key) in self.band_keys(&signature).enumerate() {
+            self.bands[band].entry(key).or_default().push(index);
+        }
+
+        self.shingles.push(shingles);
+
+        true
+    }
+
+    fn band_keys<'a>(&self, signature: &'a [u64; MINHASH_SIZE]) -> impl Iterator<Item = u64> + 'a {
+        signature.chunks(self.rows)
+            .map(|band| band.iter().fold(0, |key, value| mix(key ^ value)))
+    }
+
+    fn find_similar(&self, shingles: &HashSet<u64>, signature: &[u64; MINHASH_SIZE]) -> Option<usize> {
+        let mut checked = HashSet::new();
+
+        for (band, key) in self.band_keys(signature).enumerate() {
+            let Some(candidates) = self.bands[band].get(&key) else {
+                continue;
+            };
+
+            for candidate in candidates {
+                if checked.insert(*candidate) && jaccard(shingles, &self.shingles[*candidate]) >= self.threshold {
+                    return Some(*candidate);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Messages {
+    /// Remove messages similar to the previous ones
+    ///
+    /// Messages are near-duplicates if the Jaccard similarity
+    /// of their word pairs is not less than the threshold,
+    /// see `NearDuplicates`.
+    pub fn dedup_similar(mut self, threshold: f64) -> Self {
+        let mut near_duplicates = NearDuplicates::new(threshold);
+
+        self.messages.retain(|message| near_duplicates.insert(message));
+
+        self
+    }
+}
+
+mod tests {
+    #[test]
+    fn near_duplicates() {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("this is the best copypasta ever written by anyone"),
+            String::from("hello there"),
+            String::from("this is the best copypasta ever written by anyone !!"),
+            String::from("this is not a copypasta at all"),
+            String::from("hello there")
+        ]);
+
+        let deduped = messages.clone().dedup_similar(0.8);
+
+        assert_eq!(deduped.messages().len(), 3);
+        assert_eq!(deduped.messages()[2].join(" "), "this is not a copypasta at all");
+
+        // Exact duplicates only
+        assert_eq!(messages.clone().dedup_similar(1.0).messages().len(), 4);
+
+        let mut near_duplicates = NearDuplicates::new(0.5);
+
+        assert!(near_duplicates.insert(&["a", "b", "c", "d"]));
+        assert!(!near_duplicates.insert(&["a", "b", "c", "e"]));
+        assert!(near_duplicates.insert(&["e", "d", "c", "b"]));
+
+        assert_eq!(near_duplicates.find(&["x", "a", "b", "c"]), Some(0));
+        assert_eq!(near_duplicates.find(&["x", "y"]), None);
+    }
+}
@@ -1,31 +1,45 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::prelude::{
     Messages,
-    Tokens
+    Tokens,
+    TokensRemap
 };
 
+use crate::Error;
+
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// List of the tokenized messages in the order of the source messages
 pub struct TokenizedMessages {
-    pub(crate) messages: HashSet<Vec<u64>>
+    pub(crate) messages: Vec<Vec<u64>>
 }
 
 impl TokenizedMessages {
-    pub fn tokenize_message(messages: &Messages, tokens: &Tokens) -> anyhow::Result<Self> {
-        let mut tokenized = HashSet::new();
+    #[inline]
+    pub fn tokenize_message(messages: &Messages, tokens: &Tokens) -> Result<Self, Error> {
+        Self::tokenize_message_with_unk(messages, tokens, None)
+    }
+
+    /// Tokenize messages replacing unknown words by the `unk` word
+    ///
+    /// Fails on unknown words if `unk` is not given or has no token.
+    pub fn tokenize_message_with_unk(messages: &Messages, tokens: &Tokens, unk: Option<&str>) -> Result<Self, Error> {
+        let unk = unk.and_then(|unk| tokens.find_token(unk));
+
+        let mut tokenized = Vec::with_capacity(messages.messages().len());
 
         for message in messages.messages() {
             let mut message_tokens = Vec::with_capacity(message.len());
 
             for word in message {
-                let Some(token) = tokens.find_token(word) else {
-                    anyhow::bail!("Could not find token for word: {word}");
+                let Some(token) = tokens.find_token(word).or(unk) else {
+                    return Err(Error::UnknownWord(word.to_owned()));
                 };
 
                 message_tokens.push(token);
             }
 
-            tokenized.insert(message_tokens);
+            tokenized.push(message_tokens);
         }
 
         Ok(Self {
@@ -33,10 +47,64 @@ impl TokenizedMessages {
         })
     }
 
+    /// Convert tokens of the messages back to their words
+    ///
+    /// Fails on tokens unknown to the bundle.
+    pub fn detokenize(&self, tokens: &Tokens) -> Result<Messages, Error> {
+        let mut messages = Vec::with_capacity(self.messages.len());
+
+        for message in &self.messages {
+            let words = message.iter()
+                .map(|token| tokens.find_word(*token).map(String::from).ok_or(Error::TokenNotFound(*token)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            messages.push(words);
+        }
+
+        Ok(Messages {
+            messages
+        })
+    }
+
     #[inline]
-    pub fn messages(&self) -> &HashSet<Vec<u64>> {
+    pub fn messages(&self) -> &[Vec<u64>] {
         &self.messages
     }
+
+    /// Get amount of occurrences of every token
+    pub fn token_counts(&self) -> HashMap<u64, u64> {
+        let mut counts = HashMap::new();
+
+        for token in self.messages.iter().flatten() {
+            *counts.entry(*token).or_default() += 1;
+        }
+
+        counts
+    }
+
+    /// Translate tokens of all the messages
+    pub fn remap(mut self, remap: &TokensRemap) -> Self {
+        if remap.is_empty() {
+            return self;
+        }
+
+        for message in &mut self.messages {
+            for token in message {
+                *token = remap.get(*token);
+            }
+        }
+
+        self
+    }
+
+    /// Remove repeated messages keeping their first occurrences
+    pub fn dedup(mut self) -> Self {
+        let mut seen = HashSet::with_capacity(self.messages.len());
+
+        self.messages.retain(|message| seen.insert(message.clone()));
+
+        self
+    }
 }
 
 mod tests {
@@ -61,6 +129,26 @@ mod tests {
         assert!(tokenized.messages.contains(&vec![hello, world]));
         assert!(tokenized.messages.contains(&vec![example, text]));
 
+        assert_eq!(tokenized.detokenize(&tokens)?.messages(), messages.messages());
+
         Ok(())
     }
+
+    #[test]
+    fn unknown_word() {
+        use super::{Messages, Tokens, TokenizedMessages, Error};
+
+        let tokens = Tokens::parse_from_messages(&Messages::parse_from_lines(&[
+            String::from("Hello, World!")
+        ]));
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Goodbye, World!")
+        ]);
+
+        assert!(matches!(
+            TokenizedMessages::tokenize_message(&messages, &tokens),
+            Err(Error::UnknownWord(word)) if word == "goodbye,"
+        ));
+    }
 }
@@ -0,0 +1,340 @@
+use crate::prelude::{
+    Messages,
+    Model,
+    Tokens,
+    Punctuation,
+    Bpe,
+    PUNCTUATION_HEADER
+};
+
+use crate::bpe::join_subwords;
+use crate::Error;
+
+/// Model header storing the tokenizer its words were made by
+pub const TOKENIZER_HEADER: &str = "tokenizer";
+
+/// Splits the text into words of the model and joins them back
+pub trait Tokenizer {
+    /// Split the text into words
+    fn tokenize(&self, text: &str) -> Vec<String>;
+
+    /// Join the words into the text
+    fn detokenize(&self, words: &[&str]) -> String;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+/// Kind of the tokenizer stored in the model headers
+pub enum TokenizerKind {
+    #[default]
+    /// Words separated by whitespace
+    Whitespace,
+
+    /// Words with punctuation split into separate words
+    Punctuation,
+
+    /// Byte-pair encoding subwords
+    Bpe,
+
+    /// Every character is a word, including spaces
+    Chars
+}
+
+impl TokenizerKind {
+    #[inline]
+    /// Name of the tokenizer stored in the model headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Whitespace  => "whitespace",
+            Self::Punctuation => "punctuation",
+            Self::Bpe         => "bpe",
+            Self::Chars       => "chars"
+        }
+    }
+
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "whitespace"  => Some(Self::Whitespace),
+            "punctuation" => Some(Self::Punctuation),
+            "bpe"         => Some(Self::Bpe),
+            "chars"       => Some(Self::Chars),
+
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Split the text by whitespace
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    #[inline]
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace()
+            .map(String::from)
+            .collect()
+    }
+
+    #[inline]
+    fn detokenize(&self, words: &[&str]) -> String {
+        words.join(" ")
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Split the text into characters
+///
+/// Runs of whitespace become a single space word,
+/// and words are joined without separators.
+pub struct CharTokenizer;
+
+impl Tokenizer for CharTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut words = Vec::with_capacity(text.len());
+
+        for word in text.split_whitespace() {
+            if !words.is_empty() {
+                words.push(String::from(" "));
+            }
+
+            words.extend(word.chars().map(String::from));
+        }
+
+        words
+    }
+
+    #[inline]
+    fn detokenize(&self, words: &[&str]) -> String {
+        words.concat()
+    }
+}
+
+impl Tokenizer for Punctuation {
+    #[inline]
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self.split_words(text.split_whitespace())
+    }
+
+    #[inline]
+    fn detokenize(&self, words: &[&str]) -> String {
+        self.join(words)
+    }
+}
+
+impl Tokenizer for Bpe {
+    #[inline]
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace()
+            .flat_map(|word| self.encode_word(word))
+            .collect()
+    }
+
+    #[inline]
+    fn detokenize(&self, words: &[&str]) -> String {
+        join_subwords(words).join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Tokenizer the model was trained with
+///
+/// Subword models don't store their merges, so words
+/// are split into the longest subwords of the model.
+/// Words which can't be split are kept as is.
+pub struct ModelTokenizer<'a> {
+    kind: TokenizerKind,
+    punctuation: Option<&'a str>,
+    tokens: &'a Tokens
+}
+
+impl ModelTokenizer<'_> {
+    #[inline]
+    pub fn kind(&self) -> TokenizerKind {
+        self.kind
+    }
+}
+
+impl Tokenizer for ModelTokenizer<'_> {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        if self.kind == TokenizerKind::Chars {
+            return CharTokenizer.tokenize(text);
+        }
+
+        let mut words = match self.punctuation {
+            Some(chars) => Punctuation::new(chars).tokenize(text),
+            None => WhitespaceTokenizer.tokenize(text)
+        };
+
+        if self.kind == TokenizerKind::Bpe {
+            words = words.into_iter()
+                .flat_map(|word| {
+                    let subwords = self.tokens.find_subwords(&word.to_lowercase())
+                        .and_then(|tokens| {
+                            tokens.into_iter()
+                                .map(|token| self.tokens.find_word(token).map(String::from))
+                                .collect::<Option<Vec<_>>>()
+                        });
+
+                    subwords.unwrap_or_else(|| vec![word])
+                })
+                .collect();
+        }
+
+        words
+    }
+
+    fn detokenize(&self, words: &[&str]) -> String {
+        if self.kind == TokenizerKind::Chars {
+            return CharTokenizer.detokenize(words);
+        }
+
+        let words = if self.kind == TokenizerKind::Bpe {
+            join_subwords(words)
+        } else {
+            words.iter()
+                .map(|word| word.to_string())
+                .collect()
+        };
+
+        match self.punctuation {
+            Some(chars) => Punctuation::new(chars).join(&words),
+            None => words.join(" ")
+        }
+    }
+}
+
+impl Messages {
+    /// Re-split words of the messages by the tokenizer
+    pub fn tokenize(self, tokenizer: &(impl Tokenizer + ?Sized)) -> Self {
+        let messages = self.messages.into_iter()
+            .map(|message| tokenizer.tokenize(&message.join(" ")))
+            .filter(|message| !message.is_empty())
+            .collect();
+
+        Self {
+            messages
+        }
+    }
+}
+
+impl Model {
+    /// Kind of the tokenizer stored in the `tokenizer` header
+    ///
+    /// Models without the header were made by the punctuation or the
+    /// subwords tokenizer if they have the corresponding headers.
+    pub fn tokenizer_kind(&self) -> TokenizerKind {
+        if let Some(kind) = self.headers.get(TOKENIZER_HEADER).and_then(|name| TokenizerKind::from_name(name)) {
+            return kind;
+        }
+
+        if self.has_subwords() {
+            TokenizerKind::Bpe
+        } else if self.punctuation().is_some() {
+            TokenizerKind::Punctuation
+        } else {
+            TokenizerKind::Whitespace
+        }
+    }
+
+    #[inline]
+    /// Tokenizer matching the one the model was trained with
+    pub fn tokenizer(&self) -> ModelTokenizer<'_> {
+        ModelTokenizer {
+            kind: self.tokenizer_kind(),
+            punctuation: self.headers.get(PUNCTUATION_HEADER).map(String::as_str),
+            tokens: &self.tokens
+        }
+    }
+
+    /// Convert the text to tokens using the model tokenizer
+    ///
+    /// Words are matched ignoring case, unknown words fail.
+    pub fn tokenize_text(&self, text: &str) -> Result<Vec<u64>, Error> {
+        self.tokenizer()
+            .tokenize(text)
+            .into_iter()
+            .map(|word| self.tokens.find_token_ignore_case(&word).ok_or(Error::UnknownWord(word)))
+            .collect()
+    }
+}
+
+mod tests {
+    #[test]
+    fn tokenizers() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        assert_eq!(WhitespaceTokenizer.tokenize(" hello,  world "), ["hello,", "world"]);
+        assert_eq!(WhitespaceTokenizer.detokenize(&["hello,", "world"]), "hello, world");
+
+        assert_eq!(CharTokenizer.tokenize("hi  you"), ["h", "i", " ", "y", "o", "u"]);
+        assert_eq!(CharTokenizer.detokenize(&["h", "i", " ", "y", "o", "u"]), "hi you");
+
+        assert_eq!(Punctuation::default().tokenize("hello, world!"), ["hello", ",", "world", "!"]);
+        assert_eq!(Punctuation::default().detokenize(&["hello", ",", "world", "!"]), "hello, world!");
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("ab ba"),
+            String::from("abab")
+        ]);
+
+        let bpe = Bpe::train(&messages, 10);
+
+        let words = bpe.tokenize("abab ba");
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+
+        assert_eq!(bpe.detokenize(&words), "abab ba");
+
+        let messages = messages.tokenize(&CharTokenizer);
+
+        assert_eq!(messages.messages()[0], ["a", "b", " ", "b", "a"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn model_tokenizer() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::Error;
+
+        let model = |lines: &[&str], tokenizer: &dyn Tokenizer| -> anyhow::Result<Model> {
+            let messages = Messages::parse_from_lines(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>())
+                .tokenize(tokenizer);
+
+            let tokens = Tokens::parse_from_messages(&messages);
+
+            let dataset = Dataset::default()
+                .with_messages(TokenizedMessages::tokenize_message(&messages, &tokens)?, 1)
+                .with_tokens(tokens);
+
+            Ok(Model::build(dataset, true, true))
+        };
+
+        let chars = model(&["hello world"], &CharTokenizer)?
+            .with_header(TOKENIZER_HEADER, TokenizerKind::Chars.name());
+
+        assert_eq!(chars.tokenizer_kind(), TokenizerKind::Chars);
+        assert_eq!(chars.tokenizer().tokenize("he wo"), ["h", "e", " ", "w", "o"]);
+        assert_eq!(chars.join_words(&["h", "e", " ", "w", "o"]), "he wo");
+
+        assert_eq!(chars.tokenize_text("hello")?.len(), 5);
+        assert!(matches!(chars.tokenize_text("hi"), Err(Error::UnknownWord(word)) if word == "i"));
+
+        // Legacy models are recognized by their headers
+        let punctuation = model(&["hello, world!"], &Punctuation::default())?
+            .with_header(PUNCTUATION_HEADER, DEFAULT_PUNCTUATION);
+
+        assert_eq!(punctuation.tokenizer_kind(), TokenizerKind::Punctuation);
+        assert_eq!(punctuation.tokenizer().tokenize("world, hello!"), ["world", ",", "hello", "!"]);
+
+        let subwords = model(&["lo@@ w ne@@ w"], &WhitespaceTokenizer)?
+            .with_header(SUBWORDS_HEADER, SUBWORD_MARKER);
+
+        assert_eq!(subwords.tokenizer_kind(), TokenizerKind::Bpe);
+        assert_eq!(subwords.tokenizer().tokenize("lonew xyz"), ["lo@@", "ne@@", "w", "xyz"]);
+        assert_eq!(subwords.join_words(&["lo@@", "ne@@", "w"]), "lonew");
+
+        Ok(())
+    }
+}
@@ -1,58 +1,505 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
+use std::sync::OnceLock;
 
 use crate::prelude::Messages;
+use crate::Error;
 
 pub const START_TOKEN: u64 = u64::MIN;
 pub const END_TOKEN: u64 = u64::MAX;
 
+/// Token separating the context from the reply in dialogues
+pub const REPLY_TOKEN: u64 = u64::MAX - 1;
+
 pub const START_TOKEN_NAME: &str = "<START>";
 pub const END_TOKEN_NAME: &str = "<END>";
+pub const REPLY_TOKEN_NAME: &str = "<REPLY>";
+
+/// Default word replacing pruned words
+pub const UNK_TOKEN_NAME: &str = "<UNK>";
+
+/// Default normalized edit distance of the words with typos,
+/// see `Tokens::find_similar_word`
+pub const DEFAULT_TYPO_DISTANCE: f64 = 0.34;
+
+/// Serialize the map sorted by keys so equal maps have equal bytes
+pub(crate) fn serialize_sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + serde::Serialize,
+    V: serde::Serialize,
+    S: serde::Serializer
+{
+    serde::Serialize::serialize(&map.iter().collect::<BTreeMap<_, _>>(), serializer)
+}
+
+#[inline]
+/// Check if the token is used by the model itself
+pub(crate) fn is_reserved(token: u64) -> bool {
+    matches!(token, START_TOKEN | END_TOKEN | REPLY_TOKEN)
+}
+
+/// Stable token of the word
+///
+/// Attempts after the first one are used to resolve collisions.
+fn hash_token(word: &str, attempt: u64) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+
+    hasher.update(word.as_bytes());
+
+    if attempt > 0 {
+        hasher.update(&attempt.to_le_bytes());
+    }
+
+    let mut token = [0; 8];
+
+    token.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+
+    u64::from_le_bytes(token)
+}
+
+/// Levenshtein distance between the words in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+
+        row[0] = i + 1;
+
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+
+            diagonal = row[j + 1];
+
+            row[j + 1] = substitution
+                .min(row[j] + 1)
+                .min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Stable token reserved for the special word
+///
+/// Special words are recognized by having this token,
+/// so they don't need to be stored separately.
+pub fn special_token(word: &str) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+
+    hasher.update(b"special\0");
+    hasher.update(word.as_bytes());
+
+    let mut token = [0; 8];
+
+    token.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+
+    u64::from_le_bytes(token)
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+/// Translation of tokens from one bundle to another
+pub struct TokensRemap {
+    pub(crate) tokens: HashMap<u64, u64>
+}
+
+impl TokensRemap {
+    #[inline]
+    /// Get translated token, tokens without translation are not changed
+    pub fn get(&self, token: u64) -> u64 {
+        self.tokens.get(&token)
+            .copied()
+            .unwrap_or(token)
+    }
+
+    #[inline]
+    /// Amount of changed tokens
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
 
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Tokens {
+    #[serde(serialize_with = "serialize_sorted")]
     pub(crate) token_word: HashMap<u64, String>,
-    pub(crate) word_token: HashMap<String, u64>
+
+    #[serde(serialize_with = "serialize_sorted")]
+    pub(crate) word_token: HashMap<String, u64>,
+
+    /// Occurrences of the tokens in the parsed messages,
+    /// empty if the words were not counted
+    #[serde(default, serialize_with = "serialize_sorted")]
+    pub(crate) counts: HashMap<u64, u64>,
+
+    /// lowercase word -> token, built on the first case-insensitive lookup
+    #[serde(skip)]
+    lowercase_token: OnceLock<HashMap<String, u64>>
+}
+
+#[derive(serde::Deserialize)]
+/// Tokens written before the words were counted
+pub(crate) struct LegacyTokens {
+    token_word: HashMap<u64, String>,
+    word_token: HashMap<String, u64>
+}
+
+impl From<LegacyTokens> for Tokens {
+    #[inline]
+    fn from(tokens: LegacyTokens) -> Self {
+        Self {
+            token_word: tokens.token_word,
+            word_token: tokens.word_token,
+            ..Self::default()
+        }
+    }
 }
 
 impl Tokens {
+    #[inline]
+    pub(crate) fn insert_pair(&mut self, word: String, token: u64) {
+        self.word_token.insert(word.clone(), token);
+        self.token_word.insert(token, word);
+
+        self.lowercase_token.take();
+    }
+
+    #[inline]
+    fn remove_token(&mut self, token: u64) -> Option<String> {
+        let word = self.token_word.remove(&token)?;
+
+        self.word_token.remove(&word);
+        self.counts.remove(&token);
+        self.lowercase_token.take();
+
+        Some(word)
+    }
+
+    /// Parse tokens counting occurrences of the words
     pub fn parse_from_messages(messages: &Messages) -> Self {
-        let mut token_word = HashMap::new();
-        let mut word_token = HashMap::new();
+        let mut tokens = Self::default();
 
         for message in messages.messages() {
             for word in message {
-                if !word_token.contains_key(word) {
-                    let mut token = rand::random::<u64>();
+                let token = tokens.insert_word(word);
+
+                *tokens.counts.entry(token).or_default() += 1;
+            }
+        }
+
+        tokens
+    }
 
-                    while token_word.contains_key(&token) || token == START_TOKEN || token == END_TOKEN {
+    /// Parse tokens with ids derived from the words' hashes
+    ///
+    /// Unlike `parse_from_messages`, the same words always
+    /// get the same tokens, so builds are reproducible.
+    pub fn parse_from_messages_hashed(messages: &Messages) -> Self {
+        let words = messages.messages()
+            .iter()
+            .flatten()
+            .collect::<BTreeSet<_>>();
+
+        let mut tokens = Self::default();
+
+        for word in words {
+            tokens.insert_word_hashed(word);
+        }
+
+        tokens.count_words(messages);
+
+        tokens
+    }
+
+    /// Add occurrences of the known words in the messages to the counts
+    pub fn count_words(&mut self, messages: &Messages) {
+        for word in messages.messages().iter().flatten() {
+            if let Some(token) = self.word_token.get(word) {
+                *self.counts.entry(*token).or_default() += 1;
+            }
+        }
+    }
+
+    #[inline]
+    /// Check if occurrences of the words were counted
+    pub fn has_counts(&self) -> bool {
+        !self.counts.is_empty()
+    }
+
+    #[inline]
+    /// Occurrences of the tokens in the parsed messages
+    pub fn counts(&self) -> &HashMap<u64, u64> {
+        &self.counts
+    }
+
+    #[inline]
+    /// Get amount of occurrences of the token, 0 if it wasn't counted
+    pub fn count(&self, token: u64) -> u64 {
+        self.counts.get(&token)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the most frequent words with their counts
+    ///
+    /// Words with equal counts are sorted alphabetically.
+    pub fn top(&self, count: usize) -> Vec<(&str, u64)> {
+        let mut words = self.token_word.iter()
+            .map(|(token, word)| (word.as_str(), self.count(*token)))
+            .collect::<Vec<_>>();
+
+        words.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        words.truncate(count);
+
+        words
+    }
+
+    /// Get words seen not more than `max_count` times with their counts
+    ///
+    /// Words are sorted by their counts, then alphabetically.
+    /// Special words are skipped.
+    pub fn rare(&self, max_count: u64) -> Vec<(&str, u64)> {
+        let mut words = self.token_word.iter()
+            .filter(|(token, word)| special_token(word) != **token)
+            .map(|(token, word)| (word.as_str(), self.count(*token)))
+            .filter(|(_, count)| *count <= max_count)
+            .collect::<Vec<_>>();
+
+        words.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+
+        words
+    }
+
+    /// Get token of the word, assigning the hash of the word
+    /// if the word is unknown
+    pub fn insert_word_hashed(&mut self, word: &str) -> u64 {
+        if let Some(token) = self.word_token.get(word) {
+            return *token;
+        }
+
+        let mut attempt = 0;
+        let mut token = hash_token(word, attempt);
+
+        while self.token_word.contains_key(&token) || is_reserved(token) {
+            attempt += 1;
+            token = hash_token(word, attempt);
+        }
+
+        self.insert_pair(word.to_owned(), token);
+
+        token
+    }
+
+    /// Register special word, e.g. `<URL>` placeholder
+    ///
+    /// Special words get reserved tokens and are never pruned.
+    /// Returns translation of the word's previous token if it had one.
+    pub fn register_special(&mut self, word: &str) -> TokensRemap {
+        let token = special_token(word);
+
+        let mut remap = TokensRemap::default();
+
+        if self.word_token.get(word) == Some(&token) {
+            return remap;
+        }
+
+        let mut count = None;
+
+        if let Some(old_token) = self.find_token(word) {
+            count = self.counts.remove(&old_token);
+
+            self.remove_token(old_token);
+
+            remap.tokens.insert(old_token, token);
+        }
+
+        // Move another word which took the reserved token
+        let other_count = self.counts.remove(&token);
+
+        if let Some(other) = self.remove_token(token) {
+            let other_token = self.insert_word(&other);
+
+            if let Some(other_count) = other_count {
+                self.counts.insert(other_token, other_count);
+            }
+
+            remap.tokens.insert(token, other_token);
+        }
+
+        self.insert_pair(word.to_owned(), token);
+
+        if let Some(count) = count {
+            self.counts.insert(token, count);
+        }
+
+        remap
+    }
+
+    /// Check if tokens of all the words are derived from their hashes
+    ///
+    /// True for bundles made by `parse_from_messages_hashed`
+    /// and `insert_word_hashed`, false for the empty bundle.
+    pub fn is_hashed(&self) -> bool {
+        !self.is_empty() && self.token_word.iter().all(|(token, word)| {
+            // Collisions are practically impossible, so only check a few attempts
+            special_token(word) == *token || (0..4).any(|attempt| hash_token(word, attempt) == *token)
+        })
+    }
+
+    #[inline]
+    /// Check if the token belongs to a special word
+    pub fn is_special(&self, token: u64) -> bool {
+        self.token_word.get(&token)
+            .is_some_and(|word| special_token(word) == token)
+    }
+
+    /// Iterate over registered special words
+    pub fn special_words(&self) -> impl Iterator<Item = &'_ str> {
+        self.token_word.iter()
+            .filter(|(token, word)| special_token(word) == **token)
+            .map(|(_, word)| word.as_str())
+    }
+
+    /// Get token of the word, assigning a new random one
+    /// if the word is unknown
+    pub fn insert_word(&mut self, word: &str) -> u64 {
+        if let Some(token) = self.word_token.get(word) {
+            return *token;
+        }
+
+        let mut token = rand::random::<u64>();
+
+        while self.token_word.contains_key(&token) || is_reserved(token) {
+            token = rand::random::<u64>();
+        }
+
+        self.insert_pair(word.to_owned(), token);
+
+        token
+    }
+
+    #[inline]
+    pub fn merge(self, tokens: Tokens) -> Self {
+        self.merge_with_remap(tokens).0
+    }
+
+    /// Merge tokens bundles and get the translation of
+    /// the merged bundle's tokens to the resulting ones
+    ///
+    /// Words of the merged bundle keep tokens of this bundle,
+    /// and new words get new tokens if theirs are already taken.
+    /// Counts of the words are summed.
+    pub fn merge_with_remap(mut self, mut tokens: Tokens) -> (Self, TokensRemap) {
+        let mut remap = TokensRemap::default();
+
+        for (word, mut token) in tokens.word_token {
+            let count = tokens.counts.remove(&token);
+            let old_token = token;
+
+            match self.word_token.get(&word) {
+                Some(existing) => token = *existing,
+
+                None => {
+                    while self.token_word.contains_key(&token) || is_reserved(token) {
                         token = rand::random::<u64>();
                     }
 
-                    word_token.insert(word.to_owned(), token);
-                    token_word.insert(token, word.to_owned());
+                    self.insert_pair(word, token);
                 }
             }
+
+            if let Some(count) = count {
+                *self.counts.entry(token).or_default() += count;
+            }
+
+            if token != old_token {
+                remap.tokens.insert(old_token, token);
+            }
         }
 
-        Self {
-            token_word,
-            word_token
+        (self, remap)
+    }
+
+    /// Replace tokens seen less than `min_count` times by the `unk` word
+    ///
+    /// Returns translation of the removed tokens to the `unk` token.
+    /// The `unk` word and special words are kept even if they're rare.
+    pub fn prune(mut self, counts: &HashMap<u64, u64>, min_count: u64, unk: &str) -> (Self, TokensRemap) {
+        let removed = self.token_word.iter()
+            .filter(|(token, word)| {
+                word.as_str() != unk && special_token(word) != **token && counts.get(token).copied().unwrap_or_default() < min_count
+            })
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+
+        let mut remap = TokensRemap::default();
+
+        if removed.is_empty() {
+            return (self, remap);
+        }
+
+        let counted = self.has_counts();
+
+        for token in &removed {
+            self.remove_token(*token);
         }
+
+        let unk = self.insert_word_hashed(unk);
+
+        let unk_count = removed.iter()
+            .map(|token| counts.get(token).copied().unwrap_or_default())
+            .sum::<u64>();
+
+        if counted && unk_count > 0 {
+            *self.counts.entry(unk).or_default() += unk_count;
+        }
+
+        remap.tokens = removed.into_iter()
+            .map(|token| (token, unk))
+            .collect();
+
+        (self, remap)
     }
 
-    pub fn merge(mut self, tokens: Tokens) -> Self {
-        for (word, mut token) in tokens.word_token {
-            if !self.word_token.contains_key(&word) {
-                while self.token_word.contains_key(&token) || token == START_TOKEN || token == END_TOKEN {
-                    token = rand::random::<u64>();
-                }
+    /// Remove words whose tokens don't satisfy the predicate
+    ///
+    /// Special words are always kept. Returns amount of removed words.
+    pub fn retain(&mut self, f: impl Fn(u64) -> bool) -> usize {
+        let removed = self.token_word.iter()
+            .filter(|(token, word)| special_token(word) != **token && !f(**token))
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+
+        for token in &removed {
+            self.remove_token(*token);
+        }
 
-                self.word_token.insert(word.clone(), token);
-                self.token_word.insert(token, word);
+        removed.len()
+    }
+
+    /// Get translation of this bundle's tokens to the other bundle
+    ///
+    /// Fails if some word has no token in the other bundle.
+    pub fn remap_to(&self, other: &Tokens) -> Result<TokensRemap, Error> {
+        let mut remap = TokensRemap::default();
+
+        for (word, token) in &self.word_token {
+            let Some(other_token) = other.find_token(word) else {
+                return Err(Error::UnknownWord(word.clone()));
+            };
+
+            if *token != other_token {
+                remap.tokens.insert(*token, other_token);
             }
         }
 
-        self
+        Ok(remap)
     }
 
     #[inline]
@@ -60,11 +507,114 @@ impl Tokens {
         self.word_token.get(word.as_ref()).copied()
     }
 
+    /// Find token of the word ignoring its case
+    ///
+    /// Exact match is preferred. Otherwise, if several words differ
+    /// only in case, the lowercase one or the first in the alphabetical
+    /// order is used.
+    pub fn find_token_ignore_case(&self, word: impl AsRef<str>) -> Option<u64> {
+        let word = word.as_ref();
+
+        if let Some(token) = self.find_token(word) {
+            return Some(token);
+        }
+
+        let index = self.lowercase_token.get_or_init(|| {
+            let mut index = HashMap::<String, (&str, u64)>::new();
+
+            for (word, token) in &self.word_token {
+                let lowercase = word.to_lowercase();
+
+                let replace = match index.get(&lowercase) {
+                    Some((current, _)) => *current != lowercase && (word == &lowercase || word.as_str() < *current),
+                    None => true
+                };
+
+                if replace {
+                    index.insert(lowercase, (word, *token));
+                }
+            }
+
+            index.into_iter()
+                .map(|(lowercase, (_, token))| (lowercase, token))
+                .collect()
+        });
+
+        index.get(&word.to_lowercase()).copied()
+    }
+
+    /// Find the known word closest to the given one by normalized edit distance
+    ///
+    /// Edit distance is divided by the length of the longer word, so
+    /// `max_distance` of 0.34 allows about one typo per three characters.
+    /// At least one typo is allowed for non-zero `max_distance`, so short
+    /// words can be matched as well. Words are compared ignoring case,
+    /// special words are never suggested.
+    /// Among equally close words the alphabetically first one is used.
+    pub fn find_similar_word(&self, word: impl AsRef<str>, max_distance: f64) -> Option<(&str, u64)> {
+        let word = word.as_ref().to_lowercase();
+        let len = word.chars().count();
+
+        let mut closest: Option<(f64, &str, u64)> = None;
+
+        for (known, token) in &self.word_token {
+            if special_token(known) == *token {
+                continue;
+            }
+
+            let known_len = known.chars().count();
+            let longest = len.max(known_len).max(1) as f64;
+
+            let allowed = |edits: usize| {
+                edits as f64 / longest <= max_distance || (edits == 1 && max_distance > 0.0)
+            };
+
+            // Length difference is the lower bound of the distance
+            if !allowed(known_len.abs_diff(len)) {
+                continue;
+            }
+
+            let edits = edit_distance(&word, &known.to_lowercase());
+
+            if !allowed(edits) {
+                continue;
+            }
+
+            let distance = edits as f64 / longest;
+
+            let replace = match closest {
+                Some((closest_distance, closest_word, _)) => distance < closest_distance
+                    || (distance == closest_distance && known.as_str() < closest_word),
+
+                None => true
+            };
+
+            if replace {
+                closest = Some((distance, known, *token));
+            }
+        }
+
+        closest.map(|(_, word, token)| (word, token))
+    }
+
+    /// Find tokens of all the case variants of the words
+    pub fn find_all_ignore_case<T: AsRef<str>>(&self, words: impl IntoIterator<Item = T>) -> HashSet<u64> {
+        let words = words.into_iter()
+            .map(|word| word.as_ref().to_lowercase())
+            .collect::<HashSet<_>>();
+
+        self.word_token.iter()
+            .filter(|(word, _)| words.contains(&word.to_lowercase()))
+            .map(|(_, token)| *token)
+            .collect()
+    }
+
     #[inline]
     pub fn find_word(&self, token: u64) -> Option<&str> {
         match token {
             START_TOKEN => Some(START_TOKEN_NAME),
             END_TOKEN => Some(END_TOKEN_NAME),
+            REPLY_TOKEN => Some(REPLY_TOKEN_NAME),
 
             _ => self.token_word.get(&token)
                 .map(|word| word.as_str())
@@ -81,12 +631,12 @@ impl Tokens {
         self.token_word.is_empty()
     }
 
-    pub fn detokenize_message(&self, tokens: &[u64]) -> anyhow::Result<String> {
+    pub fn detokenize_message(&self, tokens: &[u64]) -> Result<String, Error> {
         let mut words = Vec::with_capacity(tokens.len());
 
         for token in tokens {
             let Some(word) = self.find_word(*token) else {
-                anyhow::bail!("Could not find word for token: {token}");
+                return Err(Error::TokenNotFound(*token));
             };
 
             words.push(word.to_owned());
@@ -119,6 +669,236 @@ mod tests {
         assert_eq!(tokens.find_word(text), Some("text"));
     }
 
+    #[test]
+    fn hashed() -> anyhow::Result<()> {
+        use super::{Tokens, Messages};
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Example text")
+        ]);
+
+        let tokens = Tokens::parse_from_messages_hashed(&messages);
+        let other = Tokens::parse_from_messages_hashed(&messages);
+
+        assert_eq!(tokens.find_token("hello,"), other.find_token("hello,"));
+        assert_eq!(postcard::to_allocvec(&tokens)?, postcard::to_allocvec(&other)?);
+
+        // Tokens don't depend on the other words
+        let single = Tokens::parse_from_messages_hashed(&Messages::parse_from_lines(&[
+            String::from("text")
+        ]));
+
+        assert_eq!(single.find_token("text"), tokens.find_token("text"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn remap() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+
+        let other_messages = Messages::parse_from_lines(&[
+            String::from("Hello, there")
+        ]);
+
+        // Make the "there" token collide with the "hello," token
+        let mut other = Tokens::parse_from_messages(&other_messages);
+
+        let hello = tokens.find_token("hello,").unwrap();
+        let there = other.find_token("there").unwrap();
+
+        other.token_word.remove(&there);
+        other.token_word.insert(hello, String::from("there"));
+        other.word_token.insert(String::from("there"), hello);
+
+        let tokenized = TokenizedMessages::tokenize_message(&other_messages, &other)?;
+
+        let (merged, remap) = tokens.clone().merge_with_remap(other.clone());
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(remap.len(), 2);
+
+        let tokenized = tokenized.remap(&remap);
+
+        assert_eq!(merged.detokenize_message(&tokenized.messages()[0])?, "hello, there");
+        assert_eq!(other.remap_to(&merged)?, remap);
+        assert!(merged.remap_to(&tokens).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Hello, there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let hello = tokens.find_token("hello,").unwrap();
+
+        let counts = tokenized.token_counts();
+
+        let (pruned, remap) = tokens.prune(&counts, 2, UNK_TOKEN_NAME);
+
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(pruned.find_token("hello,"), Some(hello));
+        assert_eq!(remap.len(), 2);
+
+        let tokenized = tokenized.remap(&remap);
+
+        assert_eq!(pruned.detokenize_message(&tokenized.messages()[0])?, "hello, <UNK>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn counts() {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world hello"),
+            String::from("hello there world")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let hashed = Tokens::parse_from_messages_hashed(&messages);
+
+        let hello = tokens.find_token("hello").unwrap();
+
+        assert_eq!(tokens.count(hello), 3);
+        assert_eq!(tokens.counts(), &TokenizedMessages::tokenize_message(&messages, &tokens).unwrap().token_counts());
+        assert_eq!(hashed.count(hashed.find_token("world").unwrap()), 2);
+
+        assert_eq!(tokens.top(2), [("hello", 3), ("world", 2)]);
+        assert_eq!(tokens.rare(2), [("there", 1), ("world", 2)]);
+
+        // Counts are summed when merging
+        let merged = tokens.clone().merge(hashed);
+
+        assert_eq!(merged.top(1), [("hello", 6)]);
+
+        let (pruned, _) = tokens.prune(&Default::default(), 0, UNK_TOKEN_NAME);
+
+        assert_eq!(pruned.rare(u64::MAX).len(), 3);
+        assert!(!Tokens::default().has_counts());
+    }
+
+    #[test]
+    fn special() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::tokens::special_token;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("visit https://example.com now")
+        ]);
+
+        let messages = messages.anonymize(&Anonymizer::special_tokens());
+
+        let mut tokens = Tokens::parse_from_messages(&messages);
+
+        let url = tokens.find_token("<URL>").unwrap();
+
+        let remap = tokens.register_special("<URL>");
+
+        assert_eq!(remap.get(url), special_token("<URL>"));
+        assert!(tokens.register_special("<URL>").is_empty());
+        assert!(tokens.register_special("<USER>").is_empty());
+
+        assert!(tokens.is_special(special_token("<USER>")));
+        assert!(!tokens.is_special(tokens.find_token("visit").unwrap()));
+
+        let mut special = tokens.special_words().collect::<Vec<_>>();
+
+        special.sort();
+
+        assert_eq!(special, ["<URL>", "<USER>"]);
+
+        // Special words are not pruned
+        let (pruned, _) = tokens.prune(&Default::default(), 1, UNK_TOKEN_NAME);
+
+        assert!(pruned.find_token("<USER>").is_some());
+        assert!(pruned.find_token("visit").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_case() {
+        use crate::prelude::*;
+
+        let lines = [
+            String::from("Hello World"),
+            String::from("HELLO hello world")
+        ];
+
+        let messages = Messages::parse_from_lines_with_filter(&lines, |word| word.to_string());
+
+        let mut tokens = Tokens::parse_from_messages(&messages);
+
+        let hello = tokens.find_token("hello").unwrap();
+
+        assert_eq!(tokens.find_token("world"), tokens.find_token_ignore_case("WORLD"));
+        assert_eq!(tokens.find_token_ignore_case("Hello"), tokens.find_token("Hello"));
+        assert_eq!(tokens.find_token_ignore_case("hELLO"), Some(hello));
+        assert_eq!(tokens.find_token_ignore_case("there"), None);
+
+        // Index is updated with the new words
+        let there = tokens.insert_word("There");
+
+        assert_eq!(tokens.find_token_ignore_case("there"), Some(there));
+
+        let all = tokens.find_all_ignore_case(["hello", "THERE"]);
+
+        assert_eq!(all.len(), 4);
+        assert!(all.contains(&hello) && all.contains(&there));
+    }
+
+    #[test]
+    fn closest_word() {
+        use crate::prelude::*;
+
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("привет", "привет"), 0);
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello world word to")
+        ]);
+
+        let mut tokens = Tokens::parse_from_messages(&messages);
+
+        tokens.register_special(UNK_TOKEN_NAME);
+
+        let hello = tokens.find_token("hello").unwrap();
+        let word = tokens.find_token("word").unwrap();
+        let to = tokens.find_token("to").unwrap();
+
+        assert_eq!(tokens.find_similar_word("HELO", DEFAULT_TYPO_DISTANCE), Some(("hello", hello)));
+        assert_eq!(tokens.find_similar_word("wordl", DEFAULT_TYPO_DISTANCE), Some(("word", word)));
+        assert_eq!(tokens.find_similar_word("worxx", 0.3), None);
+        assert_eq!(tokens.find_similar_word("xyz", DEFAULT_TYPO_DISTANCE), None);
+        assert_eq!(tokens.find_similar_word("worx", 0.0), None);
+
+        // Short words still allow a single typo
+        assert_eq!(tokens.find_similar_word("tp", DEFAULT_TYPO_DISTANCE), Some(("to", to)));
+        assert_eq!(tokens.find_similar_word("xp", DEFAULT_TYPO_DISTANCE), None);
+
+        // Special words are not suggested
+        assert_eq!(tokens.find_similar_word("<UNK", 0.5), None);
+    }
+
     #[test]
     fn merging() {
         use super::{Tokens, Messages};
@@ -0,0 +1,303 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::prelude::{
+    Tokens,
+    TokensRemap,
+    TokenizedMessages,
+    Dataset
+};
+
+use crate::tokens::is_reserved;
+use crate::Error;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+/// Changes of the tokens bundle edited by hand
+pub struct TokensEdit {
+    /// Translation of the merged and moved words' tokens
+    pub remap: TokensRemap,
+
+    /// Tokens of the deleted words
+    pub removed: HashSet<u64>
+}
+
+impl TokensEdit {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.remap.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Tokens {
+    /// Write the tokens as tab separated `token`, `word`
+    /// and `count` lines
+    ///
+    /// Words are sorted ignoring case so their variants are
+    /// next to each other. Count is 0 for the uncounted words.
+    pub fn write_tsv(&self, mut writer: impl Write) -> Result<(), Error> {
+        let mut words = self.token_word.iter().collect::<Vec<_>>();
+
+        words.sort_by_cached_key(|(_, word)| (word.to_lowercase(), word.to_string()));
+
+        writeln!(writer, "# token\tword\tcount")?;
+
+        for (token, word) in words {
+            writeln!(writer, "{token}\t{word}\t{}", self.count(*token))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Parse tokens from the table written by `write_tsv`
+    ///
+    /// Empty lines and lines starting with `#` are skipped, count
+    /// column is optional. Lines of the same word are merged into
+    /// the first one, so returns translation of the merged tokens.
+    pub fn parse_tsv(text: &str) -> Result<(Self, TokensRemap), Error> {
+        let mut tokens = Self::default();
+        let mut merged = TokensRemap::default();
+
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| Error::InvalidTokensTable {
+                line: i + 1,
+                reason: reason.to_string()
+            };
+
+            let mut columns = line.split('\t');
+
+            let (Some(token), Some(word)) = (columns.next(), columns.next()) else {
+                return Err(invalid("expected tab separated token and word"));
+            };
+
+            let token = token.trim()
+                .parse::<u64>()
+                .map_err(|_| invalid("token is not a number"))?;
+
+            if is_reserved(token) {
+                return Err(invalid("token is reserved by the model"));
+            }
+
+            let word = word.trim();
+
+            if word.is_empty() {
+                return Err(invalid("word is empty"));
+            }
+
+            let count = match columns.next().map(str::trim) {
+                Some(count) if !count.is_empty() => count.parse::<u64>()
+                    .map_err(|_| invalid("count is not a number"))?,
+
+                _ => 0
+            };
+
+            let target = match tokens.find_token(word) {
+                Some(existing) => {
+                    if existing != token {
+                        merged.tokens.insert(token, existing);
+                    }
+
+                    existing
+                }
+
+                None => {
+                    if tokens.token_word.contains_key(&token) || merged.tokens.contains_key(&token) {
+                        return Err(Error::TokenCollision {
+                            word: word.to_string(),
+                            token
+                        });
+                    }
+
+                    tokens.insert_pair(word.to_string(), token);
+
+                    token
+                }
+            };
+
+            if count > 0 {
+                *tokens.counts.entry(target).or_default() += count;
+            }
+        }
+
+        // Merged tokens can't be reused by the following lines
+        if let Some(token) = merged.tokens.keys().find(|token| tokens.token_word.contains_key(token)) {
+            return Err(Error::TokenCollision {
+                word: tokens.token_word[token].clone(),
+                token: *token
+            });
+        }
+
+        Ok((tokens, merged))
+    }
+
+    /// Find changes turning this bundle into the edited one
+    ///
+    /// Tokens missing in the edited bundle are translated by the
+    /// `merged` translation or to the edited token of their word,
+    /// other missing tokens are removed.
+    pub fn edit_to(&self, edited: &Tokens, merged: &TokensRemap) -> TokensEdit {
+        let mut edit = TokensEdit::default();
+
+        for (token, word) in &self.token_word {
+            if edited.token_word.contains_key(token) {
+                continue;
+            }
+
+            let target = merged.tokens.get(token)
+                .copied()
+                .or_else(|| edited.find_token(word));
+
+            match target {
+                Some(target) => {
+                    edit.remap.tokens.insert(*token, target);
+                }
+
+                None => {
+                    edit.removed.insert(*token);
+                }
+            }
+        }
+
+        edit
+    }
+
+    /// Apply the edit to the tokens
+    ///
+    /// Words of the edited bundle replace the words of their tokens,
+    /// counts of the translated tokens are summed. Words taken by
+    /// other tokens are not changed.
+    pub fn apply_edit(&mut self, edited: &Tokens, edit: &TokensEdit) {
+        for (from, to) in &edit.remap.tokens {
+            if let Some(count) = self.counts.remove(from) {
+                *self.counts.entry(*to).or_default() += count;
+            }
+        }
+
+        self.retain(|token| !edit.removed.contains(&token) && !edit.remap.tokens.contains_key(&token));
+
+        let targets = edit.remap.tokens.values().collect::<HashSet<_>>();
+
+        for (token, word) in &edited.token_word {
+            if !self.token_word.contains_key(token) && !targets.contains(token) {
+                continue;
+            }
+
+            if self.word_token.get(word).is_some_and(|other| other != token) {
+                continue;
+            }
+
+            if let Some(old) = self.token_word.get(token) {
+                if old == word {
+                    continue;
+                }
+
+                self.word_token.remove(old);
+            }
+
+            self.insert_pair(word.clone(), *token);
+        }
+    }
+}
+
+impl TokenizedMessages {
+    /// Translate the tokens by the edit removing the deleted ones
+    ///
+    /// Messages left empty are removed.
+    pub fn apply_tokens_edit(mut self, edit: &TokensEdit) -> Self {
+        self = self.remap(&edit.remap);
+
+        if !edit.removed.is_empty() {
+            for message in &mut self.messages {
+                message.retain(|token| !edit.removed.contains(token));
+            }
+
+            self.messages.retain(|message| !message.is_empty());
+        }
+
+        self
+    }
+}
+
+impl Dataset {
+    /// Translate the messages and tokens by the edit of the tokens bundle
+    ///
+    /// See `Tokens::apply_edit`.
+    pub fn apply_tokens_edit(mut self, edited: &Tokens, edit: &TokensEdit) -> Self {
+        for (messages, _) in &mut self.messages {
+            *messages = std::mem::take(messages).apply_tokens_edit(edit);
+        }
+
+        self.tokens.apply_edit(edited, edit);
+
+        self
+    }
+}
+
+mod tests {
+    #[test]
+    fn tokens_table() -> anyhow::Result<()> {
+        use crate::prelude::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("hello helo world"),
+            String::from("hello junk")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        let mut table = Vec::new();
+
+        tokens.write_tsv(&mut table)?;
+
+        let table = String::from_utf8(table)?;
+
+        // Round trip keeps the tokens and counts
+        let (parsed, merged) = Tokens::parse_tsv(&table)?;
+
+        assert!(merged.is_empty());
+        assert!(tokens.edit_to(&parsed, &merged).is_empty());
+        assert_eq!(parsed.top(1), [("hello", 2)]);
+
+        // Fix the typo, delete junk and rename world
+        let table = table.lines()
+            .filter(|line| !line.contains("junk"))
+            .map(|line| line.replace("helo", "hello").replace("world", "World"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let (edited, merged) = Tokens::parse_tsv(&table)?;
+
+        let hello = tokens.find_token("hello").unwrap();
+        let helo = tokens.find_token("helo").unwrap();
+        let junk = tokens.find_token("junk").unwrap();
+
+        assert_eq!(edited.len(), 2);
+        assert_eq!(edited.find_token("hello"), Some(hello));
+        assert_eq!(edited.count(hello), 3);
+        assert_eq!(merged.get(helo), hello);
+
+        let edit = tokens.edit_to(&edited, &merged);
+
+        assert_eq!(edit.removed, [junk].into());
+
+        let dataset = Dataset::default()
+            .with_messages(tokenized, 1)
+            .with_tokens(tokens)
+            .apply_tokens_edit(&edited, &edit);
+
+        assert_eq!(dataset.tokens().len(), 2);
+        assert_eq!(dataset.tokens().detokenize_message(&dataset.messages()[0].0.messages()[0])?, "hello hello World");
+        assert_eq!(dataset.tokens().detokenize_message(&dataset.messages()[0].0.messages()[1])?, "hello");
+
+        assert!(Tokens::parse_tsv("1\thello\n1\tworld").is_err());
+        assert!(Tokens::parse_tsv("hello\t1").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use crate::prelude::{
+    Messages,
+    Tokens,
+    TokenizedMessages,
+    Dataset,
+    Model,
+    TransitionsTable,
+    START_TOKEN,
+    END_TOKEN
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Inconsistency found in the artifacts chain
+pub enum Violation {
+    /// Word of the message has no token
+    UnknownWord(String),
+
+    /// Token has no word in the tokens bundle
+    UnknownToken(u64),
+
+    /// Detokenized message is not in the messages bundle
+    MessageMismatch(String),
+
+    /// Transition of the model which no message of the dataset contains
+    UnbackedTransition {
+        order: usize,
+        current: Vec<u64>,
+        next: Vec<u64>
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownWord(word) => write!(f, "word {word:?} has no token"),
+            Self::UnknownToken(token) => write!(f, "token {token} has no word"),
+            Self::MessageMismatch(message) => write!(f, "detokenized message {message:?} is not in the messages bundle"),
+
+            Self::UnbackedTransition { order, current, next } => {
+                write!(f, "{order}-gram transition {current:?} -> {next:?} is not backed by any message")
+            }
+        }
+    }
+}
+
+/// Check that every word of the messages has a token
+pub fn verify_tokens(messages: &Messages, tokens: &Tokens) -> Vec<Violation> {
+    let mut violations = messages.messages()
+        .iter()
+        .flatten()
+        .filter(|word| tokens.find_token(word).is_none())
+        .map(|word| Violation::UnknownWord(word.clone()))
+        .collect::<Vec<_>>();
+
+    violations.sort_by_key(|violation| violation.to_string());
+    violations.dedup();
+
+    violations
+}
+
+/// Check that detokenizing tokenized messages reproduces the parsed messages
+pub fn verify_tokenized(messages: &Messages, tokens: &Tokens, tokenized: &TokenizedMessages) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    let messages = messages.messages()
+        .iter()
+        .collect::<HashSet<_>>();
+
+    for message in tokenized.messages() {
+        let words = message.iter()
+            .map(|token| tokens.find_word(*token).map(String::from).ok_or(*token))
+            .collect::<Result<Vec<_>, _>>();
+
+        match words {
+            Ok(words) if !messages.contains(&words) => {
+                violations.push(Violation::MessageMismatch(words.join(" ")));
+            }
+
+            Err(token) => violations.push(Violation::UnknownToken(token)),
+
+            _ => ()
+        }
+    }
+
+    violations
+}
+
+/// Check that every token of the dataset messages has a word
+pub fn verify_dataset(dataset: &Dataset) -> Vec<Violation> {
+    let mut violations = dataset.messages()
+        .iter()
+        .flat_map(|(messages, _)| messages.messages().iter().flatten())
+        .filter(|token| dataset.tokens().find_word(**token).is_none())
+        .map(|token| Violation::UnknownToken(*token))
+        .collect::<Vec<_>>();
+
+    violations.sort_by_key(|violation| violation.to_string());
+    violations.dedup();
+
+    violations
+}
+
+fn verify_table<const SIZE: usize>(model: &TransitionsTable<SIZE>, dataset: &TransitionsTable<SIZE>, tokens: &Tokens) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    for (current, transitions) in model.iter() {
+        let backed = dataset.get(current);
+
+        for next in transitions.keys() {
+            if !backed.is_some_and(|backed| backed.contains_key(next)) {
+                violations.push(Violation::UnbackedTransition {
+                    order: SIZE,
+                    current: current.tokens().to_vec(),
+                    next: next.tokens().to_vec()
+                });
+            }
+
+            for token in next.tokens() {
+                if *token != START_TOKEN && *token != END_TOKEN && tokens.find_word(*token).is_none() {
+                    violations.push(Violation::UnknownToken(*token));
+                }
+            }
+        }
+    }
+
+    violations
+}
+
+/// Check that every transition of the model is backed
+/// by at least one message of the dataset and every token
+/// of the transitions has a word
+///
+/// Counts are not compared since the model could be decayed.
+pub fn verify_model(model: &Model, dataset: &Dataset) -> Vec<Violation> {
+    let transitions = model.transitions();
+
+    let backed = dataset.build_transitions_with_order(transitions.order());
+
+    let mut violations = verify_table(&transitions.unigrams, &backed.unigrams, model.tokens());
+
+    if let (Some(table), Some(backed)) = (&transitions.bigrams, &backed.bigrams) {
+        violations.extend(verify_table(table, backed, model.tokens()));
+    }
+
+    if let (Some(table), Some(backed)) = (&transitions.trigrams, &backed.trigrams) {
+        violations.extend(verify_table(table, backed, model.tokens()));
+    }
+
+    if let (Some(table), Some(backed)) = (&transitions.quadgrams, &backed.quadgrams) {
+        violations.extend(verify_table(table, backed, model.tokens()));
+    }
+
+    if let (Some(table), Some(backed)) = (&transitions.pentagrams, &backed.pentagrams) {
+        violations.extend(verify_table(table, backed, model.tokens()));
+    }
+
+    violations
+}
+
+mod tests {
+    #[test]
+    fn verify() -> anyhow::Result<()> {
+        use crate::prelude::*;
+        use crate::verify::*;
+
+        let messages = Messages::parse_from_lines(&[
+            String::from("Hello, World!"),
+            String::from("Hello, there")
+        ]);
+
+        let tokens = Tokens::parse_from_messages(&messages);
+        let tokenized = TokenizedMessages::tokenize_message(&messages, &tokens)?;
+
+        assert!(verify_tokens(&messages, &tokens).is_empty());
+        assert!(verify_tokenized(&messages, &tokens, &tokenized).is_empty());
+
+        let dataset = Dataset::default()
+            .with_messages(tokenized.clone(), 1)
+            .with_tokens(tokens.clone());
+
+        assert!(verify_dataset(&dataset).is_empty());
+
+        let mut model = Model::build(dataset.clone(), true, true);
+
+        assert!(verify_model(&model, &dataset).is_empty());
+
+        let other = Messages::parse_from_lines(&[
+            String::from("Hello, World!")
+        ]);
+
+        assert_eq!(verify_tokenized(&other, &tokens, &tokenized).len(), 1);
+
+        let hello = tokens.find_token("hello,").unwrap();
+
+        model.observe(&[hello, hello]);
+        model.flush();
+
+        let violations = verify_model(&model, &dataset);
+
+        assert!(violations.contains(&Violation::UnbackedTransition {
+            order: 1,
+            current: vec![hello],
+            next: vec![hello]
+        }));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use wasm_bindgen::prelude::*;
+
+use crate::prelude::{
+    Model,
+    GenerationParams,
+    GenerationOverrides,
+    GenerationBounds
+};
+
+#[wasm_bindgen]
+/// Language model running in the browser
+///
+/// ```js
+/// const bytes = new Uint8Array(await (await fetch("model.bin")).arrayBuffer());
+/// const model = new WasmModel(bytes);
+///
+/// console.log(model.generate("hello", JSON.stringify({ max_len: 30 })));
+/// ```
+pub struct WasmModel {
+    model: Model,
+    params: GenerationParams
+}
+
+#[wasm_bindgen]
+impl WasmModel {
+    #[wasm_bindgen(constructor)]
+    /// Load model from the bytes of its bundle
+    ///
+    /// Compressed and plain bundles are both supported.
+    /// Mapped models can't be loaded from bytes.
+    pub fn new(bytes: &[u8]) -> Result<WasmModel, JsError> {
+        Ok(Self {
+            model: Model::from_bytes(bytes)?,
+            params: GenerationParams::default()
+        })
+    }
+
+    /// Get value of the model header
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.model.headers()
+            .get(name)
+            .cloned()
+    }
+
+    /// Amount of words known to the model
+    pub fn words(&self) -> usize {
+        self.model.tokens().len()
+    }
+
+    /// Replace default generation params by the JSON object
+    ///
+    /// Missing params take their default values, see `GenerationParams`.
+    pub fn set_params(&mut self, params: &str) -> Result<(), JsError> {
+        self.params = serde_json::from_str(params)?;
+
+        Ok(())
+    }
+
+    /// Generate text continuing the prompt
+    ///
+    /// Prompt is split by the tokenizer the model was built with,
+    /// empty prompt generates a new message. `overrides` is an
+    /// optional JSON object with `temperature`, `max_len`, `seed`
+    /// and `top_k` fields changing the params of this request.
+    pub fn generate(&self, prompt: &str, overrides: Option<String>) -> Result<String, JsError> {
+        let params = match overrides {
+            Some(overrides) => {
+                let overrides = serde_json::from_str::<GenerationOverrides>(&overrides)?;
+
+                self.params.with_overrides(&overrides, &GenerationBounds::unlimited())
+            }
+
+            None => self.params
+        };
+
+        let beginning = self.model.tokenize_text(prompt)?;
+
+        Ok(self.model.generate_text(beginning, &params)?)
+    }
+}